@@ -0,0 +1,19 @@
+-- Client order ids mirror the on-chain replay protection on execute_trade.
+
+ALTER TABLE trades
+  ADD COLUMN IF NOT EXISTS client_order_id bigint NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS trades_vault_client_order_id_unique
+  ON trades (vault_address, client_order_id)
+  WHERE client_order_id IS NOT NULL;
+
+DO $$
+BEGIN
+  IF NOT EXISTS (
+    SELECT 1 FROM pg_constraint WHERE conname = 'trades_client_order_id_positive'
+  ) THEN
+    ALTER TABLE trades
+      ADD CONSTRAINT trades_client_order_id_positive
+      CHECK (client_order_id IS NULL OR client_order_id > 0);
+  END IF;
+END $$;
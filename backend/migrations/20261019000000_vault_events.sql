@@ -0,0 +1,32 @@
+-- On-chain events decoded by the indexer, plus its resumption cursor.
+
+CREATE TABLE IF NOT EXISTS vault_events (
+  id uuid PRIMARY KEY,
+  tx_signature text NOT NULL,
+  event_index integer NOT NULL,
+  slot bigint NOT NULL,
+  block_time timestamptz NULL,
+  event_type text NOT NULL,
+  vault_address text NOT NULL,
+  actor text NOT NULL,
+  amount_lamports bigint NULL,
+  payload jsonb NOT NULL,
+  event_timestamp timestamptz NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  CONSTRAINT vault_events_tx_event_unique UNIQUE (tx_signature, event_index),
+  CONSTRAINT vault_events_event_index_nonnegative CHECK (event_index >= 0),
+  CONSTRAINT vault_events_slot_nonnegative CHECK (slot >= 0)
+);
+
+CREATE INDEX IF NOT EXISTS idx_vault_events_vault_slot
+  ON vault_events (vault_address, slot DESC, event_index DESC);
+
+CREATE INDEX IF NOT EXISTS idx_vault_events_type_slot
+  ON vault_events (event_type, slot DESC);
+
+CREATE TABLE IF NOT EXISTS indexer_state (
+  name text PRIMARY KEY,
+  last_slot bigint NOT NULL,
+  last_signature text NULL,
+  updated_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,22 @@
+-- Outcomes of cleanup_vault transactions submitted by the keeper.
+
+CREATE TABLE IF NOT EXISTS keeper_cleanups (
+  id uuid PRIMARY KEY,
+  vault_address text NOT NULL,
+  owner text NOT NULL,
+  cleaner text NOT NULL,
+  status text NOT NULL,
+  tx_signature text NULL,
+  reward_lamports bigint NOT NULL DEFAULT 0,
+  error text NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  CONSTRAINT keeper_cleanups_status_check
+    CHECK (status IN ('confirmed', 'simulation_failed', 'failed')),
+  CONSTRAINT keeper_cleanups_reward_nonnegative CHECK (reward_lamports >= 0)
+);
+
+CREATE INDEX IF NOT EXISTS idx_keeper_cleanups_created_at
+  ON keeper_cleanups (created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_keeper_cleanups_vault
+  ON keeper_cleanups (vault_address, created_at DESC);
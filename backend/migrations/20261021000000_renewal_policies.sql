@@ -0,0 +1,22 @@
+-- Owner-signed opt-ins to session renewal prompts.
+
+CREATE TABLE IF NOT EXISTS renewal_policies (
+  owner text PRIMARY KEY,
+  vault_address text NOT NULL,
+  webhook_url text NULL,
+  max_renewals integer NULL,
+  valid_until timestamptz NOT NULL,
+  issued_at timestamptz NOT NULL,
+  signature text NOT NULL,
+  renewals_prompted integer NOT NULL DEFAULT 0,
+  last_prompted_expiry bigint NULL,
+  revoked_at timestamptz NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  updated_at timestamptz NOT NULL DEFAULT now(),
+  CONSTRAINT renewal_policies_max_renewals_positive CHECK (max_renewals IS NULL OR max_renewals > 0),
+  CONSTRAINT renewal_policies_renewals_prompted_nonnegative CHECK (renewals_prompted >= 0)
+);
+
+CREATE INDEX IF NOT EXISTS idx_renewal_policies_active
+  ON renewal_policies (valid_until)
+  WHERE revoked_at IS NULL;
@@ -0,0 +1,25 @@
+-- Signed transactions relayed through POST /tx/submit and their lifecycle.
+
+CREATE TABLE IF NOT EXISTS transactions (
+  signature text PRIMARY KEY,
+  vault_address text NOT NULL,
+  fee_payer text NOT NULL,
+  transaction_base64 text NOT NULL,
+  recent_blockhash text NOT NULL,
+  status text NOT NULL,
+  slot bigint NULL,
+  error text NULL,
+  send_attempts integer NOT NULL DEFAULT 0,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  updated_at timestamptz NOT NULL DEFAULT now(),
+  CONSTRAINT transactions_status_check
+    CHECK (status IN ('submitted', 'processed', 'confirmed', 'finalized', 'failed')),
+  CONSTRAINT transactions_send_attempts_nonnegative CHECK (send_attempts >= 0)
+);
+
+CREATE INDEX IF NOT EXISTS idx_transactions_vault_created_at
+  ON transactions (vault_address, created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_transactions_pending
+  ON transactions (created_at)
+  WHERE status IN ('submitted', 'processed', 'confirmed');
@@ -0,0 +1,12 @@
+-- Fees the backend's fee payer sponsored per owner and UTC day.
+
+CREATE TABLE IF NOT EXISTS sponsorship_usage (
+  owner text NOT NULL,
+  day date NOT NULL,
+  transaction_count integer NOT NULL DEFAULT 0,
+  fee_lamports bigint NOT NULL DEFAULT 0,
+  updated_at timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY (owner, day),
+  CONSTRAINT sponsorship_usage_transaction_count_nonnegative CHECK (transaction_count >= 0),
+  CONSTRAINT sponsorship_usage_fee_lamports_nonnegative CHECK (fee_lamports >= 0)
+);
@@ -0,0 +1,20 @@
+-- Delegate keypairs held by the backend. `sealed_key` is only readable by the
+-- signer named in `signer` and is erased when the key is destroyed.
+
+CREATE TABLE IF NOT EXISTS custodial_keys (
+  id uuid PRIMARY KEY,
+  owner text NOT NULL,
+  pubkey text NOT NULL,
+  signer text NOT NULL,
+  sealed_key bytea NULL,
+  token_hash text NOT NULL,
+  issued_at timestamptz NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  destroyed_at timestamptz NULL,
+  CONSTRAINT custodial_keys_pubkey_unique UNIQUE (pubkey),
+  CONSTRAINT custodial_keys_sealed_until_destroyed CHECK ((sealed_key IS NULL) = (destroyed_at IS NOT NULL))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS custodial_keys_active_owner_unique
+  ON custodial_keys (owner)
+  WHERE destroyed_at IS NULL;
@@ -0,0 +1,7 @@
+-- Position of an unfinished catch-up walk, so each poll fetches one bounded
+-- page instead of paging back to the cursor again.
+
+ALTER TABLE indexer_state
+  ADD COLUMN IF NOT EXISTS backfill_head_slot bigint NULL,
+  ADD COLUMN IF NOT EXISTS backfill_head_signature text NULL,
+  ADD COLUMN IF NOT EXISTS backfill_before text NULL;
@@ -0,0 +1,10 @@
+-- Fees the backend's fee payer sponsored across all owners per UTC day.
+
+CREATE TABLE IF NOT EXISTS sponsorship_totals (
+  day date PRIMARY KEY,
+  transaction_count integer NOT NULL DEFAULT 0,
+  fee_lamports bigint NOT NULL DEFAULT 0,
+  updated_at timestamptz NOT NULL DEFAULT now(),
+  CONSTRAINT sponsorship_totals_transaction_count_nonnegative CHECK (transaction_count >= 0),
+  CONSTRAINT sponsorship_totals_fee_lamports_nonnegative CHECK (fee_lamports >= 0)
+);
@@ -0,0 +1,474 @@
+//! Custodial delegate keys.
+//!
+//! With `CUSTODY_ENABLED` the backend generates delegate keypairs for owners,
+//! stores them sealed in `custodial_keys`, and signs `execute_trade`
+//! transactions for callers presenting the key's custody token. Key material
+//! only passes through a [`KeySigner`], so the local master-key sealer can be
+//! swapped for a KMS without touching the rest of the flow.
+
+use std::fmt;
+
+use aes_gcm_siv::{
+    aead::{Aead, NewAead, Payload},
+    Aes256GcmSiv, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Utc;
+use rand::RngCore;
+use serde::Serialize;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::db::models::{CustodialKeyRecord, NewCustodialKey};
+use crate::db::queries;
+use crate::error::{AppError, Result};
+use crate::renewals;
+use crate::solana::{self, TxEnvelope};
+use crate::AppState;
+
+const MASTER_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TOKEN_LEN: usize = 32;
+
+/// A freshly generated keypair in the form it is stored.
+pub struct SealedKey {
+    pub pubkey: Pubkey,
+    pub sealed: Vec<u8>,
+}
+
+/// Holds delegate key material on the backend's behalf.
+pub trait KeySigner: Send + Sync {
+    /// Recorded with each key so keys outlive a change of signer.
+    fn name(&self) -> &'static str;
+
+    /// Generates a keypair and returns its pubkey with the material to store.
+    fn generate(&self) -> Result<SealedKey>;
+
+    /// Signs `message` with the key `pubkey` whose stored material is `sealed`.
+    fn sign(&self, pubkey: &Pubkey, sealed: &[u8], message: &[u8]) -> Result<Signature>;
+}
+
+/// Seals keypairs with AES-256-GCM-SIV under a master key, binding each
+/// ciphertext to its pubkey.
+pub struct LocalKeySigner {
+    cipher: Aes256GcmSiv,
+}
+
+impl LocalKeySigner {
+    pub fn new(master_key: &[u8]) -> Result<Self> {
+        let cipher = Aes256GcmSiv::new_from_slice(master_key).map_err(|_| {
+            AppError::Validation(format!("custody master key must be {MASTER_KEY_LEN} bytes"))
+        })?;
+        Ok(Self { cipher })
+    }
+
+    /// Reads a base64 master key from `path`.
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let encoded = Zeroizing::new(std::fs::read_to_string(path)?);
+        let master_key = Zeroizing::new(BASE64.decode(encoded.trim())?);
+        Ok(Self::new(&master_key)?)
+    }
+
+    fn open(&self, pubkey: &Pubkey, sealed: &[u8]) -> Result<Keypair> {
+        if sealed.len() <= NONCE_LEN {
+            return Err(AppError::Internal(
+                "sealed custodial key is truncated".into(),
+            ));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at NONCE_LEN");
+        let secret = Zeroizing::new(
+            self.cipher
+                .decrypt(
+                    &Nonce::from(nonce),
+                    Payload {
+                        msg: ciphertext,
+                        aad: pubkey.as_ref(),
+                    },
+                )
+                .map_err(|_| {
+                    AppError::Internal(format!("failed to unseal custodial key {pubkey}"))
+                })?,
+        );
+        let keypair = Keypair::from_bytes(&secret)
+            .map_err(|e| AppError::Internal(format!("custodial key {pubkey} is invalid: {e}")))?;
+        if keypair.pubkey() != *pubkey {
+            return Err(AppError::Internal(format!(
+                "custodial key {pubkey} does not match its pubkey"
+            )));
+        }
+        Ok(keypair)
+    }
+}
+
+impl fmt::Debug for LocalKeySigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalKeySigner").finish_non_exhaustive()
+    }
+}
+
+impl KeySigner for LocalKeySigner {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn generate(&self) -> Result<SealedKey> {
+        let keypair = Keypair::new();
+        let pubkey = keypair.pubkey();
+        let secret = Zeroizing::new(keypair.to_bytes());
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: secret.as_ref(),
+                    aad: pubkey.as_ref(),
+                },
+            )
+            .map_err(|_| AppError::Internal("failed to seal custodial key".into()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(SealedKey { pubkey, sealed })
+    }
+
+    fn sign(&self, pubkey: &Pubkey, sealed: &[u8], message: &[u8]) -> Result<Signature> {
+        Ok(self.open(pubkey, sealed)?.sign_message(message))
+    }
+}
+
+/// Key management steps an owner authorizes by signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CustodyAction {
+    Create,
+    Rotate,
+    Destroy,
+}
+
+impl CustodyAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            CustodyAction::Create => "create",
+            CustodyAction::Rotate => "rotate",
+            CustodyAction::Destroy => "destroy",
+        }
+    }
+}
+
+/// The exact text the owner signs to authorize `action`.
+pub fn action_message(action: CustodyAction, owner: &Pubkey, issued_at: i64) -> String {
+    format!(
+        "Ephemeral Vault custodial delegate key\n\
+         action: {}\n\
+         owner: {owner}\n\
+         issued at: {issued_at}",
+        action.as_str()
+    )
+}
+
+/// Checks the owner's signature over `action` issued at `issued_at`.
+pub fn authorize(
+    action: CustodyAction,
+    owner: &Pubkey,
+    issued_at: i64,
+    signature: &str,
+) -> Result<()> {
+    renewals::check_issued_at(issued_at, Utc::now().timestamp())?;
+    renewals::verify_signature(owner, &action_message(action, owner, issued_at), signature)?;
+    Ok(())
+}
+
+fn new_token() -> String {
+    let mut token = Zeroizing::new([0u8; TOKEN_LEN]);
+    rand::thread_rng().fill_bytes(token.as_mut());
+    bs58::encode(token.as_ref()).into_string()
+}
+
+fn token_hash(token: &str) -> String {
+    solana_sdk::hash::hash(token.as_bytes()).to_string()
+}
+
+fn signer(state: &AppState) -> Result<&dyn KeySigner> {
+    state
+        .key_signer
+        .as_deref()
+        .filter(|_| state.config.custody_enabled)
+        .ok_or_else(|| AppError::Validation("custodial delegate keys are disabled".into()))
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustodialKeyDto {
+    pub id: Uuid,
+    pub owner: String,
+    pub pubkey: String,
+    pub signer: String,
+    pub active: bool,
+    pub created_at: chrono::DateTime<Utc>,
+    pub destroyed_at: Option<chrono::DateTime<Utc>>,
+}
+
+impl From<CustodialKeyRecord> for CustodialKeyDto {
+    fn from(record: CustodialKeyRecord) -> Self {
+        Self {
+            id: record.id,
+            active: record.destroyed_at.is_none(),
+            owner: record.owner,
+            pubkey: record.pubkey,
+            signer: record.signer,
+            created_at: record.created_at,
+            destroyed_at: record.destroyed_at,
+        }
+    }
+}
+
+/// A new key with the custody token that lets callers have it sign trades.
+/// The token is only ever returned here.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IssuedCustodialKeyDto {
+    pub key: CustodialKeyDto,
+    pub custody_token: String,
+}
+
+fn new_key(
+    signer: &dyn KeySigner,
+    owner: &Pubkey,
+    issued_at: i64,
+) -> Result<(NewCustodialKey, String)> {
+    let SealedKey { pubkey, sealed } = signer.generate()?;
+    let token = new_token();
+    let key = NewCustodialKey {
+        owner: owner.to_string(),
+        pubkey: pubkey.to_string(),
+        signer: signer.name().to_string(),
+        sealed_key: sealed,
+        token_hash: token_hash(&token),
+        issued_at: renewals::timestamp(issued_at),
+    };
+    Ok((key, token))
+}
+
+/// Generates the owner's custodial delegate key.
+pub async fn create(
+    state: &AppState,
+    owner: &Pubkey,
+    issued_at: i64,
+) -> Result<IssuedCustodialKeyDto> {
+    let (key, custody_token) = new_key(signer(state)?, owner, issued_at)?;
+    let record = queries::insert_custodial_key(&state.db, &key).await?;
+    Ok(IssuedCustodialKeyDto {
+        key: record.into(),
+        custody_token,
+    })
+}
+
+/// Replaces the owner's active key with a new one and a new custody token.
+/// The old key is destroyed; the new one must be approved as delegate.
+pub async fn rotate(
+    state: &AppState,
+    owner: &Pubkey,
+    issued_at: i64,
+) -> Result<IssuedCustodialKeyDto> {
+    let (key, custody_token) = new_key(signer(state)?, owner, issued_at)?;
+    let record = queries::rotate_custodial_key(&state.db, &key)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!(
+                "no custodial key issued before {issued_at} for {owner}"
+            ))
+        })?;
+    Ok(IssuedCustodialKeyDto {
+        key: record.into(),
+        custody_token,
+    })
+}
+
+/// Erases the owner's active key material.
+pub async fn destroy(state: &AppState, owner: &Pubkey, issued_at: i64) -> Result<CustodialKeyDto> {
+    let record = queries::destroy_custodial_key(
+        &state.db,
+        &owner.to_string(),
+        renewals::timestamp(issued_at),
+    )
+    .await?
+    .ok_or_else(|| {
+        AppError::NotFound(format!(
+            "no custodial key issued before {issued_at} for {owner}"
+        ))
+    })?;
+    Ok(record.into())
+}
+
+/// The owner's active custodial key.
+pub async fn active_key(state: &AppState, owner: &Pubkey) -> Result<CustodialKeyRecord> {
+    queries::get_active_custodial_key(&state.db, &owner.to_string())
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("no custodial key for {owner}")))
+}
+
+/// Signs `envelope` as `delegate` when `custody_token` belongs to its active
+/// custodial key.
+pub async fn sign_as_delegate(
+    state: &AppState,
+    delegate: &Pubkey,
+    custody_token: &str,
+    envelope: TxEnvelope,
+) -> Result<TxEnvelope> {
+    let signer = signer(state)?;
+    let key = queries::get_active_custodial_key_by_pubkey(&state.db, &delegate.to_string())
+        .await?
+        .filter(|key| key.token_hash == token_hash(custody_token))
+        .ok_or(AppError::UnauthorizedDelegate)?;
+    if key.signer != signer.name() {
+        return Err(AppError::Internal(format!(
+            "custodial key {delegate} is held by the {} signer",
+            key.signer
+        )));
+    }
+    let sealed = key
+        .sealed_key
+        .as_deref()
+        .ok_or(AppError::UnauthorizedDelegate)?;
+    sign_envelope(signer, delegate, sealed, envelope)
+}
+
+/// Adds `delegate`'s signature to `envelope`, keeping any others.
+fn sign_envelope(
+    signer: &dyn KeySigner,
+    delegate: &Pubkey,
+    sealed: &[u8],
+    mut envelope: TxEnvelope,
+) -> Result<TxEnvelope> {
+    let mut tx = solana::decode_transaction_base64(&envelope.transaction_base64)?;
+    let index = tx
+        .message
+        .static_account_keys()
+        .iter()
+        .take(usize::from(tx.message.header().num_required_signatures))
+        .position(|key| key == delegate)
+        .ok_or_else(|| {
+            AppError::Internal(format!(
+                "transaction does not need a signature from {delegate}"
+            ))
+        })?;
+    tx.signatures[index] = signer.sign(delegate, sealed, &tx.message.serialize())?;
+    envelope.transaction_base64 = solana::encode_transaction_base64(&tx)?;
+    Ok(envelope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_signer() -> LocalKeySigner {
+        LocalKeySigner::new(&[7u8; MASTER_KEY_LEN]).unwrap()
+    }
+
+    #[test]
+    fn sealed_keys_sign_only_for_their_own_pubkey() {
+        let signer = local_signer();
+        let SealedKey { pubkey, sealed } = signer.generate().unwrap();
+        assert!(!sealed.windows(32).any(|window| window == pubkey.as_ref()));
+
+        let signature = signer.sign(&pubkey, &sealed, b"trade").unwrap();
+        assert!(signature.verify(pubkey.as_ref(), b"trade"));
+
+        assert!(signer
+            .sign(&Pubkey::new_unique(), &sealed, b"trade")
+            .is_err());
+        let other_master = LocalKeySigner::new(&[8u8; MASTER_KEY_LEN]).unwrap();
+        assert!(other_master.sign(&pubkey, &sealed, b"trade").is_err());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(signer.sign(&pubkey, &tampered, b"trade").is_err());
+        assert!(signer
+            .sign(&pubkey, &sealed[..NONCE_LEN], b"trade")
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn signs_trades_as_the_custodial_delegate() {
+        let svm = crate::test_support::LocalSvm::new(1_700_000_000);
+        let rpc = svm.rpc();
+        let config = crate::test_support::config();
+        let signer = local_signer();
+        let SealedKey { pubkey, sealed } = signer.generate().unwrap();
+
+        let envelope = solana::build_execute_trade_tx(
+            &rpc,
+            &config,
+            Pubkey::new_unique(),
+            pubkey,
+            5_000,
+            1_000_000,
+            1,
+            &solana::TxOptions {
+                skip_state_checks: true,
+                ..solana::TxOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        let signed = sign_envelope(&signer, &pubkey, &sealed, envelope.clone()).unwrap();
+        let tx = solana::decode_transaction_base64(&signed.transaction_base64).unwrap();
+        assert_eq!(tx.message.static_account_keys()[0], pubkey);
+        assert!(tx.verify_with_results().into_iter().all(|ok| ok));
+
+        let stranger = Pubkey::new_unique();
+        assert!(sign_envelope(&signer, &stranger, &sealed, envelope).is_err());
+    }
+
+    #[test]
+    fn rejects_master_keys_of_the_wrong_length() {
+        assert!(LocalKeySigner::new(&[0u8; 16]).is_err());
+        assert!(LocalKeySigner::new(&[0u8; MASTER_KEY_LEN]).is_ok());
+    }
+
+    #[test]
+    fn actions_are_authorized_by_the_owners_signature() {
+        let owner = Keypair::new();
+        let issued_at = Utc::now().timestamp();
+        let message = action_message(CustodyAction::Rotate, &owner.pubkey(), issued_at);
+        let signature = owner.sign_message(message.as_bytes()).to_string();
+
+        assert!(authorize(
+            CustodyAction::Rotate,
+            &owner.pubkey(),
+            issued_at,
+            &signature
+        )
+        .is_ok());
+        assert!(authorize(
+            CustodyAction::Destroy,
+            &owner.pubkey(),
+            issued_at,
+            &signature
+        )
+        .is_err());
+        assert!(authorize(
+            CustodyAction::Rotate,
+            &Pubkey::new_unique(),
+            issued_at,
+            &signature
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn custody_tokens_are_unique_and_stored_hashed() {
+        let token = new_token();
+        assert_ne!(token, new_token());
+        assert_ne!(token_hash(&token), token);
+        assert_eq!(token_hash(&token), token_hash(&token));
+    }
+}
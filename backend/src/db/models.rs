@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -14,6 +14,7 @@ pub struct TradeRecord {
     pub fee_sol: f64,
     pub status: String,
     pub slot: Option<i64>,
+    pub client_order_id: Option<i64>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -29,6 +30,200 @@ pub struct VaultSnapshot {
     pub snapshot_at: DateTime<Utc>,
 }
 
+/// One vault's state as recorded by the snapshot job.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewVaultSnapshot {
+    pub vault_address: String,
+    pub owner: String,
+    pub balance_sol: f64,
+    pub approved_amount_sol: f64,
+    pub trades_executed: i32,
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct VaultEventRecord {
+    pub id: Uuid,
+    pub tx_signature: String,
+    pub event_index: i32,
+    pub slot: i64,
+    pub block_time: Option<DateTime<Utc>>,
+    pub event_type: String,
+    pub vault_address: String,
+    pub actor: String,
+    pub amount_lamports: Option<i64>,
+    pub payload: serde_json::Value,
+    pub event_timestamp: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A decoded program event ready to be stored in `vault_events`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewVaultEvent {
+    pub tx_signature: String,
+    pub event_index: i32,
+    pub slot: i64,
+    pub block_time: Option<DateTime<Utc>>,
+    pub event_type: String,
+    pub vault_address: String,
+    pub actor: String,
+    pub amount_lamports: Option<i64>,
+    pub payload: serde_json::Value,
+    pub event_timestamp: DateTime<Utc>,
+}
+
+/// Where the indexer resumes: the newest transaction before which everything
+/// is processed, and the position of a catch-up walk still in progress.
+#[derive(Debug, Clone, Default, PartialEq, Eq, sqlx::FromRow)]
+pub struct IndexerCursor {
+    pub last_slot: i64,
+    pub last_signature: Option<String>,
+    /// Newest transaction of the walk; becomes `last_*` when it finishes.
+    pub backfill_head_slot: Option<i64>,
+    pub backfill_head_signature: Option<String>,
+    /// Oldest transaction the walk has processed; the next page ends here.
+    pub backfill_before: Option<String>,
+}
+
+/// One `cleanup_vault` attempt by the keeper.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewKeeperCleanup {
+    pub vault_address: String,
+    pub owner: String,
+    pub cleaner: String,
+    pub status: String,
+    pub tx_signature: Option<String>,
+    pub reward_lamports: i64,
+    pub error: Option<String>,
+}
+
+/// Totals over every recorded keeper cleanup.
+#[derive(Debug, Clone, Default, PartialEq, Eq, sqlx::FromRow)]
+pub struct KeeperStats {
+    pub attempts: i64,
+    pub confirmed: i64,
+    pub simulation_failed: i64,
+    pub failed: i64,
+    pub total_reward_lamports: i64,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub last_cleanup_at: Option<DateTime<Utc>>,
+}
+
+/// An owner's opt-in to session renewal prompts. Revoked policies keep their
+/// row so older signed messages cannot be replayed.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct RenewalPolicyRecord {
+    pub owner: String,
+    pub vault_address: String,
+    pub webhook_url: Option<String>,
+    pub max_renewals: Option<i32>,
+    pub valid_until: DateTime<Utc>,
+    pub issued_at: DateTime<Utc>,
+    pub signature: String,
+    pub renewals_prompted: i32,
+    pub last_prompted_expiry: Option<i64>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A verified renewal policy ready to be stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewRenewalPolicy {
+    pub owner: String,
+    pub vault_address: String,
+    pub webhook_url: Option<String>,
+    pub max_renewals: Option<i32>,
+    pub valid_until: DateTime<Utc>,
+    pub issued_at: DateTime<Utc>,
+    pub signature: String,
+}
+
+/// A transaction relayed through `POST /tx/submit`.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SubmittedTransaction {
+    pub signature: String,
+    pub vault_address: String,
+    pub fee_payer: String,
+    #[serde(skip_serializing)]
+    pub transaction_base64: String,
+    pub recent_blockhash: String,
+    pub status: String,
+    pub slot: Option<i64>,
+    pub error: Option<String>,
+    pub send_attempts: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewSubmittedTransaction {
+    pub signature: String,
+    pub vault_address: String,
+    pub fee_payer: String,
+    pub transaction_base64: String,
+    pub recent_blockhash: String,
+}
+
+/// Transactions and fees sponsored for one owner on one UTC day.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SponsorshipUsage {
+    pub owner: String,
+    pub day: NaiveDate,
+    pub transaction_count: i32,
+    pub fee_lamports: i64,
+}
+
+/// Transactions and fees sponsored across all owners on one UTC day.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SponsorshipTotals {
+    pub day: NaiveDate,
+    pub transaction_count: i32,
+    pub fee_lamports: i64,
+}
+
+/// Daily sponsorship budgets, per owner and across all owners.
+#[derive(Clone, Copy, Debug)]
+pub struct SponsorshipLimits {
+    pub owner_transactions: i64,
+    pub owner_fee_lamports: i64,
+    pub total_transactions: i64,
+    pub total_fee_lamports: i64,
+}
+
+/// The outcome of reserving one sponsored transaction.
+#[derive(Clone, Debug)]
+pub enum SponsorshipReservation {
+    Reserved(SponsorshipUsage),
+    OwnerBudgetSpent,
+    TotalBudgetSpent,
+}
+
+/// A delegate keypair held by the backend. Not serializable: it carries the
+/// sealed key material.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct CustodialKeyRecord {
+    pub id: Uuid,
+    pub owner: String,
+    pub pubkey: String,
+    pub signer: String,
+    pub sealed_key: Option<Vec<u8>>,
+    pub token_hash: String,
+    pub issued_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub destroyed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewCustodialKey {
+    pub owner: String,
+    pub pubkey: String,
+    pub signer: String,
+    pub sealed_key: Vec<u8>,
+    pub token_hash: String,
+    pub issued_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewTrade {
     pub vault_address: String,
@@ -38,6 +233,8 @@ pub struct NewTrade {
     pub fee_sol: f64,
     pub status: String,
     pub slot: Option<i64>,
+    #[serde(default)]
+    pub client_order_id: Option<i64>,
 }
 
 impl NewTrade {
@@ -63,6 +260,12 @@ impl NewTrade {
             return Err(AppError::Validation("slot must be non-negative".into()));
         }
 
+        if matches!(self.client_order_id, Some(id) if id <= 0) {
+            return Err(AppError::Validation(
+                "client_order_id must be positive".into(),
+            ));
+        }
+
         Ok(())
     }
 }
@@ -88,6 +291,7 @@ mod tests {
             fee_sol: 0.000005,
             status: "confirmed".into(),
             slot: Some(42),
+            client_order_id: Some(7),
         }
     }
 
@@ -104,6 +308,17 @@ mod tests {
         assert!(trade.validate().is_err());
     }
 
+    #[test]
+    fn rejects_non_positive_client_order_id() {
+        let mut trade = valid_trade();
+        trade.client_order_id = Some(0);
+
+        assert!(trade.validate().is_err());
+
+        trade.client_order_id = None;
+        assert!(trade.validate().is_ok());
+    }
+
     #[test]
     fn rejects_negative_amounts() {
         let mut trade = valid_trade();
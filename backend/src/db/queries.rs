@@ -1,13 +1,19 @@
-use crate::db::models::{NewTrade, TradeRecord};
+use crate::db::models::{
+    CustodialKeyRecord, IndexerCursor, KeeperStats, NewCustodialKey, NewKeeperCleanup,
+    NewRenewalPolicy, NewSubmittedTransaction, NewTrade, NewVaultEvent, NewVaultSnapshot,
+    RenewalPolicyRecord, SponsorshipLimits, SponsorshipReservation, SponsorshipTotals,
+    SponsorshipUsage, SubmittedTransaction, TradeRecord, VaultSnapshot,
+};
 use crate::error::{AppError, Result};
+use chrono::{DateTime, NaiveDate, Utc};
 use sqlx::PgPool;
 
 pub async fn insert_trade(pool: &PgPool, trade: &NewTrade) -> Result<TradeRecord> {
     let rec = sqlx::query_as::<_, TradeRecord>(
         r#"
-        INSERT INTO trades (id, vault_address, tx_hash, trade_type, amount_sol, fee_sol, status, slot)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-        RETURNING id, vault_address, tx_hash, trade_type, amount_sol, fee_sol, status, slot, created_at
+        INSERT INTO trades (id, vault_address, tx_hash, trade_type, amount_sol, fee_sol, status, slot, client_order_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id, vault_address, tx_hash, trade_type, amount_sol, fee_sol, status, slot, client_order_id, created_at
         "#,
     )
     .bind(uuid::Uuid::new_v4())
@@ -18,6 +24,7 @@ pub async fn insert_trade(pool: &PgPool, trade: &NewTrade) -> Result<TradeRecord
     .bind(trade.fee_sol)
     .bind(&trade.status)
     .bind(trade.slot)
+    .bind(trade.client_order_id)
     .fetch_one(pool)
     .await
     .map_err(map_insert_trade_error)?;
@@ -26,8 +33,14 @@ pub async fn insert_trade(pool: &PgPool, trade: &NewTrade) -> Result<TradeRecord
 
 fn map_insert_trade_error(err: sqlx::Error) -> AppError {
     if let sqlx::Error::Database(db_err) = &err {
-        if db_err.constraint() == Some("trades_tx_hash_unique") {
-            return AppError::Conflict("tx_hash already exists".into());
+        match db_err.constraint() {
+            Some("trades_tx_hash_unique") => {
+                return AppError::Conflict("tx_hash already exists".into());
+            }
+            Some("trades_vault_client_order_id_unique") => {
+                return AppError::Conflict("client_order_id already exists for this vault".into());
+            }
+            _ => {}
         }
     }
 
@@ -42,7 +55,7 @@ pub async fn get_trades_for_vault(
 ) -> Result<Vec<TradeRecord>> {
     let records = sqlx::query_as::<_, TradeRecord>(
         r#"
-        SELECT id, vault_address, tx_hash, trade_type, amount_sol, fee_sol, status, slot, created_at
+        SELECT id, vault_address, tx_hash, trade_type, amount_sol, fee_sol, status, slot, client_order_id, created_at
         FROM trades
         WHERE vault_address = $1
         ORDER BY created_at DESC
@@ -69,10 +82,700 @@ pub async fn count_trades_for_vault(pool: &PgPool, vault_address: &str) -> Resul
 
 pub async fn get_recent_trades(pool: &PgPool, limit: i64) -> Result<Vec<TradeRecord>> {
     let records = sqlx::query_as::<_, TradeRecord>(
-        "SELECT id, vault_address, tx_hash, trade_type, amount_sol, fee_sol, status, slot, created_at FROM trades ORDER BY created_at DESC LIMIT $1",
+        "SELECT id, vault_address, tx_hash, trade_type, amount_sol, fee_sol, status, slot, client_order_id, created_at FROM trades ORDER BY created_at DESC LIMIT $1",
     )
     .bind(limit)
     .fetch_all(pool)
     .await?;
     Ok(records)
 }
+
+pub async fn get_indexer_cursor(pool: &PgPool, name: &str) -> Result<IndexerCursor> {
+    let cursor = sqlx::query_as::<_, IndexerCursor>(
+        r#"
+        SELECT last_slot, last_signature, backfill_head_slot, backfill_head_signature, backfill_before
+        FROM indexer_state
+        WHERE name = $1
+        "#,
+    )
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+    Ok(cursor.unwrap_or_default())
+}
+
+/// Stores one indexer batch atomically and moves the cursor to `cursor`.
+///
+/// Events are written once per `(tx_signature, event_index)`, so replaying a
+/// batch is harmless. Trades decoded from chain replace any client-submitted
+/// row for the same transaction or client order id. Returns the number of
+/// newly inserted events.
+pub async fn record_indexed_batch(
+    pool: &PgPool,
+    name: &str,
+    events: &[NewVaultEvent],
+    trades: &[NewTrade],
+    cursor: &IndexerCursor,
+) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+    let mut inserted = 0;
+
+    for event in events {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO vault_events (id, tx_signature, event_index, slot, block_time, event_type, vault_address, actor, amount_lamports, payload, event_timestamp)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (tx_signature, event_index) DO NOTHING
+            "#,
+        )
+        .bind(uuid::Uuid::new_v4())
+        .bind(&event.tx_signature)
+        .bind(event.event_index)
+        .bind(event.slot)
+        .bind(event.block_time)
+        .bind(&event.event_type)
+        .bind(&event.vault_address)
+        .bind(&event.actor)
+        .bind(event.amount_lamports)
+        .bind(&event.payload)
+        .bind(event.event_timestamp)
+        .execute(&mut tx)
+        .await?;
+        inserted += result.rows_affected();
+    }
+
+    for trade in trades {
+        if let Some(client_order_id) = trade.client_order_id {
+            sqlx::query(
+                "DELETE FROM trades WHERE vault_address = $1 AND client_order_id = $2 AND tx_hash <> $3",
+            )
+            .bind(&trade.vault_address)
+            .bind(client_order_id)
+            .bind(&trade.tx_hash)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO trades (id, vault_address, tx_hash, trade_type, amount_sol, fee_sol, status, slot, client_order_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (tx_hash) DO UPDATE SET
+              vault_address = EXCLUDED.vault_address,
+              trade_type = EXCLUDED.trade_type,
+              amount_sol = EXCLUDED.amount_sol,
+              fee_sol = EXCLUDED.fee_sol,
+              status = EXCLUDED.status,
+              slot = EXCLUDED.slot,
+              client_order_id = EXCLUDED.client_order_id
+            "#,
+        )
+        .bind(uuid::Uuid::new_v4())
+        .bind(&trade.vault_address)
+        .bind(&trade.tx_hash)
+        .bind(&trade.trade_type)
+        .bind(trade.amount_sol)
+        .bind(trade.fee_sol)
+        .bind(&trade.status)
+        .bind(trade.slot)
+        .bind(trade.client_order_id)
+        .execute(&mut tx)
+        .await?;
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO indexer_state (name, last_slot, last_signature, backfill_head_slot, backfill_head_signature, backfill_before, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        ON CONFLICT (name) DO UPDATE SET
+          last_slot = EXCLUDED.last_slot,
+          last_signature = EXCLUDED.last_signature,
+          backfill_head_slot = EXCLUDED.backfill_head_slot,
+          backfill_head_signature = EXCLUDED.backfill_head_signature,
+          backfill_before = EXCLUDED.backfill_before,
+          updated_at = now()
+        "#,
+    )
+    .bind(name)
+    .bind(cursor.last_slot)
+    .bind(&cursor.last_signature)
+    .bind(cursor.backfill_head_slot)
+    .bind(&cursor.backfill_head_signature)
+    .bind(&cursor.backfill_before)
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(inserted)
+}
+
+/// Writes one snapshot run in a single transaction, all stamped `snapshot_at`.
+pub async fn insert_vault_snapshots(
+    pool: &PgPool,
+    snapshots: &[NewVaultSnapshot],
+    snapshot_at: DateTime<Utc>,
+) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+    for snapshot in snapshots {
+        sqlx::query(
+            r#"
+            INSERT INTO vault_snapshots (id, vault_address, owner, balance_sol, approved_amount_sol, trades_executed, status, snapshot_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(uuid::Uuid::new_v4())
+        .bind(&snapshot.vault_address)
+        .bind(&snapshot.owner)
+        .bind(snapshot.balance_sol)
+        .bind(snapshot.approved_amount_sol)
+        .bind(snapshot.trades_executed)
+        .bind(&snapshot.status)
+        .bind(snapshot_at)
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(snapshots.len() as u64)
+}
+
+/// The newest `limit` snapshots of a vault within `[from, to]`, oldest first.
+pub async fn get_vault_snapshots(
+    pool: &PgPool,
+    vault_address: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<VaultSnapshot>> {
+    let records = sqlx::query_as::<_, VaultSnapshot>(
+        r#"
+        SELECT * FROM (
+          SELECT id, vault_address, owner, balance_sol, approved_amount_sol, trades_executed, status, snapshot_at
+          FROM vault_snapshots
+          WHERE vault_address = $1 AND snapshot_at BETWEEN $2 AND $3
+          ORDER BY snapshot_at DESC
+          LIMIT $4
+        ) recent
+        ORDER BY snapshot_at ASC
+        "#,
+    )
+    .bind(vault_address)
+    .bind(from)
+    .bind(to)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(records)
+}
+
+pub async fn insert_keeper_cleanup(pool: &PgPool, cleanup: &NewKeeperCleanup) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO keeper_cleanups (id, vault_address, owner, cleaner, status, tx_signature, reward_lamports, error)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+    )
+    .bind(uuid::Uuid::new_v4())
+    .bind(&cleanup.vault_address)
+    .bind(&cleanup.owner)
+    .bind(&cleanup.cleaner)
+    .bind(&cleanup.status)
+    .bind(&cleanup.tx_signature)
+    .bind(cleanup.reward_lamports)
+    .bind(&cleanup.error)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Rewards are summed over confirmed cleanups only.
+pub async fn get_keeper_stats(pool: &PgPool) -> Result<KeeperStats> {
+    let stats = sqlx::query_as::<_, KeeperStats>(
+        r#"
+        SELECT
+          COUNT(*) AS attempts,
+          COUNT(*) FILTER (WHERE status = 'confirmed') AS confirmed,
+          COUNT(*) FILTER (WHERE status = 'simulation_failed') AS simulation_failed,
+          COUNT(*) FILTER (WHERE status = 'failed') AS failed,
+          COALESCE(SUM(reward_lamports) FILTER (WHERE status = 'confirmed'), 0)::bigint AS total_reward_lamports,
+          MAX(created_at) AS last_attempt_at,
+          MAX(created_at) FILTER (WHERE status = 'confirmed') AS last_cleanup_at
+        FROM keeper_cleanups
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(stats)
+}
+
+const RENEWAL_POLICY_COLUMNS: &str = "owner, vault_address, webhook_url, max_renewals, valid_until, issued_at, signature, renewals_prompted, last_prompted_expiry, revoked_at, created_at, updated_at";
+
+/// Stores `policy`, replacing the owner's previous policy and resetting its
+/// prompt count. Returns `Conflict` when a policy or revocation issued at
+/// the same time or later is already stored.
+pub async fn upsert_renewal_policy(
+    pool: &PgPool,
+    policy: &NewRenewalPolicy,
+) -> Result<RenewalPolicyRecord> {
+    let record = sqlx::query_as::<_, RenewalPolicyRecord>(&format!(
+        r#"
+        INSERT INTO renewal_policies (owner, vault_address, webhook_url, max_renewals, valid_until, issued_at, signature)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (owner) DO UPDATE SET
+          vault_address = EXCLUDED.vault_address,
+          webhook_url = EXCLUDED.webhook_url,
+          max_renewals = EXCLUDED.max_renewals,
+          valid_until = EXCLUDED.valid_until,
+          issued_at = EXCLUDED.issued_at,
+          signature = EXCLUDED.signature,
+          renewals_prompted = 0,
+          last_prompted_expiry = NULL,
+          revoked_at = NULL,
+          updated_at = now()
+        WHERE renewal_policies.issued_at < EXCLUDED.issued_at
+        RETURNING {RENEWAL_POLICY_COLUMNS}
+        "#
+    ))
+    .bind(&policy.owner)
+    .bind(&policy.vault_address)
+    .bind(&policy.webhook_url)
+    .bind(policy.max_renewals)
+    .bind(policy.valid_until)
+    .bind(policy.issued_at)
+    .bind(&policy.signature)
+    .fetch_optional(pool)
+    .await?;
+
+    record.ok_or_else(|| AppError::Conflict("a newer renewal policy is already registered".into()))
+}
+
+pub async fn get_renewal_policy(pool: &PgPool, owner: &str) -> Result<Option<RenewalPolicyRecord>> {
+    let record = sqlx::query_as::<_, RenewalPolicyRecord>(&format!(
+        "SELECT {RENEWAL_POLICY_COLUMNS} FROM renewal_policies WHERE owner = $1"
+    ))
+    .bind(owner)
+    .fetch_optional(pool)
+    .await?;
+    Ok(record)
+}
+
+/// Revokes the owner's policy if it was issued before `issued_at`. Returns
+/// whether a policy was revoked.
+pub async fn revoke_renewal_policy(
+    pool: &PgPool,
+    owner: &str,
+    issued_at: DateTime<Utc>,
+) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE renewal_policies
+        SET revoked_at = now(), issued_at = $2, updated_at = now()
+        WHERE owner = $1 AND issued_at < $2
+        "#,
+    )
+    .bind(owner)
+    .bind(issued_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Policies that are unrevoked, unexpired at `now` and under their prompt cap.
+pub async fn get_active_renewal_policies(
+    pool: &PgPool,
+    now: DateTime<Utc>,
+) -> Result<Vec<RenewalPolicyRecord>> {
+    let records = sqlx::query_as::<_, RenewalPolicyRecord>(&format!(
+        r#"
+        SELECT {RENEWAL_POLICY_COLUMNS}
+        FROM renewal_policies
+        WHERE revoked_at IS NULL
+          AND valid_until > $1
+          AND (max_renewals IS NULL OR renewals_prompted < max_renewals)
+        ORDER BY owner
+        "#
+    ))
+    .bind(now)
+    .fetch_all(pool)
+    .await?;
+    Ok(records)
+}
+
+/// Counts a prompt for the session ending at `session_expiry`.
+pub async fn mark_renewal_prompted(pool: &PgPool, owner: &str, session_expiry: i64) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE renewal_policies
+        SET renewals_prompted = renewals_prompted + 1,
+            last_prompted_expiry = $2,
+            updated_at = now()
+        WHERE owner = $1
+        "#,
+    )
+    .bind(owner)
+    .bind(session_expiry)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+const TRANSACTION_COLUMNS: &str = "signature, vault_address, fee_payer, transaction_base64, recent_blockhash, status, slot, error, send_attempts, created_at, updated_at";
+
+/// Records a new submission with status `submitted`. Returns `None` when the
+/// signature was already submitted.
+pub async fn insert_submitted_transaction(
+    pool: &PgPool,
+    tx: &NewSubmittedTransaction,
+) -> Result<Option<SubmittedTransaction>> {
+    let record = sqlx::query_as::<_, SubmittedTransaction>(&format!(
+        r#"
+        INSERT INTO transactions (signature, vault_address, fee_payer, transaction_base64, recent_blockhash, status)
+        VALUES ($1, $2, $3, $4, $5, 'submitted')
+        ON CONFLICT (signature) DO NOTHING
+        RETURNING {TRANSACTION_COLUMNS}
+        "#
+    ))
+    .bind(&tx.signature)
+    .bind(&tx.vault_address)
+    .bind(&tx.fee_payer)
+    .bind(&tx.transaction_base64)
+    .bind(&tx.recent_blockhash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(record)
+}
+
+pub async fn get_submitted_transaction(
+    pool: &PgPool,
+    signature: &str,
+) -> Result<Option<SubmittedTransaction>> {
+    let record = sqlx::query_as::<_, SubmittedTransaction>(&format!(
+        "SELECT {TRANSACTION_COLUMNS} FROM transactions WHERE signature = $1"
+    ))
+    .bind(signature)
+    .fetch_optional(pool)
+    .await?;
+    Ok(record)
+}
+
+pub async fn get_vault_transactions(
+    pool: &PgPool,
+    vault_address: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<SubmittedTransaction>> {
+    let records = sqlx::query_as::<_, SubmittedTransaction>(&format!(
+        r#"
+        SELECT {TRANSACTION_COLUMNS}
+        FROM transactions
+        WHERE vault_address = $1
+        ORDER BY created_at DESC
+        LIMIT $2 OFFSET $3
+        "#
+    ))
+    .bind(vault_address)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+    Ok(records)
+}
+
+/// Submissions that are not finalized or failed yet, oldest first.
+pub async fn get_pending_transactions(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<SubmittedTransaction>> {
+    let records = sqlx::query_as::<_, SubmittedTransaction>(&format!(
+        r#"
+        SELECT {TRANSACTION_COLUMNS}
+        FROM transactions
+        WHERE status IN ('submitted', 'processed', 'confirmed')
+        ORDER BY created_at
+        LIMIT $1
+        "#
+    ))
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(records)
+}
+
+pub async fn update_transaction_status(
+    pool: &PgPool,
+    signature: &str,
+    status: &str,
+    slot: Option<i64>,
+    error: Option<&str>,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE transactions
+        SET status = $2, slot = COALESCE($3, slot), error = $4, updated_at = now()
+        WHERE signature = $1
+        "#,
+    )
+    .bind(signature)
+    .bind(status)
+    .bind(slot)
+    .bind(error)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn add_transaction_send_attempts(
+    pool: &PgPool,
+    signature: &str,
+    attempts: i32,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE transactions
+        SET send_attempts = send_attempts + $2, updated_at = now()
+        WHERE signature = $1
+        "#,
+    )
+    .bind(signature)
+    .bind(attempts)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+const SPONSORSHIP_USAGE_COLUMNS: &str = "owner, day, transaction_count, fee_lamports";
+
+/// Counts one sponsored transaction costing `fee_lamports` against the
+/// owner's usage for `day` and against the total across all owners, unless
+/// either would go over its budget in `limits`.
+pub async fn reserve_sponsorship(
+    pool: &PgPool,
+    owner: &str,
+    day: NaiveDate,
+    fee_lamports: i64,
+    limits: &SponsorshipLimits,
+) -> Result<SponsorshipReservation> {
+    let mut tx = pool.begin().await?;
+    let total = sqlx::query(
+        r#"
+        INSERT INTO sponsorship_totals (day, transaction_count, fee_lamports)
+        SELECT $1, 1, $2
+        WHERE $3 >= 1 AND $2 <= $4
+        ON CONFLICT (day) DO UPDATE SET
+          transaction_count = sponsorship_totals.transaction_count + 1,
+          fee_lamports = sponsorship_totals.fee_lamports + EXCLUDED.fee_lamports,
+          updated_at = now()
+        WHERE sponsorship_totals.transaction_count < $3
+          AND sponsorship_totals.fee_lamports + EXCLUDED.fee_lamports <= $4
+        RETURNING day
+        "#,
+    )
+    .bind(day)
+    .bind(fee_lamports)
+    .bind(limits.total_transactions)
+    .bind(limits.total_fee_lamports)
+    .fetch_optional(&mut tx)
+    .await?;
+    if total.is_none() {
+        return Ok(SponsorshipReservation::TotalBudgetSpent);
+    }
+
+    let usage = sqlx::query_as::<_, SponsorshipUsage>(&format!(
+        r#"
+        INSERT INTO sponsorship_usage (owner, day, transaction_count, fee_lamports)
+        SELECT $1, $2, 1, $3
+        WHERE $4 >= 1 AND $3 <= $5
+        ON CONFLICT (owner, day) DO UPDATE SET
+          transaction_count = sponsorship_usage.transaction_count + 1,
+          fee_lamports = sponsorship_usage.fee_lamports + EXCLUDED.fee_lamports,
+          updated_at = now()
+        WHERE sponsorship_usage.transaction_count < $4
+          AND sponsorship_usage.fee_lamports + EXCLUDED.fee_lamports <= $5
+        RETURNING {SPONSORSHIP_USAGE_COLUMNS}
+        "#
+    ))
+    .bind(owner)
+    .bind(day)
+    .bind(fee_lamports)
+    .bind(limits.owner_transactions)
+    .bind(limits.owner_fee_lamports)
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(usage) = usage else {
+        return Ok(SponsorshipReservation::OwnerBudgetSpent);
+    };
+    tx.commit().await?;
+    Ok(SponsorshipReservation::Reserved(usage))
+}
+
+pub async fn get_sponsorship_usage(
+    pool: &PgPool,
+    owner: &str,
+    day: NaiveDate,
+) -> Result<Option<SponsorshipUsage>> {
+    let usage = sqlx::query_as::<_, SponsorshipUsage>(&format!(
+        "SELECT {SPONSORSHIP_USAGE_COLUMNS} FROM sponsorship_usage WHERE owner = $1 AND day = $2"
+    ))
+    .bind(owner)
+    .bind(day)
+    .fetch_optional(pool)
+    .await?;
+    Ok(usage)
+}
+
+pub async fn get_sponsorship_totals(
+    pool: &PgPool,
+    day: NaiveDate,
+) -> Result<Option<SponsorshipTotals>> {
+    let totals = sqlx::query_as::<_, SponsorshipTotals>(
+        "SELECT day, transaction_count, fee_lamports FROM sponsorship_totals WHERE day = $1",
+    )
+    .bind(day)
+    .fetch_optional(pool)
+    .await?;
+    Ok(totals)
+}
+
+const CUSTODIAL_KEY_COLUMNS: &str =
+    "id, owner, pubkey, signer, sealed_key, token_hash, issued_at, created_at, destroyed_at";
+
+fn map_insert_custodial_key_error(err: sqlx::Error) -> AppError {
+    if let sqlx::Error::Database(db_err) = &err {
+        if db_err.constraint() == Some("custodial_keys_active_owner_unique") {
+            return AppError::Conflict(
+                "owner already has a custodial key; rotate or destroy it first".into(),
+            );
+        }
+    }
+
+    AppError::Database(err)
+}
+
+async fn insert_custodial_key_with<'e, E>(
+    executor: E,
+    key: &NewCustodialKey,
+) -> Result<Option<CustodialKeyRecord>>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    sqlx::query_as::<_, CustodialKeyRecord>(&format!(
+        r#"
+        INSERT INTO custodial_keys (id, owner, pubkey, signer, sealed_key, token_hash, issued_at)
+        SELECT $1, $2, $3, $4, $5, $6, $7
+        WHERE NOT EXISTS (
+          SELECT 1 FROM custodial_keys WHERE owner = $2 AND issued_at >= $7
+        )
+        RETURNING {CUSTODIAL_KEY_COLUMNS}
+        "#
+    ))
+    .bind(uuid::Uuid::new_v4())
+    .bind(&key.owner)
+    .bind(&key.pubkey)
+    .bind(&key.signer)
+    .bind(&key.sealed_key)
+    .bind(&key.token_hash)
+    .bind(key.issued_at)
+    .fetch_optional(executor)
+    .await
+    .map_err(map_insert_custodial_key_error)
+}
+
+/// Stores a new key for an owner without an active one. Keys must be issued
+/// after every earlier key of the owner, so a create signature cannot be
+/// replayed.
+pub async fn insert_custodial_key(
+    pool: &PgPool,
+    key: &NewCustodialKey,
+) -> Result<CustodialKeyRecord> {
+    insert_custodial_key_with(pool, key).await?.ok_or_else(|| {
+        AppError::Conflict("a custodial key was already issued at or after issuedAt".into())
+    })
+}
+
+/// Erases the owner's active key if it was issued before `issued_at`.
+pub async fn destroy_custodial_key(
+    pool: &PgPool,
+    owner: &str,
+    issued_at: DateTime<Utc>,
+) -> Result<Option<CustodialKeyRecord>> {
+    destroy_custodial_key_with(pool, owner, issued_at).await
+}
+
+async fn destroy_custodial_key_with<'e, E>(
+    executor: E,
+    owner: &str,
+    issued_at: DateTime<Utc>,
+) -> Result<Option<CustodialKeyRecord>>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let record = sqlx::query_as::<_, CustodialKeyRecord>(&format!(
+        r#"
+        UPDATE custodial_keys
+        SET sealed_key = NULL, destroyed_at = now()
+        WHERE owner = $1 AND destroyed_at IS NULL AND issued_at < $2
+        RETURNING {CUSTODIAL_KEY_COLUMNS}
+        "#
+    ))
+    .bind(owner)
+    .bind(issued_at)
+    .fetch_optional(executor)
+    .await?;
+    Ok(record)
+}
+
+/// Destroys the owner's active key and stores `key` in its place, or returns
+/// `None` when there is no active key issued before `key`.
+pub async fn rotate_custodial_key(
+    pool: &PgPool,
+    key: &NewCustodialKey,
+) -> Result<Option<CustodialKeyRecord>> {
+    let mut tx = pool.begin().await?;
+    if destroy_custodial_key_with(&mut tx, &key.owner, key.issued_at)
+        .await?
+        .is_none()
+    {
+        return Ok(None);
+    }
+    let record = insert_custodial_key_with(&mut tx, key).await?;
+    if record.is_some() {
+        tx.commit().await?;
+    }
+    Ok(record)
+}
+
+/// The owner's keys, newest first.
+pub async fn list_custodial_keys(pool: &PgPool, owner: &str) -> Result<Vec<CustodialKeyRecord>> {
+    let records = sqlx::query_as::<_, CustodialKeyRecord>(&format!(
+        "SELECT {CUSTODIAL_KEY_COLUMNS} FROM custodial_keys WHERE owner = $1 ORDER BY created_at DESC"
+    ))
+    .bind(owner)
+    .fetch_all(pool)
+    .await?;
+    Ok(records)
+}
+
+pub async fn get_active_custodial_key(
+    pool: &PgPool,
+    owner: &str,
+) -> Result<Option<CustodialKeyRecord>> {
+    let record = sqlx::query_as::<_, CustodialKeyRecord>(&format!(
+        "SELECT {CUSTODIAL_KEY_COLUMNS} FROM custodial_keys WHERE owner = $1 AND destroyed_at IS NULL"
+    ))
+    .bind(owner)
+    .fetch_optional(pool)
+    .await?;
+    Ok(record)
+}
+
+pub async fn get_active_custodial_key_by_pubkey(
+    pool: &PgPool,
+    pubkey: &str,
+) -> Result<Option<CustodialKeyRecord>> {
+    let record = sqlx::query_as::<_, CustodialKeyRecord>(&format!(
+        "SELECT {CUSTODIAL_KEY_COLUMNS} FROM custodial_keys WHERE pubkey = $1 AND destroyed_at IS NULL"
+    ))
+    .bind(pubkey)
+    .fetch_optional(pool)
+    .await?;
+    Ok(record)
+}
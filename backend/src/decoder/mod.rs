@@ -0,0 +1,573 @@
+//! Human-readable transaction decoding for `POST /tx/decode`.
+//!
+//! Vault program instructions are matched by their Anchor discriminator and
+//! Borsh-decoded with the `ephemeralvault::instruction` types; System and
+//! Compute Budget instructions use their known layouts. Anything else is
+//! returned with its raw data.
+
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Serialize;
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    compute_budget,
+    message::{v0::LoadedAddresses, VersionedMessage},
+    pubkey::Pubkey,
+    signature::Signature,
+    system_instruction::SystemInstruction,
+    system_program,
+    transaction::VersionedTransaction,
+};
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::solana::{self, from_anchor_pubkey, TxLifetimeMode, TxVersion};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedSignatureDto {
+    pub pubkey: String,
+    /// `None` while the signer has not signed yet.
+    pub signature: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedAccountDto {
+    pub pubkey: String,
+    /// The account's role in the instruction, when its layout is known.
+    pub name: Option<String>,
+    pub signer: bool,
+    pub writable: bool,
+    /// Whether the address was loaded from a lookup table.
+    pub from_lookup_table: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedInstructionDto {
+    pub index: usize,
+    pub program_id: String,
+    /// `ephemeral_vault`, `system`, `compute_budget` or `unknown`.
+    pub program: &'static str,
+    /// The instruction name, when the program and instruction are known.
+    pub name: Option<String>,
+    pub args: Option<Value>,
+    pub accounts: Vec<DecodedAccountDto>,
+    pub data_base64: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedTxDto {
+    pub transaction_version: TxVersion,
+    pub fee_payer: String,
+    pub recent_blockhash: String,
+    pub lifetime: TxLifetimeMode,
+    pub nonce_account: Option<String>,
+    pub signatures: Vec<DecodedSignatureDto>,
+    pub accounts: Vec<DecodedAccountDto>,
+    pub instructions: Vec<DecodedInstructionDto>,
+}
+
+/// A decoded instruction: its name, account roles and arguments.
+type Known = (&'static str, &'static [&'static str], Value);
+
+fn decode_as<T: Discriminator + AnchorDeserialize>(data: &[u8]) -> Option<T> {
+    let mut body = data.strip_prefix(T::DISCRIMINATOR)?;
+    T::deserialize(&mut body).ok()
+}
+
+const OWNER_ACCOUNTS: &[&str] = &["vault", "user"];
+const OWNER_SYSTEM_ACCOUNTS: &[&str] = &["vault", "user", "systemProgram"];
+const DELEGATE_ACCOUNTS: &[&str] = &["vault", "delegate"];
+
+fn vault_instruction(data: &[u8]) -> Option<Known> {
+    use ephemeralvault::instruction as ix;
+
+    if let Some(ix) = decode_as::<ix::CreateEphemeralVault>(data) {
+        return Some((
+            "create_ephemeral_vault",
+            &["user", "vault", "systemProgram"],
+            json!({ "approvedAmount": ix.approved_amount }),
+        ));
+    }
+    if let Some(ix) = decode_as::<ix::ApproveDelegate>(data) {
+        return Some((
+            "approve_delegate",
+            OWNER_ACCOUNTS,
+            json!({
+                "delegate": from_anchor_pubkey(ix.delegate).to_string(),
+                "customDuration": ix.custom_duration,
+            }),
+        ));
+    }
+    if decode_as::<ix::RenewSession>(data).is_some() {
+        return Some(("renew_session", OWNER_ACCOUNTS, json!({})));
+    }
+    if let Some(ix) = decode_as::<ix::AutoDepositForTrade>(data) {
+        return Some((
+            "auto_deposit_for_trade",
+            OWNER_SYSTEM_ACCOUNTS,
+            json!({ "tradeFeeEstimate": ix.trade_fee_estimate }),
+        ));
+    }
+    if let Some(ix) = decode_as::<ix::ExecuteTrade>(data) {
+        return Some((
+            "execute_trade",
+            DELEGATE_ACCOUNTS,
+            json!({
+                "tradeFee": ix.trade_fee,
+                "tradeAmount": ix.trade_amount,
+                "clientOrderId": ix.client_order_id,
+            }),
+        ));
+    }
+    if let Some(ix) = decode_as::<ix::ExecuteTradesBatch>(data) {
+        let entries: Vec<_> = ix
+            .entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "tradeFee": entry.trade_fee,
+                    "tradeAmount": entry.trade_amount,
+                    "clientId": entry.client_id,
+                })
+            })
+            .collect();
+        return Some((
+            "execute_trades_batch",
+            DELEGATE_ACCOUNTS,
+            json!({ "entries": entries }),
+        ));
+    }
+    if let Some(ix) = decode_as::<ix::WithdrawBalance>(data) {
+        return Some((
+            "withdraw_balance",
+            OWNER_ACCOUNTS,
+            json!({ "amount": ix.amount }),
+        ));
+    }
+    if decode_as::<ix::RevokeAccess>(data).is_some() {
+        return Some(("revoke_access", OWNER_ACCOUNTS, json!({})));
+    }
+    if decode_as::<ix::ReactivateVault>(data).is_some() {
+        return Some(("reactivate_vault", OWNER_ACCOUNTS, json!({})));
+    }
+    if let Some(ix) = decode_as::<ix::UpdateApprovedAmount>(data) {
+        return Some((
+            "update_approved_amount",
+            OWNER_ACCOUNTS,
+            json!({ "newApprovedAmount": ix.new_approved_amount }),
+        ));
+    }
+    if decode_as::<ix::EmergencyPause>(data).is_some() {
+        return Some(("emergency_pause", OWNER_ACCOUNTS, json!({})));
+    }
+    if decode_as::<ix::UnpauseVault>(data).is_some() {
+        return Some(("unpause_vault", OWNER_ACCOUNTS, json!({})));
+    }
+    if decode_as::<ix::CleanupVault>(data).is_some() {
+        return Some((
+            "cleanup_vault",
+            &["vault", "userWallet", "cleaner"],
+            json!({}),
+        ));
+    }
+    if decode_as::<ix::GetVaultStats>(data).is_some() {
+        return Some(("get_vault_stats", &["vault"], json!({})));
+    }
+    if let Some(ix) = decode_as::<ix::CheckDelegate>(data) {
+        return Some((
+            "check_delegate",
+            DELEGATE_ACCOUNTS,
+            json!({ "minRemainingBudget": ix.min_remaining_budget }),
+        ));
+    }
+    if decode_as::<ix::MigrateVault>(data).is_some() {
+        return Some(("migrate_vault", OWNER_SYSTEM_ACCOUNTS, json!({})));
+    }
+    None
+}
+
+fn system_instruction(data: &[u8]) -> Option<Known> {
+    let known: Known = match bincode::deserialize::<SystemInstruction>(data).ok()? {
+        SystemInstruction::CreateAccount {
+            lamports,
+            space,
+            owner,
+        } => (
+            "create_account",
+            &["from", "to"],
+            json!({ "lamports": lamports, "space": space, "owner": owner.to_string() }),
+        ),
+        SystemInstruction::Assign { owner } => (
+            "assign",
+            &["account"],
+            json!({ "owner": owner.to_string() }),
+        ),
+        SystemInstruction::Transfer { lamports } => {
+            ("transfer", &["from", "to"], json!({ "lamports": lamports }))
+        }
+        SystemInstruction::CreateAccountWithSeed {
+            base,
+            seed,
+            lamports,
+            space,
+            owner,
+        } => (
+            "create_account_with_seed",
+            &["from", "to", "base"],
+            json!({
+                "base": base.to_string(),
+                "seed": seed,
+                "lamports": lamports,
+                "space": space,
+                "owner": owner.to_string(),
+            }),
+        ),
+        SystemInstruction::AdvanceNonceAccount => (
+            "advance_nonce_account",
+            &["nonceAccount", "recentBlockhashesSysvar", "authority"],
+            json!({}),
+        ),
+        SystemInstruction::WithdrawNonceAccount(lamports) => (
+            "withdraw_nonce_account",
+            &[
+                "nonceAccount",
+                "to",
+                "recentBlockhashesSysvar",
+                "rentSysvar",
+                "authority",
+            ],
+            json!({ "lamports": lamports }),
+        ),
+        SystemInstruction::InitializeNonceAccount(authority) => (
+            "initialize_nonce_account",
+            &["nonceAccount", "recentBlockhashesSysvar", "rentSysvar"],
+            json!({ "authority": authority.to_string() }),
+        ),
+        SystemInstruction::AuthorizeNonceAccount(authority) => (
+            "authorize_nonce_account",
+            &["nonceAccount", "authority"],
+            json!({ "newAuthority": authority.to_string() }),
+        ),
+        SystemInstruction::Allocate { space } => {
+            ("allocate", &["account"], json!({ "space": space }))
+        }
+        other => (system_instruction_name(&other), &[], Value::Null),
+    };
+    Some(known)
+}
+
+fn system_instruction_name(instruction: &SystemInstruction) -> &'static str {
+    match instruction {
+        SystemInstruction::AllocateWithSeed { .. } => "allocate_with_seed",
+        SystemInstruction::AssignWithSeed { .. } => "assign_with_seed",
+        SystemInstruction::TransferWithSeed { .. } => "transfer_with_seed",
+        SystemInstruction::UpgradeNonceAccount => "upgrade_nonce_account",
+        _ => "unknown",
+    }
+}
+
+/// Compute Budget instructions are a Borsh enum: a tag byte, then the value.
+fn compute_budget_instruction(data: &[u8]) -> Option<Known> {
+    let (tag, value) = data.split_first()?;
+    let u32_value = || Some(u32::from_le_bytes(value.try_into().ok()?));
+    let known: Known = match tag {
+        1 => ("request_heap_frame", &[], json!({ "bytes": u32_value()? })),
+        2 => (
+            "set_compute_unit_limit",
+            &[],
+            json!({ "units": u32_value()? }),
+        ),
+        3 => (
+            "set_compute_unit_price",
+            &[],
+            json!({ "microLamports": u64::from_le_bytes(value.try_into().ok()?) }),
+        ),
+        4 => (
+            "set_loaded_accounts_data_size_limit",
+            &[],
+            json!({ "bytes": u32_value()? }),
+        ),
+        _ => return None,
+    };
+    Some(known)
+}
+
+/// Decodes `tx`, whose lookup table addresses are `loaded`.
+pub fn decode(
+    tx: &VersionedTransaction,
+    loaded: &LoadedAddresses,
+    program_id: &Pubkey,
+) -> DecodedTxDto {
+    let message = &tx.message;
+    let static_len = message.static_account_keys().len();
+    let keys = solana::message_account_keys(message, loaded);
+    let account = |index: usize, name: Option<&str>| DecodedAccountDto {
+        pubkey: keys[index].to_string(),
+        name: name.map(str::to_string),
+        signer: message.is_signer(index),
+        writable: message.is_maybe_writable(index),
+        from_lookup_table: index >= static_len,
+    };
+
+    let instructions = message
+        .instructions()
+        .iter()
+        .enumerate()
+        .map(|(index, ix)| {
+            let program = keys[usize::from(ix.program_id_index)];
+            let (label, known) = if program == *program_id {
+                ("ephemeral_vault", vault_instruction(&ix.data))
+            } else if program == system_program::ID {
+                ("system", system_instruction(&ix.data))
+            } else if program == compute_budget::ID {
+                ("compute_budget", compute_budget_instruction(&ix.data))
+            } else {
+                ("unknown", None)
+            };
+            let roles = known.as_ref().map_or(&[][..], |(_, roles, _)| *roles);
+            DecodedInstructionDto {
+                index,
+                program_id: program.to_string(),
+                program: label,
+                name: known.as_ref().map(|(name, _, _)| name.to_string()),
+                args: known
+                    .map(|(_, _, args)| args)
+                    .filter(|args| !args.is_null()),
+                accounts: ix
+                    .accounts
+                    .iter()
+                    .enumerate()
+                    .map(|(position, &key)| account(usize::from(key), roles.get(position).copied()))
+                    .collect(),
+                data_base64: BASE64.encode(&ix.data),
+            }
+        })
+        .collect();
+
+    let nonce_account = solana::durable_nonce_account(message, &keys);
+    DecodedTxDto {
+        transaction_version: match message {
+            VersionedMessage::Legacy(_) => TxVersion::Legacy,
+            VersionedMessage::V0(_) => TxVersion::V0,
+        },
+        fee_payer: keys[0].to_string(),
+        recent_blockhash: message.recent_blockhash().to_string(),
+        lifetime: if nonce_account.is_some() {
+            TxLifetimeMode::DurableNonce
+        } else {
+            TxLifetimeMode::Blockhash
+        },
+        nonce_account: nonce_account.map(|account| account.to_string()),
+        signatures: tx
+            .signatures
+            .iter()
+            .zip(message.static_account_keys())
+            .map(|(signature, pubkey)| DecodedSignatureDto {
+                pubkey: pubkey.to_string(),
+                signature: (*signature != Signature::default()).then(|| signature.to_string()),
+            })
+            .collect(),
+        accounts: (0..keys.len()).map(|index| account(index, None)).collect(),
+        instructions,
+    }
+}
+
+/// Decodes a base64 transaction, resolving its lookup tables over RPC.
+pub async fn decode_transaction_base64(
+    rpc: &RpcClient,
+    config: &Config,
+    transaction_base64: &str,
+) -> Result<DecodedTxDto> {
+    let tx = solana::decode_transaction_base64(transaction_base64)?;
+    let loaded = solana::load_addresses(rpc, &tx.message).await?;
+    Ok(decode(&tx, &loaded, &solana::program_id(config)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, program_id, LocalSvm};
+    use anchor_lang::InstructionData;
+    use solana_sdk::{
+        account::Account,
+        address_lookup_table::AddressLookupTableAccount,
+        compute_budget::ComputeBudgetInstruction,
+        hash::Hash,
+        instruction::{AccountMeta, Instruction},
+        message::v0,
+        signature::Keypair,
+        signer::Signer,
+        system_instruction,
+        transaction::Transaction,
+    };
+
+    fn execute_trade(vault: Pubkey, delegate: Pubkey) -> Instruction {
+        Instruction {
+            program_id: program_id(),
+            accounts: vec![
+                AccountMeta::new(vault, false),
+                AccountMeta::new_readonly(delegate, true),
+            ],
+            data: ephemeralvault::instruction::ExecuteTrade {
+                trade_fee: 5_000,
+                trade_amount: 1_000_000,
+                client_order_id: 42,
+            }
+            .data(),
+        }
+    }
+
+    #[test]
+    fn decodes_vault_system_and_compute_budget_instructions() {
+        let delegate = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let tx: VersionedTransaction = Transaction::new_with_payer(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(50_000),
+                ComputeBudgetInstruction::set_compute_unit_price(7),
+                system_instruction::transfer(&delegate, &vault, 10_000),
+                execute_trade(vault, delegate),
+                Instruction::new_with_bytes(Pubkey::new_unique(), &[1, 2, 3], vec![]),
+            ],
+            Some(&delegate),
+        )
+        .into();
+
+        let decoded = decode(&tx, &LoadedAddresses::default(), &program_id());
+        assert_eq!(decoded.transaction_version, TxVersion::Legacy);
+        assert_eq!(decoded.fee_payer, delegate.to_string());
+        assert_eq!(decoded.recent_blockhash, Hash::default().to_string());
+        assert_eq!(decoded.lifetime, TxLifetimeMode::Blockhash);
+        assert_eq!(
+            decoded.signatures,
+            vec![DecodedSignatureDto {
+                pubkey: delegate.to_string(),
+                signature: None,
+            }]
+        );
+
+        let names: Vec<_> = decoded
+            .instructions
+            .iter()
+            .map(|ix| (ix.program, ix.name.as_deref()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("compute_budget", Some("set_compute_unit_limit")),
+                ("compute_budget", Some("set_compute_unit_price")),
+                ("system", Some("transfer")),
+                ("ephemeral_vault", Some("execute_trade")),
+                ("unknown", None),
+            ]
+        );
+        assert_eq!(
+            decoded.instructions[0].args,
+            Some(json!({ "units": 50_000 }))
+        );
+        assert_eq!(
+            decoded.instructions[1].args,
+            Some(json!({ "microLamports": 7 }))
+        );
+        assert_eq!(
+            decoded.instructions[2].args,
+            Some(json!({ "lamports": 10_000 }))
+        );
+
+        let trade = &decoded.instructions[3];
+        assert_eq!(
+            trade.args,
+            Some(json!({ "tradeFee": 5_000, "tradeAmount": 1_000_000, "clientOrderId": 42 }))
+        );
+        assert_eq!(
+            trade.accounts,
+            vec![
+                DecodedAccountDto {
+                    pubkey: vault.to_string(),
+                    name: Some("vault".into()),
+                    signer: false,
+                    writable: true,
+                    from_lookup_table: false,
+                },
+                DecodedAccountDto {
+                    pubkey: delegate.to_string(),
+                    name: Some("delegate".into()),
+                    signer: true,
+                    writable: true,
+                    from_lookup_table: false,
+                },
+            ]
+        );
+
+        let unknown = &decoded.instructions[4];
+        assert_eq!(unknown.args, None);
+        assert_eq!(unknown.data_base64, BASE64.encode([1, 2, 3]));
+    }
+
+    #[test]
+    fn leaves_undecodable_data_unnamed() {
+        let vault = Pubkey::new_unique();
+        let mut truncated = execute_trade(vault, Pubkey::new_unique());
+        truncated.data.truncate(12);
+        let mut budget = ComputeBudgetInstruction::set_compute_unit_price(7);
+        budget.data.pop();
+        let tx: VersionedTransaction =
+            Transaction::new_with_payer(&[truncated, budget], Some(&Pubkey::new_unique())).into();
+
+        let decoded = decode(&tx, &LoadedAddresses::default(), &program_id());
+        for ix in &decoded.instructions {
+            assert_eq!((ix.name.as_deref(), ix.args.as_ref()), (None, None));
+            assert!(ix.accounts.iter().all(|account| account.name.is_none()));
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_lookup_table_accounts_and_signatures() {
+        let svm = LocalSvm::new(1_700_000_000);
+        let rpc = svm.rpc();
+        let delegate = Keypair::new();
+        svm.set_account(
+            delegate.pubkey(),
+            Account::new(1_000_000_000, 0, &system_program::ID),
+        );
+        let vault = Pubkey::new_unique();
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![vault],
+        };
+        svm.set_lookup_table(table.key, table.addresses.clone());
+
+        let blockhash = rpc.get_latest_blockhash().await.unwrap();
+        let message = v0::Message::try_compile(
+            &delegate.pubkey(),
+            &[execute_trade(vault, delegate.pubkey())],
+            &[table],
+            blockhash,
+        )
+        .unwrap();
+        let tx =
+            VersionedTransaction::try_new(VersionedMessage::V0(message), &[&delegate]).unwrap();
+        let encoded = solana::encode_transaction_base64(&tx).unwrap();
+
+        let decoded = decode_transaction_base64(&rpc, &test_support::config(), &encoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded.transaction_version, TxVersion::V0);
+        assert_eq!(decoded.recent_blockhash, blockhash.to_string());
+        assert_eq!(
+            decoded.signatures[0].signature,
+            Some(tx.signatures[0].to_string())
+        );
+        let vault_account = &decoded.instructions[0].accounts[0];
+        assert_eq!(vault_account.pubkey, vault.to_string());
+        assert_eq!(vault_account.name.as_deref(), Some("vault"));
+        assert!(vault_account.writable);
+        assert!(vault_account.from_lookup_table);
+        assert_eq!(decoded.accounts.len(), 3);
+    }
+}
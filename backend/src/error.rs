@@ -3,6 +3,7 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use ephemeralvault::EphemeralVaultError;
 use serde_json::json;
 use thiserror::Error;
 
@@ -29,9 +30,22 @@ pub enum AppError {
     #[error("Solana RPC error: {0}")]
     SolanaRpc(String),
 
+    #[error("Not found: {0}")]
+    NotFound(String),
+
     #[error("Conflict: {0}")]
     Conflict(String),
 
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
+    #[error("Vault check failed: {} ({}): {}", .0.name(), u32::from(*.0), .0)]
+    VaultCheck(EphemeralVaultError),
+
+    /// An error raised by one operation of a composed request.
+    #[error("operations[{index}]: {source}")]
+    Operation { index: usize, source: Box<AppError> },
+
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
@@ -45,25 +59,119 @@ pub enum AppError {
     Internal(String),
 }
 
+impl AppError {
+    /// A stable machine-readable code for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_error",
+            AppError::VaultNotFound(_) => "vault_not_found",
+            AppError::SessionExpired => "session_expired",
+            AppError::ExceedsApprovedLimit => "exceeds_approved_limit",
+            AppError::UnauthorizedDelegate => "unauthorized_delegate",
+            AppError::InvalidSignature(_) => "invalid_signature",
+            AppError::SolanaRpc(_) => "solana_rpc_error",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::RateLimited(_) => "rate_limited",
+            AppError::VaultCheck(_) => "vault_check_failed",
+            AppError::Operation { source, .. } => source.code(),
+            AppError::Database(_)
+            | AppError::Serialization(_)
+            | AppError::SerializationMessage(_)
+            | AppError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::VaultNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::SessionExpired => StatusCode::GONE,
+            AppError::ExceedsApprovedLimit => StatusCode::BAD_REQUEST,
+            AppError::UnauthorizedDelegate => StatusCode::FORBIDDEN,
+            AppError::InvalidSignature(_) => StatusCode::BAD_REQUEST,
+            AppError::SolanaRpc(_) => StatusCode::BAD_GATEWAY,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::VaultCheck(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Operation { source, .. } => source.status(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, message) = match &self {
-            AppError::Validation(_) => (StatusCode::BAD_REQUEST, self.to_string()),
-            AppError::VaultNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
-            AppError::SessionExpired => (StatusCode::GONE, self.to_string()),
-            AppError::ExceedsApprovedLimit => (StatusCode::BAD_REQUEST, self.to_string()),
-            AppError::UnauthorizedDelegate => (StatusCode::FORBIDDEN, self.to_string()),
-            AppError::InvalidSignature(_) => (StatusCode::BAD_REQUEST, self.to_string()),
-            AppError::SolanaRpc(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
-            AppError::Conflict(_) => (StatusCode::CONFLICT, self.to_string()),
-            _ => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal server error".into(),
-            ),
+        let status = self.status();
+        let message = if status == StatusCode::INTERNAL_SERVER_ERROR {
+            "Internal server error".into()
+        } else {
+            self.to_string()
         };
 
-        (status, Json(json!({ "error": { "message": message } }))).into_response()
+        (
+            status,
+            Json(json!({ "error": { "code": self.code(), "message": message } })),
+        )
+            .into_response()
     }
 }
 
 pub type Result<T> = std::result::Result<T, AppError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+
+    async fn body(error: AppError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        (status, serde_json::from_slice(&bytes).expect("json"))
+    }
+
+    #[tokio::test]
+    async fn responses_carry_machine_readable_codes() {
+        assert_eq!(
+            body(AppError::RateLimited("budget used up".into())).await,
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                json!({ "error": { "code": "rate_limited", "message": "Rate limited: budget used up" } })
+            )
+        );
+        assert_eq!(
+            body(AppError::VaultCheck(EphemeralVaultError::VaultPaused)).await,
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({ "error": {
+                    "code": "vault_check_failed",
+                    "message": "Vault check failed: VaultPaused (6004): Vault is paused"
+                } })
+            )
+        );
+        assert_eq!(
+            body(AppError::Operation {
+                index: 2,
+                source: Box::new(AppError::SessionExpired),
+            })
+            .await,
+            (
+                StatusCode::GONE,
+                json!({ "error": { "code": "session_expired", "message": "operations[2]: Session expired" } })
+            )
+        );
+        assert_eq!(
+            body(AppError::Internal("secret detail".into())).await,
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": { "code": "internal_error", "message": "Internal server error" } })
+            )
+        );
+    }
+}
@@ -4,15 +4,22 @@ use axum::{
     response::IntoResponse,
     Json,
 };
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use solana_sdk::{pubkey::Pubkey, signature::Signature};
 
 use crate::{
-    db::{models::NewTrade, queries},
+    custody,
+    db::{
+        models::{NewTrade, RenewalPolicyRecord, SubmittedTransaction, VaultSnapshot},
+        queries,
+    },
+    decoder,
     error::{AppError, Result},
-    solana,
+    renewals, rpc, solana, sponsorship,
     state::AppState,
+    submission,
 };
 
 const MIN_APPROVED_AMOUNT_LAMPORTS: u64 = 1_000_000;
@@ -33,10 +40,42 @@ fn default_limit() -> i64 {
     50
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListVaultsQuery {
+    owner: Option<String>,
+    status: Option<solana::VaultStatusDto>,
+    paused: Option<bool>,
+    has_delegate: Option<bool>,
+    delegate: Option<String>,
+    expiring_within_secs: Option<i64>,
+    cleanup_eligible: Option<bool>,
+    cursor: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+const DEFAULT_HISTORY_DAYS: i64 = 7;
+const MAX_HISTORY_POINTS: i64 = 5_000;
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    #[serde(default = "default_history_limit")]
+    limit: i64,
+}
+
+fn default_history_limit() -> i64 {
+    500
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UserRequest {
     user_pubkey: String,
+    #[serde(flatten)]
+    options: solana::TxOptions,
 }
 
 #[derive(Debug, Deserialize)]
@@ -47,6 +86,8 @@ pub struct CreateVaultRequest {
     delegate_pubkey: Option<String>,
     custom_duration_seconds: Option<i64>,
     initial_deposit_lamports: Option<u64>,
+    #[serde(flatten)]
+    options: solana::TxOptions,
 }
 
 #[derive(Debug, Deserialize)]
@@ -54,6 +95,8 @@ pub struct CreateVaultRequest {
 pub struct AmountRequest {
     user_pubkey: String,
     amount_lamports: u64,
+    #[serde(flatten)]
+    options: solana::TxOptions,
 }
 
 #[derive(Debug, Deserialize)]
@@ -62,6 +105,21 @@ pub struct ApproveDelegateRequest {
     user_pubkey: String,
     delegate_pubkey: String,
     custom_duration_seconds: Option<i64>,
+    #[serde(default)]
+    funding_lamports: u64,
+    #[serde(flatten)]
+    options: solana::TxOptions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApproveCustodialDelegateRequest {
+    user_pubkey: String,
+    custom_duration_seconds: Option<i64>,
+    #[serde(default)]
+    funding_lamports: u64,
+    #[serde(flatten)]
+    options: solana::TxOptions,
 }
 
 #[derive(Debug, Deserialize)]
@@ -69,6 +127,47 @@ pub struct ApproveDelegateRequest {
 pub struct UpdateApprovedAmountRequest {
     user_pubkey: String,
     new_approved_amount_lamports: u64,
+    #[serde(flatten)]
+    options: solana::TxOptions,
+}
+
+/// One entry of `ComposeRequest::operations`, tagged by `op`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum ComposeOperationRequest {
+    CreateVault {
+        approved_amount_lamports: u64,
+    },
+    Deposit {
+        amount_lamports: u64,
+    },
+    Withdraw {
+        amount_lamports: u64,
+    },
+    Pause,
+    Unpause,
+    Revoke,
+    RenewSession,
+    ApproveDelegate {
+        delegate_pubkey: String,
+        custom_duration_seconds: Option<i64>,
+        #[serde(default)]
+        funding_lamports: u64,
+    },
+    Reactivate,
+    UpdateApprovedAmount {
+        new_approved_amount_lamports: u64,
+    },
+    MigrateVault,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComposeRequest {
+    user_pubkey: String,
+    operations: Vec<ComposeOperationRequest>,
+    #[serde(flatten)]
+    options: solana::TxOptions,
 }
 
 #[derive(Debug, Deserialize)]
@@ -78,6 +177,30 @@ pub struct ExecuteTradeRequest {
     delegate_pubkey: String,
     trade_fee_lamports: u64,
     trade_amount_lamports: u64,
+    client_order_id: u64,
+    /// Has the backend sign as the delegate's custodial key.
+    custody_token: Option<String>,
+    #[serde(flatten)]
+    options: solana::TxOptions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeEntryRequest {
+    trade_fee_lamports: u64,
+    trade_amount_lamports: u64,
+    client_id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteTradesBatchRequest {
+    vault_pubkey: String,
+    delegate_pubkey: String,
+    entries: Vec<TradeEntryRequest>,
+    custody_token: Option<String>,
+    #[serde(flatten)]
+    options: solana::TxOptions,
 }
 
 #[derive(Debug, Deserialize)]
@@ -85,6 +208,47 @@ pub struct ExecuteTradeRequest {
 pub struct CleanupRequest {
     vault_pubkey: String,
     cleaner_pubkey: String,
+    #[serde(flatten)]
+    options: solana::TxOptions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateNonceRequest {
+    user_pubkey: String,
+    seed: Option<String>,
+    #[serde(flatten)]
+    options: solana::TxOptions,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NonceQuery {
+    seed: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenewalPolicyRequest {
+    pub owner: String,
+    pub webhook_url: Option<String>,
+    pub max_renewals: Option<u32>,
+    pub valid_until: i64,
+    pub issued_at: i64,
+    pub signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeRenewalPolicyRequest {
+    pub issued_at: i64,
+    pub signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustodyActionRequest {
+    pub issued_at: i64,
+    pub signature: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -93,11 +257,64 @@ pub struct SimulateTransactionRequest {
     transaction_base64: String,
 }
 
-fn parse_pubkey(raw: &str, field: &str) -> Result<Pubkey> {
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodeTransactionRequest {
+    transaction_base64: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmitTransactionRequest {
+    transaction_base64: String,
+}
+
+pub(crate) fn parse_pubkey(raw: &str, field: &str) -> Result<Pubkey> {
     raw.parse::<Pubkey>()
         .map_err(|e| AppError::InvalidSignature(format!("invalid {field}: {e}")))
 }
 
+fn vault_list_filter(query: &ListVaultsQuery) -> Result<solana::VaultListFilter> {
+    if query.expiring_within_secs.is_some_and(|window| window <= 0) {
+        return Err(AppError::Validation(
+            "expiringWithinSecs must be greater than 0".into(),
+        ));
+    }
+
+    Ok(solana::VaultListFilter {
+        owner: query
+            .owner
+            .as_deref()
+            .map(|raw| parse_pubkey(raw, "owner"))
+            .transpose()?,
+        status: query.status,
+        paused: query.paused,
+        has_delegate: query.has_delegate,
+        delegate: query
+            .delegate
+            .as_deref()
+            .map(|raw| parse_pubkey(raw, "delegate"))
+            .transpose()?,
+        expiring_within_secs: query.expiring_within_secs,
+        cleanup_eligible: query.cleanup_eligible,
+    })
+}
+
+/// Resolves the requested window, defaulting to the last week up to now.
+fn history_range(
+    query: &HistoryQuery,
+    now: DateTime<Utc>,
+) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let to = query.to.unwrap_or(now);
+    let from = query
+        .from
+        .unwrap_or(to - Duration::days(DEFAULT_HISTORY_DAYS));
+    if from > to {
+        return Err(AppError::Validation("from must not be after to".into()));
+    }
+    Ok((from, to))
+}
+
 fn validate_lamports_range(value: u64, field: &str, min: u64, max: u64) -> Result<()> {
     if value < min || value > max {
         return Err(AppError::Validation(format!(
@@ -118,6 +335,95 @@ fn validate_positive_lamports(value: u64, field: &str) -> Result<()> {
     Ok(())
 }
 
+fn validate_trade_lamports(trade_fee: u64, trade_amount: u64) -> Result<()> {
+    validate_positive_lamports(trade_fee, "tradeFeeLamports")?;
+    validate_lamports_range(
+        trade_amount,
+        "tradeAmountLamports",
+        MIN_APPROVED_AMOUNT_LAMPORTS,
+        MAX_APPROVED_AMOUNT_LAMPORTS,
+    )
+}
+
+/// The program takes any non-zero id, but `trades.client_order_id` is a
+/// `bigint`, so ids the backend could not record are refused.
+fn validate_client_order_id(client_order_id: u64, field: &str) -> Result<()> {
+    if client_order_id == 0 || client_order_id > i64::MAX as u64 {
+        return Err(AppError::Validation(format!(
+            "{field} must be between 1 and {}",
+            i64::MAX
+        )));
+    }
+
+    Ok(())
+}
+
+/// Validates an operation's arguments as its single-operation endpoint does.
+fn compose_operation(operation: ComposeOperationRequest) -> Result<solana::VaultOperation> {
+    use solana::VaultOperation;
+
+    Ok(match operation {
+        ComposeOperationRequest::CreateVault {
+            approved_amount_lamports,
+        } => {
+            validate_lamports_range(
+                approved_amount_lamports,
+                "approvedAmountLamports",
+                MIN_APPROVED_AMOUNT_LAMPORTS,
+                MAX_APPROVED_AMOUNT_LAMPORTS,
+            )?;
+            VaultOperation::CreateVault {
+                approved_amount: approved_amount_lamports,
+            }
+        }
+        ComposeOperationRequest::Deposit { amount_lamports } => {
+            validate_lamports_range(
+                amount_lamports,
+                "amountLamports",
+                MIN_DEPOSIT_LAMPORTS,
+                MAX_DEPOSIT_LAMPORTS,
+            )?;
+            VaultOperation::Deposit {
+                amount: amount_lamports,
+            }
+        }
+        ComposeOperationRequest::Withdraw { amount_lamports } => VaultOperation::Withdraw {
+            amount: amount_lamports,
+        },
+        ComposeOperationRequest::Pause => VaultOperation::Pause,
+        ComposeOperationRequest::Unpause => VaultOperation::Unpause,
+        ComposeOperationRequest::Revoke => VaultOperation::Revoke,
+        ComposeOperationRequest::RenewSession => VaultOperation::RenewSession,
+        ComposeOperationRequest::ApproveDelegate {
+            delegate_pubkey,
+            custom_duration_seconds,
+            funding_lamports,
+        } => {
+            validate_custom_duration(custom_duration_seconds)?;
+            VaultOperation::ApproveDelegate {
+                delegate: parse_pubkey(&delegate_pubkey, "delegatePubkey")?,
+                custom_duration: custom_duration_seconds,
+                funding_lamports,
+            }
+        }
+        ComposeOperationRequest::Reactivate => VaultOperation::Reactivate,
+        ComposeOperationRequest::UpdateApprovedAmount {
+            new_approved_amount_lamports,
+        } => {
+            validate_lamports_range(
+                new_approved_amount_lamports,
+                "newApprovedAmountLamports",
+                MIN_APPROVED_AMOUNT_LAMPORTS,
+                MAX_APPROVED_AMOUNT_LAMPORTS,
+            )?;
+            VaultOperation::UpdateApprovedAmount {
+                new_approved_amount: new_approved_amount_lamports,
+            }
+        }
+        ComposeOperationRequest::MigrateVault => VaultOperation::MigrateVault,
+    })
+}
+
 fn validate_custom_duration(duration: Option<i64>) -> Result<()> {
     if let Some(duration) = duration {
         if duration <= 0 || duration > MAX_SESSION_DURATION_SECONDS {
@@ -140,6 +446,7 @@ pub struct ReadinessResponse {
     status: &'static str,
     postgres: DependencyStatus,
     solana_rpc: DependencyStatus,
+    rpc_endpoints: Vec<rpc::EndpointHealthDto>,
 }
 
 #[derive(Debug, Serialize)]
@@ -190,6 +497,7 @@ pub async fn ready(State(state): State<AppState>) -> impl IntoResponse {
             status,
             postgres,
             solana_rpc,
+            rpc_endpoints: state.rpc_pool.health(),
         }),
     )
 }
@@ -205,6 +513,21 @@ pub async fn get_vault(
     Ok(Json(vault))
 }
 
+pub async fn list_vaults(
+    State(state): State<AppState>,
+    Query(query): Query<ListVaultsQuery>,
+) -> Result<Json<solana::VaultPageDto>> {
+    let filter = vault_list_filter(&query)?;
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|raw| parse_pubkey(raw, "cursor"))
+        .transpose()?;
+    let limit = query.limit.clamp(1, 100) as usize;
+    let page = solana::list_vaults(&state.rpc, &state.config, &filter, cursor, limit).await?;
+    Ok(Json(page))
+}
+
 pub async fn get_vault_stats(
     State(state): State<AppState>,
     Path(user_pubkey): Path<String>,
@@ -227,6 +550,37 @@ pub async fn get_trades(
     Ok(Json(trades))
 }
 
+pub async fn get_vault_history(
+    State(state): State<AppState>,
+    Path(vault_pubkey): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<VaultSnapshot>>> {
+    let vault = parse_pubkey(&vault_pubkey, "vault pubkey")?;
+    vault_history(&state, vault, &query).await
+}
+
+pub async fn get_owner_vault_history(
+    State(state): State<AppState>,
+    Path(user_pubkey): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<VaultSnapshot>>> {
+    let user = parse_pubkey(&user_pubkey, "user pubkey")?;
+    let (vault, _) = solana::derive_vault_pda(&solana::program_id(&state.config)?, &user);
+    vault_history(&state, vault, &query).await
+}
+
+async fn vault_history(
+    state: &AppState,
+    vault: Pubkey,
+    query: &HistoryQuery,
+) -> Result<Json<Vec<VaultSnapshot>>> {
+    let (from, to) = history_range(query, Utc::now())?;
+    let limit = query.limit.clamp(1, MAX_HISTORY_POINTS);
+    let snapshots =
+        queries::get_vault_snapshots(&state.db, &vault.to_string(), from, to, limit).await?;
+    Ok(Json(snapshots))
+}
+
 pub async fn create_trade(
     State(state): State<AppState>,
     Json(body): Json<NewTrade>,
@@ -270,6 +624,7 @@ pub async fn tx_create_vault(
         delegate,
         body.custom_duration_seconds,
         body.initial_deposit_lamports,
+        &body.options,
     )
     .await?;
 
@@ -287,8 +642,14 @@ pub async fn tx_deposit(
         MIN_DEPOSIT_LAMPORTS,
         MAX_DEPOSIT_LAMPORTS,
     )?;
-    let tx =
-        solana::build_deposit_tx(&state.rpc, &state.config, user, body.amount_lamports).await?;
+    let tx = solana::build_deposit_tx(
+        &state.rpc,
+        &state.config,
+        user,
+        body.amount_lamports,
+        &body.options,
+    )
+    .await?;
     Ok(Json(tx))
 }
 
@@ -300,8 +661,14 @@ pub async fn tx_withdraw(
     if body.amount_lamports > 0 {
         validate_positive_lamports(body.amount_lamports, "amountLamports")?;
     }
-    let tx =
-        solana::build_withdraw_tx(&state.rpc, &state.config, user, body.amount_lamports).await?;
+    let tx = solana::build_withdraw_tx(
+        &state.rpc,
+        &state.config,
+        user,
+        body.amount_lamports,
+        &body.options,
+    )
+    .await?;
     Ok(Json(tx))
 }
 
@@ -310,7 +677,9 @@ pub async fn tx_pause(
     Json(body): Json<UserRequest>,
 ) -> Result<Json<solana::TxEnvelope>> {
     let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
-    let tx = solana::build_pause_tx(&state.rpc, &state.config, user).await?;
+    let options = sponsorship::options(&state, &body.options)?;
+    let tx = solana::build_pause_tx(&state.rpc, &state.config, user, &options).await?;
+    let tx = sponsorship::finish(&state, user, &options, tx).await?;
     Ok(Json(tx))
 }
 
@@ -319,7 +688,9 @@ pub async fn tx_unpause(
     Json(body): Json<UserRequest>,
 ) -> Result<Json<solana::TxEnvelope>> {
     let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
-    let tx = solana::build_unpause_tx(&state.rpc, &state.config, user).await?;
+    let options = sponsorship::options(&state, &body.options)?;
+    let tx = solana::build_unpause_tx(&state.rpc, &state.config, user, &options).await?;
+    let tx = sponsorship::finish(&state, user, &options, tx).await?;
     Ok(Json(tx))
 }
 
@@ -328,7 +699,9 @@ pub async fn tx_revoke(
     Json(body): Json<UserRequest>,
 ) -> Result<Json<solana::TxEnvelope>> {
     let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
-    let tx = solana::build_revoke_tx(&state.rpc, &state.config, user).await?;
+    let options = sponsorship::options(&state, &body.options)?;
+    let tx = solana::build_revoke_tx(&state.rpc, &state.config, user, &options).await?;
+    let tx = sponsorship::finish(&state, user, &options, tx).await?;
     Ok(Json(tx))
 }
 
@@ -337,7 +710,9 @@ pub async fn tx_renew_session(
     Json(body): Json<UserRequest>,
 ) -> Result<Json<solana::TxEnvelope>> {
     let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
-    let tx = solana::build_renew_session_tx(&state.rpc, &state.config, user).await?;
+    let options = sponsorship::options(&state, &body.options)?;
+    let tx = solana::build_renew_session_tx(&state.rpc, &state.config, user, &options).await?;
+    let tx = sponsorship::finish(&state, user, &options, tx).await?;
     Ok(Json(tx))
 }
 
@@ -348,14 +723,41 @@ pub async fn tx_approve_delegate(
     let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
     let delegate = parse_pubkey(&body.delegate_pubkey, "delegatePubkey")?;
     validate_custom_duration(body.custom_duration_seconds)?;
+    let options = sponsorship::options(&state, &body.options)?;
     let tx = solana::build_approve_delegate_tx(
         &state.rpc,
         &state.config,
         user,
         delegate,
         body.custom_duration_seconds,
+        body.funding_lamports,
+        &options,
     )
     .await?;
+    let tx = sponsorship::finish(&state, user, &options, tx).await?;
+    Ok(Json(tx))
+}
+
+pub async fn tx_approve_custodial_delegate(
+    State(state): State<AppState>,
+    Json(body): Json<ApproveCustodialDelegateRequest>,
+) -> Result<Json<solana::TxEnvelope>> {
+    let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
+    validate_custom_duration(body.custom_duration_seconds)?;
+    let key = custody::active_key(&state, &user).await?;
+    let delegate = parse_pubkey(&key.pubkey, "custodial key")?;
+    let options = sponsorship::options(&state, &body.options)?;
+    let tx = solana::build_approve_delegate_tx(
+        &state.rpc,
+        &state.config,
+        user,
+        delegate,
+        body.custom_duration_seconds,
+        body.funding_lamports,
+        &options,
+    )
+    .await?;
+    let tx = sponsorship::finish(&state, user, &options, tx).await?;
     Ok(Json(tx))
 }
 
@@ -364,7 +766,18 @@ pub async fn tx_reactivate(
     Json(body): Json<UserRequest>,
 ) -> Result<Json<solana::TxEnvelope>> {
     let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
-    let tx = solana::build_reactivate_tx(&state.rpc, &state.config, user).await?;
+    let options = sponsorship::options(&state, &body.options)?;
+    let tx = solana::build_reactivate_tx(&state.rpc, &state.config, user, &options).await?;
+    let tx = sponsorship::finish(&state, user, &options, tx).await?;
+    Ok(Json(tx))
+}
+
+pub async fn tx_migrate_vault(
+    State(state): State<AppState>,
+    Json(body): Json<UserRequest>,
+) -> Result<Json<solana::TxEnvelope>> {
+    let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
+    let tx = solana::build_migrate_vault_tx(&state.rpc, &state.config, user, &body.options).await?;
     Ok(Json(tx))
 }
 
@@ -379,29 +792,49 @@ pub async fn tx_update_approved_amount(
         MIN_APPROVED_AMOUNT_LAMPORTS,
         MAX_APPROVED_AMOUNT_LAMPORTS,
     )?;
+    let options = sponsorship::options(&state, &body.options)?;
     let tx = solana::build_update_approved_amount_tx(
         &state.rpc,
         &state.config,
         user,
         body.new_approved_amount_lamports,
+        &options,
     )
     .await?;
+    let tx = sponsorship::finish(&state, user, &options, tx).await?;
     Ok(Json(tx))
 }
 
+pub async fn tx_compose(
+    State(state): State<AppState>,
+    Json(body): Json<ComposeRequest>,
+) -> Result<Json<solana::ComposedTxsDto>> {
+    let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
+    let operations = body
+        .operations
+        .into_iter()
+        .enumerate()
+        .map(|(index, operation)| {
+            compose_operation(operation).map_err(|source| AppError::Operation {
+                index,
+                source: Box::new(source),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let txs =
+        solana::build_composed_txs(&state.rpc, &state.config, user, &operations, &body.options)
+            .await?;
+    Ok(Json(txs))
+}
+
 pub async fn tx_execute_trade(
     State(state): State<AppState>,
     Json(body): Json<ExecuteTradeRequest>,
 ) -> Result<Json<solana::TxEnvelope>> {
     let vault = parse_pubkey(&body.vault_pubkey, "vaultPubkey")?;
     let delegate = parse_pubkey(&body.delegate_pubkey, "delegatePubkey")?;
-    validate_positive_lamports(body.trade_fee_lamports, "tradeFeeLamports")?;
-    validate_lamports_range(
-        body.trade_amount_lamports,
-        "tradeAmountLamports",
-        MIN_APPROVED_AMOUNT_LAMPORTS,
-        MAX_APPROVED_AMOUNT_LAMPORTS,
-    )?;
+    validate_trade_lamports(body.trade_fee_lamports, body.trade_amount_lamports)?;
+    validate_client_order_id(body.client_order_id, "clientOrderId")?;
     let tx = solana::build_execute_trade_tx(
         &state.rpc,
         &state.config,
@@ -409,8 +842,58 @@ pub async fn tx_execute_trade(
         delegate,
         body.trade_fee_lamports,
         body.trade_amount_lamports,
+        body.client_order_id,
+        &body.options,
     )
     .await?;
+    let tx = match &body.custody_token {
+        Some(token) => custody::sign_as_delegate(&state, &delegate, token, tx).await?,
+        None => tx,
+    };
+    Ok(Json(tx))
+}
+
+pub async fn tx_execute_trades_batch(
+    State(state): State<AppState>,
+    Json(body): Json<ExecuteTradesBatchRequest>,
+) -> Result<Json<solana::TradeBatchTxDto>> {
+    let vault = parse_pubkey(&body.vault_pubkey, "vaultPubkey")?;
+    let delegate = parse_pubkey(&body.delegate_pubkey, "delegatePubkey")?;
+    if body.entries.is_empty() {
+        return Err(AppError::Validation("entries must not be empty".into()));
+    }
+
+    let entries = body
+        .entries
+        .iter()
+        .map(|entry| {
+            validate_trade_lamports(entry.trade_fee_lamports, entry.trade_amount_lamports)?;
+            validate_client_order_id(entry.client_id, "clientId")?;
+            Ok(ephemeralvault::TradeEntry {
+                trade_fee: entry.trade_fee_lamports,
+                trade_amount: entry.trade_amount_lamports,
+                client_id: entry.client_id,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let tx = solana::build_execute_trades_batch_tx(
+        &state.rpc,
+        &state.config,
+        vault,
+        delegate,
+        &entries,
+        &body.options,
+    )
+    .await?;
+    let tx = match &body.custody_token {
+        Some(token) => solana::TradeBatchTxDto {
+            transaction: custody::sign_as_delegate(&state, &delegate, token, tx.transaction)
+                .await?,
+            ..tx
+        },
+        None => tx,
+    };
     Ok(Json(tx))
 }
 
@@ -420,10 +903,42 @@ pub async fn tx_cleanup(
 ) -> Result<Json<solana::TxEnvelope>> {
     let vault = parse_pubkey(&body.vault_pubkey, "vaultPubkey")?;
     let cleaner = parse_pubkey(&body.cleaner_pubkey, "cleanerPubkey")?;
-    let tx = solana::build_cleanup_tx(&state.rpc, &state.config, vault, cleaner).await?;
+    let tx =
+        solana::build_cleanup_tx(&state.rpc, &state.config, vault, cleaner, &body.options).await?;
     Ok(Json(tx))
 }
 
+pub async fn tx_create_nonce(
+    State(state): State<AppState>,
+    Json(body): Json<CreateNonceRequest>,
+) -> Result<Json<solana::NonceTxDto>> {
+    let user = parse_pubkey(&body.user_pubkey, "userPubkey")?;
+    let seed = body.seed.as_deref().unwrap_or(solana::DEFAULT_NONCE_SEED);
+    let tx =
+        solana::build_create_nonce_tx(&state.rpc, &state.config, user, seed, &body.options).await?;
+    Ok(Json(tx))
+}
+
+pub async fn get_nonce_account(
+    State(state): State<AppState>,
+    Path(user_pubkey): Path<String>,
+    Query(query): Query<NonceQuery>,
+) -> Result<Json<solana::NonceAccountDto>> {
+    let user = parse_pubkey(&user_pubkey, "user pubkey")?;
+    let seed = query.seed.as_deref().unwrap_or(solana::DEFAULT_NONCE_SEED);
+    let nonce = solana::fetch_nonce_account(&state.rpc, user, seed).await?;
+    Ok(Json(nonce))
+}
+
+pub async fn get_sponsorship_budget(
+    State(state): State<AppState>,
+    Path(user_pubkey): Path<String>,
+) -> Result<Json<sponsorship::SponsorshipBudgetDto>> {
+    let owner = parse_pubkey(&user_pubkey, "user pubkey")?;
+    let budget = sponsorship::budget(&state, owner).await?;
+    Ok(Json(budget))
+}
+
 pub async fn tx_simulate(
     State(state): State<AppState>,
     Json(body): Json<SimulateTransactionRequest>,
@@ -435,10 +950,27 @@ pub async fn tx_simulate(
     }
 
     let simulation =
-        solana::simulate_transaction_base64(&state.rpc, &body.transaction_base64).await?;
+        solana::simulate_transaction_base64(&state.rpc, &state.config, &body.transaction_base64)
+            .await?;
     Ok(Json(simulation))
 }
 
+pub async fn tx_decode(
+    State(state): State<AppState>,
+    Json(body): Json<DecodeTransactionRequest>,
+) -> Result<Json<decoder::DecodedTxDto>> {
+    if body.transaction_base64.trim().is_empty() {
+        return Err(AppError::Validation(
+            "transactionBase64 must not be empty".into(),
+        ));
+    }
+
+    let decoded =
+        decoder::decode_transaction_base64(&state.rpc, &state.config, &body.transaction_base64)
+            .await?;
+    Ok(Json(decoded))
+}
+
 pub async fn tx_status(
     State(state): State<AppState>,
     Path(signature): Path<String>,
@@ -450,6 +982,193 @@ pub async fn tx_status(
     Ok(Json(status))
 }
 
+pub async fn tx_submit(
+    State(state): State<AppState>,
+    Json(body): Json<SubmitTransactionRequest>,
+) -> Result<Json<SubmittedTransaction>> {
+    if body.transaction_base64.trim().is_empty() {
+        return Err(AppError::Validation(
+            "transactionBase64 must not be empty".into(),
+        ));
+    }
+
+    let submitted = submission::submit(&state, body.transaction_base64.trim()).await?;
+    Ok(Json(submitted))
+}
+
+pub async fn get_transaction(
+    State(state): State<AppState>,
+    Path(signature): Path<String>,
+) -> Result<Json<SubmittedTransaction>> {
+    let signature = signature
+        .parse::<Signature>()
+        .map_err(|e| AppError::Validation(format!("invalid signature: {e}")))?;
+    let transaction = queries::get_submitted_transaction(&state.db, &signature.to_string())
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("no submitted transaction {signature}")))?;
+    Ok(Json(transaction))
+}
+
+pub async fn get_vault_transactions(
+    State(state): State<AppState>,
+    Path(vault_pubkey): Path<String>,
+    Query(query): Query<PaginationQuery>,
+) -> Result<Json<Vec<SubmittedTransaction>>> {
+    let vault = parse_pubkey(&vault_pubkey, "vault pubkey")?;
+    let limit = query.limit.clamp(1, 100);
+    let offset = query.offset.max(0);
+    let transactions =
+        queries::get_vault_transactions(&state.db, &vault.to_string(), limit, offset).await?;
+    Ok(Json(transactions))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeeperStatsResponse {
+    enabled: bool,
+    attempts: i64,
+    confirmed: i64,
+    simulation_failed: i64,
+    failed: i64,
+    total_reward_lamports: i64,
+    total_reward_sol: f64,
+    last_attempt_at: Option<DateTime<Utc>>,
+    last_cleanup_at: Option<DateTime<Utc>>,
+}
+
+pub async fn keeper_stats(State(state): State<AppState>) -> Result<Json<KeeperStatsResponse>> {
+    let stats = queries::get_keeper_stats(&state.db).await?;
+    Ok(Json(KeeperStatsResponse {
+        enabled: state.config.keeper_enabled,
+        attempts: stats.attempts,
+        confirmed: stats.confirmed,
+        simulation_failed: stats.simulation_failed,
+        failed: stats.failed,
+        total_reward_lamports: stats.total_reward_lamports,
+        total_reward_sol: solana::to_sol(stats.total_reward_lamports.max(0) as u64),
+        last_attempt_at: stats.last_attempt_at,
+        last_cleanup_at: stats.last_cleanup_at,
+    }))
+}
+
+pub async fn register_renewal_policy(
+    State(state): State<AppState>,
+    Json(body): Json<RenewalPolicyRequest>,
+) -> Result<Json<RenewalPolicyRecord>> {
+    let policy = renewals::RenewalPolicy {
+        owner: parse_pubkey(&body.owner, "owner")?,
+        webhook_url: body.webhook_url.filter(|url| !url.trim().is_empty()),
+        max_renewals: body.max_renewals,
+        valid_until: body.valid_until,
+        issued_at: body.issued_at,
+    };
+    policy.validate(Utc::now().timestamp())?;
+    let signature = renewals::verify_signature(&policy.owner, &policy.message(), &body.signature)?;
+    if let Some(url) = &policy.webhook_url {
+        renewals::check_webhook_target(url).await?;
+    }
+
+    let record = policy.into_record(&solana::program_id(&state.config)?, signature);
+    let stored = queries::upsert_renewal_policy(&state.db, &record).await?;
+    Ok(Json(stored))
+}
+
+pub async fn get_renewal_policy(
+    State(state): State<AppState>,
+    Path(user_pubkey): Path<String>,
+) -> Result<Json<RenewalPolicyRecord>> {
+    let owner = parse_pubkey(&user_pubkey, "user pubkey")?;
+    let policy = queries::get_renewal_policy(&state.db, &owner.to_string())
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("no renewal policy for {owner}")))?;
+    Ok(Json(policy))
+}
+
+pub async fn revoke_renewal_policy(
+    State(state): State<AppState>,
+    Path(user_pubkey): Path<String>,
+    Json(body): Json<RevokeRenewalPolicyRequest>,
+) -> Result<StatusCode> {
+    let owner = parse_pubkey(&user_pubkey, "user pubkey")?;
+    renewals::check_issued_at(body.issued_at, Utc::now().timestamp())?;
+    renewals::verify_signature(
+        &owner,
+        &renewals::revocation_message(&owner, body.issued_at),
+        &body.signature,
+    )?;
+
+    let revoked = queries::revoke_renewal_policy(
+        &state.db,
+        &owner.to_string(),
+        renewals::timestamp(body.issued_at),
+    )
+    .await?;
+    if !revoked {
+        return Err(AppError::NotFound(format!(
+            "no renewal policy issued before {} for {owner}",
+            body.issued_at
+        )));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn create_custodial_key(
+    State(state): State<AppState>,
+    Path(user_pubkey): Path<String>,
+    Json(body): Json<CustodyActionRequest>,
+) -> Result<(StatusCode, Json<custody::IssuedCustodialKeyDto>)> {
+    let owner = parse_pubkey(&user_pubkey, "user pubkey")?;
+    custody::authorize(
+        custody::CustodyAction::Create,
+        &owner,
+        body.issued_at,
+        &body.signature,
+    )?;
+    let issued = custody::create(&state, &owner, body.issued_at).await?;
+    Ok((StatusCode::CREATED, Json(issued)))
+}
+
+pub async fn list_custodial_keys(
+    State(state): State<AppState>,
+    Path(user_pubkey): Path<String>,
+) -> Result<Json<Vec<custody::CustodialKeyDto>>> {
+    let owner = parse_pubkey(&user_pubkey, "user pubkey")?;
+    let keys = queries::list_custodial_keys(&state.db, &owner.to_string()).await?;
+    Ok(Json(keys.into_iter().map(Into::into).collect()))
+}
+
+pub async fn rotate_custodial_key(
+    State(state): State<AppState>,
+    Path(user_pubkey): Path<String>,
+    Json(body): Json<CustodyActionRequest>,
+) -> Result<Json<custody::IssuedCustodialKeyDto>> {
+    let owner = parse_pubkey(&user_pubkey, "user pubkey")?;
+    custody::authorize(
+        custody::CustodyAction::Rotate,
+        &owner,
+        body.issued_at,
+        &body.signature,
+    )?;
+    let issued = custody::rotate(&state, &owner, body.issued_at).await?;
+    Ok(Json(issued))
+}
+
+pub async fn destroy_custodial_key(
+    State(state): State<AppState>,
+    Path(user_pubkey): Path<String>,
+    Json(body): Json<CustodyActionRequest>,
+) -> Result<Json<custody::CustodialKeyDto>> {
+    let owner = parse_pubkey(&user_pubkey, "user pubkey")?;
+    custody::authorize(
+        custody::CustodyAction::Destroy,
+        &owner,
+        body.issued_at,
+        &body.signature,
+    )?;
+    let destroyed = custody::destroy(&state, &owner, body.issued_at).await?;
+    Ok(Json(destroyed))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -467,6 +1186,21 @@ mod tests {
         assert!(validate_positive_lamports(0, "amount").is_err());
     }
 
+    #[test]
+    fn validates_trade_lamports() {
+        assert!(validate_trade_lamports(5_000, MIN_APPROVED_AMOUNT_LAMPORTS).is_ok());
+        assert!(validate_trade_lamports(0, MIN_APPROVED_AMOUNT_LAMPORTS).is_err());
+        assert!(validate_trade_lamports(5_000, MIN_APPROVED_AMOUNT_LAMPORTS - 1).is_err());
+    }
+
+    #[test]
+    fn validates_client_order_id() {
+        assert!(validate_client_order_id(1, "clientOrderId").is_ok());
+        assert!(validate_client_order_id(0, "clientOrderId").is_err());
+        assert!(validate_client_order_id(i64::MAX as u64, "clientOrderId").is_ok());
+        assert!(validate_client_order_id(i64::MAX as u64 + 1, "clientId").is_err());
+    }
+
     #[test]
     fn validates_custom_duration_bounds() {
         assert!(validate_custom_duration(None).is_ok());
@@ -475,4 +1209,51 @@ mod tests {
         assert!(validate_custom_duration(Some(0)).is_err());
         assert!(validate_custom_duration(Some(MAX_SESSION_DURATION_SECONDS + 1)).is_err());
     }
+
+    #[test]
+    fn resolves_history_range() {
+        let now = Utc::now();
+        let query = |from, to| HistoryQuery {
+            from,
+            to,
+            limit: default_history_limit(),
+        };
+
+        let (from, to) = history_range(&query(None, None), now).unwrap();
+        assert_eq!(to, now);
+        assert_eq!(to - from, Duration::days(DEFAULT_HISTORY_DAYS));
+
+        let start = now - Duration::hours(1);
+        assert_eq!(
+            history_range(&query(Some(start), None), now).unwrap(),
+            (start, now)
+        );
+        assert!(history_range(&query(Some(now), Some(start)), now).is_err());
+    }
+
+    #[test]
+    fn builds_vault_list_filter_from_query() {
+        let delegate = Pubkey::new_unique();
+        let query: ListVaultsQuery = serde_json::from_value(json!({
+            "status": "paused",
+            "hasDelegate": true,
+            "delegate": delegate.to_string(),
+            "cleanupEligible": false,
+        }))
+        .unwrap();
+
+        let filter = vault_list_filter(&query).unwrap();
+        assert_eq!(filter.status, Some(solana::VaultStatusDto::Paused));
+        assert_eq!(filter.delegate, Some(delegate));
+        assert_eq!(filter.has_delegate, Some(true));
+        assert_eq!(filter.cleanup_eligible, Some(false));
+        assert_eq!(query.limit, default_limit());
+
+        let bad_window: ListVaultsQuery =
+            serde_json::from_value(json!({ "expiringWithinSecs": 0 })).unwrap();
+        assert!(vault_list_filter(&bad_window).is_err());
+        let bad_owner: ListVaultsQuery =
+            serde_json::from_value(json!({ "owner": "nope" })).unwrap();
+        assert!(vault_list_filter(&bad_owner).is_err());
+    }
 }
@@ -0,0 +1,901 @@
+//! Background indexer that follows program transactions and stores the
+//! Anchor events they emit.
+//!
+//! Each poll fetches one page of `getSignaturesForAddress` newer than the
+//! stored cursor and decodes every `Program data:` log the vault program
+//! emitted in those transactions. A backlog larger than a page is walked from
+//! the newest transaction back to the cursor, one page per poll, with the
+//! walk's position stored alongside the cursor. Events land in
+//! `vault_events`; trade events also upsert `trades`, replacing rows that
+//! clients reported through `POST /trades`.
+
+use std::time::Duration;
+
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::RpcTransactionConfig,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use sqlx::PgPool;
+
+use crate::db::models::{IndexerCursor, NewTrade, NewVaultEvent};
+use crate::db::queries;
+use crate::error::{AppError, Result};
+use crate::solana::{from_anchor_pubkey, program_id, to_sol};
+use crate::AppState;
+
+/// Row name in `indexer_state`.
+pub const INDEXER_NAME: &str = "vault_events";
+
+/// Largest `getSignaturesForAddress` page the RPC serves.
+const SIGNATURE_PAGE_LIMIT: usize = 1000;
+
+/// Transactions fetched per poll; the rest are picked up by the next poll.
+const MAX_TRANSACTIONS_PER_POLL: usize = 100;
+
+const PROGRAM_DATA_PREFIX: &str = "Program data: ";
+
+/// Returns the decoded `Program data:` payloads emitted by `program_id`
+/// itself, skipping data logged by other programs it invokes or is invoked by.
+pub fn program_data(program_id: &Pubkey, logs: &[String]) -> Vec<Vec<u8>> {
+    let invoke_prefix = format!("Program {program_id} invoke [");
+    let mut stack: Vec<bool> = Vec::new();
+    let mut data = Vec::new();
+
+    for log in logs {
+        if let Some(payload) = log.strip_prefix(PROGRAM_DATA_PREFIX) {
+            if stack.last() == Some(&true) {
+                if let Ok(bytes) = BASE64.decode(payload.trim()) {
+                    data.push(bytes);
+                }
+            }
+        } else if log.starts_with("Program ") && log.contains(" invoke [") {
+            stack.push(log.starts_with(&invoke_prefix));
+        } else if log.starts_with("Program ")
+            && (log.ends_with(" success") || log.contains(" failed: "))
+        {
+            stack.pop();
+        }
+    }
+
+    data
+}
+
+/// Every event emitted by the vault program.
+pub enum VaultEvent {
+    VaultCreated(ephemeralvault::VaultCreated),
+    DelegateApproved(ephemeralvault::DelegateApproved),
+    SessionRenewed(ephemeralvault::SessionRenewed),
+    AutoDeposit(ephemeralvault::AutoDepositEvent),
+    TradeExecuted(ephemeralvault::TradeExecuted),
+    TradesBatchExecuted(ephemeralvault::TradesBatchExecuted),
+    BalanceWithdrawn(ephemeralvault::BalanceWithdrawn),
+    AccessRevoked(ephemeralvault::AccessRevoked),
+    VaultReactivated(ephemeralvault::VaultReactivated),
+    ApprovedAmountUpdated(ephemeralvault::ApprovedAmountUpdated),
+    VaultPaused(ephemeralvault::VaultPaused),
+    VaultUnpaused(ephemeralvault::VaultUnpaused),
+    VaultMigrated(ephemeralvault::VaultMigrated),
+    VaultCleaned(ephemeralvault::VaultCleaned),
+}
+
+fn decode_as<T: Discriminator + AnchorDeserialize>(data: &[u8]) -> Option<T> {
+    let mut body = data.strip_prefix(T::DISCRIMINATOR)?;
+    T::deserialize(&mut body).ok()
+}
+
+impl VaultEvent {
+    /// Decodes an event payload, or `None` for unknown discriminators.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        use ephemeralvault as ev;
+
+        decode_as::<ev::VaultCreated>(data)
+            .map(Self::VaultCreated)
+            .or_else(|| decode_as::<ev::DelegateApproved>(data).map(Self::DelegateApproved))
+            .or_else(|| decode_as::<ev::SessionRenewed>(data).map(Self::SessionRenewed))
+            .or_else(|| decode_as::<ev::AutoDepositEvent>(data).map(Self::AutoDeposit))
+            .or_else(|| decode_as::<ev::TradeExecuted>(data).map(Self::TradeExecuted))
+            .or_else(|| decode_as::<ev::TradesBatchExecuted>(data).map(Self::TradesBatchExecuted))
+            .or_else(|| decode_as::<ev::BalanceWithdrawn>(data).map(Self::BalanceWithdrawn))
+            .or_else(|| decode_as::<ev::AccessRevoked>(data).map(Self::AccessRevoked))
+            .or_else(|| decode_as::<ev::VaultReactivated>(data).map(Self::VaultReactivated))
+            .or_else(|| {
+                decode_as::<ev::ApprovedAmountUpdated>(data).map(Self::ApprovedAmountUpdated)
+            })
+            .or_else(|| decode_as::<ev::VaultPaused>(data).map(Self::VaultPaused))
+            .or_else(|| decode_as::<ev::VaultUnpaused>(data).map(Self::VaultUnpaused))
+            .or_else(|| decode_as::<ev::VaultMigrated>(data).map(Self::VaultMigrated))
+            .or_else(|| decode_as::<ev::VaultCleaned>(data).map(Self::VaultCleaned))
+    }
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Self::VaultCreated(_) => "vault_created",
+            Self::DelegateApproved(_) => "delegate_approved",
+            Self::SessionRenewed(_) => "session_renewed",
+            Self::AutoDeposit(_) => "auto_deposit",
+            Self::TradeExecuted(_) => "trade_executed",
+            Self::TradesBatchExecuted(_) => "trades_batch_executed",
+            Self::BalanceWithdrawn(_) => "balance_withdrawn",
+            Self::AccessRevoked(_) => "access_revoked",
+            Self::VaultReactivated(_) => "vault_reactivated",
+            Self::ApprovedAmountUpdated(_) => "approved_amount_updated",
+            Self::VaultPaused(_) => "vault_paused",
+            Self::VaultUnpaused(_) => "vault_unpaused",
+            Self::VaultMigrated(_) => "vault_migrated",
+            Self::VaultCleaned(_) => "vault_cleaned",
+        }
+    }
+
+    /// Vault address, acting wallet, headline lamport amount, on-chain
+    /// timestamp and the full event as JSON.
+    fn summary(&self) -> (Pubkey, Pubkey, Option<u64>, i64, Value) {
+        let key = |pubkey| from_anchor_pubkey(pubkey);
+        match self {
+            Self::VaultCreated(e) => (
+                key(e.vault_pda),
+                key(e.user),
+                Some(e.approved_amount),
+                e.timestamp,
+                json!({ "approvedAmount": e.approved_amount }),
+            ),
+            Self::DelegateApproved(e) => (
+                key(e.vault_pda),
+                key(e.user),
+                None,
+                e.timestamp,
+                json!({
+                    "delegate": key(e.delegate).to_string(),
+                    "expiresAt": e.expires_at,
+                }),
+            ),
+            Self::SessionRenewed(e) => (
+                key(e.vault_pda),
+                key(e.user),
+                None,
+                e.timestamp,
+                json!({
+                    "delegate": key(e.delegate).to_string(),
+                    "newExpiresAt": e.new_expires_at,
+                }),
+            ),
+            Self::AutoDeposit(e) => (
+                key(e.vault_pda),
+                key(e.user),
+                Some(e.amount),
+                e.timestamp,
+                json!({
+                    "amount": e.amount,
+                    "totalDeposited": e.total_deposited,
+                    "availableAmount": e.available_amount,
+                }),
+            ),
+            Self::TradeExecuted(e) => (
+                key(e.vault_pda),
+                key(e.delegate),
+                Some(e.trade_amount),
+                e.timestamp,
+                json!({
+                    "clientOrderId": e.client_order_id,
+                    "tradeFee": e.trade_fee,
+                    "tradeAmount": e.trade_amount,
+                    "remainingAvailable": e.remaining_available,
+                    "tradeNumber": e.trade_number,
+                }),
+            ),
+            Self::TradesBatchExecuted(e) => (
+                key(e.vault_pda),
+                key(e.delegate),
+                Some(e.total_amount),
+                e.timestamp,
+                json!({
+                    "clientIds": e.client_ids,
+                    "totalFee": e.total_fee,
+                    "totalAmount": e.total_amount,
+                    "remainingAvailable": e.remaining_available,
+                    "firstTradeNumber": e.first_trade_number,
+                    "lastTradeNumber": e.last_trade_number,
+                }),
+            ),
+            Self::BalanceWithdrawn(e) => (
+                key(e.vault_pda),
+                key(e.user),
+                Some(e.amount),
+                e.timestamp,
+                json!({ "amount": e.amount }),
+            ),
+            Self::AccessRevoked(e) => (
+                key(e.vault_pda),
+                key(e.user),
+                Some(e.returned_amount),
+                e.timestamp,
+                json!({
+                    "wasDelegated": e.was_delegated,
+                    "returnedAmount": e.returned_amount,
+                }),
+            ),
+            Self::VaultReactivated(e) => {
+                (key(e.vault_pda), key(e.user), None, e.timestamp, json!({}))
+            }
+            Self::ApprovedAmountUpdated(e) => (
+                key(e.vault_pda),
+                key(e.user),
+                Some(e.new_amount),
+                e.timestamp,
+                json!({
+                    "oldAmount": e.old_amount,
+                    "newAmount": e.new_amount,
+                }),
+            ),
+            Self::VaultPaused(e) => (key(e.vault_pda), key(e.user), None, e.timestamp, json!({})),
+            Self::VaultUnpaused(e) => (key(e.vault_pda), key(e.user), None, e.timestamp, json!({})),
+            Self::VaultMigrated(e) => (
+                key(e.vault_pda),
+                key(e.user),
+                None,
+                e.timestamp,
+                json!({
+                    "fromVersion": e.from_version,
+                    "toVersion": e.to_version,
+                }),
+            ),
+            Self::VaultCleaned(e) => (
+                key(e.vault_pda),
+                key(e.cleaner),
+                Some(e.returned_to_user),
+                e.timestamp,
+                json!({
+                    "userWallet": key(e.user_wallet).to_string(),
+                    "returnedToUser": e.returned_to_user,
+                    "cleanerReward": e.cleaner_reward,
+                }),
+            ),
+        }
+    }
+}
+
+/// An event located by transaction and position within its logs.
+pub struct IndexedEvent {
+    pub signature: String,
+    pub event_index: u32,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub event: VaultEvent,
+}
+
+fn to_i64(value: u64, field: &str) -> Result<i64> {
+    i64::try_from(value).map_err(|_| AppError::Internal(format!("{field} overflows i64")))
+}
+
+fn to_datetime(seconds: i64, field: &str) -> Result<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds, 0)
+        .ok_or_else(|| AppError::Internal(format!("{field} is out of range")))
+}
+
+impl IndexedEvent {
+    pub fn record(&self) -> Result<NewVaultEvent> {
+        let (vault, actor, amount, timestamp, payload) = self.event.summary();
+        Ok(NewVaultEvent {
+            tx_signature: self.signature.clone(),
+            event_index: i32::try_from(self.event_index)
+                .map_err(|_| AppError::Internal("event_index overflows i32".into()))?,
+            slot: to_i64(self.slot, "slot")?,
+            block_time: self
+                .block_time
+                .map(|seconds| to_datetime(seconds, "block_time"))
+                .transpose()?,
+            event_type: self.event.event_type().to_string(),
+            vault_address: vault.to_string(),
+            actor: actor.to_string(),
+            // Amounts past `i64::MAX` only fit the JSON payload.
+            amount_lamports: amount.and_then(|value| i64::try_from(value).ok()),
+            payload,
+            event_timestamp: to_datetime(timestamp, "timestamp")?,
+        })
+    }
+}
+
+/// Builds one `trades` row per transaction that settled fills.
+///
+/// `trades.tx_hash` is unique, so a transaction with several trade events is
+/// stored as one batch row with summed amounts. A single `execute_trade` keeps
+/// its client order id, unless the id is past `i64::MAX` and so cannot be
+/// stored; the program accepts any non-zero id, and the event keeps it.
+pub fn trades(events: &[IndexedEvent]) -> Result<Vec<NewTrade>> {
+    let mut trades: Vec<NewTrade> = Vec::new();
+
+    for indexed in events {
+        let (vault, amount, fee, single, client_order_id) = match &indexed.event {
+            VaultEvent::TradeExecuted(e) => (
+                e.vault_pda,
+                e.trade_amount,
+                e.trade_fee,
+                true,
+                i64::try_from(e.client_order_id).ok(),
+            ),
+            VaultEvent::TradesBatchExecuted(e) => {
+                (e.vault_pda, e.total_amount, e.total_fee, false, None)
+            }
+            _ => continue,
+        };
+        let vault = from_anchor_pubkey(vault).to_string();
+
+        match trades.last_mut() {
+            Some(trade) if trade.tx_hash == indexed.signature => {
+                trade.trade_type = "execute_trades_batch".into();
+                trade.amount_sol += to_sol(amount);
+                trade.fee_sol += to_sol(fee);
+                trade.client_order_id = None;
+            }
+            _ => trades.push(NewTrade {
+                vault_address: vault,
+                tx_hash: indexed.signature.clone(),
+                trade_type: if single {
+                    "execute_trade"
+                } else {
+                    "execute_trades_batch"
+                }
+                .into(),
+                amount_sol: to_sol(amount),
+                fee_sol: to_sol(fee),
+                status: "confirmed".into(),
+                slot: Some(to_i64(indexed.slot, "slot")?),
+                client_order_id,
+            }),
+        }
+    }
+
+    Ok(trades)
+}
+
+/// Events found by one poll and the cursor to store once they are written.
+pub struct IndexedBatch {
+    pub events: Vec<IndexedEvent>,
+    pub cursor: IndexerCursor,
+    /// Whether newer transactions were left for the next poll.
+    pub has_more: bool,
+}
+
+fn parse_signature(signature: Option<&str>) -> Result<Option<Signature>> {
+    signature
+        .map(|signature| {
+            signature
+                .parse::<Signature>()
+                .map_err(|e| AppError::Internal(format!("invalid indexer cursor: {e}")))
+        })
+        .transpose()
+}
+
+/// Fetches up to `max_transactions` program transactions newer than
+/// `cursor` and decodes their events, oldest first within the page. Failed
+/// transactions emit no events.
+///
+/// Without a walk in progress the page is the newest one. If it is full,
+/// older transactions remain, so the cursor records a walk: its head is the
+/// page's newest transaction and the next poll continues before the page's
+/// oldest. A page that is not full reaches `last_signature`, and the walk's
+/// head becomes the new `last_signature`.
+pub async fn fetch_new_events(
+    rpc: &RpcClient,
+    program_id: &Pubkey,
+    cursor: &IndexerCursor,
+    max_transactions: usize,
+) -> Result<IndexedBatch> {
+    let limit = max_transactions.min(SIGNATURE_PAGE_LIMIT);
+    let mut page = rpc
+        .get_signatures_for_address_with_config(
+            program_id,
+            GetConfirmedSignaturesForAddress2Config {
+                before: parse_signature(cursor.backfill_before.as_deref())?,
+                until: parse_signature(cursor.last_signature.as_deref())?,
+                limit: Some(limit),
+                commitment: Some(CommitmentConfig::confirmed()),
+            },
+        )
+        .await
+        .map_err(|e| AppError::SolanaRpc(e.to_string()))?;
+    page.truncate(limit);
+
+    let has_more = page.len() == limit;
+    let mut next = cursor.clone();
+    if next.backfill_head_signature.is_none() {
+        if let Some(newest) = page.first() {
+            next.backfill_head_slot = Some(to_i64(newest.slot, "slot")?);
+            next.backfill_head_signature = Some(newest.signature.clone());
+        }
+    }
+    if has_more {
+        next.backfill_before = page.last().map(|oldest| oldest.signature.clone());
+    } else {
+        if let Some(head) = next.backfill_head_signature.take() {
+            next.last_slot = next.backfill_head_slot.unwrap_or(next.last_slot);
+            next.last_signature = Some(head);
+        }
+        next.backfill_head_slot = None;
+        next.backfill_before = None;
+    }
+    page.reverse();
+
+    let mut events = Vec::new();
+    for status in page {
+        if status.err.is_some() {
+            continue;
+        }
+
+        let signature = status
+            .signature
+            .parse::<Signature>()
+            .map_err(|e| AppError::SolanaRpc(format!("invalid signature: {e}")))?;
+        let tx = rpc
+            .get_transaction_with_config(
+                &signature,
+                RpcTransactionConfig {
+                    encoding: None,
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    max_supported_transaction_version: Some(0),
+                },
+            )
+            .await
+            .map_err(|e| AppError::SolanaRpc(e.to_string()))?;
+
+        let Some(meta) = tx.transaction.meta else {
+            continue;
+        };
+        if meta.err.is_some() {
+            continue;
+        }
+        let logs = Option::<Vec<String>>::from(meta.log_messages).unwrap_or_default();
+
+        for (event_index, data) in program_data(program_id, &logs).iter().enumerate() {
+            let Some(event) = VaultEvent::decode(data) else {
+                continue;
+            };
+            events.push(IndexedEvent {
+                signature: status.signature.clone(),
+                event_index: event_index as u32,
+                slot: tx.slot,
+                block_time: tx.block_time,
+                event,
+            });
+        }
+    }
+
+    Ok(IndexedBatch {
+        events,
+        cursor: next,
+        has_more,
+    })
+}
+
+/// Runs one indexing pass. Returns whether more transactions are waiting.
+pub async fn poll_once(rpc: &RpcClient, db: &PgPool, program_id: &Pubkey) -> Result<bool> {
+    let cursor = queries::get_indexer_cursor(db, INDEXER_NAME).await?;
+    let batch = fetch_new_events(rpc, program_id, &cursor, MAX_TRANSACTIONS_PER_POLL).await?;
+    if batch.cursor == cursor {
+        return Ok(false);
+    }
+
+    let records = batch
+        .events
+        .iter()
+        .map(IndexedEvent::record)
+        .collect::<Result<Vec<_>>>()?;
+    let trades = trades(&batch.events)?;
+    let inserted =
+        queries::record_indexed_batch(db, INDEXER_NAME, &records, &trades, &batch.cursor).await?;
+
+    tracing::debug!(
+        inserted,
+        last_slot = batch.cursor.last_slot,
+        "indexed vault events"
+    );
+    Ok(batch.has_more)
+}
+
+/// Polls forever, every `INDEXER_POLL_INTERVAL_SECS` once caught up.
+pub async fn run(state: AppState) {
+    let program_id = match program_id(&state.config) {
+        Ok(program_id) => program_id,
+        Err(e) => {
+            tracing::error!("indexer disabled: {e}");
+            return;
+        }
+    };
+    let interval = Duration::from_secs(state.config.indexer_poll_interval_secs);
+    tracing::info!("Event indexer started for program {program_id}");
+
+    loop {
+        match poll_once(&state.rpc, &state.db, &program_id).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => tracing::warn!("indexer poll failed: {e}"),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mock_rpc;
+    use anchor_lang::{prelude::Pubkey as AnchorPubkey, Event};
+    use solana_client::rpc_request::RpcRequest;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const OTHER_PROGRAM: &str = "Other1111111111111111111111111111111111111";
+
+    /// Replays `getSignaturesForAddress` and `getTransaction` from fixtures.
+    /// `signatures` are newest first, as the RPC returns them.
+    fn respond(
+        signatures: &[Value],
+        transactions: &HashMap<String, Value>,
+        request: RpcRequest,
+        params: &Value,
+    ) -> Value {
+        match request {
+            RpcRequest::GetSignaturesForAddress => {
+                let config = params[1].as_object().cloned().unwrap_or_default();
+                let bound = |key: &str| config.get(key).and_then(Value::as_str);
+                let limit = config.get("limit").and_then(Value::as_u64).unwrap_or(1000) as usize;
+                let page: Vec<Value> = signatures
+                    .iter()
+                    .skip_while(|entry| {
+                        bound("before").is_some_and(|before| entry["signature"] != before)
+                    })
+                    .skip(usize::from(bound("before").is_some()))
+                    .take_while(|entry| {
+                        bound("until").is_none_or(|until| entry["signature"] != until)
+                    })
+                    .take(limit)
+                    .cloned()
+                    .collect();
+                Value::Array(page)
+            }
+            RpcRequest::GetTransaction => {
+                let signature = params[0].as_str().unwrap_or_default();
+                transactions.get(signature).cloned().unwrap_or(Value::Null)
+            }
+            other => panic!("unexpected request {other}"),
+        }
+    }
+
+    struct Fixture {
+        program_id: Pubkey,
+        signatures: Vec<Value>,
+        transactions: HashMap<String, Value>,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Self {
+                program_id: ephemeralvault::ID.to_bytes().into(),
+                signatures: Vec::new(),
+                transactions: HashMap::new(),
+            }
+        }
+
+        /// Records a transaction; later calls are newer.
+        fn push(&mut self, slot: u64, failed: bool, logs: Vec<String>) -> String {
+            let signature = Signature::from([self.signatures.len() as u8 + 1; 64]).to_string();
+            let err = if failed {
+                json!({ "InstructionError": [0, { "Custom": 6000 }] })
+            } else {
+                Value::Null
+            };
+            self.signatures.insert(
+                0,
+                json!({
+                    "signature": signature,
+                    "slot": slot,
+                    "err": err,
+                    "memo": null,
+                    "blockTime": 1_700_000_000 + slot as i64,
+                    "confirmationStatus": "confirmed",
+                }),
+            );
+            let status = if failed {
+                json!({ "Err": err })
+            } else {
+                json!({ "Ok": null })
+            };
+            self.transactions.insert(
+                signature.clone(),
+                json!({
+                    "slot": slot,
+                    "blockTime": 1_700_000_000 + slot as i64,
+                    "transaction": ["", "base64"],
+                    "meta": {
+                        "err": err,
+                        "status": status,
+                        "fee": 5000,
+                        "preBalances": [],
+                        "postBalances": [],
+                        "logMessages": logs,
+                    },
+                }),
+            );
+            signature
+        }
+
+        fn rpc(&self) -> RpcClient {
+            let signatures = self.signatures.clone();
+            let transactions = self.transactions.clone();
+            mock_rpc(move |request, params| respond(&signatures, &transactions, request, params))
+        }
+
+        /// Logs of a top-level vault instruction emitting `events`.
+        fn logs(&self, events: &[Vec<u8>]) -> Vec<String> {
+            let mut logs = vec![format!("Program {} invoke [1]", self.program_id)];
+            for data in events {
+                logs.push(format!("{PROGRAM_DATA_PREFIX}{}", BASE64.encode(data)));
+            }
+            logs.push(format!("Program {} success", self.program_id));
+            logs
+        }
+    }
+
+    fn pubkey(byte: u8) -> AnchorPubkey {
+        AnchorPubkey::new_from_array([byte; 32])
+    }
+
+    fn trade(client_order_id: u64, trade_amount: u64) -> Vec<u8> {
+        ephemeralvault::TradeExecuted {
+            delegate: pubkey(2),
+            vault_pda: pubkey(3),
+            client_order_id,
+            trade_fee: 5_000_000,
+            trade_amount,
+            remaining_available: 100_000_000,
+            trade_number: 1,
+            timestamp: 1_700_000_000,
+        }
+        .data()
+    }
+
+    fn cleaned() -> Vec<u8> {
+        ephemeralvault::VaultCleaned {
+            cleaner: pubkey(4),
+            user_wallet: pubkey(1),
+            vault_pda: pubkey(3),
+            returned_to_user: 900_000_000,
+            cleaner_reward: 10_000_000,
+            timestamp: 1_700_000_500,
+        }
+        .data()
+    }
+
+    fn indexed(signature: &str, event_index: u32, data: &[u8]) -> IndexedEvent {
+        IndexedEvent {
+            signature: signature.into(),
+            event_index,
+            slot: 42,
+            block_time: Some(1_700_000_042),
+            event: VaultEvent::decode(data).expect("known event"),
+        }
+    }
+
+    #[test]
+    fn program_data_ignores_other_programs() {
+        let program_id: Pubkey = ephemeralvault::ID.to_bytes().into();
+        let ours = BASE64.encode(trade(7, 1));
+        let logs = vec![
+            format!("Program {OTHER_PROGRAM} invoke [1]"),
+            format!("{PROGRAM_DATA_PREFIX}{}", BASE64.encode(b"not ours")),
+            format!("Program {program_id} invoke [2]"),
+            format!("{PROGRAM_DATA_PREFIX}{ours}"),
+            "Program 11111111111111111111111111111111 invoke [3]".into(),
+            format!("{PROGRAM_DATA_PREFIX}{}", BASE64.encode(b"system")),
+            "Program 11111111111111111111111111111111 success".into(),
+            format!("Program {program_id} consumed 5000 of 200000 compute units"),
+            format!("Program {program_id} success"),
+            format!("{PROGRAM_DATA_PREFIX}{}", BASE64.encode(b"outer again")),
+            format!("Program {OTHER_PROGRAM} success"),
+        ];
+
+        assert_eq!(program_data(&program_id, &logs), vec![trade(7, 1)]);
+    }
+
+    #[test]
+    fn decodes_events_into_records() {
+        let record = indexed("sig", 1, &cleaned()).record().unwrap();
+
+        assert_eq!(record.event_type, "vault_cleaned");
+        assert_eq!(record.event_index, 1);
+        assert_eq!(record.slot, 42);
+        assert_eq!(
+            record.vault_address,
+            from_anchor_pubkey(pubkey(3)).to_string()
+        );
+        assert_eq!(record.actor, from_anchor_pubkey(pubkey(4)).to_string());
+        assert_eq!(record.amount_lamports, Some(900_000_000));
+        assert_eq!(record.payload["cleanerReward"], 10_000_000);
+        assert_eq!(record.event_timestamp.timestamp(), 1_700_000_500);
+        assert_eq!(record.block_time.unwrap().timestamp(), 1_700_000_042);
+        assert!(VaultEvent::decode(b"unknown event").is_none());
+    }
+
+    #[test]
+    fn trade_events_become_confirmed_trades() {
+        let batch = ephemeralvault::TradesBatchExecuted {
+            delegate: pubkey(2),
+            vault_pda: pubkey(3),
+            client_ids: vec![1, 2],
+            total_fee: 2_000_000,
+            total_amount: 300_000_000,
+            remaining_available: 0,
+            first_trade_number: 2,
+            last_trade_number: 3,
+            timestamp: 1_700_000_000,
+        }
+        .data();
+        let events = [
+            indexed("single", 0, &trade(7, 250_000_000)),
+            indexed("single", 1, &cleaned()),
+            indexed("batch", 0, &batch),
+            indexed("pair", 0, &trade(8, 100_000_000)),
+            indexed("pair", 1, &trade(9, 100_000_000)),
+        ];
+
+        let trades = trades(&events).unwrap();
+
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].tx_hash, "single");
+        assert_eq!(trades[0].trade_type, "execute_trade");
+        assert_eq!(trades[0].client_order_id, Some(7));
+        assert_eq!(trades[0].amount_sol, 0.25);
+        assert_eq!(trades[0].fee_sol, 0.005);
+        assert_eq!(trades[0].status, "confirmed");
+        assert_eq!(trades[0].slot, Some(42));
+        assert_eq!(trades[1].trade_type, "execute_trades_batch");
+        assert_eq!(trades[1].client_order_id, None);
+        assert_eq!(trades[1].amount_sol, 0.3);
+        assert_eq!(trades[2].trade_type, "execute_trades_batch");
+        assert_eq!(trades[2].client_order_id, None);
+        assert_eq!(trades[2].amount_sol, 0.2);
+        assert_eq!(trades[2].fee_sol, 0.01);
+    }
+
+    #[test]
+    fn indexes_client_order_ids_past_i64_max() {
+        let event = indexed("huge", 0, &trade(u64::MAX, 250_000_000));
+
+        let record = event.record().unwrap();
+        assert_eq!(record.payload["clientOrderId"], u64::MAX);
+
+        let trades = trades(&[event]).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].trade_type, "execute_trade");
+        assert_eq!(trades[0].client_order_id, None);
+        assert_eq!(trades[0].amount_sol, 0.25);
+    }
+
+    #[tokio::test]
+    async fn fetches_new_events_oldest_first_and_resumes_from_cursor() {
+        let mut fixture = Fixture::new();
+        let first = fixture.push(10, false, fixture.logs(&[trade(1, 1_000)]));
+        let failed = fixture.push(11, true, fixture.logs(&[]));
+        let mut other = fixture.logs(&[cleaned(), trade(2, 2_000)]);
+        other.insert(0, format!("Program {OTHER_PROGRAM} invoke [1]"));
+        other.push(format!("Program {OTHER_PROGRAM} success"));
+        let last = fixture.push(12, false, other);
+        let rpc = fixture.rpc();
+
+        let batch = fetch_new_events(&rpc, &fixture.program_id, &IndexerCursor::default(), 100)
+            .await
+            .unwrap();
+
+        let seen: Vec<_> = batch
+            .events
+            .iter()
+            .map(|e| (e.signature.as_str(), e.event_index, e.event.event_type()))
+            .collect();
+        assert_eq!(
+            seen,
+            vec![
+                (first.as_str(), 0, "trade_executed"),
+                (last.as_str(), 0, "vault_cleaned"),
+                (last.as_str(), 1, "trade_executed"),
+            ]
+        );
+        assert!(batch.events.iter().all(|e| e.signature != failed));
+        assert_eq!(batch.events[1].slot, 12);
+        assert_eq!(batch.events[1].block_time, Some(1_700_000_012));
+        assert_eq!(
+            batch.cursor,
+            IndexerCursor {
+                last_slot: 12,
+                last_signature: Some(last.clone()),
+                ..IndexerCursor::default()
+            }
+        );
+        assert!(!batch.has_more);
+
+        let again = fetch_new_events(&rpc, &fixture.program_id, &batch.cursor, 100)
+            .await
+            .unwrap();
+        assert!(again.events.is_empty());
+        assert_eq!(again.cursor, batch.cursor);
+    }
+
+    #[tokio::test]
+    async fn walks_a_backlog_one_page_per_poll() {
+        let mut fixture = Fixture::new();
+        let first = fixture.push(10, false, fixture.logs(&[trade(1, 1_000)]));
+        let failed = fixture.push(11, true, fixture.logs(&[]));
+        let third = fixture.push(12, false, fixture.logs(&[trade(2, 2_000)]));
+        let last = fixture.push(13, false, fixture.logs(&[trade(3, 3_000)]));
+        let pages = Arc::new(AtomicUsize::new(0));
+        let rpc = {
+            let (signatures, transactions) =
+                (fixture.signatures.clone(), fixture.transactions.clone());
+            let pages = Arc::clone(&pages);
+            mock_rpc(move |request, params| {
+                if request == RpcRequest::GetSignaturesForAddress {
+                    pages.fetch_add(1, Ordering::SeqCst);
+                }
+                respond(&signatures, &transactions, request, params)
+            })
+        };
+        let poll = |cursor: IndexerCursor| {
+            let rpc = &rpc;
+            let program_id = fixture.program_id;
+            async move {
+                fetch_new_events(rpc, &program_id, &cursor, 2)
+                    .await
+                    .unwrap()
+            }
+        };
+        let signatures = |batch: &IndexedBatch| -> Vec<String> {
+            batch.events.iter().map(|e| e.signature.clone()).collect()
+        };
+
+        let newest = poll(IndexerCursor::default()).await;
+        assert_eq!(signatures(&newest), vec![third.clone(), last.clone()]);
+        assert!(newest.has_more);
+        assert_eq!(
+            newest.cursor,
+            IndexerCursor {
+                last_slot: 0,
+                last_signature: None,
+                backfill_head_slot: Some(13),
+                backfill_head_signature: Some(last.clone()),
+                backfill_before: Some(third),
+            }
+        );
+
+        let older = poll(newest.cursor).await;
+        assert_eq!(signatures(&older), vec![first.clone()]);
+        assert!(older.events.iter().all(|e| e.signature != failed));
+        assert!(older.has_more);
+        assert_eq!(older.cursor.backfill_before, Some(first));
+
+        let done = poll(older.cursor).await;
+        assert!(done.events.is_empty());
+        assert!(!done.has_more);
+        assert_eq!(
+            done.cursor,
+            IndexerCursor {
+                last_slot: 13,
+                last_signature: Some(last.clone()),
+                ..IndexerCursor::default()
+            }
+        );
+        assert_eq!(pages.load(Ordering::SeqCst), 3);
+
+        let newer = fixture.push(14, false, fixture.logs(&[trade(4, 4_000)]));
+        let rpc = fixture.rpc();
+        let caught_up = fetch_new_events(&rpc, &fixture.program_id, &done.cursor, 2)
+            .await
+            .unwrap();
+        assert_eq!(signatures(&caught_up), vec![newer.clone()]);
+        assert!(!caught_up.has_more);
+        assert_eq!(caught_up.cursor.last_signature, Some(newer));
+        assert_eq!(caught_up.cursor.backfill_head_signature, None);
+    }
+}
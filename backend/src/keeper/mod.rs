@@ -0,0 +1,385 @@
+//! Keeper that closes inactive vaults past the cleanup grace period with
+//! `cleanup_vault`, signing with its own keypair and keeping the reward.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signature},
+    signer::Signer,
+    transaction::Transaction,
+};
+
+use crate::config::Config;
+use crate::db::models::NewKeeperCleanup;
+use crate::db::queries;
+use crate::error::Result;
+use crate::indexer::{self, VaultEvent};
+use crate::program_errors;
+use crate::solana::{self, ProgramVault, VaultListFilter, VaultStatusDto};
+use crate::AppState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CleanupStatus {
+    Confirmed,
+    /// Rejected by simulation; nothing was sent.
+    SimulationFailed,
+    /// Sent but not confirmed, or the RPC failed.
+    Failed,
+}
+
+impl CleanupStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Confirmed => "confirmed",
+            Self::SimulationFailed => "simulation_failed",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CleanupOutcome {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub status: CleanupStatus,
+    pub signature: Option<Signature>,
+    pub reward_lamports: u64,
+    pub error: Option<String>,
+}
+
+impl CleanupOutcome {
+    pub fn record(&self, cleaner: &Pubkey) -> NewKeeperCleanup {
+        NewKeeperCleanup {
+            vault_address: self.vault.to_string(),
+            owner: self.owner.to_string(),
+            cleaner: cleaner.to_string(),
+            status: self.status.as_str().to_string(),
+            tx_signature: self.signature.map(|signature| signature.to_string()),
+            reward_lamports: i64::try_from(self.reward_lamports).unwrap_or(i64::MAX),
+            error: self.error.clone(),
+        }
+    }
+}
+
+/// A vault the program lets anyone close, with the reward it should pay.
+#[derive(Clone, Debug)]
+pub struct CleanupCandidate {
+    pub vault: ProgramVault,
+    pub expected_reward_lamports: u64,
+}
+
+/// The cleanable vaults paying at least `min_reward_lamports`, most
+/// rewarding first, capped at `max`.
+pub fn cleanup_candidates(
+    vaults: Vec<ProgramVault>,
+    now_ts: i64,
+    min_reward_lamports: u64,
+    max: usize,
+) -> Result<Vec<CleanupCandidate>> {
+    let mut candidates = Vec::new();
+    for vault in vaults {
+        let stats = solana::to_vault_stats_dto(&vault.vault, vault.surplus_lamports, now_ts)?;
+        if stats.can_cleanup && stats.expected_cleanup_reward_lamports >= min_reward_lamports {
+            candidates.push(CleanupCandidate {
+                vault,
+                expected_reward_lamports: stats.expected_cleanup_reward_lamports,
+            });
+        }
+    }
+
+    candidates.sort_by(|a, b| {
+        b.expected_reward_lamports
+            .cmp(&a.expected_reward_lamports)
+            .then(a.vault.address.cmp(&b.vault.address))
+    });
+    candidates.truncate(max);
+    Ok(candidates)
+}
+
+/// The `cleaner_reward` of a `VaultCleaned` event in simulation logs.
+fn simulated_reward(program_id: &Pubkey, logs: &[String]) -> Option<u64> {
+    indexer::program_data(program_id, logs)
+        .iter()
+        .find_map(|data| match VaultEvent::decode(data) {
+            Some(VaultEvent::VaultCleaned(event)) => Some(event.cleaner_reward),
+            _ => None,
+        })
+}
+
+/// Simulates, then signs and submits `cleanup_vault` for one vault.
+///
+/// The reward is taken from the simulated `VaultCleaned` event when the RPC
+/// returns logs, and from the vault's stats otherwise.
+pub async fn cleanup_vault(
+    rpc: &RpcClient,
+    program_id: Pubkey,
+    keeper: &Keypair,
+    candidate: &CleanupCandidate,
+) -> CleanupOutcome {
+    let vault = &candidate.vault;
+    let mut outcome = CleanupOutcome {
+        vault: vault.address,
+        owner: vault.vault.user_wallet,
+        status: CleanupStatus::Failed,
+        signature: None,
+        reward_lamports: candidate.expected_reward_lamports,
+        error: None,
+    };
+
+    let blockhash = match solana::latest_blockhash(rpc).await {
+        Ok(blockhash) => blockhash,
+        Err(e) => {
+            outcome.error = Some(e.to_string());
+            return outcome;
+        }
+    };
+    let tx = Transaction::new_signed_with_payer(
+        &[solana::cleanup_instruction(
+            program_id,
+            vault.address,
+            vault.vault.user_wallet,
+            keeper.pubkey(),
+        )],
+        Some(&keeper.pubkey()),
+        &[keeper],
+        blockhash,
+    );
+
+    let simulation = match rpc.simulate_transaction(&tx).await {
+        Ok(response) => response.value,
+        Err(e) => {
+            outcome.error = Some(format!("failed to simulate transaction: {e}"));
+            return outcome;
+        }
+    };
+    let logs = simulation.logs.unwrap_or_default();
+    if let Some(err) = simulation.err {
+        outcome.status = CleanupStatus::SimulationFailed;
+        outcome.error = Some(program_errors::decode(&err, &logs, true).to_string());
+        return outcome;
+    }
+    if let Some(reward) = simulated_reward(&program_id, &logs) {
+        outcome.reward_lamports = reward;
+    }
+
+    outcome.signature = Some(tx.signatures[0]);
+    match rpc.send_and_confirm_transaction(&tx).await {
+        Ok(_) => outcome.status = CleanupStatus::Confirmed,
+        Err(e) => outcome.error = Some(e.to_string()),
+    }
+    outcome
+}
+
+/// Cleans up to `KEEPER_MAX_CLEANUPS_PER_SCAN` eligible vaults, one at a time.
+///
+/// Only zero-copy vaults are scanned: `cleanup_vault` cannot load legacy
+/// accounts until they are migrated.
+pub async fn scan_once(
+    rpc: &RpcClient,
+    config: &Config,
+    keeper: &Keypair,
+    now_ts: i64,
+) -> Result<Vec<CleanupOutcome>> {
+    let program_id = solana::program_id(config)?;
+    let inactive = VaultListFilter {
+        status: Some(VaultStatusDto::Inactive),
+        ..VaultListFilter::default()
+    };
+    let vaults = solana::fetch_program_vaults(rpc, config, inactive.rpc_filters()).await?;
+    let candidates = cleanup_candidates(
+        vaults,
+        now_ts,
+        config.keeper_min_reward_lamports,
+        usize::try_from(config.keeper_max_cleanups_per_scan).unwrap_or(usize::MAX),
+    )?;
+
+    let mut outcomes = Vec::with_capacity(candidates.len());
+    for candidate in &candidates {
+        outcomes.push(cleanup_vault(rpc, program_id, keeper, candidate).await);
+    }
+    Ok(outcomes)
+}
+
+/// Scans for cleanable vaults every `KEEPER_INTERVAL_SECS` and records each
+/// attempt in `keeper_cleanups`.
+pub async fn run(state: AppState) {
+    let Some(path) = state.config.keeper_keypair_path.as_deref() else {
+        tracing::error!("cleanup keeper enabled without KEEPER_KEYPAIR_PATH");
+        return;
+    };
+    let keeper = match read_keypair_file(path) {
+        Ok(keeper) => keeper,
+        Err(e) => {
+            tracing::error!("failed to read keeper keypair {path}: {e}");
+            return;
+        }
+    };
+
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.keeper_interval_secs));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    tracing::info!(
+        "Cleanup keeper {} started, every {}s",
+        keeper.pubkey(),
+        state.config.keeper_interval_secs
+    );
+
+    loop {
+        interval.tick().await;
+        let outcomes =
+            match scan_once(&state.rpc, &state.config, &keeper, Utc::now().timestamp()).await {
+                Ok(outcomes) => outcomes,
+                Err(e) => {
+                    tracing::warn!("keeper scan failed: {e}");
+                    continue;
+                }
+            };
+
+        for outcome in outcomes {
+            match outcome.status {
+                CleanupStatus::Confirmed => tracing::info!(
+                    vault = %outcome.vault,
+                    reward = outcome.reward_lamports,
+                    "cleaned up vault"
+                ),
+                _ => tracing::warn!(
+                    vault = %outcome.vault,
+                    status = outcome.status.as_str(),
+                    "vault cleanup failed: {}",
+                    outcome.error.as_deref().unwrap_or_default()
+                ),
+            }
+            if let Err(e) =
+                queries::insert_keeper_cleanup(&state.db, &outcome.record(&keeper.pubkey())).await
+            {
+                tracing::warn!("failed to record keeper cleanup: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solana::EphemeralVaultAccount;
+    use crate::test_support::{self, program_id, LocalSvm, LAMPORTS_PER_SIGNATURE};
+    use solana_sdk::{account::Account, system_program};
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn vault_state(owner: Pubkey, is_active: bool, last_activity: i64) -> EphemeralVaultAccount {
+        EphemeralVaultAccount {
+            approved_amount: 2_000_000_000,
+            is_active,
+            ..test_support::vault(owner, last_activity)
+        }
+    }
+
+    fn funded_keeper(svm: &LocalSvm) -> Keypair {
+        let keeper = Keypair::new();
+        svm.set_account(
+            keeper.pubkey(),
+            Account::new(1_000_000_000, 0, &system_program::ID),
+        );
+        keeper
+    }
+
+    fn candidate(vault: &EphemeralVaultAccount, surplus: u64) -> ProgramVault {
+        ProgramVault {
+            address: vault.vault_pda,
+            vault: vault.clone(),
+            surplus_lamports: surplus,
+        }
+    }
+
+    #[test]
+    fn candidates_are_cleanable_rewarding_vaults_best_first() {
+        let small = vault_state(Pubkey::new_unique(), false, NOW - 60);
+        let large = vault_state(Pubkey::new_unique(), false, NOW - 60);
+        let dust = vault_state(Pubkey::new_unique(), false, NOW - 60);
+        let active = vault_state(Pubkey::new_unique(), true, NOW - 60);
+        let in_grace = vault_state(Pubkey::new_unique(), false, NOW);
+        let vaults = vec![
+            candidate(&small, 100_000_000),
+            candidate(&dust, 1_000),
+            candidate(&active, 1_000_000_000),
+            candidate(&large, 1_000_000_000),
+            candidate(&in_grace, 1_000_000_000),
+        ];
+
+        let picked = cleanup_candidates(vaults.clone(), NOW, 5_000, 10).unwrap();
+        let addresses: Vec<Pubkey> = picked.iter().map(|c| c.vault.address).collect();
+        assert_eq!(addresses, vec![large.vault_pda, small.vault_pda]);
+        assert_eq!(
+            picked[0].expected_reward_lamports,
+            ephemeralvault::cleanup_reward(1_000_000_000).unwrap()
+        );
+
+        let capped = cleanup_candidates(vaults, NOW, 0, 1).unwrap();
+        assert_eq!(capped.len(), 1);
+        assert_eq!(capped[0].vault.address, large.vault_pda);
+    }
+
+    #[tokio::test]
+    async fn scan_closes_eligible_vaults_and_collects_the_reward() {
+        let svm = LocalSvm::new(NOW);
+        let keeper = funded_keeper(&svm);
+        let surplus = 1_000_000_000;
+        let stale = vault_state(Pubkey::new_unique(), false, NOW - 60);
+        let stale_vault = svm.add_vault(&stale, surplus);
+        let live = vault_state(Pubkey::new_unique(), true, NOW - 60);
+        let live_vault = svm.add_vault(&live, surplus);
+        let vault_lamports = svm.balance(&stale_vault);
+
+        let outcomes = scan_once(&svm.rpc(), &test_support::config(), &keeper, NOW)
+            .await
+            .unwrap();
+
+        let reward = ephemeralvault::cleanup_reward(surplus).unwrap();
+        assert_eq!(outcomes.len(), 1);
+        let outcome = &outcomes[0];
+        assert_eq!(outcome.status, CleanupStatus::Confirmed, "{outcome:?}");
+        assert_eq!(outcome.vault, stale_vault);
+        assert_eq!(outcome.owner, stale.user_wallet);
+        assert_eq!(outcome.reward_lamports, reward);
+        assert!(outcome.signature.is_some());
+
+        assert!(svm.account(&stale_vault).is_none());
+        assert_eq!(svm.balance(&stale.user_wallet), vault_lamports - reward);
+        assert_eq!(
+            svm.balance(&keeper.pubkey()),
+            1_000_000_000 + reward - LAMPORTS_PER_SIGNATURE
+        );
+        assert!(svm.account(&live_vault).is_some());
+
+        let record = outcome.record(&keeper.pubkey());
+        assert_eq!(record.status, "confirmed");
+        assert_eq!(record.reward_lamports, reward as i64);
+        assert_eq!(record.cleaner, keeper.pubkey().to_string());
+    }
+
+    #[tokio::test]
+    async fn rejected_simulation_is_not_submitted() {
+        let svm = LocalSvm::new(NOW);
+        let keeper = funded_keeper(&svm);
+        let stale = vault_state(Pubkey::new_unique(), false, NOW - 60);
+        let vault = svm.add_vault(&stale, 1_000_000_000);
+        // The chain's clock is still inside the grace period.
+        svm.set_unix_timestamp(NOW - 60);
+
+        let target = CleanupCandidate {
+            vault: candidate(&stale, 1_000_000_000),
+            expected_reward_lamports: 1,
+        };
+        let outcome = cleanup_vault(&svm.rpc(), program_id(), &keeper, &target).await;
+
+        assert_eq!(outcome.status, CleanupStatus::SimulationFailed);
+        assert!(outcome.signature.is_none());
+        assert!(outcome.error.unwrap().contains("SessionNotExpired (6010)"));
+        assert!(svm.account(&vault).is_some());
+        assert_eq!(svm.balance(&keeper.pubkey()), 1_000_000_000);
+    }
+}
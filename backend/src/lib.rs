@@ -1,10 +1,24 @@
 pub mod config;
+pub mod custody;
 pub mod db;
+pub mod decoder;
 pub mod error;
 pub mod handlers;
+pub mod indexer;
+pub mod keeper;
+pub mod preflight;
+pub mod program_errors;
+pub mod renewals;
 pub mod routes;
+pub mod rpc;
+pub mod snapshots;
 pub mod solana;
+pub mod sponsorship;
 pub mod state;
+pub mod submission;
+#[cfg(test)]
+mod test_support;
+pub mod websocket;
 
 pub use state::AppState;
 
@@ -1,8 +1,11 @@
 use std::sync::Arc;
 
-use backend::{build_server, config::Config, AppState};
-use solana_client::nonblocking::rpc_client::RpcClient;
-use solana_sdk::commitment_config::CommitmentConfig;
+use anyhow::Context;
+use backend::{
+    build_server, config::Config, custody::LocalKeySigner, rpc::RpcPool, websocket::VaultFeed,
+    AppState,
+};
+use solana_sdk::signature::read_keypair_file;
 use sqlx::PgPool;
 
 #[tokio::main]
@@ -29,19 +32,65 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("Database connected and migrations applied");
 
-    let rpc = Arc::new(RpcClient::new_with_commitment(
-        config.rpc_url.clone(),
-        CommitmentConfig::confirmed(),
-    ));
+    let rpc_pool = Arc::new(RpcPool::from_config(&config));
+    let rpc = Arc::new(rpc_pool.client());
 
-    tracing::info!("Solana RPC configured: {}", config.rpc_url);
+    for endpoint in &config.rpc_endpoints {
+        tracing::info!(
+            "Solana RPC configured: {} (weight {})",
+            backend::rpc::redact_url(&endpoint.url),
+            endpoint.weight
+        );
+    }
     tracing::info!("Program ID configured: {}", config.program_id);
 
+    let fee_payer = match config.fee_payer_keypair_path.as_deref() {
+        Some(path) if config.sponsorship_enabled => Some(Arc::new(
+            read_keypair_file(path)
+                .map_err(|e| anyhow::anyhow!("{e}"))
+                .with_context(|| format!("failed to read fee payer keypair {path}"))?,
+        )),
+        _ => None,
+    };
+
+    let key_signer = match config.custody_master_key_path.as_deref() {
+        Some(path) if config.custody_enabled => Some(Arc::new(
+            LocalKeySigner::from_file(path)
+                .with_context(|| format!("failed to read custody master key {path}"))?,
+        )
+            as Arc<dyn backend::custody::KeySigner>),
+        _ => None,
+    };
+
     let state = AppState {
         config: config.clone(),
         db,
         rpc,
+        rpc_pool,
+        vault_feed: Arc::new(VaultFeed::default()),
+        fee_payer,
+        key_signer,
     };
 
+    tokio::spawn(backend::websocket::run(state.clone()));
+    tokio::spawn(backend::submission::run(state.clone()));
+    tokio::spawn(backend::rpc::run(state.clone()));
+
+    if config.snapshot_enabled {
+        tokio::spawn(backend::snapshots::run(state.clone()));
+    }
+
+    if config.indexer_enabled {
+        tokio::spawn(backend::indexer::run(state.clone()));
+    }
+
+    if config.renewal_enabled {
+        tokio::spawn(backend::renewals::run(state.clone()));
+    }
+
+    if config.keeper_enabled {
+        tokio::spawn(backend::keeper::run(state.clone()));
+    }
+
     build_server(state, &config.server_host, config.server_port).await
 }
@@ -0,0 +1,842 @@
+//! Pre-flight vault checks for the transaction builders.
+//!
+//! Before building, the vault is read together with the cluster clock and put
+//! through the checks its instruction runs on-chain, so a request that would
+//! fail gets a precise error instead of a transaction the user pays to see
+//! rejected. Requests set `skipStateChecks` to build regardless.
+
+use anchor_lang::Discriminator;
+use ephemeralvault::{
+    EphemeralVault, EphemeralVaultError, SessionStatus, TradeEntry, SESSION_DURATION,
+};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{account::Account, clock::Clock, pubkey::Pubkey, sysvar};
+
+use crate::error::{AppError, Result};
+use crate::program_errors;
+use crate::solana::{self, TxOptions, VaultOperation};
+
+/// A vault as its next instruction will see it.
+#[derive(Clone, Copy)]
+pub struct VaultState {
+    pub address: Pubkey,
+    pub vault: EphemeralVault,
+    /// Whether the account still uses the `version: 1` Borsh layout.
+    pub legacy: bool,
+    /// Lamports above the rent-exempt minimum, all a withdrawal can move.
+    pub surplus_lamports: u64,
+    /// The cluster clock's unix timestamp.
+    pub now: i64,
+}
+
+fn rejected(error: EphemeralVaultError) -> AppError {
+    match error {
+        EphemeralVaultError::SessionExpired => AppError::SessionExpired,
+        EphemeralVaultError::TradeLimitExceeded => AppError::ExceedsApprovedLimit,
+        error => AppError::VaultCheck(error),
+    }
+}
+
+fn require(condition: bool, error: EphemeralVaultError) -> Result<()> {
+    if condition {
+        Ok(())
+    } else {
+        Err(rejected(error))
+    }
+}
+
+impl VaultState {
+    fn parse(address: Pubkey, account: &Account, now: i64) -> Result<Self> {
+        let data = &account.data;
+        let vault = if data.len() == ephemeralvault::VAULT_SPACE
+            && data[..8] == *EphemeralVault::DISCRIMINATOR
+        {
+            // Read directly: the client order window is not part of
+            // `EphemeralVaultAccount`.
+            bytemuck::pod_read_unaligned::<EphemeralVault>(&data[8..])
+        } else {
+            EphemeralVault::from(&solana::parse_vault_account(data)?)
+        };
+        Ok(Self {
+            address,
+            vault,
+            legacy: data.len() == ephemeralvault::LEGACY_VAULT_SPACE,
+            surplus_lamports: solana::vault_rent_surplus(account),
+            now,
+        })
+    }
+
+    /// Every instruction but `migrate_vault` needs the zero-copy layout.
+    pub fn require_migrated(&self) -> Result<()> {
+        if self.legacy {
+            return Err(AppError::Validation(format!(
+                "vault {} uses the legacy layout; migrate it first",
+                self.address
+            )));
+        }
+        Ok(())
+    }
+
+    fn require_live(&self) -> Result<()> {
+        self.require_migrated()?;
+        require(self.vault.active(), EphemeralVaultError::VaultInactive)?;
+        require(!self.vault.paused(), EphemeralVaultError::VaultPaused)
+    }
+
+    /// `auto_deposit_for_trade`
+    pub fn deposit(&self, amount: u64) -> Result<()> {
+        self.require_live()?;
+        let available = self
+            .vault
+            .available_amount
+            .checked_add(amount)
+            .ok_or(rejected(EphemeralVaultError::MathOverflow))?;
+        require(
+            available <= self.vault.approved_amount,
+            EphemeralVaultError::OverDeposit,
+        )
+    }
+
+    /// `withdraw_balance`; an `amount` of zero withdraws whatever is available.
+    pub fn withdraw(&self, amount: u64) -> Result<()> {
+        self.require_migrated()?;
+        require(
+            amount == 0
+                || (amount <= self.vault.available_amount && amount <= self.surplus_lamports),
+            EphemeralVaultError::InsufficientFunds,
+        )
+    }
+
+    /// `approve_delegate`
+    pub fn approve_delegate(&self, user: Pubkey, delegate: Pubkey) -> Result<()> {
+        self.require_live()?;
+        require(
+            delegate != user && delegate != Pubkey::default(),
+            EphemeralVaultError::InvalidDelegate,
+        )
+    }
+
+    /// `renew_session`, which only succeeds inside the renewal window.
+    pub fn renew(&self) -> Result<()> {
+        self.require_live()?;
+        if self.vault.delegate().is_none() {
+            return Err(rejected(EphemeralVaultError::NoActiveSession));
+        }
+        match self.vault.session_status(self.now) {
+            SessionStatus::ExpiringSoon => Ok(()),
+            SessionStatus::Active => Err(rejected(EphemeralVaultError::SessionNotExpiringSoon)),
+            SessionStatus::Expired => Err(rejected(EphemeralVaultError::SessionExpired)),
+            SessionStatus::NoSession => Err(rejected(EphemeralVaultError::NoActiveSession)),
+        }
+    }
+
+    /// `reactivate_vault`
+    pub fn reactivate(&self) -> Result<()> {
+        self.require_migrated()?;
+        require(
+            !self.vault.active(),
+            EphemeralVaultError::VaultAlreadyActive,
+        )
+    }
+
+    /// `migrate_vault`
+    pub fn migrate(&self) -> Result<()> {
+        require(self.legacy, EphemeralVaultError::VaultAlreadyMigrated)
+    }
+
+    /// `update_approved_amount`
+    pub fn update_approved_amount(&self, new_approved_amount: u64) -> Result<()> {
+        self.require_migrated()?;
+        require(
+            new_approved_amount >= self.vault.available_amount
+                && new_approved_amount >= self.vault.used_amount,
+            EphemeralVaultError::ApprovedAmountTooLow,
+        )
+    }
+
+    /// `execute_trade` and `execute_trades_batch`, through the program's own
+    /// `check_trades`.
+    pub fn trades(&self, delegate: Pubkey, entries: &[TradeEntry]) -> Result<()> {
+        self.require_migrated()?;
+        let Err(err) =
+            self.vault
+                .check_trades(solana::to_anchor_pubkey(delegate), self.now, entries)
+        else {
+            return Ok(());
+        };
+        let code = match &err {
+            anchor_lang::error::Error::AnchorError(err) => err.error_code_number,
+            anchor_lang::error::Error::ProgramError(_) => {
+                return Err(AppError::Internal(format!("trade check failed: {err}")))
+            }
+        };
+        match program_errors::program_error(code) {
+            Some(EphemeralVaultError::Unauthorized) => Err(AppError::UnauthorizedDelegate),
+            Some(error) => Err(rejected(error)),
+            None => Err(AppError::Internal(format!("trade check failed: {err}"))),
+        }
+    }
+
+    /// `cleanup_vault`
+    pub fn cleanup(&self) -> Result<()> {
+        self.require_migrated()?;
+        require(!self.vault.active(), EphemeralVaultError::VaultStillActive)?;
+        let allowed = self
+            .vault
+            .cleanup_allowed(self.now)
+            .map_err(|e| AppError::Internal(format!("cleanup check failed: {e}")))?;
+        require(allowed, EphemeralVaultError::SessionNotExpired)
+    }
+}
+
+/// Projection of what each composed operation leaves behind, so later
+/// operations are checked against the state they will actually meet.
+impl VaultState {
+    /// The vault `create_ephemeral_vault` initializes.
+    fn created(address: Pubkey, user: Pubkey, approved_amount: u64, now: i64) -> Self {
+        let mut vault: EphemeralVault = bytemuck::Zeroable::zeroed();
+        vault.user_wallet = solana::to_anchor_pubkey(user);
+        vault.vault_pda = solana::to_anchor_pubkey(address);
+        vault.created_at = now;
+        vault.last_activity = now;
+        vault.approved_amount = approved_amount;
+        vault.is_active = 1;
+        vault.version = 2;
+        Self {
+            address,
+            vault,
+            legacy: false,
+            surplus_lamports: 0,
+            now,
+        }
+    }
+
+    fn check_operation(&self, user: Pubkey, operation: &VaultOperation) -> Result<()> {
+        match *operation {
+            VaultOperation::CreateVault { .. } => Err(AppError::Conflict(format!(
+                "vault {} already exists",
+                self.address
+            ))),
+            VaultOperation::Deposit { amount } => self.deposit(amount),
+            VaultOperation::Withdraw { amount } => self.withdraw(amount),
+            VaultOperation::Pause | VaultOperation::Unpause | VaultOperation::Revoke => {
+                self.require_migrated()
+            }
+            VaultOperation::RenewSession => self.renew(),
+            VaultOperation::ApproveDelegate { delegate, .. } => {
+                self.approve_delegate(user, delegate)
+            }
+            VaultOperation::Reactivate => self.reactivate(),
+            VaultOperation::UpdateApprovedAmount {
+                new_approved_amount,
+            } => self.update_approved_amount(new_approved_amount),
+            VaultOperation::MigrateVault => self.migrate(),
+        }
+    }
+
+    fn clear_delegate(&mut self) {
+        self.vault.delegate_wallet = Default::default();
+        self.vault.delegated_at = 0;
+        self.vault.session_expires_at = 0;
+    }
+
+    /// Applies a checked `operation` as the program would.
+    fn apply(&mut self, operation: &VaultOperation) {
+        let vault = &mut self.vault;
+        match *operation {
+            VaultOperation::CreateVault { .. } => {}
+            VaultOperation::Deposit { amount } => {
+                vault.available_amount += amount;
+                vault.total_deposited += amount;
+                self.surplus_lamports += amount;
+            }
+            VaultOperation::Withdraw { amount } => {
+                let amount = match amount {
+                    0 => vault.available_amount.min(self.surplus_lamports),
+                    amount => amount,
+                };
+                vault.available_amount -= amount;
+                vault.total_withdrawn += amount;
+                self.surplus_lamports -= amount;
+            }
+            VaultOperation::Pause => vault.is_paused = 1,
+            VaultOperation::Unpause => vault.is_paused = 0,
+            VaultOperation::Revoke => {
+                let withdrawn = vault.available_amount.min(self.surplus_lamports);
+                vault.available_amount -= withdrawn;
+                vault.total_withdrawn += withdrawn;
+                vault.is_active = 0;
+                self.surplus_lamports = 0;
+                self.clear_delegate();
+            }
+            VaultOperation::RenewSession => {
+                vault.session_expires_at = self.now + SESSION_DURATION;
+            }
+            VaultOperation::ApproveDelegate {
+                delegate,
+                custom_duration,
+                ..
+            } => {
+                let duration = custom_duration
+                    .unwrap_or(SESSION_DURATION)
+                    .min(SESSION_DURATION);
+                vault.delegate_wallet = solana::to_anchor_pubkey(delegate);
+                vault.delegated_at = self.now;
+                vault.session_expires_at = self.now + duration;
+            }
+            VaultOperation::Reactivate => {
+                vault.is_active = 1;
+                vault.is_paused = 0;
+                self.clear_delegate();
+            }
+            VaultOperation::UpdateApprovedAmount {
+                new_approved_amount,
+            } => vault.approved_amount = new_approved_amount,
+            VaultOperation::MigrateVault => {
+                vault.version = 2;
+                self.legacy = false;
+            }
+        }
+    }
+}
+
+/// Reads `address` and the cluster clock in one request.
+async fn fetch(rpc: &RpcClient, address: Pubkey) -> Result<(Option<Account>, i64)> {
+    let mut accounts = rpc
+        .get_multiple_accounts(&[address, sysvar::clock::ID])
+        .await
+        .map_err(|e| AppError::SolanaRpc(format!("failed to fetch vault {address}: {e}")))?;
+    let clock = accounts
+        .pop()
+        .flatten()
+        .ok_or_else(|| AppError::SolanaRpc("clock sysvar is unavailable".into()))?;
+    let clock: Clock = bincode::deserialize(&clock.data)
+        .map_err(|e| AppError::SolanaRpc(format!("failed to decode clock sysvar: {e}")))?;
+    Ok((accounts.pop().flatten(), clock.unix_timestamp))
+}
+
+/// Reads the vault at `address` as of the cluster clock.
+pub(crate) async fn load(rpc: &RpcClient, address: Pubkey) -> Result<VaultState> {
+    let (account, now) = fetch(rpc, address).await?;
+    let account = account.ok_or_else(|| AppError::VaultNotFound(address.to_string()))?;
+    VaultState::parse(address, &account, now)
+}
+
+/// Runs `check` against the vault at `address` unless `options` opt out.
+pub(crate) async fn check(
+    rpc: &RpcClient,
+    options: &TxOptions,
+    address: Pubkey,
+    check: impl FnOnce(&VaultState) -> Result<()>,
+) -> Result<()> {
+    if options.skip_state_checks {
+        return Ok(());
+    }
+    check(&load(rpc, address).await?)
+}
+
+/// Walks `operations` in order from the vault's current state, failing at
+/// the first operation the program would reject.
+pub(crate) async fn check_sequence(
+    rpc: &RpcClient,
+    options: &TxOptions,
+    address: Pubkey,
+    user: Pubkey,
+    operations: &[VaultOperation],
+) -> Result<()> {
+    if options.skip_state_checks {
+        return Ok(());
+    }
+    let (account, now) = fetch(rpc, address).await?;
+    let mut state = account
+        .map(|account| VaultState::parse(address, &account, now))
+        .transpose()?;
+
+    for (index, operation) in operations.iter().enumerate() {
+        let checked = match (&state, operation) {
+            (None, VaultOperation::CreateVault { approved_amount }) => {
+                state = Some(VaultState::created(address, user, *approved_amount, now));
+                continue;
+            }
+            (None, _) => Err(AppError::VaultNotFound(address.to_string())),
+            (Some(vault), operation) => vault.check_operation(user, operation),
+        };
+        checked.map_err(|source| AppError::Operation {
+            index,
+            source: Box::new(source),
+        })?;
+        if let Some(vault) = &mut state {
+            vault.apply(operation);
+        }
+    }
+    Ok(())
+}
+
+/// Fails when a vault already exists at `address`, as `create_ephemeral_vault`
+/// would.
+pub(crate) async fn check_absent(
+    rpc: &RpcClient,
+    options: &TxOptions,
+    address: Pubkey,
+) -> Result<()> {
+    if options.skip_state_checks {
+        return Ok(());
+    }
+    match fetch(rpc, address).await? {
+        (Some(_), _) => Err(AppError::Conflict(format!(
+            "vault {address} already exists"
+        ))),
+        (None, _) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solana::{
+        build_approve_delegate_tx, build_cleanup_tx, build_create_vault_tx, build_deposit_tx,
+        build_execute_trade_tx, build_execute_trades_batch_tx, build_migrate_vault_tx,
+        build_pause_tx, build_reactivate_tx, build_renew_session_tx,
+        build_update_approved_amount_tx, build_withdraw_tx, derive_vault_pda,
+        EphemeralVaultAccount,
+    };
+    use crate::test_support::{self, LocalSvm};
+
+    const NOW: i64 = 1_700_000_000;
+
+    struct Fixture {
+        svm: LocalSvm,
+        owner: Pubkey,
+        delegate: Pubkey,
+        vault: EphemeralVaultAccount,
+    }
+
+    /// A live session with 1_000 seconds left, outside the renewal window.
+    fn fixture() -> Fixture {
+        let svm = LocalSvm::new(NOW);
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let vault = EphemeralVaultAccount {
+            approved_amount: 2_000_000,
+            used_amount: 250_000,
+            available_amount: 500_000,
+            delegate_wallet: Some(delegate),
+            delegated_at: Some(NOW - 100),
+            session_expires_at: Some(NOW + 1_000),
+            total_deposited: 500_000,
+            trade_count: 1,
+            ..test_support::vault(owner, NOW - 100)
+        };
+        svm.add_vault(&vault, 400_000);
+        Fixture {
+            svm,
+            owner,
+            delegate,
+            vault,
+        }
+    }
+
+    fn is_check(result: Result<impl Sized>, expected: EphemeralVaultError) -> bool {
+        matches!(result, Err(AppError::VaultCheck(error)) if u32::from(error) == u32::from(expected))
+    }
+
+    #[tokio::test]
+    async fn rejects_owner_requests_the_program_would_fail() {
+        let Fixture {
+            svm, owner, vault, ..
+        } = fixture();
+        let rpc = svm.rpc();
+        let config = test_support::config();
+        let options = TxOptions::default();
+
+        assert!(is_check(
+            build_renew_session_tx(&rpc, &config, owner, &options).await,
+            EphemeralVaultError::SessionNotExpiringSoon
+        ));
+        assert!(is_check(
+            build_deposit_tx(&rpc, &config, owner, 1_600_000, &options).await,
+            EphemeralVaultError::OverDeposit
+        ));
+        assert!(build_deposit_tx(&rpc, &config, owner, 1_500_000, &options)
+            .await
+            .is_ok());
+        assert!(is_check(
+            build_withdraw_tx(&rpc, &config, owner, 450_000, &options).await,
+            EphemeralVaultError::InsufficientFunds
+        ));
+        assert!(build_withdraw_tx(&rpc, &config, owner, 0, &options)
+            .await
+            .is_ok());
+        assert!(is_check(
+            build_update_approved_amount_tx(&rpc, &config, owner, 400_000, &options).await,
+            EphemeralVaultError::ApprovedAmountTooLow
+        ));
+        for delegate in [owner, Pubkey::default()] {
+            assert!(is_check(
+                build_approve_delegate_tx(&rpc, &config, owner, delegate, None, 0, &options).await,
+                EphemeralVaultError::InvalidDelegate
+            ));
+        }
+        assert!(is_check(
+            build_reactivate_tx(&rpc, &config, owner, &options).await,
+            EphemeralVaultError::VaultAlreadyActive
+        ));
+        assert!(is_check(
+            build_migrate_vault_tx(&rpc, &config, owner, &options).await,
+            EphemeralVaultError::VaultAlreadyMigrated
+        ));
+        assert!(matches!(
+            build_create_vault_tx(&rpc, &config, owner, 2_000_000, None, None, None, &options)
+                .await,
+            Err(AppError::Conflict(_))
+        ));
+
+        svm.set_unix_timestamp(NOW + 800);
+        assert!(build_renew_session_tx(&rpc, &config, owner, &options)
+            .await
+            .is_ok());
+        svm.set_unix_timestamp(NOW + 1_000);
+        assert!(matches!(
+            build_renew_session_tx(&rpc, &config, owner, &options).await,
+            Err(AppError::SessionExpired)
+        ));
+
+        svm.add_vault(
+            &EphemeralVaultAccount {
+                is_paused: true,
+                ..vault
+            },
+            400_000,
+        );
+        assert!(is_check(
+            build_deposit_tx(&rpc, &config, owner, 1_000_000, &options).await,
+            EphemeralVaultError::VaultPaused
+        ));
+        assert!(build_pause_tx(&rpc, &config, owner, &options).await.is_ok());
+
+        let stranger = Pubkey::new_unique();
+        assert!(matches!(
+            build_pause_tx(&rpc, &config, stranger, &options).await,
+            Err(AppError::VaultNotFound(_))
+        ));
+        assert!(build_create_vault_tx(
+            &rpc, &config, stranger, 2_000_000, None, None, None, &options
+        )
+        .await
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_trades_the_program_would_fail() {
+        let Fixture {
+            svm,
+            delegate,
+            vault,
+            ..
+        } = fixture();
+        let rpc = svm.rpc();
+        let config = test_support::config();
+        let trade = |delegate: Pubkey, fee: u64, amount: u64, options: TxOptions| {
+            let rpc = &rpc;
+            let config = &config;
+            async move {
+                build_execute_trade_tx(
+                    rpc,
+                    config,
+                    vault.vault_pda,
+                    delegate,
+                    fee,
+                    amount,
+                    7,
+                    &options,
+                )
+                .await
+            }
+        };
+
+        assert!(trade(delegate, 5_000, 1_000_000, TxOptions::default())
+            .await
+            .is_ok());
+        assert!(matches!(
+            trade(Pubkey::new_unique(), 5_000, 1_000_000, TxOptions::default()).await,
+            Err(AppError::UnauthorizedDelegate)
+        ));
+        assert!(matches!(
+            trade(delegate, 5_000, 1_800_000, TxOptions::default()).await,
+            Err(AppError::ExceedsApprovedLimit)
+        ));
+        assert!(is_check(
+            trade(delegate, 600_000, 1_000, TxOptions::default()).await,
+            EphemeralVaultError::InsufficientFunds
+        ));
+
+        svm.set_unix_timestamp(NOW + 1_000);
+        assert!(matches!(
+            trade(delegate, 5_000, 1_000_000, TxOptions::default()).await,
+            Err(AppError::SessionExpired)
+        ));
+        let unchecked = TxOptions {
+            skip_state_checks: true,
+            ..TxOptions::default()
+        };
+        assert!(trade(delegate, 5_000, 1_000_000, unchecked).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn cleanup_reads_the_owner_from_the_checked_vault() {
+        let Fixture { svm, owner, .. } = fixture();
+        let config = test_support::config();
+        let missing = Pubkey::new_unique();
+        let failing = test_support::mock_rpc(|_, _| serde_json::Value::Null);
+        let (vault_pda, _) = derive_vault_pda(&solana::program_id(&config).unwrap(), &owner);
+
+        for skip_state_checks in [false, true] {
+            let options = TxOptions {
+                skip_state_checks,
+                ..TxOptions::default()
+            };
+            assert!(matches!(
+                build_cleanup_tx(&svm.rpc(), &config, missing, owner, &options).await,
+                Err(AppError::VaultNotFound(_))
+            ));
+            assert!(matches!(
+                build_cleanup_tx(&failing, &config, vault_pda, owner, &options).await,
+                Err(AppError::SolanaRpc(_))
+            ));
+        }
+        assert!(is_check(
+            build_cleanup_tx(&svm.rpc(), &config, vault_pda, owner, &TxOptions::default()).await,
+            EphemeralVaultError::VaultStillActive
+        ));
+    }
+
+    #[tokio::test]
+    async fn checks_the_packed_part_of_trade_batches() {
+        let Fixture {
+            svm,
+            delegate,
+            vault,
+            ..
+        } = fixture();
+        let rpc = svm.rpc();
+        let entries = (2..=ephemeralvault::MAX_BATCH_TRADES as u64 + 9)
+            .map(|client_id| TradeEntry {
+                trade_fee: 1_000,
+                trade_amount: 1_000,
+                client_id,
+            })
+            .collect::<Vec<_>>();
+
+        let batch = build_execute_trades_batch_tx(
+            &rpc,
+            &test_support::config(),
+            vault.vault_pda,
+            delegate,
+            &entries,
+            &TxOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert!(
+            batch.included_entries > 0
+                && batch.included_entries <= ephemeralvault::MAX_BATCH_TRADES
+        );
+        assert_eq!(
+            batch.included_entries + batch.remaining_entries,
+            entries.len()
+        );
+
+        let account = svm.account(&vault.vault_pda).unwrap();
+        let state = VaultState::parse(vault.vault_pda, &account, NOW).unwrap();
+        assert!(is_check(
+            state.trades(delegate, &[]),
+            EphemeralVaultError::EmptyTradeBatch
+        ));
+        assert!(is_check(
+            state.trades(delegate, &entries),
+            EphemeralVaultError::TradeBatchTooLarge
+        ));
+    }
+
+    /// The index and error of a failed `check_sequence`.
+    fn failed_at(result: Result<()>) -> (usize, AppError) {
+        match result {
+            Err(AppError::Operation { index, source }) => (index, *source),
+            other => panic!("expected an operation error, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn checks_composed_operations_against_projected_state() {
+        let Fixture {
+            svm,
+            owner,
+            delegate,
+            vault,
+        } = fixture();
+        let rpc = svm.rpc();
+        let options = TxOptions::default();
+        let address = vault.vault_pda;
+        svm.add_vault(
+            &EphemeralVaultAccount {
+                is_paused: true,
+                ..vault
+            },
+            400_000,
+        );
+        let sequence = |operations: Vec<VaultOperation>| {
+            let rpc = &rpc;
+            let options = &options;
+            async move { check_sequence(rpc, options, address, owner, &operations).await }
+        };
+
+        let (index, error) = failed_at(
+            sequence(vec![
+                VaultOperation::Deposit { amount: 1_000_000 },
+                VaultOperation::Unpause,
+            ])
+            .await,
+        );
+        assert_eq!(index, 0);
+        assert!(is_check(
+            Err::<(), _>(error),
+            EphemeralVaultError::VaultPaused
+        ));
+
+        sequence(vec![
+            VaultOperation::Unpause,
+            VaultOperation::UpdateApprovedAmount {
+                new_approved_amount: 5_000_000,
+            },
+            VaultOperation::Deposit { amount: 4_000_000 },
+            VaultOperation::Withdraw { amount: 4_200_000 },
+        ])
+        .await
+        .unwrap();
+
+        let (index, error) = failed_at(
+            sequence(vec![
+                VaultOperation::Unpause,
+                VaultOperation::Deposit { amount: 1_000_000 },
+                VaultOperation::Withdraw { amount: 1_500_000 },
+            ])
+            .await,
+        );
+        assert_eq!(index, 2);
+        assert!(is_check(
+            Err::<(), _>(error),
+            EphemeralVaultError::InsufficientFunds
+        ));
+
+        // Revoking deactivates the vault until it is reactivated, which also
+        // clears the delegate.
+        let (index, error) = failed_at(
+            sequence(vec![
+                VaultOperation::Revoke,
+                VaultOperation::ApproveDelegate {
+                    delegate,
+                    custom_duration: None,
+                    funding_lamports: 0,
+                },
+            ])
+            .await,
+        );
+        assert_eq!(index, 1);
+        assert!(is_check(
+            Err::<(), _>(error),
+            EphemeralVaultError::VaultInactive
+        ));
+        let (index, error) = failed_at(
+            sequence(vec![
+                VaultOperation::Revoke,
+                VaultOperation::Reactivate,
+                VaultOperation::RenewSession,
+            ])
+            .await,
+        );
+        assert_eq!(index, 2);
+        assert!(is_check(
+            Err::<(), _>(error),
+            EphemeralVaultError::NoActiveSession
+        ));
+        sequence(vec![
+            VaultOperation::Revoke,
+            VaultOperation::Reactivate,
+            VaultOperation::ApproveDelegate {
+                delegate,
+                custom_duration: Some(200),
+                funding_lamports: 0,
+            },
+            VaultOperation::RenewSession,
+        ])
+        .await
+        .unwrap();
+
+        let (index, error) = failed_at(
+            sequence(vec![VaultOperation::CreateVault {
+                approved_amount: 2_000_000,
+            }])
+            .await,
+        );
+        assert_eq!(index, 0);
+        assert!(matches!(error, AppError::Conflict(_)));
+    }
+
+    #[tokio::test]
+    async fn composed_sequences_may_start_by_creating_the_vault() {
+        let svm = LocalSvm::new(NOW);
+        let rpc = svm.rpc();
+        let owner = Pubkey::new_unique();
+        let program_id = solana::program_id(&test_support::config()).unwrap();
+        let (address, _) = derive_vault_pda(&program_id, &owner);
+        let options = TxOptions::default();
+
+        let (index, error) = failed_at(
+            check_sequence(
+                &rpc,
+                &options,
+                address,
+                owner,
+                &[VaultOperation::Deposit { amount: 1_000_000 }],
+            )
+            .await,
+        );
+        assert_eq!(index, 0);
+        assert!(matches!(error, AppError::VaultNotFound(_)));
+
+        let created = [
+            VaultOperation::CreateVault {
+                approved_amount: 2_000_000,
+            },
+            VaultOperation::ApproveDelegate {
+                delegate: Pubkey::new_unique(),
+                custom_duration: None,
+                funding_lamports: 0,
+            },
+            VaultOperation::Deposit { amount: 1_500_000 },
+        ];
+        check_sequence(&rpc, &options, address, owner, &created)
+            .await
+            .unwrap();
+
+        let (index, error) = failed_at(
+            check_sequence(
+                &rpc,
+                &options,
+                address,
+                owner,
+                &[
+                    created[0],
+                    VaultOperation::Deposit { amount: 1_500_000 },
+                    VaultOperation::Deposit { amount: 1_000_000 },
+                ],
+            )
+            .await,
+        );
+        assert_eq!(index, 2);
+        assert!(is_check(
+            Err::<(), _>(error),
+            EphemeralVaultError::OverDeposit
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+//! Structured transaction errors.
+//!
+//! Turns a `TransactionError` into `{ code, name, message, instructionIndex }`:
+//! custom codes raised by the vault program are named after their
+//! `EphemeralVaultError` variant and `#[msg]` text, and other Anchor errors are
+//! read from the `AnchorError` line the failing instruction logged.
+
+use std::fmt;
+
+use ephemeralvault::EphemeralVaultError;
+use serde::Serialize;
+use solana_sdk::{instruction::InstructionError, transaction::TransactionError};
+
+/// Every `EphemeralVaultError`, in declaration and therefore code order.
+const PROGRAM_ERRORS: &[EphemeralVaultError] = &[
+    EphemeralVaultError::Unauthorized,
+    EphemeralVaultError::VaultInactive,
+    EphemeralVaultError::VaultAlreadyActive,
+    EphemeralVaultError::VaultStillActive,
+    EphemeralVaultError::VaultPaused,
+    EphemeralVaultError::SessionExpired,
+    EphemeralVaultError::NoActiveSession,
+    EphemeralVaultError::SessionNotExpiringSoon,
+    EphemeralVaultError::OverDeposit,
+    EphemeralVaultError::InsufficientFunds,
+    EphemeralVaultError::SessionNotExpired,
+    EphemeralVaultError::InvalidApprovedAmount,
+    EphemeralVaultError::DepositTooSmall,
+    EphemeralVaultError::DepositTooLarge,
+    EphemeralVaultError::MathOverflow,
+    EphemeralVaultError::InvalidTradeAmount,
+    EphemeralVaultError::DelegateNotProperlySet,
+    EphemeralVaultError::InvalidDelegate,
+    EphemeralVaultError::InvalidSessionDuration,
+    EphemeralVaultError::TradeLimitExceeded,
+    EphemeralVaultError::ApprovedAmountTooLow,
+    EphemeralVaultError::VaultAlreadyMigrated,
+    EphemeralVaultError::EmptyTradeBatch,
+    EphemeralVaultError::TradeBatchTooLarge,
+    EphemeralVaultError::InvalidClientOrderId,
+    EphemeralVaultError::DuplicateClientOrderId,
+    EphemeralVaultError::StaleClientOrderId,
+    EphemeralVaultError::InsufficientDelegateBudget,
+];
+
+const ANCHOR_ERROR_MARKER: &str = "AnchorError";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxErrorDto {
+    /// The program's custom error code, when the failure carried one.
+    pub code: Option<u32>,
+    pub name: String,
+    pub message: String,
+    /// The instruction that failed, for instruction errors.
+    pub instruction_index: Option<u8>,
+}
+
+impl fmt::Display for TxErrorDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(index) = self.instruction_index {
+            write!(f, "instruction {index}: ")?;
+        }
+        write!(f, "{}", self.name)?;
+        if let Some(code) = self.code {
+            write!(f, " ({code})")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// The vault program error with custom code `code`.
+pub fn program_error(code: u32) -> Option<EphemeralVaultError> {
+    PROGRAM_ERRORS
+        .iter()
+        .copied()
+        .find(|error| u32::from(*error) == code)
+}
+
+/// An Anchor error reported in program logs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnchorLogError {
+    pub name: String,
+    pub code: u32,
+    pub message: String,
+}
+
+/// The last `AnchorError ... Error Code: X. Error Number: N. Error Message: M.`
+/// line in `logs`.
+pub fn anchor_log_error(logs: &[String]) -> Option<AnchorLogError> {
+    logs.iter().rev().find_map(|line| {
+        let (_, rest) = line.split_once(ANCHOR_ERROR_MARKER)?;
+        let (_, rest) = rest.split_once("Error Code: ")?;
+        let (name, rest) = rest.split_once(". Error Number: ")?;
+        let (code, message) = rest.split_once(". Error Message: ")?;
+        Some(AnchorLogError {
+            name: name.trim().to_string(),
+            code: code.trim().parse().ok()?,
+            message: message.trim().trim_end_matches('.').to_string(),
+        })
+    })
+}
+
+/// The variant name of a `Debug`-formatted enum value, without its fields.
+fn variant_name(debug: String) -> String {
+    match debug.find(['(', ' ', '{']) {
+        Some(end) => debug[..end].to_string(),
+        None => debug,
+    }
+}
+
+/// Decodes `err`. Pass `from_vault_program = false` when the failing
+/// instruction is known to belong to another program, so its custom codes
+/// are not named after vault errors.
+pub fn decode(err: &TransactionError, logs: &[String], from_vault_program: bool) -> TxErrorDto {
+    let TransactionError::InstructionError(index, instruction_error) = err else {
+        return TxErrorDto {
+            code: None,
+            name: variant_name(format!("{err:?}")),
+            message: err.to_string(),
+            instruction_index: None,
+        };
+    };
+    let InstructionError::Custom(code) = instruction_error else {
+        return TxErrorDto {
+            code: None,
+            name: variant_name(format!("{instruction_error:?}")),
+            message: instruction_error.to_string(),
+            instruction_index: Some(*index),
+        };
+    };
+
+    let (name, message) = match (
+        program_error(*code).filter(|_| from_vault_program),
+        anchor_log_error(logs).filter(|logged| logged.code == *code),
+    ) {
+        (Some(error), _) => (error.name(), error.to_string()),
+        (None, Some(logged)) => (logged.name, logged.message),
+        (None, None) => ("Custom".to_string(), instruction_error.to_string()),
+    };
+    TxErrorDto {
+        code: Some(*code),
+        name,
+        message,
+        instruction_index: Some(*index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails to compile when a variant is added without listing it above.
+    fn listed(error: EphemeralVaultError) -> bool {
+        match error {
+            EphemeralVaultError::Unauthorized
+            | EphemeralVaultError::VaultInactive
+            | EphemeralVaultError::VaultAlreadyActive
+            | EphemeralVaultError::VaultStillActive
+            | EphemeralVaultError::VaultPaused
+            | EphemeralVaultError::SessionExpired
+            | EphemeralVaultError::NoActiveSession
+            | EphemeralVaultError::SessionNotExpiringSoon
+            | EphemeralVaultError::OverDeposit
+            | EphemeralVaultError::InsufficientFunds
+            | EphemeralVaultError::SessionNotExpired
+            | EphemeralVaultError::InvalidApprovedAmount
+            | EphemeralVaultError::DepositTooSmall
+            | EphemeralVaultError::DepositTooLarge
+            | EphemeralVaultError::MathOverflow
+            | EphemeralVaultError::InvalidTradeAmount
+            | EphemeralVaultError::DelegateNotProperlySet
+            | EphemeralVaultError::InvalidDelegate
+            | EphemeralVaultError::InvalidSessionDuration
+            | EphemeralVaultError::TradeLimitExceeded
+            | EphemeralVaultError::ApprovedAmountTooLow
+            | EphemeralVaultError::VaultAlreadyMigrated
+            | EphemeralVaultError::EmptyTradeBatch
+            | EphemeralVaultError::TradeBatchTooLarge
+            | EphemeralVaultError::InvalidClientOrderId
+            | EphemeralVaultError::DuplicateClientOrderId
+            | EphemeralVaultError::StaleClientOrderId
+            | EphemeralVaultError::InsufficientDelegateBudget => PROGRAM_ERRORS
+                .iter()
+                .any(|listed| listed.name() == error.name()),
+        }
+    }
+
+    #[test]
+    fn lists_every_program_error_in_code_order() {
+        for (offset, error) in PROGRAM_ERRORS.iter().enumerate() {
+            assert!(listed(*error));
+            assert_eq!(u32::from(*error), 6_000 + offset as u32, "{}", error.name());
+        }
+        let paused = program_error(6_004).unwrap();
+        assert_eq!(paused.name(), "VaultPaused");
+        assert_eq!(paused.to_string(), "Vault is paused");
+        assert!(program_error(6_000 + PROGRAM_ERRORS.len() as u32).is_none());
+    }
+
+    #[test]
+    fn names_vault_program_errors() {
+        let err = TransactionError::InstructionError(2, InstructionError::Custom(6_005));
+
+        assert_eq!(
+            decode(&err, &[], true),
+            TxErrorDto {
+                code: Some(6_005),
+                name: "SessionExpired".into(),
+                message: "Session has expired".into(),
+                instruction_index: Some(2),
+            }
+        );
+        assert_eq!(
+            decode(&err, &[], true).to_string(),
+            "instruction 2: SessionExpired (6005): Session has expired"
+        );
+
+        let other_program = decode(&err, &[], false);
+        assert_eq!(other_program.name, "Custom");
+        assert_eq!(other_program.code, Some(6_005));
+    }
+
+    #[test]
+    fn reads_anchor_errors_from_logs() {
+        let program_id = solana_sdk::pubkey::Pubkey::new_unique();
+        let logs = vec![
+            format!("Program {program_id} invoke [1]"),
+            "Program log: Instruction: ExecuteTrade".to_string(),
+            "Program log: AnchorError caused by account: vault. Error Code: ConstraintSeeds. \
+             Error Number: 2006. Error Message: A seeds constraint was violated."
+                .to_string(),
+            format!("Program {program_id} failed: custom program error: 0x7d6"),
+        ];
+        let err = TransactionError::InstructionError(0, InstructionError::Custom(2_006));
+
+        assert_eq!(
+            decode(&err, &logs, true),
+            TxErrorDto {
+                code: Some(2_006),
+                name: "ConstraintSeeds".into(),
+                message: "A seeds constraint was violated".into(),
+                instruction_index: Some(0),
+            }
+        );
+        assert_eq!(
+            anchor_log_error(&logs[..2]),
+            None,
+            "lines without an AnchorError are ignored"
+        );
+    }
+
+    #[test]
+    fn names_runtime_errors_by_variant() {
+        let fee = decode(&TransactionError::InsufficientFundsForFee, &[], true);
+        assert_eq!(fee.name, "InsufficientFundsForFee");
+        assert_eq!(fee.code, None);
+        assert_eq!(fee.instruction_index, None);
+
+        let err = TransactionError::InstructionError(
+            1,
+            InstructionError::BorshIoError("bad data".into()),
+        );
+        let decoded = decode(&err, &[], true);
+        assert_eq!(decoded.name, "BorshIoError");
+        assert_eq!(decoded.instruction_index, Some(1));
+        assert!(decoded.message.contains("bad data"), "{}", decoded.message);
+    }
+}
@@ -0,0 +1,605 @@
+//! Opt-in session renewal prompts.
+//!
+//! `renew_session` must be signed by the owner within the last
+//! `SESSION_RENEWAL_WINDOW` seconds of a session. Owners who register a
+//! signed renewal policy get an unsigned renewal transaction pushed to them
+//! when that window opens: over `/ws` to connections watching the vault,
+//! and to the policy's webhook if it has one.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+use crate::config::Config;
+use crate::db::models::{NewRenewalPolicy, RenewalPolicyRecord};
+use crate::db::queries;
+use crate::error::{AppError, Result};
+use crate::solana::{self, SessionStatusDto, VaultDto};
+use crate::websocket::{ServerMessage, VaultFeed};
+use crate::AppState;
+
+/// How far `issuedAt` may be from the server clock.
+pub const MAX_ISSUED_AT_SKEW_SECS: i64 = 600;
+/// Longest a single policy may stay valid.
+pub const MAX_POLICY_VALIDITY_SECS: i64 = 90 * 24 * 60 * 60;
+const MAX_WEBHOOK_URL_LEN: usize = 2_048;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// `getMultipleAccounts` accepts at most 100 addresses.
+const ACCOUNTS_PER_REQUEST: usize = 100;
+
+/// The terms an owner signs to opt in to renewal prompts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenewalPolicy {
+    pub owner: Pubkey,
+    pub webhook_url: Option<String>,
+    /// Stop prompting after this many renewal windows.
+    pub max_renewals: Option<u32>,
+    pub valid_until: i64,
+    pub issued_at: i64,
+}
+
+impl RenewalPolicy {
+    /// The exact text the owner signs with their wallet's `signMessage`.
+    pub fn message(&self) -> String {
+        format!(
+            "Ephemeral Vault session renewal policy\n\
+             owner: {}\n\
+             webhook: {}\n\
+             max renewals: {}\n\
+             valid until: {}\n\
+             issued at: {}",
+            self.owner,
+            self.webhook_url.as_deref().unwrap_or("none"),
+            self.max_renewals
+                .map_or_else(|| "unlimited".to_string(), |max| max.to_string()),
+            self.valid_until,
+            self.issued_at,
+        )
+    }
+
+    pub fn validate(&self, now_ts: i64) -> Result<()> {
+        check_issued_at(self.issued_at, now_ts)?;
+
+        if self.valid_until <= now_ts {
+            return Err(AppError::Validation(
+                "validUntil must be in the future".into(),
+            ));
+        }
+        if self.valid_until - now_ts > MAX_POLICY_VALIDITY_SECS {
+            return Err(AppError::Validation(format!(
+                "validUntil must be within {MAX_POLICY_VALIDITY_SECS} seconds"
+            )));
+        }
+
+        if self.max_renewals == Some(0) || self.max_renewals > Some(i32::MAX as u32) {
+            return Err(AppError::Validation(
+                "maxRenewals must be a positive 32-bit integer".into(),
+            ));
+        }
+
+        if let Some(url) = &self.webhook_url {
+            if url.len() > MAX_WEBHOOK_URL_LEN || url.chars().any(char::is_whitespace) {
+                return Err(AppError::Validation("webhookUrl is invalid".into()));
+            }
+            let url = webhook_url(url)?;
+            if let Some(ip) = host_ip(&url) {
+                require_public(ip)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn into_record(self, program_id: &Pubkey, signature: Signature) -> NewRenewalPolicy {
+        let (vault, _) = solana::derive_vault_pda(program_id, &self.owner);
+        NewRenewalPolicy {
+            owner: self.owner.to_string(),
+            vault_address: vault.to_string(),
+            webhook_url: self.webhook_url,
+            max_renewals: self.max_renewals.map(|max| max as i32),
+            valid_until: timestamp(self.valid_until),
+            issued_at: timestamp(self.issued_at),
+            signature: signature.to_string(),
+        }
+    }
+}
+
+/// The text an owner signs to revoke their policy.
+pub fn revocation_message(owner: &Pubkey, issued_at: i64) -> String {
+    format!(
+        "Ephemeral Vault session renewal policy revocation\n\
+         owner: {owner}\n\
+         issued at: {issued_at}"
+    )
+}
+
+fn webhook_url(raw: &str) -> Result<reqwest::Url> {
+    let url = reqwest::Url::parse(raw)
+        .map_err(|e| AppError::Validation(format!("webhookUrl is invalid: {e}")))?;
+    if url.scheme() != "https" || url.host().is_none() {
+        return Err(AppError::Validation(
+            "webhookUrl must be an https:// URL".into(),
+        ));
+    }
+    Ok(url)
+}
+
+/// The host of `url` when it is an IP literal rather than a domain.
+fn host_ip(url: &reqwest::Url) -> Option<IpAddr> {
+    let host = url.host_str()?;
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Whether `ip` is reachable on the public internet, as opposed to
+/// loopback, private, link-local (including cloud metadata at
+/// 169.254.169.254) and other reserved ranges.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || first == 0
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(embedded) => is_public(embedded.into()),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local fc00::/7 and link-local fe80::/10.
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The IPv4 host an IPv6 address reaches through IPv4-mapped
+/// (`::ffff:a.b.c.d`), IPv4-compatible (`::a.b.c.d`), NAT64 (`64:ff9b::/96`)
+/// or 6to4 (`2002::/16`) translation.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let octets = ip.octets();
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, ..] => Some(Ipv4Addr::new(
+            octets[12], octets[13], octets[14], octets[15],
+        )),
+        [0x2002, ..] => Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])),
+        _ => ip.to_ipv4(),
+    }
+}
+
+fn require_public(ip: IpAddr) -> Result<()> {
+    if !is_public(ip) {
+        return Err(AppError::Validation(format!(
+            "webhookUrl must point to a public address, not {ip}"
+        )));
+    }
+    Ok(())
+}
+
+/// Resolves the webhook host and refuses it unless every address it resolves
+/// to is public, so a policy cannot aim the server at its own network. Run
+/// on registration and again before each delivery.
+pub async fn check_webhook_target(raw: &str) -> Result<()> {
+    resolve_webhook_target(raw).await.map(|_| ())
+}
+
+/// The webhook URL and the checked public addresses its host resolves to.
+async fn resolve_webhook_target(raw: &str) -> Result<(reqwest::Url, Vec<SocketAddr>)> {
+    let url = webhook_url(raw)?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    if let Some(ip) = host_ip(&url) {
+        require_public(ip)?;
+        return Ok((url, vec![SocketAddr::new(ip, port)]));
+    }
+    let host = url.host_str().unwrap_or_default();
+    let addresses: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| AppError::Validation(format!("webhookUrl host does not resolve: {e}")))?
+        .collect();
+    if addresses.is_empty() {
+        return Err(AppError::Validation(
+            "webhookUrl host does not resolve".into(),
+        ));
+    }
+    addresses
+        .iter()
+        .try_for_each(|address| require_public(address.ip()))?;
+    Ok((url, addresses))
+}
+
+pub fn check_issued_at(issued_at: i64, now_ts: i64) -> Result<()> {
+    if (issued_at - now_ts).abs() > MAX_ISSUED_AT_SKEW_SECS {
+        return Err(AppError::Validation(format!(
+            "issuedAt must be within {MAX_ISSUED_AT_SKEW_SECS} seconds of the server time"
+        )));
+    }
+    Ok(())
+}
+
+/// Checks a base58 ed25519 `signature` by `owner` over `message`.
+pub fn verify_signature(owner: &Pubkey, message: &str, signature: &str) -> Result<Signature> {
+    let signature = signature
+        .parse::<Signature>()
+        .map_err(|e| AppError::InvalidSignature(format!("invalid signature: {e}")))?;
+    if !signature.verify(owner.as_ref(), message.as_bytes()) {
+        return Err(AppError::InvalidSignature(
+            "signature does not match the owner and message".into(),
+        ));
+    }
+    Ok(signature)
+}
+
+pub(crate) fn timestamp(unix: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(unix, 0).single().unwrap_or_default()
+}
+
+/// The session expiry to prompt for, if `vault` just entered its renewal
+/// window and the owner has not been prompted for this session yet.
+pub fn renewal_due(policy: &RenewalPolicyRecord, vault: &VaultDto) -> Option<i64> {
+    let expiry = vault.session_expiry?;
+    (vault.session_status == SessionStatusDto::ExpiringSoon
+        && vault.can_renew
+        && policy.last_prompted_expiry != Some(expiry))
+    .then_some(expiry)
+}
+
+#[derive(Clone, Debug)]
+pub struct RenewalPrompt {
+    pub owner: String,
+    pub vault: Pubkey,
+    pub session_expiry: i64,
+    pub webhook_url: Option<String>,
+    pub message: ServerMessage,
+}
+
+/// Builds a prompt for every policy whose vault is due for renewal. A vault
+/// that cannot be decoded or whose renewal cannot be built is skipped, so one
+/// owner does not hold up everyone else's prompt.
+pub async fn due_prompts(
+    rpc: &RpcClient,
+    config: &Config,
+    policies: &[RenewalPolicyRecord],
+    now_ts: i64,
+) -> Result<Vec<RenewalPrompt>> {
+    let mut prompts = Vec::new();
+    for chunk in policies.chunks(ACCOUNTS_PER_REQUEST) {
+        let vaults = chunk
+            .iter()
+            .map(|policy| {
+                policy.vault_address.parse::<Pubkey>().map_err(|e| {
+                    AppError::Internal(format!("stored vault address is invalid: {e}"))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let accounts = rpc
+            .get_multiple_accounts(&vaults)
+            .await
+            .map_err(|e| AppError::SolanaRpc(format!("failed to fetch vaults: {e}")))?;
+
+        for ((policy, vault), account) in chunk.iter().zip(vaults).zip(accounts) {
+            let Some(account) = account else { continue };
+            let dto = match solana::parse_vault_account(&account.data).and_then(|decoded| {
+                solana::to_vault_dto(vault, decoded, solana::vault_rent_surplus(&account), now_ts)
+            }) {
+                Ok(dto) => dto,
+                Err(e) => {
+                    tracing::warn!("skipping renewal check for {vault}: {e}");
+                    continue;
+                }
+            };
+            let Some(session_expiry) = renewal_due(policy, &dto) else {
+                continue;
+            };
+
+            let owner = dto
+                .owner
+                .parse::<Pubkey>()
+                .map_err(|e| AppError::Internal(format!("decoded owner is invalid: {e}")))?;
+            let tx = match solana::build_renew_session_tx(
+                rpc,
+                config,
+                owner,
+                &solana::TxOptions::default(),
+            )
+            .await
+            {
+                Ok(tx) => tx,
+                Err(e) => {
+                    tracing::warn!("skipping renewal prompt for {vault}: {e}");
+                    continue;
+                }
+            };
+            prompts.push(RenewalPrompt {
+                owner: policy.owner.clone(),
+                vault,
+                session_expiry,
+                webhook_url: policy.webhook_url.clone(),
+                message: ServerMessage::SessionRenewalDue {
+                    address: dto.address,
+                    owner: dto.owner,
+                    session_expiry,
+                    seconds_until_expiry: dto.seconds_until_expiry,
+                    transaction_base64: tx.transaction_base64,
+                },
+            });
+        }
+    }
+    Ok(prompts)
+}
+
+/// Pushes `prompt` to `/ws` subscribers and posts it to the webhook. Only
+/// webhook failures are reported; websocket delivery is best effort.
+pub async fn deliver(feed: &VaultFeed, prompt: &RenewalPrompt) -> Result<()> {
+    feed.publish(prompt.vault, vec![prompt.message.clone()]);
+
+    let Some(url) = &prompt.webhook_url else {
+        return Ok(());
+    };
+    let (url, addresses) = resolve_webhook_target(url)
+        .await
+        .map_err(|e| AppError::Internal(format!("renewal webhook refused: {e}")))?;
+    // Connect only to the addresses just checked. Letting reqwest resolve
+    // the host again would let a short-lived DNS answer swap in an internal
+    // address after the check. Redirects could lead to refused addresses.
+    let http = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve_to_addrs(url.host_str().unwrap_or_default(), &addresses)
+        .build()
+        .map_err(|e| AppError::Internal(format!("failed to build webhook client: {e}")))?;
+    http.post(url)
+        .json(&prompt.message)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| AppError::Internal(format!("renewal webhook failed: {e}")))?;
+    Ok(())
+}
+
+/// Prompts every due owner once. A prompt whose webhook fails is retried on
+/// the next check. Returns the number of prompts delivered.
+pub async fn check_once(state: &AppState) -> Result<usize> {
+    let now = Utc::now();
+    let policies = queries::get_active_renewal_policies(&state.db, now).await?;
+    let prompts = due_prompts(&state.rpc, &state.config, &policies, now.timestamp()).await?;
+
+    let mut delivered = 0;
+    for prompt in &prompts {
+        match deliver(&state.vault_feed, prompt).await {
+            Ok(()) => {
+                queries::mark_renewal_prompted(&state.db, &prompt.owner, prompt.session_expiry)
+                    .await?;
+                delivered += 1;
+            }
+            Err(e) => tracing::warn!(owner = %prompt.owner, "{e}"),
+        }
+    }
+    Ok(delivered)
+}
+
+/// Checks registered policies every `RENEWAL_CHECK_INTERVAL_SECS`.
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(
+        state.config.renewal_check_interval_secs,
+    ));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    tracing::info!(
+        "Session renewal prompts started, every {}s",
+        state.config.renewal_check_interval_secs
+    );
+
+    loop {
+        interval.tick().await;
+        match check_once(&state).await {
+            Ok(delivered) => tracing::debug!(delivered, "sent renewal prompts"),
+            Err(e) => tracing::warn!("renewal check failed: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solana::EphemeralVaultAccount;
+    use crate::test_support::{self, program_id, LocalSvm};
+    use solana_sdk::{signature::Keypair, signer::Signer};
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn policy(owner: Pubkey) -> RenewalPolicy {
+        RenewalPolicy {
+            owner,
+            webhook_url: Some("https://example.com/hooks/renewal".into()),
+            max_renewals: Some(3),
+            valid_until: NOW + 86_400,
+            issued_at: NOW,
+        }
+    }
+
+    fn record(owner: Pubkey, last_prompted_expiry: Option<i64>) -> RenewalPolicyRecord {
+        let stored = policy(owner).into_record(&program_id(), Signature::default());
+        RenewalPolicyRecord {
+            owner: stored.owner,
+            vault_address: stored.vault_address,
+            webhook_url: stored.webhook_url,
+            max_renewals: stored.max_renewals,
+            valid_until: stored.valid_until,
+            issued_at: stored.issued_at,
+            signature: stored.signature,
+            renewals_prompted: 0,
+            last_prompted_expiry,
+            revoked_at: None,
+            created_at: timestamp(NOW),
+            updated_at: timestamp(NOW),
+        }
+    }
+
+    /// Stores an active vault whose session ends at `expires_at`.
+    fn add_vault(svm: &LocalSvm, owner: Pubkey, expires_at: i64) {
+        svm.add_vault(
+            &EphemeralVaultAccount {
+                delegate_wallet: Some(Pubkey::new_unique()),
+                delegated_at: Some(expires_at - 3_600),
+                session_expires_at: Some(expires_at),
+                ..test_support::vault(owner, NOW - 3_000)
+            },
+            0,
+        );
+    }
+
+    #[test]
+    fn verifies_owner_signed_policies() {
+        let owner = Keypair::new();
+        let policy = policy(owner.pubkey());
+        let message = policy.message();
+        assert!(message.contains(&format!("owner: {}", owner.pubkey())));
+        assert!(message.contains("max renewals: 3"));
+
+        let signature = owner.sign_message(message.as_bytes()).to_string();
+        assert!(verify_signature(&owner.pubkey(), &message, &signature).is_ok());
+
+        let widened = RenewalPolicy {
+            max_renewals: None,
+            ..policy.clone()
+        };
+        assert!(verify_signature(&owner.pubkey(), &widened.message(), &signature).is_err());
+        assert!(verify_signature(&Pubkey::new_unique(), &message, &signature).is_err());
+        assert!(verify_signature(&owner.pubkey(), &message, "not-a-signature").is_err());
+
+        let revocation = revocation_message(&owner.pubkey(), NOW);
+        assert_ne!(revocation, message);
+    }
+
+    #[test]
+    fn validates_policy_terms() {
+        let owner = Pubkey::new_unique();
+        assert!(policy(owner).validate(NOW).is_ok());
+
+        let with = |change: fn(&mut RenewalPolicy)| {
+            let mut policy = policy(owner);
+            change(&mut policy);
+            policy.validate(NOW)
+        };
+        assert!(with(|p| p.issued_at = NOW - MAX_ISSUED_AT_SKEW_SECS - 1).is_err());
+        assert!(with(|p| p.valid_until = NOW).is_err());
+        assert!(with(|p| p.valid_until = NOW + MAX_POLICY_VALIDITY_SECS + 1).is_err());
+        assert!(with(|p| p.max_renewals = Some(0)).is_err());
+        assert!(with(|p| p.webhook_url = Some("ftp://example.com".into())).is_err());
+        assert!(with(|p| p.webhook_url = Some("http://example.com/hook".into())).is_err());
+        assert!(with(|p| p.webhook_url = None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn refuses_webhooks_aimed_at_internal_addresses() {
+        for url in [
+            "https://127.0.0.1/hook",
+            "https://10.0.0.8/hook",
+            "https://192.168.1.1/hook",
+            "https://169.254.169.254/latest/meta-data",
+            "https://100.64.0.1/hook",
+            "https://[::1]/hook",
+            "https://[fd00::1]/hook",
+            "https://[::ffff:10.0.0.1]/hook",
+            "https://[::10.0.0.1]/hook",
+            "https://[64:ff9b::a9fe:a9fe]/hook",
+            "https://[2002:a00:1::]/hook",
+        ] {
+            let mut policy = policy(Pubkey::new_unique());
+            policy.webhook_url = Some(url.into());
+            assert!(policy.validate(NOW).is_err(), "{url}");
+            assert!(check_webhook_target(url).await.is_err(), "{url}");
+        }
+
+        assert!(check_webhook_target("https://localhost:8080/hook")
+            .await
+            .is_err());
+        assert!(check_webhook_target("https://93.184.216.34/hook")
+            .await
+            .is_ok());
+        assert!(check_webhook_target("https://[2606:4700::1111]/hook")
+            .await
+            .is_ok());
+        assert!(check_webhook_target("https://[64:ff9b::5db8:d822]/hook")
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn prompts_once_per_session_inside_the_renewal_window() {
+        let svm = LocalSvm::new(NOW);
+        let due = Pubkey::new_unique();
+        let prompted = Pubkey::new_unique();
+        let early = Pubkey::new_unique();
+        add_vault(&svm, due, NOW + 120);
+        add_vault(&svm, prompted, NOW + 120);
+        add_vault(&svm, early, NOW + 1_800);
+        let policies = vec![
+            record(due, Some(NOW - 3_000)),
+            record(prompted, Some(NOW + 120)),
+            record(early, None),
+            record(Pubkey::new_unique(), None),
+        ];
+
+        let prompts = due_prompts(&svm.rpc(), &test_support::config(), &policies, NOW)
+            .await
+            .unwrap();
+
+        assert_eq!(prompts.len(), 1);
+        let prompt = &prompts[0];
+        assert_eq!(prompt.owner, due.to_string());
+        assert_eq!(prompt.session_expiry, NOW + 120);
+        let ServerMessage::SessionRenewalDue {
+            owner,
+            seconds_until_expiry,
+            transaction_base64,
+            ..
+        } = &prompt.message
+        else {
+            panic!("unexpected prompt {:?}", prompt.message);
+        };
+        assert_eq!(owner, &due.to_string());
+        assert_eq!(*seconds_until_expiry, Some(120));
+
+        let tx = solana::decode_transaction_base64(transaction_base64).unwrap();
+        assert_eq!(tx.message.static_account_keys()[0], due);
+        assert_eq!(
+            tx.message.instructions()[0].data,
+            anchor_lang::InstructionData::data(&ephemeralvault::instruction::RenewSession {})
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_owners_whose_renewal_cannot_be_built() {
+        // The cluster clock runs 100s behind the server, so one session is
+        // not yet inside the window on-chain and its renewal is refused.
+        let svm = LocalSvm::new(NOW - 100);
+        let refused = Pubkey::new_unique();
+        let due = Pubkey::new_unique();
+        add_vault(&svm, refused, NOW + 290);
+        add_vault(&svm, due, NOW + 120);
+        let policies = vec![record(refused, None), record(due, None)];
+
+        let prompts = due_prompts(&svm.rpc(), &test_support::config(), &policies, NOW)
+            .await
+            .unwrap();
+
+        assert_eq!(prompts.len(), 1);
+        assert_eq!(prompts[0].owner, due.to_string());
+    }
+}
@@ -0,0 +1,107 @@
+//! Periodic job recording every program vault into `vault_snapshots`, which
+//! backs the vault history endpoints.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use sqlx::PgPool;
+
+use crate::config::Config;
+use crate::db::models::NewVaultSnapshot;
+use crate::db::queries;
+use crate::error::Result;
+use crate::solana::{self, to_sol, ProgramVault};
+use crate::AppState;
+
+pub fn snapshot_row(vault: &ProgramVault, now_ts: i64) -> Result<NewVaultSnapshot> {
+    let stats = solana::to_vault_stats_dto(&vault.vault, vault.surplus_lamports, now_ts)?;
+    Ok(NewVaultSnapshot {
+        vault_address: vault.address.to_string(),
+        owner: vault.vault.user_wallet.to_string(),
+        balance_sol: to_sol(vault.vault.available_amount),
+        approved_amount_sol: to_sol(vault.vault.approved_amount),
+        trades_executed: i32::try_from(vault.vault.trade_count).unwrap_or(i32::MAX),
+        status: stats.status.as_str().to_string(),
+    })
+}
+
+/// Snapshots every vault once. Returns the number of rows written.
+pub async fn snapshot_once(rpc: &RpcClient, db: &PgPool, config: &Config) -> Result<u64> {
+    let now = Utc::now();
+    let rows = solana::fetch_program_vaults(rpc, config, Vec::new())
+        .await?
+        .iter()
+        .map(|vault| snapshot_row(vault, now.timestamp()))
+        .collect::<Result<Vec<_>>>()?;
+    queries::insert_vault_snapshots(db, &rows, now).await
+}
+
+/// Snapshots all vaults every `SNAPSHOT_INTERVAL_SECS`.
+pub async fn run(state: AppState) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.snapshot_interval_secs));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    tracing::info!(
+        "Vault snapshot job started, every {}s",
+        state.config.snapshot_interval_secs
+    );
+
+    loop {
+        interval.tick().await;
+        match snapshot_once(&state.rpc, &state.db, &state.config).await {
+            Ok(written) => tracing::debug!(written, "recorded vault snapshots"),
+            Err(e) => tracing::warn!("vault snapshot failed: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solana::EphemeralVaultAccount;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn program_vault(is_paused: bool, trade_count: u64) -> ProgramVault {
+        ProgramVault {
+            address: Pubkey::new_unique(),
+            vault: EphemeralVaultAccount {
+                user_wallet: Pubkey::new_unique(),
+                vault_pda: Pubkey::new_unique(),
+                created_at: 1_700_000_000,
+                last_activity: 1_700_000_100,
+                approved_amount: 2_000_000_000,
+                used_amount: 250_000_000,
+                available_amount: 500_000_000,
+                delegate_wallet: None,
+                delegated_at: None,
+                session_expires_at: None,
+                total_deposited: 750_000_000,
+                total_withdrawn: 0,
+                trade_count,
+                is_active: true,
+                is_paused,
+                version: 2,
+                bump: 254,
+            },
+            surplus_lamports: 500_000_000,
+        }
+    }
+
+    #[test]
+    fn snapshot_row_records_balance_budget_and_status() {
+        let vault = program_vault(false, 3);
+        let row = snapshot_row(&vault, 1_700_000_200).unwrap();
+
+        assert_eq!(row.vault_address, vault.address.to_string());
+        assert_eq!(row.owner, vault.vault.user_wallet.to_string());
+        assert_eq!(row.balance_sol, 0.5);
+        assert_eq!(row.approved_amount_sol, 2.0);
+        assert_eq!(row.trades_executed, 3);
+        assert_eq!(row.status, "active");
+
+        let paused = snapshot_row(&program_vault(true, u64::MAX), 1_700_000_200).unwrap();
+        assert_eq!(paused.status, "paused");
+        assert_eq!(paused.trades_executed, i32::MAX);
+    }
+}
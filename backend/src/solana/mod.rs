@@ -1,24 +1,60 @@
-use anchor_lang::{prelude::Pubkey as AnchorPubkey, InstructionData};
+use anchor_lang::{prelude::Pubkey as AnchorPubkey, Discriminator, InstructionData};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use borsh::BorshDeserialize;
-use serde::Serialize;
-use solana_client::nonblocking::rpc_client::RpcClient;
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSimulateTransactionConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
 use solana_sdk::{
+    account::Account,
+    address_lookup_table::{state::AddressLookupTable, AddressLookupTableAccount},
+    commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
     instruction::{AccountMeta, Instruction},
-    message::Message,
+    message::{
+        v0::{self, LoadedAddresses},
+        Message, VersionedMessage,
+    },
+    nonce,
+    packet::PACKET_DATA_SIZE,
     pubkey::Pubkey,
+    rent::Rent,
     signature::Signature,
+    system_instruction::{self, SystemInstruction},
     system_program,
-    transaction::Transaction,
+    transaction::{TransactionError, VersionedTransaction},
 };
 
 use crate::config::Config;
 use crate::error::{AppError, Result};
+use crate::preflight;
+use crate::program_errors::{self, TxErrorDto};
 
 const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
-const RENEWAL_WINDOW_SECONDS: i64 = 300;
-
-#[derive(Clone, Debug, BorshDeserialize)]
+/// Most compute units one transaction may request.
+const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+/// Most operations one `POST /tx/compose` request may carry.
+pub const MAX_COMPOSED_OPERATIONS: usize = 32;
+/// Most instructions a composed transaction carries, so that each may use
+/// the runtime's default per-instruction units within the transaction cap.
+const MAX_COMPOSED_INSTRUCTIONS: usize =
+    (MAX_COMPUTE_UNIT_LIMIT / DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT) as usize;
+/// Most accounts `getRecentPrioritizationFees` accepts.
+const MAX_PRIORITIZATION_FEE_ACCOUNTS: usize = 128;
+/// Base fee per signature on public clusters.
+const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+/// Compute units the runtime grants each instruction without a limit set.
+const DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT: u32 = 200_000;
+/// Seed of an owner's nonce account when the request names none.
+pub const DEFAULT_NONCE_SEED: &str = "ephemeral-vault-nonce";
+
+/// Decoded vault state. Sentinel values of the on-chain zero-copy layout are
+/// mapped back to `Option`s.
+#[derive(Clone, Debug)]
 pub struct EphemeralVaultAccount {
     pub user_wallet: Pubkey,
     pub vault_pda: Pubkey,
@@ -39,6 +75,101 @@ pub struct EphemeralVaultAccount {
     pub bump: u8,
 }
 
+/// Borsh layout of vaults created before the zero-copy migration (`version == 1`).
+#[derive(Clone, Debug, BorshDeserialize, BorshSerialize)]
+struct LegacyEphemeralVaultAccount {
+    user_wallet: Pubkey,
+    vault_pda: Pubkey,
+    created_at: i64,
+    last_activity: i64,
+    approved_amount: u64,
+    used_amount: u64,
+    available_amount: u64,
+    delegate_wallet: Option<Pubkey>,
+    delegated_at: Option<i64>,
+    session_expires_at: Option<i64>,
+    total_deposited: u64,
+    total_withdrawn: u64,
+    trade_count: u64,
+    is_active: bool,
+    is_paused: bool,
+    version: u8,
+    bump: u8,
+}
+
+impl From<LegacyEphemeralVaultAccount> for EphemeralVaultAccount {
+    fn from(vault: LegacyEphemeralVaultAccount) -> Self {
+        Self {
+            user_wallet: vault.user_wallet,
+            vault_pda: vault.vault_pda,
+            created_at: vault.created_at,
+            last_activity: vault.last_activity,
+            approved_amount: vault.approved_amount,
+            used_amount: vault.used_amount,
+            available_amount: vault.available_amount,
+            delegate_wallet: vault.delegate_wallet,
+            delegated_at: vault.delegated_at,
+            session_expires_at: vault.session_expires_at,
+            total_deposited: vault.total_deposited,
+            total_withdrawn: vault.total_withdrawn,
+            trade_count: vault.trade_count,
+            is_active: vault.is_active,
+            is_paused: vault.is_paused,
+            version: vault.version,
+            bump: vault.bump,
+        }
+    }
+}
+
+impl From<&ephemeralvault::EphemeralVault> for EphemeralVaultAccount {
+    fn from(vault: &ephemeralvault::EphemeralVault) -> Self {
+        Self {
+            user_wallet: from_anchor_pubkey(vault.user_wallet),
+            vault_pda: from_anchor_pubkey(vault.vault_pda),
+            created_at: vault.created_at,
+            last_activity: vault.last_activity,
+            approved_amount: vault.approved_amount,
+            used_amount: vault.used_amount,
+            available_amount: vault.available_amount,
+            delegate_wallet: vault.delegate().map(from_anchor_pubkey),
+            delegated_at: vault.delegated_since(),
+            session_expires_at: vault.session_expiry(),
+            total_deposited: vault.total_deposited,
+            total_withdrawn: vault.total_withdrawn,
+            trade_count: vault.trade_count,
+            is_active: vault.active(),
+            is_paused: vault.paused(),
+            version: vault.version,
+            bump: vault.bump,
+        }
+    }
+}
+
+impl From<&EphemeralVaultAccount> for ephemeralvault::EphemeralVault {
+    fn from(vault: &EphemeralVaultAccount) -> Self {
+        Self {
+            user_wallet: to_anchor_pubkey(vault.user_wallet),
+            vault_pda: to_anchor_pubkey(vault.vault_pda),
+            delegate_wallet: to_anchor_pubkey(vault.delegate_wallet.unwrap_or_default()),
+            created_at: vault.created_at,
+            last_activity: vault.last_activity,
+            delegated_at: vault.delegated_at.unwrap_or_default(),
+            session_expires_at: vault.session_expires_at.unwrap_or_default(),
+            approved_amount: vault.approved_amount,
+            used_amount: vault.used_amount,
+            available_amount: vault.available_amount,
+            total_deposited: vault.total_deposited,
+            total_withdrawn: vault.total_withdrawn,
+            trade_count: vault.trade_count,
+            is_active: vault.is_active.into(),
+            is_paused: vault.is_paused.into(),
+            version: vault.version,
+            bump: vault.bump,
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VaultDto {
@@ -64,11 +195,16 @@ pub struct VaultDto {
     pub is_paused: bool,
     pub session_status: SessionStatusDto,
     pub status: VaultStatusDto,
+    pub remaining_budget_lamports: u64,
+    pub seconds_until_expiry: Option<i64>,
+    pub can_renew: bool,
+    pub can_cleanup: bool,
+    pub expected_cleanup_reward_lamports: u64,
     pub version: u8,
     pub bump: u8,
 }
 
-#[derive(Clone, Copy, Debug, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum SessionStatusDto {
     NoSession,
@@ -77,7 +213,18 @@ pub enum SessionStatusDto {
     Expired,
 }
 
-#[derive(Clone, Copy, Debug, Serialize)]
+impl From<ephemeralvault::SessionStatus> for SessionStatusDto {
+    fn from(status: ephemeralvault::SessionStatus) -> Self {
+        match status {
+            ephemeralvault::SessionStatus::NoSession => Self::NoSession,
+            ephemeralvault::SessionStatus::Active => Self::Active,
+            ephemeralvault::SessionStatus::ExpiringSoon => Self::ExpiringSoon,
+            ephemeralvault::SessionStatus::Expired => Self::Expired,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum VaultStatusDto {
     Active,
@@ -86,6 +233,17 @@ pub enum VaultStatusDto {
     Expired,
 }
 
+impl VaultStatusDto {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Paused => "paused",
+            Self::Inactive => "inactive",
+            Self::Expired => "expired",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VaultStatsDto {
@@ -100,6 +258,11 @@ pub struct VaultStatsDto {
     pub session_expiry: Option<i64>,
     pub session_status: SessionStatusDto,
     pub status: VaultStatusDto,
+    pub remaining_budget_lamports: u64,
+    pub seconds_until_expiry: Option<i64>,
+    pub can_renew: bool,
+    pub can_cleanup: bool,
+    pub expected_cleanup_reward_lamports: u64,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -107,13 +270,273 @@ pub struct VaultStatsDto {
 pub struct TxEnvelope {
     pub transaction_base64: String,
     pub vault_pda: String,
+    pub fee_payer: String,
+    /// Fee charged to the owner's sponsorship budget when the backend pays.
+    pub sponsored_fee_lamports: Option<u64>,
+    pub transaction_version: TxVersion,
+    pub compute_unit_limit: Option<u32>,
+    pub compute_unit_price_micro_lamports: Option<u64>,
+    pub lifetime: TxLifetimeMode,
+    /// Block height after which a blockhash transaction can no longer land.
+    pub last_valid_block_height: Option<u64>,
+    /// Nonce account a durable nonce transaction advances; it stays valid
+    /// until that nonce is advanced.
+    pub nonce_account: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TxLifetimeMode {
+    Blockhash,
+    DurableNonce,
+}
+
+/// Per-request compute budget settings. Unset fields fall back to `Config`;
+/// setting a limit or price enables the budget unless `enabled` is false.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComputeBudgetOptions {
+    pub enabled: Option<bool>,
+    pub unit_limit: Option<u32>,
+    pub unit_price_micro_lamports: Option<u64>,
+}
+
+/// Message format of built transactions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxVersion {
+    Legacy,
+    #[default]
+    V0,
+}
+
+impl TxVersion {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "legacy" => Some(Self::Legacy),
+            "v0" | "0" => Some(Self::V0),
+            _ => None,
+        }
+    }
+}
+
+/// Options accepted by every `build_*_tx`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxOptions {
+    #[serde(default)]
+    pub compute_budget: ComputeBudgetOptions,
+    /// Overrides `TRANSACTION_VERSION`.
+    pub transaction_version: Option<TxVersion>,
+    /// Durable nonce account to build against instead of a recent blockhash.
+    pub nonce_account: Option<String>,
+    /// Asks for the backend's fee payer to pay for the transaction.
+    #[serde(default)]
+    pub sponsored: bool,
+    /// Builds without first checking the vault's state against the
+    /// instruction's on-chain requirements.
+    #[serde(default)]
+    pub skip_state_checks: bool,
+    /// Pays instead of the signer; set by `sponsorship` for sponsored
+    /// requests.
+    #[serde(skip)]
+    pub fee_payer: Option<Pubkey>,
+}
+
+/// What keeps a built transaction valid: a recent blockhash, or the current
+/// value of a durable nonce account its first instruction advances.
+#[derive(Clone, Debug, PartialEq)]
+enum TxLifetime {
+    Blockhash {
+        blockhash: Hash,
+        last_valid_block_height: u64,
+    },
+    DurableNonce {
+        account: Pubkey,
+        authority: Pubkey,
+        nonce: Hash,
+    },
+}
+
+impl TxLifetime {
+    fn blockhash(&self) -> Hash {
+        match self {
+            Self::Blockhash { blockhash, .. } => *blockhash,
+            Self::DurableNonce { nonce, .. } => *nonce,
+        }
+    }
+
+    /// Instructions that must lead the transaction.
+    fn instructions(&self) -> Vec<Instruction> {
+        match self {
+            Self::Blockhash { .. } => Vec::new(),
+            Self::DurableNonce {
+                account, authority, ..
+            } => vec![system_instruction::advance_nonce_account(
+                account, authority,
+            )],
+        }
+    }
+}
+
+/// How a builder compiles its message: legacy, or v0 with the lookup tables
+/// it may draw addresses from.
+#[derive(Clone, Debug, PartialEq)]
+enum MessageFormat {
+    Legacy,
+    V0(Vec<AddressLookupTableAccount>),
+}
+
+impl MessageFormat {
+    fn version(&self) -> TxVersion {
+        match self {
+            Self::Legacy => TxVersion::Legacy,
+            Self::V0(_) => TxVersion::V0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeBatchTxDto {
+    #[serde(flatten)]
+    pub transaction: TxEnvelope,
+    pub included_entries: usize,
+    pub remaining_entries: usize,
+}
+
+/// One owner instruction of a `POST /tx/compose` sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VaultOperation {
+    CreateVault {
+        approved_amount: u64,
+    },
+    Deposit {
+        amount: u64,
+    },
+    /// Withdraws `amount`, or everything available when zero.
+    Withdraw {
+        amount: u64,
+    },
+    Pause,
+    Unpause,
+    Revoke,
+    RenewSession,
+    /// Approves `delegate`, first sending it `funding_lamports` when non-zero.
+    ApproveDelegate {
+        delegate: Pubkey,
+        custom_duration: Option<i64>,
+        funding_lamports: u64,
+    },
+    Reactivate,
+    UpdateApprovedAmount {
+        new_approved_amount: u64,
+    },
+    MigrateVault,
+}
+
+impl VaultOperation {
+    fn instructions(
+        &self,
+        program_id: Pubkey,
+        user: Pubkey,
+        vault_pda: Pubkey,
+    ) -> Vec<Instruction> {
+        match *self {
+            Self::CreateVault { approved_amount } => vec![create_vault_instruction(
+                program_id,
+                user,
+                vault_pda,
+                approved_amount,
+            )],
+            Self::Deposit { amount } => {
+                vec![deposit_instruction(program_id, user, vault_pda, amount)]
+            }
+            Self::Withdraw { amount } => {
+                vec![withdraw_instruction(program_id, user, vault_pda, amount)]
+            }
+            Self::Pause => vec![pause_instruction(program_id, user, vault_pda)],
+            Self::Unpause => vec![unpause_instruction(program_id, user, vault_pda)],
+            Self::Revoke => vec![revoke_instruction(program_id, user, vault_pda)],
+            Self::RenewSession => vec![renew_instruction(program_id, user, vault_pda)],
+            Self::ApproveDelegate {
+                delegate,
+                custom_duration,
+                funding_lamports,
+            } => {
+                let mut instructions = Vec::with_capacity(2);
+                if funding_lamports > 0 {
+                    instructions.push(system_instruction::transfer(
+                        &user,
+                        &delegate,
+                        funding_lamports,
+                    ));
+                }
+                instructions.push(approve_delegate_instruction(
+                    program_id,
+                    user,
+                    vault_pda,
+                    delegate,
+                    custom_duration,
+                ));
+                instructions
+            }
+            Self::Reactivate => vec![reactivate_instruction(program_id, user, vault_pda)],
+            Self::UpdateApprovedAmount {
+                new_approved_amount,
+            } => vec![update_approved_amount_instruction(
+                program_id,
+                user,
+                vault_pda,
+                new_approved_amount,
+            )],
+            Self::MigrateVault => vec![migrate_vault_instruction(program_id, user, vault_pda)],
+        }
+    }
+}
+
+/// One transaction of a composed sequence; send them in order, each after
+/// the previous one has landed.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComposedTxDto {
+    #[serde(flatten)]
+    pub transaction: TxEnvelope,
+    /// Indexes of the requested operations this transaction carries.
+    pub operations: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComposedTxsDto {
+    pub transactions: Vec<ComposedTxDto>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NonceTxDto {
+    #[serde(flatten)]
+    pub transaction: TxEnvelope,
+    pub nonce_account: String,
+    pub seed: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NonceAccountDto {
+    pub address: String,
+    pub authority: String,
+    /// Pass as the recent blockhash of a transaction advancing this account.
+    pub nonce: String,
+    pub lamports_per_signature: u64,
+    pub balance_lamports: u64,
 }
 
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TxSimulationDto {
     pub ok: bool,
-    pub error: Option<String>,
+    pub error: Option<TxErrorDto>,
     pub logs: Vec<String>,
     pub units_consumed: Option<u64>,
 }
@@ -126,18 +549,22 @@ pub struct TxStatusDto {
     pub slot: Option<u64>,
     pub confirmations: Option<usize>,
     pub confirmation_status: Option<String>,
-    pub error: Option<String>,
+    pub error: Option<TxErrorDto>,
 }
 
-fn to_sol(lamports: u64) -> f64 {
+pub(crate) fn to_sol(lamports: u64) -> f64 {
     lamports as f64 / LAMPORTS_PER_SOL
 }
 
-fn to_anchor_pubkey(pubkey: Pubkey) -> AnchorPubkey {
+pub(crate) fn to_anchor_pubkey(pubkey: Pubkey) -> AnchorPubkey {
     AnchorPubkey::new_from_array(pubkey.to_bytes())
 }
 
-fn program_id(config: &Config) -> Result<Pubkey> {
+pub(crate) fn from_anchor_pubkey(pubkey: AnchorPubkey) -> Pubkey {
+    Pubkey::new_from_array(pubkey.to_bytes())
+}
+
+pub(crate) fn program_id(config: &Config) -> Result<Pubkey> {
     config
         .program_id
         .parse::<Pubkey>()
@@ -148,27 +575,56 @@ pub fn derive_vault_pda(program_id: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
     Pubkey::find_program_address(&[b"vault", user.as_ref()], program_id)
 }
 
-fn parse_vault_account(data: &[u8]) -> Result<EphemeralVaultAccount> {
+pub(crate) fn parse_vault_account(data: &[u8]) -> Result<EphemeralVaultAccount> {
     if data.len() < 8 {
         return Err(AppError::Internal("vault account is too small".into()));
     }
 
-    let mut bytes = &data[8..];
-    EphemeralVaultAccount::deserialize(&mut bytes)
-        .map_err(|e| AppError::SerializationMessage(format!("failed to decode vault account: {e}")))
-}
+    if data[..8] != *ephemeralvault::EphemeralVault::DISCRIMINATOR {
+        return Err(AppError::SerializationMessage(
+            "account is not an ephemeral vault".into(),
+        ));
+    }
 
-fn session_status(vault: &EphemeralVaultAccount, now_ts: i64) -> SessionStatusDto {
-    match vault.session_expires_at {
-        None => SessionStatusDto::NoSession,
-        Some(expires_at) if now_ts >= expires_at => SessionStatusDto::Expired,
-        Some(expires_at) if expires_at - now_ts <= RENEWAL_WINDOW_SECONDS => {
-            SessionStatusDto::ExpiringSoon
+    match data.len() {
+        ephemeralvault::VAULT_SPACE => {
+            let vault = bytemuck::pod_read_unaligned::<ephemeralvault::EphemeralVault>(&data[8..]);
+            Ok(EphemeralVaultAccount::from(&vault))
+        }
+        ephemeralvault::LEGACY_VAULT_SPACE => {
+            let mut bytes = &data[8..];
+            LegacyEphemeralVaultAccount::deserialize(&mut bytes)
+                .map(EphemeralVaultAccount::from)
+                .map_err(|e| {
+                    AppError::SerializationMessage(format!("failed to decode legacy vault: {e}"))
+                })
         }
-        Some(_) => SessionStatusDto::Active,
+        len => Err(AppError::SerializationMessage(format!(
+            "unexpected vault account size {len}"
+        ))),
     }
 }
 
+/// Lamports held by a vault account above its rent-exempt minimum.
+///
+/// Uses the default rent schedule, which all public clusters share.
+pub fn vault_rent_surplus(account: &Account) -> u64 {
+    account
+        .lamports
+        .saturating_sub(Rent::default().minimum_balance(account.data.len()))
+}
+
+/// Derived stats computed by the same code as the on-chain `get_vault_stats`.
+fn onchain_stats(
+    vault: &EphemeralVaultAccount,
+    surplus_lamports: u64,
+    now_ts: i64,
+) -> Result<ephemeralvault::VaultStats> {
+    ephemeralvault::EphemeralVault::from(vault)
+        .stats(now_ts, surplus_lamports)
+        .map_err(|e| AppError::Internal(format!("failed to derive vault stats: {e}")))
+}
+
 fn vault_status(vault: &EphemeralVaultAccount, session_status: SessionStatusDto) -> VaultStatusDto {
     if !vault.is_active {
         VaultStatusDto::Inactive
@@ -181,11 +637,17 @@ fn vault_status(vault: &EphemeralVaultAccount, session_status: SessionStatusDto)
     }
 }
 
-pub fn to_vault_dto(vault_pubkey: Pubkey, vault: EphemeralVaultAccount, now_ts: i64) -> VaultDto {
-    let session_status = session_status(&vault, now_ts);
+pub fn to_vault_dto(
+    vault_pubkey: Pubkey,
+    vault: EphemeralVaultAccount,
+    surplus_lamports: u64,
+    now_ts: i64,
+) -> Result<VaultDto> {
+    let stats = onchain_stats(&vault, surplus_lamports, now_ts)?;
+    let session_status = SessionStatusDto::from(stats.session_status);
     let status = vault_status(&vault, session_status);
 
-    VaultDto {
+    Ok(VaultDto {
         address: vault_pubkey.to_string(),
         owner: vault.user_wallet.to_string(),
         delegate: vault.delegate_wallet.map(|pk| pk.to_string()),
@@ -208,16 +670,26 @@ pub fn to_vault_dto(vault_pubkey: Pubkey, vault: EphemeralVaultAccount, now_ts:
         is_paused: vault.is_paused,
         session_status,
         status,
+        remaining_budget_lamports: stats.remaining_budget,
+        seconds_until_expiry: stats.seconds_until_expiry,
+        can_renew: stats.can_renew,
+        can_cleanup: stats.can_cleanup,
+        expected_cleanup_reward_lamports: stats.expected_cleanup_reward,
         version: vault.version,
         bump: vault.bump,
-    }
+    })
 }
 
-pub fn to_vault_stats_dto(vault: &EphemeralVaultAccount, now_ts: i64) -> VaultStatsDto {
-    let session_status = session_status(vault, now_ts);
+pub fn to_vault_stats_dto(
+    vault: &EphemeralVaultAccount,
+    surplus_lamports: u64,
+    now_ts: i64,
+) -> Result<VaultStatsDto> {
+    let stats = onchain_stats(vault, surplus_lamports, now_ts)?;
+    let session_status = SessionStatusDto::from(stats.session_status);
     let status = vault_status(vault, session_status);
 
-    VaultStatsDto {
+    Ok(VaultStatsDto {
         approved_amount_lamports: vault.approved_amount,
         available_amount_lamports: vault.available_amount,
         used_amount_lamports: vault.used_amount,
@@ -229,56 +701,566 @@ pub fn to_vault_stats_dto(vault: &EphemeralVaultAccount, now_ts: i64) -> VaultSt
         session_expiry: vault.session_expires_at,
         session_status,
         status,
-    }
+        remaining_budget_lamports: stats.remaining_budget,
+        seconds_until_expiry: stats.seconds_until_expiry,
+        can_renew: stats.can_renew,
+        can_cleanup: stats.can_cleanup,
+        expected_cleanup_reward_lamports: stats.expected_cleanup_reward,
+    })
 }
 
-async fn latest_blockhash(rpc: &RpcClient) -> Result<solana_sdk::hash::Hash> {
+pub(crate) async fn latest_blockhash(rpc: &RpcClient) -> Result<Hash> {
     rpc.get_latest_blockhash()
         .await
         .map_err(|e| AppError::SolanaRpc(format!("failed to fetch recent blockhash: {e}")))
 }
 
+/// Decodes an initialized durable nonce account.
+pub(crate) fn parse_nonce_account(
+    address: &Pubkey,
+    account: &Account,
+) -> Result<nonce::state::Data> {
+    if account.owner != system_program::ID {
+        return Err(AppError::Validation(format!(
+            "{address} is not a nonce account"
+        )));
+    }
+    let versions = bincode::deserialize::<nonce::state::Versions>(&account.data)
+        .map_err(|_| AppError::Validation(format!("{address} is not a nonce account")))?;
+    match versions.state() {
+        nonce::state::State::Initialized(data) => Ok(data.clone()),
+        nonce::state::State::Uninitialized => Err(AppError::Validation(format!(
+            "nonce account {address} is not initialized"
+        ))),
+    }
+}
+
+pub(crate) async fn fetch_nonce_data(
+    rpc: &RpcClient,
+    address: &Pubkey,
+) -> Result<(Account, nonce::state::Data)> {
+    let account = rpc
+        .get_account_with_commitment(address, rpc.commitment())
+        .await
+        .map_err(|e| AppError::SolanaRpc(format!("failed to fetch nonce account {address}: {e}")))?
+        .value
+        .ok_or_else(|| AppError::NotFound(format!("nonce account {address}")))?;
+    let data = parse_nonce_account(address, &account)?;
+    Ok((account, data))
+}
+
+/// The lifetime a builder compiles against: the nonce account named in
+/// `options`, or else the latest blockhash.
+async fn tx_lifetime(rpc: &RpcClient, options: &TxOptions) -> Result<TxLifetime> {
+    let Some(raw) = &options.nonce_account else {
+        let (blockhash, last_valid_block_height) = rpc
+            .get_latest_blockhash_with_commitment(rpc.commitment())
+            .await
+            .map_err(|e| AppError::SolanaRpc(format!("failed to fetch recent blockhash: {e}")))?;
+        return Ok(TxLifetime::Blockhash {
+            blockhash,
+            last_valid_block_height,
+        });
+    };
+
+    let account = raw
+        .parse::<Pubkey>()
+        .map_err(|_| AppError::InvalidSignature("invalid nonceAccount".into()))?;
+    let (_, data) = fetch_nonce_data(rpc, &account).await?;
+    Ok(TxLifetime::DurableNonce {
+        account,
+        authority: data.authority,
+        nonce: data.blockhash(),
+    })
+}
+
+/// The nonce account `user` creates with `seed`.
+pub fn derive_nonce_account(user: &Pubkey, seed: &str) -> Result<Pubkey> {
+    Pubkey::create_with_seed(user, seed, &system_program::ID)
+        .map_err(|e| AppError::Validation(format!("invalid nonce seed: {e}")))
+}
+
+/// The durable nonce account `message` advances, if its first instruction
+/// is `advance_nonce_account`. `keys` are the message's accounts with lookup
+/// table addresses resolved.
+pub(crate) fn durable_nonce_account(message: &VersionedMessage, keys: &[Pubkey]) -> Option<Pubkey> {
+    let instruction = message.instructions().first()?;
+    if keys.get(usize::from(instruction.program_id_index)) != Some(&system_program::ID) {
+        return None;
+    }
+    match bincode::deserialize::<SystemInstruction>(&instruction.data) {
+        Ok(SystemInstruction::AdvanceNonceAccount) => instruction
+            .accounts
+            .first()
+            .and_then(|&index| keys.get(usize::from(index)))
+            .copied(),
+        _ => None,
+    }
+}
+
+pub(crate) async fn fetch_lookup_table(
+    rpc: &RpcClient,
+    address: Pubkey,
+) -> Result<AddressLookupTableAccount> {
+    let account = rpc
+        .get_account(&address)
+        .await
+        .map_err(|e| AppError::SolanaRpc(format!("failed to fetch lookup table {address}: {e}")))?;
+    let table = AddressLookupTable::deserialize(&account.data).map_err(|e| {
+        AppError::Validation(format!("{address} is not an address lookup table: {e}"))
+    })?;
+    Ok(AddressLookupTableAccount {
+        key: address,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+async fn message_format(
+    rpc: &RpcClient,
+    config: &Config,
+    options: &TxOptions,
+) -> Result<MessageFormat> {
+    match options
+        .transaction_version
+        .unwrap_or(config.transaction_version)
+    {
+        TxVersion::Legacy => Ok(MessageFormat::Legacy),
+        TxVersion::V0 => {
+            let mut tables = Vec::new();
+            if let Some(address) = &config.address_lookup_table {
+                let address = address.parse::<Pubkey>().map_err(|e| {
+                    AppError::Internal(format!("invalid ADDRESS_LOOKUP_TABLE: {e}"))
+                })?;
+                tables.push(fetch_lookup_table(rpc, address).await?);
+            }
+            Ok(MessageFormat::V0(tables))
+        }
+    }
+}
+
+/// An unsigned transaction of `instructions` in `format`.
+fn compile_transaction(
+    payer: Pubkey,
+    instructions: &[Instruction],
+    blockhash: Hash,
+    format: &MessageFormat,
+) -> Result<VersionedTransaction> {
+    let message = match format {
+        MessageFormat::Legacy => VersionedMessage::Legacy(Message::new_with_blockhash(
+            instructions,
+            Some(&payer),
+            &blockhash,
+        )),
+        MessageFormat::V0(tables) => VersionedMessage::V0(
+            v0::Message::try_compile(&payer, instructions, tables, blockhash)
+                .map_err(|e| AppError::Internal(format!("failed to compile message: {e}")))?,
+        ),
+    };
+    Ok(VersionedTransaction {
+        signatures: vec![
+            Signature::default();
+            usize::from(message.header().num_required_signatures)
+        ],
+        message,
+    })
+}
+
+fn serialized_transaction_size(
+    payer: Pubkey,
+    instructions: &[Instruction],
+    blockhash: Hash,
+    format: &MessageFormat,
+) -> Result<usize> {
+    let tx = compile_transaction(payer, instructions, blockhash, format)?;
+    bincode::serialized_size(&tx)
+        .map(|size| size as usize)
+        .map_err(|e| AppError::Internal(format!("failed to size transaction: {e}")))
+}
+
+/// Encodes `instructions` behind the instructions `lifetime` requires.
 fn encode_transaction(
     payer: Pubkey,
     instructions: Vec<Instruction>,
-    blockhash: solana_sdk::hash::Hash,
+    lifetime: &TxLifetime,
+    format: &MessageFormat,
     vault_pda: Pubkey,
 ) -> Result<TxEnvelope> {
-    let tx = Transaction::new_unsigned(Message::new_with_blockhash(
-        &instructions,
-        Some(&payer),
-        &blockhash,
-    ));
-    let bytes = bincode::serialize(&tx)
-        .map_err(|e| AppError::Internal(format!("failed to serialize transaction: {e}")))?;
-
+    let mut all = lifetime.instructions();
+    all.extend(instructions);
+    let tx = compile_transaction(payer, &all, lifetime.blockhash(), format)?;
+
+    let (mode, last_valid_block_height, nonce_account) = match lifetime {
+        TxLifetime::Blockhash {
+            last_valid_block_height,
+            ..
+        } => (
+            TxLifetimeMode::Blockhash,
+            Some(*last_valid_block_height),
+            None,
+        ),
+        TxLifetime::DurableNonce { account, .. } => (
+            TxLifetimeMode::DurableNonce,
+            None,
+            Some(account.to_string()),
+        ),
+    };
     Ok(TxEnvelope {
-        transaction_base64: BASE64.encode(bytes),
+        transaction_base64: encode_transaction_base64(&tx)?,
         vault_pda: vault_pda.to_string(),
+        fee_payer: payer.to_string(),
+        sponsored_fee_lamports: None,
+        transaction_version: format.version(),
+        compute_unit_limit: None,
+        compute_unit_price_micro_lamports: None,
+        lifetime: mode,
+        last_valid_block_height,
+        nonce_account,
     })
 }
 
-pub async fn simulate_transaction_base64(
+fn compute_budget_enabled(config: &Config, options: &TxOptions) -> bool {
+    let requested = options.compute_budget;
+    requested.enabled.unwrap_or(
+        config.compute_budget_enabled
+            || requested.unit_limit.is_some()
+            || requested.unit_price_micro_lamports.is_some(),
+    )
+}
+
+fn compute_budget_instructions(unit_limit: Option<u32>, unit_price: u64) -> Vec<Instruction> {
+    let mut instructions = Vec::with_capacity(2);
+    if let Some(unit_limit) = unit_limit {
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(unit_limit));
+    }
+    if unit_price > 0 {
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_price(unit_price));
+    }
+    instructions
+}
+
+/// The largest prefix `build_transaction` may add ahead of the caller's
+/// instructions, for sizing transactions before the budget is known.
+fn reserved_instructions(
+    config: &Config,
+    options: &TxOptions,
+    lifetime: &TxLifetime,
+) -> Vec<Instruction> {
+    let mut reserved = lifetime.instructions();
+    if compute_budget_enabled(config, options) {
+        reserved.extend(compute_budget_instructions(Some(MAX_COMPUTE_UNIT_LIMIT), 1));
+    }
+    reserved
+}
+
+/// Signature and prioritization fees `message` costs its fee payer. Without
+/// a limit each non-budget instruction is charged the runtime default.
+pub(crate) fn estimated_fee_lamports(
+    message: &VersionedMessage,
+    unit_limit: Option<u32>,
+    unit_price_micro_lamports: Option<u64>,
+) -> u64 {
+    let signatures = LAMPORTS_PER_SIGNATURE * u64::from(message.header().num_required_signatures);
+    let Some(unit_price) = unit_price_micro_lamports else {
+        return signatures;
+    };
+
+    let unit_limit = unit_limit.unwrap_or_else(|| {
+        let keys = message.static_account_keys();
+        let instructions = message
+            .instructions()
+            .iter()
+            .filter(|ix| {
+                keys.get(usize::from(ix.program_id_index)) != Some(&solana_sdk::compute_budget::ID)
+            })
+            .count() as u32;
+        instructions
+            .saturating_mul(DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT)
+            .min(MAX_COMPUTE_UNIT_LIMIT)
+    });
+    let priority = (u128::from(unit_limit) * u128::from(unit_price)).div_ceil(1_000_000);
+    signatures.saturating_add(u64::try_from(priority).unwrap_or(u64::MAX))
+}
+
+/// The `percentile` of recent prioritization fees, capped at `max`.
+fn percentile_unit_price(mut fees: Vec<u64>, percentile: u64, max: u64) -> u64 {
+    if fees.is_empty() {
+        return 0;
+    }
+    fees.sort_unstable();
+    let index = (fees.len() - 1) * percentile.min(100) as usize / 100;
+    fees[index].min(max)
+}
+
+/// Prices compute units from the fees recently paid to write the accounts
+/// `instructions` lock.
+async fn estimate_unit_price(
     rpc: &RpcClient,
-    transaction_base64: &str,
-) -> Result<TxSimulationDto> {
+    config: &Config,
+    instructions: &[Instruction],
+) -> Result<u64> {
+    let mut writable = Vec::new();
+    for meta in instructions.iter().flat_map(|ix| &ix.accounts) {
+        if meta.is_writable && !writable.contains(&meta.pubkey) {
+            writable.push(meta.pubkey);
+        }
+    }
+    writable.truncate(MAX_PRIORITIZATION_FEE_ACCOUNTS);
+
+    let fees = rpc
+        .get_recent_prioritization_fees(&writable)
+        .await
+        .map_err(|e| AppError::SolanaRpc(format!("failed to fetch prioritization fees: {e}")))?;
+    Ok(percentile_unit_price(
+        fees.into_iter().map(|fee| fee.prioritization_fee).collect(),
+        config.priority_fee_percentile,
+        config.max_compute_unit_price_micro_lamports,
+    ))
+}
+
+/// Simulates `instructions` under the maximum limit and returns the units
+/// consumed plus `COMPUTE_UNIT_MARGIN_PERCENT`, or `None` if the simulation
+/// fails and the runtime default has to do.
+async fn simulate_unit_limit(
+    rpc: &RpcClient,
+    config: &Config,
+    payer: Pubkey,
+    instructions: &[Instruction],
+    unit_price: u64,
+    lifetime: &TxLifetime,
+    format: &MessageFormat,
+) -> Result<Option<u32>> {
+    let mut simulated = lifetime.instructions();
+    simulated.extend(compute_budget_instructions(
+        Some(MAX_COMPUTE_UNIT_LIMIT),
+        unit_price,
+    ));
+    simulated.extend_from_slice(instructions);
+    let tx = compile_transaction(payer, &simulated, lifetime.blockhash(), format)?;
+
+    let response = rpc
+        .simulate_transaction_with_config(
+            &tx,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                replace_recent_blockhash: true,
+                ..RpcSimulateTransactionConfig::default()
+            },
+        )
+        .await
+        .map_err(|e| AppError::SolanaRpc(format!("failed to simulate transaction: {e}")))?;
+    if let Some(err) = response.value.err {
+        tracing::debug!("compute unit simulation failed: {err:?}");
+        return Ok(None);
+    }
+
+    Ok(response.value.units_consumed.map(|units| {
+        let with_margin = units.saturating_mul(100 + config.compute_unit_margin_percent) / 100;
+        u32::try_from(with_margin)
+            .unwrap_or(u32::MAX)
+            .min(MAX_COMPUTE_UNIT_LIMIT)
+    }))
+}
+
+/// Encodes `instructions` in the requested message format, prepending
+/// compute budget instructions when the request or `Config` enables them. An
+/// unset price is estimated from recent prioritization fees and an unset
+/// limit from a simulation. A durable nonce lifetime puts its
+/// `advance_nonce_account` ahead of everything else. `options.fee_payer`, when
+/// set, pays in place of `payer`.
+async fn build_transaction(
+    rpc: &RpcClient,
+    config: &Config,
+    options: &TxOptions,
+    payer: Pubkey,
+    instructions: Vec<Instruction>,
+    lifetime: TxLifetime,
+    vault_pda: Pubkey,
+) -> Result<TxEnvelope> {
+    let format = message_format(rpc, config, options).await?;
+    build_transaction_with_format(
+        rpc,
+        config,
+        options,
+        payer,
+        instructions,
+        lifetime,
+        &format,
+        vault_pda,
+    )
+    .await
+}
+
+/// `build_transaction` in a `format` the caller already resolved, such as
+/// the one it packed `instructions` against.
+#[allow(clippy::too_many_arguments)]
+async fn build_transaction_with_format(
+    rpc: &RpcClient,
+    config: &Config,
+    options: &TxOptions,
+    payer: Pubkey,
+    instructions: Vec<Instruction>,
+    lifetime: TxLifetime,
+    format: &MessageFormat,
+    vault_pda: Pubkey,
+) -> Result<TxEnvelope> {
+    if options.sponsored && options.fee_payer.is_none() {
+        return Err(AppError::Validation(
+            "this transaction cannot be sponsored".into(),
+        ));
+    }
+    let payer = options.fee_payer.unwrap_or(payer);
+    if !compute_budget_enabled(config, options) {
+        return encode_transaction(payer, instructions, &lifetime, format, vault_pda);
+    }
+
+    let requested = options.compute_budget;
+    if requested
+        .unit_limit
+        .is_some_and(|limit| limit == 0 || limit > MAX_COMPUTE_UNIT_LIMIT)
+    {
+        return Err(AppError::Validation(format!(
+            "computeBudget.unitLimit must be between 1 and {MAX_COMPUTE_UNIT_LIMIT}"
+        )));
+    }
+
+    let unit_price = match requested
+        .unit_price_micro_lamports
+        .or(config.compute_unit_price_micro_lamports)
+    {
+        Some(price) => price,
+        None => estimate_unit_price(rpc, config, &instructions).await?,
+    };
+    let unit_limit = match requested.unit_limit {
+        Some(limit) => Some(limit),
+        None => {
+            simulate_unit_limit(
+                rpc,
+                config,
+                payer,
+                &instructions,
+                unit_price,
+                &lifetime,
+                format,
+            )
+            .await?
+        }
+    };
+
+    let mut budgeted = compute_budget_instructions(unit_limit, unit_price);
+    budgeted.extend(instructions);
+    let mut envelope = encode_transaction(payer, budgeted, &lifetime, format, vault_pda)?;
+    envelope.compute_unit_limit = unit_limit;
+    envelope.compute_unit_price_micro_lamports = (unit_price > 0).then_some(unit_price);
+    Ok(envelope)
+}
+
+pub(crate) fn encode_transaction_base64(tx: &VersionedTransaction) -> Result<String> {
+    bincode::serialize(tx)
+        .map(|bytes| BASE64.encode(bytes))
+        .map_err(|e| AppError::Internal(format!("failed to serialize transaction: {e}")))
+}
+
+/// Decodes a legacy or versioned transaction.
+pub fn decode_transaction_base64(transaction_base64: &str) -> Result<VersionedTransaction> {
     let bytes = BASE64
         .decode(transaction_base64)
         .map_err(|e| AppError::Validation(format!("transactionBase64 is invalid base64: {e}")))?;
-    let tx = bincode::deserialize::<Transaction>(&bytes).map_err(|e| {
+    let tx = bincode::deserialize::<VersionedTransaction>(&bytes).map_err(|e| {
         AppError::Validation(format!("transactionBase64 is not a transaction: {e}"))
     })?;
+    tx.sanitize().map_err(|e| {
+        AppError::Validation(format!("transactionBase64 is not a valid transaction: {e}"))
+    })?;
+    Ok(tx)
+}
+
+/// Resolves the addresses `message` loads from lookup tables.
+pub(crate) async fn load_addresses(
+    rpc: &RpcClient,
+    message: &VersionedMessage,
+) -> Result<LoadedAddresses> {
+    let mut loaded = LoadedAddresses::default();
+    for lookup in message.address_table_lookups().unwrap_or_default() {
+        let table = fetch_lookup_table(rpc, lookup.account_key).await?;
+        let resolve = |indexes: &[u8]| {
+            indexes
+                .iter()
+                .map(|&index| table.addresses.get(usize::from(index)).copied())
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| {
+                    AppError::Validation(format!(
+                        "lookup table {} has no address at a referenced index",
+                        lookup.account_key
+                    ))
+                })
+        };
+        loaded.writable.extend(resolve(&lookup.writable_indexes)?);
+        loaded.readonly.extend(resolve(&lookup.readonly_indexes)?);
+    }
+    Ok(loaded)
+}
+
+/// Every account `message` references, in the order its instructions index
+/// them: static keys, then writable and readonly lookup table addresses.
+pub(crate) fn message_account_keys(
+    message: &VersionedMessage,
+    loaded: &LoadedAddresses,
+) -> Vec<Pubkey> {
+    message
+        .static_account_keys()
+        .iter()
+        .chain(&loaded.writable)
+        .chain(&loaded.readonly)
+        .copied()
+        .collect()
+}
+
+/// Whether a failing instruction `err` points at could have raised a vault
+/// program error: true unless it belongs to another program.
+pub(crate) fn failed_in_program(
+    message: &VersionedMessage,
+    err: &TransactionError,
+    program_id: &Pubkey,
+) -> bool {
+    let TransactionError::InstructionError(index, _) = err else {
+        return true;
+    };
+    message
+        .instructions()
+        .get(usize::from(*index))
+        .and_then(|ix| {
+            message
+                .static_account_keys()
+                .get(usize::from(ix.program_id_index))
+        })
+        .is_none_or(|program| program == program_id)
+}
+
+pub async fn simulate_transaction_base64(
+    rpc: &RpcClient,
+    config: &Config,
+    transaction_base64: &str,
+) -> Result<TxSimulationDto> {
+    let tx = decode_transaction_base64(transaction_base64)?;
+    let program_id = program_id(config)?;
 
     let response = rpc
         .simulate_transaction(&tx)
         .await
         .map_err(|e| AppError::SolanaRpc(format!("failed to simulate transaction: {e}")))?;
 
-    let error = response.value.err.map(|err| format!("{err:?}"));
+    let logs = response.value.logs.unwrap_or_default();
+    let error = response.value.err.map(|err| {
+        program_errors::decode(
+            &err,
+            &logs,
+            failed_in_program(&tx.message, &err, &program_id),
+        )
+    });
     Ok(TxSimulationDto {
         ok: error.is_none(),
         error,
-        logs: response.value.logs.unwrap_or_default(),
+        logs,
         units_consumed: response.value.units_consumed,
     })
 }
@@ -312,7 +1294,9 @@ pub async fn fetch_transaction_status(
         confirmation_status: status
             .confirmation_status
             .map(|status| format!("{status:?}").to_lowercase()),
-        error: status.err.map(|err| format!("{err:?}")),
+        error: status
+            .err
+            .map(|err| program_errors::decode(&err, &[], true)),
     })
 }
 
@@ -423,6 +1407,18 @@ fn reactivate_instruction(program_id: Pubkey, user: Pubkey, vault_pda: Pubkey) -
     }
 }
 
+fn migrate_vault_instruction(program_id: Pubkey, user: Pubkey, vault_pda: Pubkey) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new(user, true),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        data: ephemeralvault::instruction::MigrateVault {}.data(),
+    }
+}
+
 fn update_approved_amount_instruction(
     program_id: Pubkey,
     user: Pubkey,
@@ -470,6 +1466,7 @@ fn execute_trade_instruction(
     vault_pda: Pubkey,
     trade_fee: u64,
     trade_amount: u64,
+    client_order_id: u64,
 ) -> Instruction {
     Instruction {
         program_id,
@@ -480,23 +1477,126 @@ fn execute_trade_instruction(
         data: ephemeralvault::instruction::ExecuteTrade {
             trade_fee,
             trade_amount,
+            client_order_id,
         }
         .data(),
     }
 }
 
-fn cleanup_instruction(
+fn execute_trades_batch_instruction(
     program_id: Pubkey,
+    delegate: Pubkey,
     vault_pda: Pubkey,
-    user_wallet: Pubkey,
-    cleaner: Pubkey,
+    entries: &[ephemeralvault::TradeEntry],
 ) -> Instruction {
     Instruction {
         program_id,
         accounts: vec![
             AccountMeta::new(vault_pda, false),
-            AccountMeta::new(user_wallet, false),
-            AccountMeta::new(cleaner, true),
+            AccountMeta::new_readonly(delegate, true),
+        ],
+        data: ephemeralvault::instruction::ExecuteTradesBatch {
+            entries: entries.to_vec(),
+        }
+        .data(),
+    }
+}
+
+/// Returns how many leading `entries` fit into one batch transaction after
+/// the `reserved` instructions, bounded by both the packet size and the
+/// program's `MAX_BATCH_TRADES`.
+fn packable_trade_entries(
+    program_id: Pubkey,
+    delegate: Pubkey,
+    vault_pda: Pubkey,
+    entries: &[ephemeralvault::TradeEntry],
+    reserved: &[Instruction],
+    blockhash: Hash,
+    format: &MessageFormat,
+) -> Result<usize> {
+    let mut count = entries.len().min(ephemeralvault::MAX_BATCH_TRADES);
+    while count > 0 {
+        let mut instructions = reserved.to_vec();
+        instructions.push(execute_trades_batch_instruction(
+            program_id,
+            delegate,
+            vault_pda,
+            &entries[..count],
+        ));
+        if serialized_transaction_size(delegate, &instructions, blockhash, format)?
+            <= PACKET_DATA_SIZE
+        {
+            return Ok(count);
+        }
+        count -= 1;
+    }
+
+    Err(AppError::Validation(
+        "no trade entries fit into a transaction".into(),
+    ))
+}
+
+/// Splits `operations` into consecutive groups, each of which fits into one
+/// transaction after the `reserved` instructions, bounded by the packet size
+/// and `MAX_COMPOSED_INSTRUCTIONS`.
+fn pack_operations(
+    program_id: Pubkey,
+    user: Pubkey,
+    vault_pda: Pubkey,
+    operations: &[VaultOperation],
+    reserved: &[Instruction],
+    blockhash: Hash,
+    format: &MessageFormat,
+) -> Result<Vec<std::ops::Range<usize>>> {
+    let fits = |range: std::ops::Range<usize>| -> Result<bool> {
+        let composed: Vec<_> = operations[range]
+            .iter()
+            .flat_map(|op| op.instructions(program_id, user, vault_pda))
+            .collect();
+        if composed.len() > MAX_COMPOSED_INSTRUCTIONS {
+            return Ok(false);
+        }
+        let mut instructions = reserved.to_vec();
+        instructions.extend(composed);
+        Ok(
+            serialized_transaction_size(user, &instructions, blockhash, format)?
+                <= PACKET_DATA_SIZE,
+        )
+    };
+
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for end in 1..=operations.len() {
+        if fits(start..end)? {
+            continue;
+        }
+        if end - 1 == start {
+            return Err(AppError::Operation {
+                index: start,
+                source: Box::new(AppError::Validation(
+                    "operation does not fit into a transaction".into(),
+                )),
+            });
+        }
+        groups.push(start..end - 1);
+        start = end - 1;
+    }
+    groups.push(start..operations.len());
+    Ok(groups)
+}
+
+pub(crate) fn cleanup_instruction(
+    program_id: Pubkey,
+    vault_pda: Pubkey,
+    user_wallet: Pubkey,
+    cleaner: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new(user_wallet, false),
+            AccountMeta::new(cleaner, true),
         ],
         data: ephemeralvault::instruction::CleanupVault {}.data(),
     }
@@ -516,11 +1616,211 @@ pub async fn fetch_vault_by_user(
         .map_err(|e| AppError::VaultNotFound(format!("{vault_pda}: {e}")))?;
 
     let vault = parse_vault_account(&account.data)?;
-    Ok(to_vault_dto(
+    to_vault_dto(
         vault_pda,
         vault,
+        vault_rent_surplus(&account),
         chrono::Utc::now().timestamp(),
-    ))
+    )
+}
+
+/// A decoded vault returned by `getProgramAccounts`.
+#[derive(Clone, Debug)]
+pub struct ProgramVault {
+    pub address: Pubkey,
+    pub vault: EphemeralVaultAccount,
+    pub surplus_lamports: u64,
+}
+
+/// Lists the program's vaults, matching both account layouts by their shared
+/// discriminator plus any extra `filters`. Accounts that fail to decode are
+/// skipped.
+pub async fn fetch_program_vaults(
+    rpc: &RpcClient,
+    config: &Config,
+    filters: Vec<RpcFilterType>,
+) -> Result<Vec<ProgramVault>> {
+    let program_id = program_id(config)?;
+    let mut all_filters = vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+        0,
+        ephemeralvault::EphemeralVault::DISCRIMINATOR,
+    ))];
+    all_filters.extend(filters);
+
+    let accounts = rpc
+        .get_program_accounts_with_config(
+            &program_id,
+            RpcProgramAccountsConfig {
+                filters: Some(all_filters),
+                account_config: RpcAccountInfoConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    ..RpcAccountInfoConfig::default()
+                },
+                ..RpcProgramAccountsConfig::default()
+            },
+        )
+        .await
+        .map_err(|e| AppError::SolanaRpc(e.to_string()))?;
+
+    Ok(accounts
+        .into_iter()
+        .filter_map(
+            |(address, account)| match parse_vault_account(&account.data) {
+                Ok(vault) => Some(ProgramVault {
+                    address,
+                    vault,
+                    surplus_lamports: vault_rent_surplus(&account),
+                }),
+                Err(e) => {
+                    tracing::warn!("skipping undecodable vault {address}: {e}");
+                    None
+                }
+            },
+        )
+        .collect())
+}
+
+/// Offset of an `EphemeralVault` field in zero-copy account data.
+macro_rules! vault_field_offset {
+    ($field:ident) => {
+        8 + std::mem::offset_of!(ephemeralvault::EphemeralVault, $field)
+    };
+}
+
+const USER_WALLET_OFFSET: usize = vault_field_offset!(user_wallet);
+const DELEGATE_WALLET_OFFSET: usize = vault_field_offset!(delegate_wallet);
+const IS_ACTIVE_OFFSET: usize = vault_field_offset!(is_active);
+const IS_PAUSED_OFFSET: usize = vault_field_offset!(is_paused);
+
+/// Conditions for `GET /vaults`. Every field is optional and all set fields
+/// must hold.
+#[derive(Clone, Debug, Default)]
+pub struct VaultListFilter {
+    pub owner: Option<Pubkey>,
+    pub status: Option<VaultStatusDto>,
+    pub paused: Option<bool>,
+    pub has_delegate: Option<bool>,
+    pub delegate: Option<Pubkey>,
+    /// Sessions that are still live and end within this many seconds.
+    pub expiring_within_secs: Option<i64>,
+    pub cleanup_eligible: Option<bool>,
+}
+
+impl VaultListFilter {
+    /// The conditions the RPC can evaluate with `memcmp` on fixed offsets.
+    /// Only the zero-copy layout has fixed offsets, so any such condition
+    /// also restricts results to `VAULT_SPACE` accounts.
+    pub fn rpc_filters(&self) -> Vec<RpcFilterType> {
+        let memcmp =
+            |offset, bytes: &[u8]| RpcFilterType::Memcmp(Memcmp::new_base58_encoded(offset, bytes));
+        let mut filters = Vec::new();
+
+        if let Some(owner) = self.owner {
+            filters.push(memcmp(USER_WALLET_OFFSET, owner.as_ref()));
+        }
+        if let Some(delegate) = self.delegate {
+            filters.push(memcmp(DELEGATE_WALLET_OFFSET, delegate.as_ref()));
+        } else if self.has_delegate == Some(false) {
+            filters.push(memcmp(DELEGATE_WALLET_OFFSET, &[0; 32]));
+        }
+
+        let (is_active, is_paused) = match self.status {
+            Some(VaultStatusDto::Inactive) => (Some(false), None),
+            Some(VaultStatusDto::Paused) => (Some(true), Some(true)),
+            Some(VaultStatusDto::Active | VaultStatusDto::Expired) => (Some(true), Some(false)),
+            None => (None, None),
+        };
+        if let Some(is_active) = is_active {
+            filters.push(memcmp(IS_ACTIVE_OFFSET, &[u8::from(is_active)]));
+        }
+        if let Some(is_paused) = is_paused.or(self.paused) {
+            filters.push(memcmp(IS_PAUSED_OFFSET, &[u8::from(is_paused)]));
+        }
+
+        if !filters.is_empty() {
+            filters.push(RpcFilterType::DataSize(ephemeralvault::VAULT_SPACE as u64));
+        }
+        filters
+    }
+
+    /// Checks every condition against a decoded vault, including those that
+    /// depend on the current time.
+    pub fn matches(&self, vault: &VaultDto) -> bool {
+        let owner = self.owner.map(|owner| owner.to_string());
+        let delegate = self.delegate.map(|delegate| delegate.to_string());
+
+        owner.is_none_or(|owner| vault.owner == owner)
+            && self.status.is_none_or(|status| vault.status == status)
+            && self.paused.is_none_or(|paused| vault.is_paused == paused)
+            && self
+                .has_delegate
+                .is_none_or(|has_delegate| vault.delegate.is_some() == has_delegate)
+            && delegate.is_none_or(|delegate| vault.delegate.as_deref() == Some(&delegate))
+            && self.expiring_within_secs.is_none_or(|window| {
+                vault.session_status != SessionStatusDto::Expired
+                    && vault
+                        .seconds_until_expiry
+                        .is_some_and(|remaining| remaining <= window)
+            })
+            && self
+                .cleanup_eligible
+                .is_none_or(|eligible| vault.can_cleanup == eligible)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultPageDto {
+    pub vaults: Vec<VaultDto>,
+    /// Pass as `cursor` to fetch the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Orders vaults by address and returns up to `limit` matching vaults after
+/// `after`.
+fn page_vaults(
+    mut vaults: Vec<ProgramVault>,
+    filter: &VaultListFilter,
+    after: Option<Pubkey>,
+    limit: usize,
+    now_ts: i64,
+) -> Result<VaultPageDto> {
+    vaults.sort_by_key(|vault| vault.address);
+
+    let mut page: Vec<VaultDto> = Vec::new();
+    let mut next_cursor = None;
+    for vault in vaults {
+        if after.is_some_and(|after| vault.address <= after) {
+            continue;
+        }
+        let dto = to_vault_dto(vault.address, vault.vault, vault.surplus_lamports, now_ts)?;
+        if !filter.matches(&dto) {
+            continue;
+        }
+        if page.len() == limit {
+            next_cursor = page.last().map(|last| last.address.clone());
+            break;
+        }
+        page.push(dto);
+    }
+
+    Ok(VaultPageDto {
+        vaults: page,
+        next_cursor,
+    })
+}
+
+/// Lists the program's vaults matching `filter`, paginated by address.
+pub async fn list_vaults(
+    rpc: &RpcClient,
+    config: &Config,
+    filter: &VaultListFilter,
+    after: Option<Pubkey>,
+    limit: usize,
+) -> Result<VaultPageDto> {
+    let vaults = fetch_program_vaults(rpc, config, filter.rpc_filters()).await?;
+    page_vaults(vaults, filter, after, limit, chrono::Utc::now().timestamp())
 }
 
 pub async fn fetch_vault_stats_by_user(
@@ -536,9 +1836,14 @@ pub async fn fetch_vault_stats_by_user(
         .await
         .map_err(|e| AppError::VaultNotFound(format!("{vault_pda}: {e}")))?;
     let vault = parse_vault_account(&account.data)?;
-    Ok(to_vault_stats_dto(&vault, chrono::Utc::now().timestamp()))
+    to_vault_stats_dto(
+        &vault,
+        vault_rent_surplus(&account),
+        chrono::Utc::now().timestamp(),
+    )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn build_create_vault_tx(
     rpc: &RpcClient,
     config: &Config,
@@ -547,9 +1852,11 @@ pub async fn build_create_vault_tx(
     delegate: Option<Pubkey>,
     custom_duration_seconds: Option<i64>,
     initial_deposit_lamports: Option<u64>,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
+    preflight::check_absent(rpc, options, vault_pda).await?;
 
     let mut instructions = vec![create_vault_instruction(
         program_id,
@@ -572,7 +1879,16 @@ pub async fn build_create_vault_tx(
         instructions.push(deposit_instruction(program_id, user, vault_pda, amount));
     }
 
-    encode_transaction(user, instructions, latest_blockhash(rpc).await?, vault_pda)
+    build_transaction(
+        rpc,
+        config,
+        options,
+        user,
+        instructions,
+        tx_lifetime(rpc, options).await?,
+        vault_pda,
+    )
+    .await
 }
 
 pub async fn build_deposit_tx(
@@ -580,10 +1896,18 @@ pub async fn build_deposit_tx(
     config: &Config,
     user: Pubkey,
     amount_lamports: u64,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
-    encode_transaction(
+    preflight::check(rpc, options, vault_pda, |vault| {
+        vault.deposit(amount_lamports)
+    })
+    .await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
         user,
         vec![deposit_instruction(
             program_id,
@@ -591,9 +1915,10 @@ pub async fn build_deposit_tx(
             vault_pda,
             amount_lamports,
         )],
-        latest_blockhash(rpc).await?,
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await
 }
 
 pub async fn build_withdraw_tx(
@@ -601,10 +1926,18 @@ pub async fn build_withdraw_tx(
     config: &Config,
     user: Pubkey,
     amount_lamports: u64,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
-    encode_transaction(
+    preflight::check(rpc, options, vault_pda, |vault| {
+        vault.withdraw(amount_lamports)
+    })
+    .await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
         user,
         vec![withdraw_instruction(
             program_id,
@@ -612,99 +1945,198 @@ pub async fn build_withdraw_tx(
             vault_pda,
             amount_lamports,
         )],
-        latest_blockhash(rpc).await?,
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await
 }
 
-pub async fn build_pause_tx(rpc: &RpcClient, config: &Config, user: Pubkey) -> Result<TxEnvelope> {
+pub async fn build_pause_tx(
+    rpc: &RpcClient,
+    config: &Config,
+    user: Pubkey,
+    options: &TxOptions,
+) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
-    encode_transaction(
+    preflight::check(
+        rpc,
+        options,
+        vault_pda,
+        preflight::VaultState::require_migrated,
+    )
+    .await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
         user,
         vec![pause_instruction(program_id, user, vault_pda)],
-        latest_blockhash(rpc).await?,
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await
 }
 
 pub async fn build_unpause_tx(
     rpc: &RpcClient,
     config: &Config,
     user: Pubkey,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
-    encode_transaction(
+    preflight::check(
+        rpc,
+        options,
+        vault_pda,
+        preflight::VaultState::require_migrated,
+    )
+    .await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
         user,
         vec![unpause_instruction(program_id, user, vault_pda)],
-        latest_blockhash(rpc).await?,
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await
 }
 
-pub async fn build_revoke_tx(rpc: &RpcClient, config: &Config, user: Pubkey) -> Result<TxEnvelope> {
+pub async fn build_revoke_tx(
+    rpc: &RpcClient,
+    config: &Config,
+    user: Pubkey,
+    options: &TxOptions,
+) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
-    encode_transaction(
+    preflight::check(
+        rpc,
+        options,
+        vault_pda,
+        preflight::VaultState::require_migrated,
+    )
+    .await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
         user,
         vec![revoke_instruction(program_id, user, vault_pda)],
-        latest_blockhash(rpc).await?,
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await
 }
 
 pub async fn build_renew_session_tx(
     rpc: &RpcClient,
     config: &Config,
     user: Pubkey,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
-    encode_transaction(
+    preflight::check(rpc, options, vault_pda, preflight::VaultState::renew).await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
         user,
         vec![renew_instruction(program_id, user, vault_pda)],
-        latest_blockhash(rpc).await?,
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await
 }
 
+/// Approves `delegate`, first sending it `funding_lamports` from the owner
+/// when non-zero so it can pay for its trades.
 pub async fn build_approve_delegate_tx(
     rpc: &RpcClient,
     config: &Config,
     user: Pubkey,
     delegate: Pubkey,
     custom_duration_seconds: Option<i64>,
+    funding_lamports: u64,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
-    encode_transaction(
+    preflight::check(rpc, options, vault_pda, |vault| {
+        vault.approve_delegate(user, delegate)
+    })
+    .await?;
+    let mut instructions = Vec::with_capacity(2);
+    if funding_lamports > 0 {
+        instructions.push(system_instruction::transfer(
+            &user,
+            &delegate,
+            funding_lamports,
+        ));
+    }
+    instructions.push(approve_delegate_instruction(
+        program_id,
         user,
-        vec![approve_delegate_instruction(
-            program_id,
-            user,
-            vault_pda,
-            delegate,
-            custom_duration_seconds,
-        )],
-        latest_blockhash(rpc).await?,
+        vault_pda,
+        delegate,
+        custom_duration_seconds,
+    ));
+    build_transaction(
+        rpc,
+        config,
+        options,
+        user,
+        instructions,
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await
 }
 
 pub async fn build_reactivate_tx(
     rpc: &RpcClient,
     config: &Config,
     user: Pubkey,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
-    encode_transaction(
+    preflight::check(rpc, options, vault_pda, preflight::VaultState::reactivate).await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
         user,
         vec![reactivate_instruction(program_id, user, vault_pda)],
-        latest_blockhash(rpc).await?,
+        tx_lifetime(rpc, options).await?,
+        vault_pda,
+    )
+    .await
+}
+
+pub async fn build_migrate_vault_tx(
+    rpc: &RpcClient,
+    config: &Config,
+    user: Pubkey,
+    options: &TxOptions,
+) -> Result<TxEnvelope> {
+    let program_id = program_id(config)?;
+    let (vault_pda, _) = derive_vault_pda(&program_id, &user);
+    preflight::check(rpc, options, vault_pda, preflight::VaultState::migrate).await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
+        user,
+        vec![migrate_vault_instruction(program_id, user, vault_pda)],
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await
 }
 
 pub async fn build_update_approved_amount_tx(
@@ -712,10 +2144,18 @@ pub async fn build_update_approved_amount_tx(
     config: &Config,
     user: Pubkey,
     new_approved_amount_lamports: u64,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
     let (vault_pda, _) = derive_vault_pda(&program_id, &user);
-    encode_transaction(
+    preflight::check(rpc, options, vault_pda, |vault| {
+        vault.update_approved_amount(new_approved_amount_lamports)
+    })
+    .await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
         user,
         vec![update_approved_amount_instruction(
             program_id,
@@ -723,11 +2163,13 @@ pub async fn build_update_approved_amount_tx(
             vault_pda,
             new_approved_amount_lamports,
         )],
-        latest_blockhash(rpc).await?,
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn build_execute_trade_tx(
     rpc: &RpcClient,
     config: &Config,
@@ -735,9 +2177,25 @@ pub async fn build_execute_trade_tx(
     delegate: Pubkey,
     trade_fee_lamports: u64,
     trade_amount_lamports: u64,
+    client_order_id: u64,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
-    encode_transaction(
+    preflight::check(rpc, options, vault_pda, |vault| {
+        vault.trades(
+            delegate,
+            &[ephemeralvault::TradeEntry {
+                trade_fee: trade_fee_lamports,
+                trade_amount: trade_amount_lamports,
+                client_id: client_order_id,
+            }],
+        )
+    })
+    .await?;
+    build_transaction(
+        rpc,
+        config,
+        options,
         delegate,
         vec![execute_trade_instruction(
             program_id,
@@ -745,10 +2203,125 @@ pub async fn build_execute_trade_tx(
             vault_pda,
             trade_fee_lamports,
             trade_amount_lamports,
+            client_order_id,
+        )],
+        tx_lifetime(rpc, options).await?,
+        vault_pda,
+    )
+    .await
+}
+
+/// Packs as many leading `entries` as fit into one `execute_trades_batch`
+/// transaction; the caller resubmits the remainder in a follow-up request.
+pub async fn build_execute_trades_batch_tx(
+    rpc: &RpcClient,
+    config: &Config,
+    vault_pda: Pubkey,
+    delegate: Pubkey,
+    entries: &[ephemeralvault::TradeEntry],
+    options: &TxOptions,
+) -> Result<TradeBatchTxDto> {
+    let program_id = program_id(config)?;
+    let lifetime = tx_lifetime(rpc, options).await?;
+    let format = message_format(rpc, config, options).await?;
+    let included = packable_trade_entries(
+        program_id,
+        delegate,
+        vault_pda,
+        entries,
+        &reserved_instructions(config, options, &lifetime),
+        lifetime.blockhash(),
+        &format,
+    )?;
+    preflight::check(rpc, options, vault_pda, |vault| {
+        vault.trades(delegate, &entries[..included])
+    })
+    .await?;
+
+    let transaction = build_transaction_with_format(
+        rpc,
+        config,
+        options,
+        delegate,
+        vec![execute_trades_batch_instruction(
+            program_id,
+            delegate,
+            vault_pda,
+            &entries[..included],
         )],
-        latest_blockhash(rpc).await?,
+        lifetime,
+        &format,
         vault_pda,
     )
+    .await?;
+
+    Ok(TradeBatchTxDto {
+        transaction,
+        included_entries: included,
+        remaining_entries: entries.len() - included,
+    })
+}
+
+/// Builds `operations` for `user` as a sequence of transactions, in order
+/// and packed as tightly as the packet size and compute limits allow. The
+/// sequence is checked against the vault state each operation leaves behind.
+pub async fn build_composed_txs(
+    rpc: &RpcClient,
+    config: &Config,
+    user: Pubkey,
+    operations: &[VaultOperation],
+    options: &TxOptions,
+) -> Result<ComposedTxsDto> {
+    if operations.is_empty() || operations.len() > MAX_COMPOSED_OPERATIONS {
+        return Err(AppError::Validation(format!(
+            "operations must hold between 1 and {MAX_COMPOSED_OPERATIONS} entries"
+        )));
+    }
+    let program_id = program_id(config)?;
+    let (vault_pda, _) = derive_vault_pda(&program_id, &user);
+    preflight::check_sequence(rpc, options, vault_pda, user, operations).await?;
+
+    let lifetime = tx_lifetime(rpc, options).await?;
+    let format = message_format(rpc, config, options).await?;
+    let groups = pack_operations(
+        program_id,
+        user,
+        vault_pda,
+        operations,
+        &reserved_instructions(config, options, &lifetime),
+        lifetime.blockhash(),
+        &format,
+    )?;
+    if groups.len() > 1 && matches!(lifetime, TxLifetime::DurableNonce { .. }) {
+        return Err(AppError::Validation(format!(
+            "operations need {} transactions, but a durable nonce only carries one",
+            groups.len()
+        )));
+    }
+
+    let mut transactions = Vec::with_capacity(groups.len());
+    for group in groups {
+        let instructions = operations[group.clone()]
+            .iter()
+            .flat_map(|op| op.instructions(program_id, user, vault_pda))
+            .collect();
+        let transaction = build_transaction_with_format(
+            rpc,
+            config,
+            options,
+            user,
+            instructions,
+            lifetime.clone(),
+            &format,
+            vault_pda,
+        )
+        .await?;
+        transactions.push(ComposedTxDto {
+            transaction,
+            operations: group.collect(),
+        });
+    }
+    Ok(ComposedTxsDto { transactions })
 }
 
 pub async fn build_cleanup_tx(
@@ -756,30 +2329,95 @@ pub async fn build_cleanup_tx(
     config: &Config,
     vault_pda: Pubkey,
     cleaner: Pubkey,
+    options: &TxOptions,
 ) -> Result<TxEnvelope> {
     let program_id = program_id(config)?;
-    let account = rpc
-        .get_account(&vault_pda)
-        .await
-        .map_err(|e| AppError::VaultNotFound(format!("{vault_pda}: {e}")))?;
-    let vault = parse_vault_account(&account.data)?;
+    // One read supplies both the owner and the state checks.
+    let vault = preflight::load(rpc, vault_pda).await?;
+    if !options.skip_state_checks {
+        vault.cleanup()?;
+    }
 
-    encode_transaction(
+    build_transaction(
+        rpc,
+        config,
+        options,
         cleaner,
         vec![cleanup_instruction(
             program_id,
             vault_pda,
-            vault.user_wallet,
+            from_anchor_pubkey(vault.vault.user_wallet),
             cleaner,
         )],
-        latest_blockhash(rpc).await?,
+        tx_lifetime(rpc, options).await?,
+        vault_pda,
+    )
+    .await
+}
+
+/// Creates and initializes `user`'s nonce account for `seed`, funded to the
+/// rent-exempt minimum and with `user` as its authority.
+pub async fn build_create_nonce_tx(
+    rpc: &RpcClient,
+    config: &Config,
+    user: Pubkey,
+    seed: &str,
+    options: &TxOptions,
+) -> Result<NonceTxDto> {
+    let program_id = program_id(config)?;
+    let (vault_pda, _) = derive_vault_pda(&program_id, &user);
+    let nonce_account = derive_nonce_account(&user, seed)?;
+    let rent = rpc
+        .get_minimum_balance_for_rent_exemption(nonce::state::State::size())
+        .await
+        .map_err(|e| AppError::SolanaRpc(format!("failed to fetch rent exemption: {e}")))?;
+
+    let transaction = build_transaction(
+        rpc,
+        config,
+        options,
+        user,
+        system_instruction::create_nonce_account_with_seed(
+            &user,
+            &nonce_account,
+            &user,
+            seed,
+            &user,
+            rent,
+        ),
+        tx_lifetime(rpc, options).await?,
         vault_pda,
     )
+    .await?;
+
+    Ok(NonceTxDto {
+        transaction,
+        nonce_account: nonce_account.to_string(),
+        seed: seed.to_string(),
+    })
+}
+
+/// Reads `user`'s nonce account for `seed`.
+pub async fn fetch_nonce_account(
+    rpc: &RpcClient,
+    user: Pubkey,
+    seed: &str,
+) -> Result<NonceAccountDto> {
+    let address = derive_nonce_account(&user, seed)?;
+    let (account, data) = fetch_nonce_data(rpc, &address).await?;
+    Ok(NonceAccountDto {
+        address: address.to_string(),
+        authority: data.authority.to_string(),
+        nonce: data.blockhash().to_string(),
+        lamports_per_signature: data.get_lamports_per_signature(),
+        balance_lamports: account.lamports,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bytemuck::Zeroable;
 
     fn sample_vault() -> EphemeralVaultAccount {
         EphemeralVaultAccount {
@@ -803,6 +2441,79 @@ mod tests {
         }
     }
 
+    fn account_data(discriminator: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = discriminator.to_vec();
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn parse_vault_account_decodes_zero_copy_layout() {
+        let owner = Pubkey::new_unique();
+        let mut vault = ephemeralvault::EphemeralVault::zeroed();
+        vault.user_wallet = to_anchor_pubkey(owner);
+        vault.approved_amount = 2_000_000;
+        vault.available_amount = 500_000;
+        vault.is_active = 1;
+        vault.version = 2;
+        vault.bump = 254;
+
+        let data = account_data(
+            ephemeralvault::EphemeralVault::DISCRIMINATOR,
+            bytemuck::bytes_of(&vault),
+        );
+        assert_eq!(data.len(), ephemeralvault::VAULT_SPACE);
+
+        let parsed = parse_vault_account(&data).expect("zero-copy vault");
+        assert_eq!(parsed.user_wallet, owner);
+        assert_eq!(parsed.approved_amount, 2_000_000);
+        assert_eq!(parsed.available_amount, 500_000);
+        assert!(parsed.is_active);
+        assert!(!parsed.is_paused);
+        assert_eq!(parsed.delegate_wallet, None);
+        assert_eq!(parsed.delegated_at, None);
+        assert_eq!(parsed.session_expires_at, None);
+        assert_eq!(parsed.version, 2);
+    }
+
+    #[test]
+    fn parse_vault_account_decodes_legacy_layout() {
+        let vault = sample_vault();
+        let legacy = LegacyEphemeralVaultAccount {
+            user_wallet: vault.user_wallet,
+            vault_pda: vault.vault_pda,
+            created_at: vault.created_at,
+            last_activity: vault.last_activity,
+            approved_amount: vault.approved_amount,
+            used_amount: vault.used_amount,
+            available_amount: vault.available_amount,
+            delegate_wallet: vault.delegate_wallet,
+            delegated_at: vault.delegated_at,
+            session_expires_at: vault.session_expires_at,
+            total_deposited: vault.total_deposited,
+            total_withdrawn: vault.total_withdrawn,
+            trade_count: vault.trade_count,
+            is_active: vault.is_active,
+            is_paused: vault.is_paused,
+            version: 1,
+            bump: vault.bump,
+        };
+        let mut body = borsh::to_vec(&legacy).expect("borsh");
+        body.resize(ephemeralvault::LEGACY_VAULT_SPACE - 8, 0);
+        let data = account_data(ephemeralvault::EphemeralVault::DISCRIMINATOR, &body);
+
+        let parsed = parse_vault_account(&data).expect("legacy vault");
+        assert_eq!(parsed.delegate_wallet, vault.delegate_wallet);
+        assert_eq!(parsed.session_expires_at, vault.session_expires_at);
+        assert_eq!(parsed.version, 1);
+    }
+
+    #[test]
+    fn parse_vault_account_rejects_foreign_accounts() {
+        let data = account_data(&[0; 8], &[0; ephemeralvault::VAULT_SPACE - 8]);
+        assert!(parse_vault_account(&data).is_err());
+    }
+
     #[test]
     fn derive_vault_pda_uses_expected_seed_scheme() {
         let program_id = Pubkey::new_unique();
@@ -819,34 +2530,224 @@ mod tests {
     #[test]
     fn vault_stats_marks_session_states_correctly() {
         let active_vault = sample_vault();
-        let active = to_vault_stats_dto(&active_vault, 1_700_000_200);
+        let active = to_vault_stats_dto(&active_vault, 0, 1_700_000_200).unwrap();
         assert!(matches!(active.session_status, SessionStatusDto::Active));
         assert!(matches!(active.status, VaultStatusDto::Active));
+        assert_eq!(active.seconds_until_expiry, Some(400));
+        assert!(!active.can_renew);
 
-        let expiring = to_vault_stats_dto(&active_vault, 1_700_000_310);
+        let expiring = to_vault_stats_dto(&active_vault, 0, 1_700_000_310).unwrap();
         assert!(matches!(
             expiring.session_status,
             SessionStatusDto::ExpiringSoon
         ));
+        assert!(expiring.can_renew);
 
-        let expired = to_vault_stats_dto(&active_vault, 1_700_000_650);
+        let expired = to_vault_stats_dto(&active_vault, 0, 1_700_000_650).unwrap();
         assert!(matches!(expired.session_status, SessionStatusDto::Expired));
         assert!(matches!(expired.status, VaultStatusDto::Expired));
+        assert_eq!(expired.seconds_until_expiry, Some(0));
+        assert!(!expired.can_renew);
     }
 
     #[test]
     fn vault_stats_prioritizes_pause_and_inactive_flags() {
         let mut paused_vault = sample_vault();
         paused_vault.is_paused = true;
-        let paused = to_vault_stats_dto(&paused_vault, 1_700_000_650);
+        let paused = to_vault_stats_dto(&paused_vault, 0, 1_700_000_650).unwrap();
         assert!(matches!(paused.status, VaultStatusDto::Paused));
 
         let mut inactive_vault = sample_vault();
         inactive_vault.is_active = false;
-        let inactive = to_vault_stats_dto(&inactive_vault, 1_700_000_200);
+        let inactive = to_vault_stats_dto(&inactive_vault, 0, 1_700_000_200).unwrap();
         assert!(matches!(inactive.status, VaultStatusDto::Inactive));
     }
 
+    #[test]
+    fn vault_stats_reports_budget_and_cleanup_eligibility() {
+        let vault = sample_vault();
+        let stats = to_vault_stats_dto(&vault, 500_000, 1_700_000_200).unwrap();
+        assert_eq!(stats.remaining_budget_lamports, 1_750_000);
+        assert!(!stats.can_cleanup);
+        // 1% of 500_000 is below the minimum reward, which is then capped at 10%.
+        assert_eq!(stats.expected_cleanup_reward_lamports, 50_000);
+
+        let mut revoked = sample_vault();
+        revoked.is_active = false;
+        revoked.delegate_wallet = None;
+        revoked.delegated_at = None;
+        revoked.session_expires_at = None;
+        assert!(
+            !to_vault_stats_dto(&revoked, 0, revoked.last_activity)
+                .unwrap()
+                .can_cleanup
+        );
+        let cleanable =
+            to_vault_stats_dto(&revoked, 10_000_000_000, revoked.last_activity + 2).unwrap();
+        assert!(cleanable.can_cleanup);
+        assert_eq!(cleanable.expected_cleanup_reward_lamports, 100_000_000);
+    }
+
+    #[tokio::test]
+    async fn fetch_program_vaults_filters_by_discriminator_and_skips_foreign_data() {
+        let owner = Pubkey::new_unique();
+        let mut vault = ephemeralvault::EphemeralVault::zeroed();
+        vault.user_wallet = to_anchor_pubkey(owner);
+        vault.is_active = 1;
+        vault.version = 2;
+        let data = account_data(
+            ephemeralvault::EphemeralVault::DISCRIMINATOR,
+            bytemuck::bytes_of(&vault),
+        );
+        let vault_address = Pubkey::new_unique();
+        let account = |data: &[u8]| {
+            serde_json::json!({
+                "lamports": 10_000_000,
+                "data": [BASE64.encode(data), "base64"],
+                "owner": ephemeralvault::ID.to_string(),
+                "executable": false,
+                "rentEpoch": 0,
+                "space": data.len(),
+            })
+        };
+        let response = serde_json::json!([
+            { "pubkey": vault_address.to_string(), "account": account(&data) },
+            { "pubkey": Pubkey::new_unique().to_string(), "account": account(&data[..40]) },
+        ]);
+        let rpc = crate::test_support::mock_rpc(move |request, params| {
+            assert_eq!(
+                request,
+                solana_client::rpc_request::RpcRequest::GetProgramAccounts
+            );
+            assert_eq!(params[0], ephemeralvault::ID.to_string());
+            let memcmp = &params[1]["filters"][0]["memcmp"];
+            assert_eq!(memcmp["offset"], 0);
+            assert_eq!(
+                memcmp["bytes"],
+                bs58::encode(ephemeralvault::EphemeralVault::DISCRIMINATOR).into_string()
+            );
+            response.clone()
+        });
+        let config = Config {
+            program_id: ephemeralvault::ID.to_string(),
+            ..crate::test_support::config()
+        };
+
+        let vaults = fetch_program_vaults(&rpc, &config, Vec::new())
+            .await
+            .unwrap();
+
+        assert_eq!(vaults.len(), 1);
+        assert_eq!(vaults[0].address, vault_address);
+        assert_eq!(vaults[0].vault.user_wallet, owner);
+    }
+
+    #[test]
+    fn vault_filter_offsets_match_zero_copy_layout() {
+        assert_eq!(USER_WALLET_OFFSET, 8);
+        assert_eq!(DELEGATE_WALLET_OFFSET, 72);
+        assert_eq!(IS_ACTIVE_OFFSET, 184);
+        assert_eq!(IS_PAUSED_OFFSET, 185);
+
+        let delegate = Pubkey::new_unique();
+        let mut vault = ephemeralvault::EphemeralVault::zeroed();
+        vault.delegate_wallet = to_anchor_pubkey(delegate);
+        vault.is_active = 1;
+        vault.is_paused = 1;
+        let data = account_data(
+            ephemeralvault::EphemeralVault::DISCRIMINATOR,
+            bytemuck::bytes_of(&vault),
+        );
+
+        let filter = VaultListFilter {
+            delegate: Some(delegate),
+            status: Some(VaultStatusDto::Paused),
+            ..VaultListFilter::default()
+        };
+        let filters = filter.rpc_filters();
+        assert_eq!(filters.len(), 4);
+        for rpc_filter in &filters {
+            match rpc_filter {
+                RpcFilterType::Memcmp(memcmp) => assert!(memcmp.bytes_match(&data)),
+                RpcFilterType::DataSize(size) => assert_eq!(*size as usize, data.len()),
+                other => panic!("unexpected filter {other:?}"),
+            }
+        }
+
+        assert!(VaultListFilter::default().rpc_filters().is_empty());
+        let undelegated = VaultListFilter {
+            has_delegate: Some(false),
+            ..VaultListFilter::default()
+        };
+        let RpcFilterType::Memcmp(memcmp) = &undelegated.rpc_filters()[0] else {
+            panic!("expected memcmp");
+        };
+        assert!(!memcmp.bytes_match(&data));
+    }
+
+    #[test]
+    fn page_vaults_applies_time_based_filters_and_cursor() {
+        let now = 1_700_000_000;
+        let program_vault = |session_expires_at: Option<i64>| {
+            let mut vault = sample_vault();
+            vault.version = 2;
+            vault.session_expires_at = session_expires_at;
+            ProgramVault {
+                address: Pubkey::new_unique(),
+                vault,
+                surplus_lamports: 0,
+            }
+        };
+        let vaults = vec![
+            program_vault(Some(now + 60)),
+            program_vault(Some(now + 120)),
+            program_vault(Some(now + 3_600)),
+            program_vault(Some(now - 1)),
+            program_vault(None),
+        ];
+        let mut expiring: Vec<Pubkey> = vaults[..2].iter().map(|v| v.address).collect();
+        expiring.sort();
+        let filter = VaultListFilter {
+            expiring_within_secs: Some(300),
+            ..VaultListFilter::default()
+        };
+
+        let first = page_vaults(vaults.clone(), &filter, None, 1, now).unwrap();
+        assert_eq!(first.vaults.len(), 1);
+        assert_eq!(first.vaults[0].address, expiring[0].to_string());
+        assert_eq!(first.next_cursor, Some(expiring[0].to_string()));
+
+        let second = page_vaults(vaults.clone(), &filter, Some(expiring[0]), 1, now).unwrap();
+        assert_eq!(second.vaults.len(), 1);
+        assert_eq!(second.vaults[0].address, expiring[1].to_string());
+        assert_eq!(second.next_cursor, None);
+
+        let all = page_vaults(vaults, &VaultListFilter::default(), None, 10, now).unwrap();
+        assert_eq!(all.vaults.len(), 5);
+        assert!(all
+            .vaults
+            .windows(2)
+            .all(|pair| pair[0].address.parse::<Pubkey>().unwrap()
+                < pair[1].address.parse::<Pubkey>().unwrap()));
+    }
+
+    #[test]
+    fn vault_rent_surplus_excludes_rent_exempt_minimum() {
+        let rent = Rent::default().minimum_balance(ephemeralvault::VAULT_SPACE);
+        let account = Account {
+            lamports: rent + 42,
+            data: vec![0; ephemeralvault::VAULT_SPACE],
+            ..Account::default()
+        };
+        assert_eq!(vault_rent_surplus(&account), 42);
+
+        let underfunded = Account {
+            lamports: 1,
+            ..account
+        };
+        assert_eq!(vault_rent_surplus(&underfunded), 0);
+    }
+
     #[test]
     fn create_vault_instruction_matches_contract_accounts() {
         let program_id = Pubkey::new_unique();
@@ -893,6 +2794,520 @@ mod tests {
         );
     }
 
+    #[test]
+    fn migrate_vault_instruction_funds_resize_from_owner() {
+        let program_id = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let ix = migrate_vault_instruction(program_id, user, vault);
+
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(vault, false),
+                AccountMeta::new(user, true),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ]
+        );
+        assert_eq!(ix.data, ephemeralvault::instruction::MigrateVault {}.data());
+    }
+
+    fn trade_entries(count: u64) -> Vec<ephemeralvault::TradeEntry> {
+        (1..=count)
+            .map(|client_id| ephemeralvault::TradeEntry {
+                trade_fee: 5_000,
+                trade_amount: 1_000_000,
+                client_id,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn execute_trade_instruction_encodes_client_order_id() {
+        let program_id = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let ix = execute_trade_instruction(program_id, delegate, vault, 5_000, 1_000_000, 77);
+
+        assert_eq!(
+            ix.data,
+            ephemeralvault::instruction::ExecuteTrade {
+                trade_fee: 5_000,
+                trade_amount: 1_000_000,
+                client_order_id: 77,
+            }
+            .data()
+        );
+    }
+
+    #[test]
+    fn execute_trades_batch_instruction_encodes_entries() {
+        let program_id = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let entries = trade_entries(3);
+        let ix = execute_trades_batch_instruction(program_id, delegate, vault, &entries);
+
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(vault, false),
+                AccountMeta::new_readonly(delegate, true),
+            ]
+        );
+        assert_eq!(
+            ix.data,
+            ephemeralvault::instruction::ExecuteTradesBatch { entries }.data()
+        );
+    }
+
+    #[test]
+    fn packable_trade_entries_respects_batch_and_packet_limits() {
+        let program_id = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let blockhash = Hash::new_unique();
+        let legacy = MessageFormat::Legacy;
+
+        let few = trade_entries(3);
+        assert_eq!(
+            packable_trade_entries(program_id, delegate, vault, &few, &[], blockhash, &legacy)
+                .unwrap(),
+            3
+        );
+
+        let many = trade_entries(200);
+        let included =
+            packable_trade_entries(program_id, delegate, vault, &many, &[], blockhash, &legacy)
+                .unwrap();
+        assert!(included > 0 && included <= ephemeralvault::MAX_BATCH_TRADES);
+
+        let ix = execute_trades_batch_instruction(program_id, delegate, vault, &many[..included]);
+        assert!(
+            serialized_transaction_size(delegate, &[ix], blockhash, &legacy).unwrap()
+                <= PACKET_DATA_SIZE
+        );
+
+        let reserved = compute_budget_instructions(Some(MAX_COMPUTE_UNIT_LIMIT), 1);
+        let budgeted = packable_trade_entries(
+            program_id, delegate, vault, &many, &reserved, blockhash, &legacy,
+        )
+        .unwrap();
+        assert!(budgeted > 0 && budgeted <= included);
+        let mut instructions = reserved;
+        instructions.push(execute_trades_batch_instruction(
+            program_id,
+            delegate,
+            vault,
+            &many[..budgeted],
+        ));
+        assert!(
+            serialized_transaction_size(delegate, &instructions, blockhash, &legacy).unwrap()
+                <= PACKET_DATA_SIZE
+        );
+
+        let v0 = MessageFormat::V0(vec![AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![vault],
+        }]);
+        let versioned =
+            packable_trade_entries(program_id, delegate, vault, &many, &[], blockhash, &v0)
+                .unwrap();
+        let ix = execute_trades_batch_instruction(program_id, delegate, vault, &many[..versioned]);
+        assert!(
+            serialized_transaction_size(delegate, &[ix], blockhash, &v0).unwrap()
+                <= PACKET_DATA_SIZE
+        );
+    }
+
+    #[test]
+    fn compile_transaction_draws_v0_accounts_from_lookup_tables() {
+        let program_id = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let blockhash = Hash::new_unique();
+        let ix = pause_instruction(program_id, user, vault);
+
+        let legacy = compile_transaction(
+            user,
+            std::slice::from_ref(&ix),
+            blockhash,
+            &MessageFormat::Legacy,
+        )
+        .unwrap();
+        assert!(matches!(legacy.message, VersionedMessage::Legacy(_)));
+        assert_eq!(legacy.signatures.len(), 1);
+
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![Pubkey::new_unique(), vault],
+        };
+        let v0 = compile_transaction(
+            user,
+            &[ix],
+            blockhash,
+            &MessageFormat::V0(vec![table.clone()]),
+        )
+        .unwrap();
+        assert!(!v0.message.static_account_keys().contains(&vault));
+        let lookups = v0.message.address_table_lookups().unwrap();
+        assert_eq!(lookups.len(), 1);
+        assert_eq!(lookups[0].account_key, table.key);
+        assert_eq!(lookups[0].writable_indexes, vec![1]);
+
+        let keys = message_account_keys(
+            &v0.message,
+            &LoadedAddresses {
+                writable: vec![vault],
+                readonly: Vec::new(),
+            },
+        );
+        let accounts: Vec<_> = v0.message.instructions()[0]
+            .accounts
+            .iter()
+            .map(|&index| keys[usize::from(index)])
+            .collect();
+        assert_eq!(accounts, vec![vault, user]);
+
+        let encoded = BASE64.encode(bincode::serialize(&v0).unwrap());
+        assert_eq!(decode_transaction_base64(&encoded).unwrap(), v0);
+        let encoded = BASE64.encode(bincode::serialize(&legacy).unwrap());
+        assert_eq!(decode_transaction_base64(&encoded).unwrap(), legacy);
+    }
+
+    #[test]
+    fn percentile_unit_price_picks_percentile_and_caps() {
+        assert_eq!(percentile_unit_price(Vec::new(), 75, 1_000), 0);
+        assert_eq!(
+            percentile_unit_price(vec![300, 0, 5_000, 100, 200], 75, 10_000),
+            300
+        );
+        assert_eq!(
+            percentile_unit_price(vec![300, 0, 5_000, 100, 200], 100, 10_000),
+            5_000
+        );
+        assert_eq!(
+            percentile_unit_price(vec![300, 0, 5_000, 100, 200], 100, 1_000),
+            1_000
+        );
+        assert_eq!(
+            percentile_unit_price(vec![300, 0, 5_000, 100, 200], 0, 1_000),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn compute_budget_uses_simulated_limit_and_recent_fees() {
+        let svm = crate::test_support::LocalSvm::new(1_700_100_000);
+        let rpc = svm.rpc();
+        let config = crate::test_support::config();
+        let owner = Pubkey::new_unique();
+        let (vault_pda, bump) = derive_vault_pda(&program_id(&config).unwrap(), &owner);
+        let vault = svm.add_vault(
+            &EphemeralVaultAccount {
+                user_wallet: owner,
+                vault_pda,
+                available_amount: 0,
+                delegate_wallet: None,
+                delegated_at: None,
+                session_expires_at: None,
+                is_active: false,
+                version: 2,
+                bump,
+                ..sample_vault()
+            },
+            0,
+        );
+        let cleaner = Pubkey::new_unique();
+        svm.set_account(cleaner, Account::new(1_000_000_000, 0, &system_program::ID));
+        svm.set_prioritization_fees(vec![300, 0, 5_000, 100, 200]);
+        let build = |options: TxOptions| {
+            let rpc = &rpc;
+            let config = &config;
+            async move { build_cleanup_tx(rpc, config, vault, cleaner, &options).await }
+        };
+
+        let plain = build(TxOptions::default()).await.unwrap();
+        assert_eq!(plain.compute_unit_limit, None);
+        assert_eq!(plain.compute_unit_price_micro_lamports, None);
+        let tx = decode_transaction_base64(&plain.transaction_base64).unwrap();
+        assert_eq!(tx.message.instructions().len(), 1);
+
+        let mut options = TxOptions::default();
+        options.compute_budget.enabled = Some(true);
+        let budgeted = build(options.clone()).await.unwrap();
+        // Two budget instructions and the cleanup, plus the 10% margin.
+        let expected_limit =
+            (2 * 150 + crate::test_support::UNITS_PER_VAULT_INSTRUCTION as u32) * 110 / 100;
+        assert_eq!(budgeted.compute_unit_limit, Some(expected_limit));
+        assert_eq!(budgeted.compute_unit_price_micro_lamports, Some(300));
+        let tx = decode_transaction_base64(&budgeted.transaction_base64).unwrap();
+        let data: Vec<_> = tx
+            .message
+            .instructions()
+            .iter()
+            .map(|ix| ix.data.clone())
+            .collect();
+        assert_eq!(
+            data[..2],
+            [
+                ComputeBudgetInstruction::set_compute_unit_limit(expected_limit).data,
+                ComputeBudgetInstruction::set_compute_unit_price(300).data,
+            ]
+        );
+
+        options.compute_budget = ComputeBudgetOptions {
+            enabled: None,
+            unit_limit: Some(50_000),
+            unit_price_micro_lamports: Some(7),
+        };
+        let explicit = build(options.clone()).await.unwrap();
+        assert_eq!(explicit.compute_unit_limit, Some(50_000));
+        assert_eq!(explicit.compute_unit_price_micro_lamports, Some(7));
+
+        options.compute_budget.enabled = Some(false);
+        assert_eq!(
+            build(options.clone()).await.unwrap().compute_unit_limit,
+            None
+        );
+
+        options.compute_budget.enabled = None;
+        options.compute_budget.unit_limit = Some(0);
+        assert!(matches!(build(options).await, Err(AppError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn durable_nonce_transactions_advance_the_nonce_first() {
+        let svm = crate::test_support::LocalSvm::new(1_700_000_200);
+        let rpc = svm.rpc();
+        let config = crate::test_support::config();
+        let owner = Pubkey::new_unique();
+        let (vault_pda, bump) = derive_vault_pda(&program_id(&config).unwrap(), &owner);
+        svm.add_vault(
+            &EphemeralVaultAccount {
+                user_wallet: owner,
+                vault_pda,
+                version: 2,
+                bump,
+                ..sample_vault()
+            },
+            0,
+        );
+        svm.set_account(owner, Account::new(1_000_000_000, 0, &system_program::ID));
+
+        let recent = build_pause_tx(&rpc, &config, owner, &TxOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(recent.lifetime, TxLifetimeMode::Blockhash);
+        assert!(recent.last_valid_block_height.is_some());
+        assert_eq!(recent.nonce_account, None);
+
+        let nonce_account = derive_nonce_account(&owner, DEFAULT_NONCE_SEED).unwrap();
+        let nonce = svm.add_nonce_account(nonce_account, owner);
+        let options = TxOptions {
+            compute_budget: ComputeBudgetOptions {
+                enabled: None,
+                unit_limit: Some(50_000),
+                unit_price_micro_lamports: Some(7),
+            },
+            nonce_account: Some(nonce_account.to_string()),
+            ..TxOptions::default()
+        };
+        let durable = build_pause_tx(&rpc, &config, owner, &options)
+            .await
+            .unwrap();
+        assert_eq!(durable.lifetime, TxLifetimeMode::DurableNonce);
+        assert_eq!(durable.last_valid_block_height, None);
+        assert_eq!(durable.nonce_account, Some(nonce_account.to_string()));
+
+        let tx = decode_transaction_base64(&durable.transaction_base64).unwrap();
+        assert_eq!(*tx.message.recent_blockhash(), nonce);
+        let keys = tx.message.static_account_keys();
+        let programs: Vec<_> = tx
+            .message
+            .instructions()
+            .iter()
+            .map(|ix| keys[usize::from(ix.program_id_index)])
+            .collect();
+        assert_eq!(
+            programs,
+            vec![
+                system_program::ID,
+                solana_sdk::compute_budget::ID,
+                solana_sdk::compute_budget::ID,
+                program_id(&config).unwrap(),
+            ]
+        );
+        assert_eq!(
+            durable_nonce_account(&tx.message, keys),
+            Some(nonce_account)
+        );
+        let recent = decode_transaction_base64(&recent.transaction_base64).unwrap();
+        assert_eq!(
+            durable_nonce_account(&recent.message, recent.message.static_account_keys()),
+            None
+        );
+
+        svm.expire_blockhash();
+        let simulation = simulate_transaction_base64(&rpc, &config, &durable.transaction_base64)
+            .await
+            .unwrap();
+        assert!(simulation.ok, "{:?}", simulation.error);
+
+        let unknown = TxOptions {
+            nonce_account: Some(Pubkey::new_unique().to_string()),
+            ..TxOptions::default()
+        };
+        assert!(matches!(
+            build_pause_tx(&rpc, &config, owner, &unknown).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn composed_operations_split_across_ordered_transactions() {
+        let svm = crate::test_support::LocalSvm::new(1_700_000_200);
+        let rpc = svm.rpc();
+        let config = crate::test_support::config();
+        let owner = Pubkey::new_unique();
+        svm.set_account(owner, Account::new(1_000_000_000, 0, &system_program::ID));
+
+        let mut operations = vec![
+            VaultOperation::CreateVault {
+                approved_amount: 100_000_000,
+            },
+            VaultOperation::ApproveDelegate {
+                delegate: Pubkey::new_unique(),
+                custom_duration: None,
+                funding_lamports: 5_000_000,
+            },
+        ];
+        operations.extend((0..8).map(|_| VaultOperation::Deposit { amount: 1_000_000 }));
+
+        let composed = build_composed_txs(&rpc, &config, owner, &operations, &TxOptions::default())
+            .await
+            .unwrap();
+        let groups: Vec<_> = composed
+            .transactions
+            .iter()
+            .map(|tx| tx.operations.clone())
+            .collect();
+        assert_eq!(groups, vec![(0..6).collect::<Vec<_>>(), (6..10).collect()]);
+        let counts: Vec<_> = composed
+            .transactions
+            .iter()
+            .map(|composed| {
+                decode_transaction_base64(&composed.transaction.transaction_base64)
+                    .unwrap()
+                    .message
+                    .instructions()
+                    .len()
+            })
+            .collect();
+        assert_eq!(counts, vec![MAX_COMPOSED_INSTRUCTIONS, 4]);
+
+        let nonce_account = derive_nonce_account(&owner, DEFAULT_NONCE_SEED).unwrap();
+        svm.add_nonce_account(nonce_account, owner);
+        let durable = TxOptions {
+            nonce_account: Some(nonce_account.to_string()),
+            ..TxOptions::default()
+        };
+        assert!(matches!(
+            build_composed_txs(&rpc, &config, owner, &operations, &durable).await,
+            Err(AppError::Validation(_))
+        ));
+        let single = build_composed_txs(&rpc, &config, owner, &operations[..2], &durable)
+            .await
+            .unwrap();
+        assert_eq!(single.transactions.len(), 1);
+        assert_eq!(
+            single.transactions[0].transaction.lifetime,
+            TxLifetimeMode::DurableNonce
+        );
+
+        assert!(matches!(
+            build_composed_txs(&rpc, &config, owner, &[], &TxOptions::default()).await,
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn approve_delegate_tx_can_fund_the_delegate_first() {
+        let svm = crate::test_support::LocalSvm::new(1_700_000_200);
+        let rpc = svm.rpc();
+        let config = crate::test_support::config();
+        let owner = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        // Only the encoding matters here; there is no vault to check.
+        let options = TxOptions {
+            skip_state_checks: true,
+            ..TxOptions::default()
+        };
+
+        let funded =
+            build_approve_delegate_tx(&rpc, &config, owner, delegate, None, 10_000_000, &options)
+                .await
+                .unwrap();
+        let tx = decode_transaction_base64(&funded.transaction_base64).unwrap();
+        let instructions = tx.message.instructions();
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(
+            bincode::deserialize::<SystemInstruction>(&instructions[0].data).unwrap(),
+            SystemInstruction::Transfer {
+                lamports: 10_000_000
+            }
+        );
+
+        let unfunded = build_approve_delegate_tx(&rpc, &config, owner, delegate, None, 0, &options)
+            .await
+            .unwrap();
+        let tx = decode_transaction_base64(&unfunded.transaction_base64).unwrap();
+        assert_eq!(tx.message.instructions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn create_nonce_tx_funds_the_owners_derived_nonce_account() {
+        let svm = crate::test_support::LocalSvm::new(1_700_000_200);
+        let rpc = svm.rpc();
+        let config = crate::test_support::config();
+        let owner = Pubkey::new_unique();
+
+        let created = build_create_nonce_tx(&rpc, &config, owner, "desk-1", &TxOptions::default())
+            .await
+            .unwrap();
+        let address = Pubkey::create_with_seed(&owner, "desk-1", &system_program::ID).unwrap();
+        assert_eq!(created.nonce_account, address.to_string());
+        assert_eq!(created.seed, "desk-1");
+
+        let tx = decode_transaction_base64(&created.transaction.transaction_base64).unwrap();
+        let instructions = tx.message.instructions();
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(
+            bincode::deserialize::<SystemInstruction>(&instructions[0].data).unwrap(),
+            SystemInstruction::CreateAccountWithSeed {
+                base: owner,
+                seed: "desk-1".into(),
+                lamports: Rent::default().minimum_balance(nonce::state::State::size()),
+                space: nonce::state::State::size() as u64,
+                owner: system_program::ID,
+            }
+        );
+        assert_eq!(
+            bincode::deserialize::<SystemInstruction>(&instructions[1].data).unwrap(),
+            SystemInstruction::InitializeNonceAccount(owner)
+        );
+
+        assert!(matches!(
+            fetch_nonce_account(&rpc, owner, "desk-1").await,
+            Err(AppError::NotFound(_))
+        ));
+        let nonce = svm.add_nonce_account(address, owner);
+        let fetched = fetch_nonce_account(&rpc, owner, "desk-1").await.unwrap();
+        assert_eq!(fetched.address, address.to_string());
+        assert_eq!(fetched.authority, owner.to_string());
+        assert_eq!(fetched.nonce, nonce.to_string());
+    }
+
     #[test]
     fn cleanup_instruction_orders_accounts_for_close() {
         let program_id = Pubkey::new_unique();
@@ -912,4 +3327,173 @@ mod tests {
         );
         assert_eq!(ix.data, ephemeralvault::instruction::CleanupVault {}.data());
     }
+
+    /// Encodes `ix` in the input layout of the program's `entrypoint` fuzz
+    /// target, mapping each account onto its fixed pool slot.
+    fn fuzz_input(warp_minutes: u8, pool: &[Pubkey; 8], ix: &Instruction) -> Vec<u8> {
+        let mut input = vec![warp_minutes, ix.accounts.len() as u8];
+        for meta in &ix.accounts {
+            let slot = pool
+                .iter()
+                .position(|key| *key == meta.pubkey)
+                .expect("account in fuzz pool") as u8;
+            input.push(slot | (u8::from(meta.is_signer) << 4) | (u8::from(meta.is_writable) << 5));
+        }
+        input.extend_from_slice(&ix.data);
+        input
+    }
+
+    /// One fuzz seed per instruction encoder, named after the instruction.
+    fn fuzz_seeds() -> Vec<(&'static str, Vec<u8>)> {
+        let program_id = from_anchor_pubkey(ephemeralvault::ID);
+        let user = Pubkey::new_from_array([1; 32]);
+        let delegate = Pubkey::new_from_array([2; 32]);
+        let attacker = Pubkey::new_from_array([3; 32]);
+        let user_vault = derive_vault_pda(&program_id, &user).0;
+        let attacker_vault = derive_vault_pda(&program_id, &attacker).0;
+        let pool = [
+            user,
+            delegate,
+            attacker,
+            user_vault,
+            attacker_vault,
+            system_program::ID,
+            program_id,
+            derive_vault_pda(&program_id, &Pubkey::new_from_array([4; 32])).0,
+        ];
+        let sol = 1_000_000_000;
+
+        vec![
+            (
+                "create_ephemeral_vault",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &create_vault_instruction(program_id, user, user_vault, 2 * sol),
+                ),
+            ),
+            (
+                "approve_delegate",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &approve_delegate_instruction(
+                        program_id,
+                        user,
+                        user_vault,
+                        delegate,
+                        Some(600),
+                    ),
+                ),
+            ),
+            (
+                "renew_session",
+                fuzz_input(55, &pool, &renew_instruction(program_id, user, user_vault)),
+            ),
+            (
+                "auto_deposit_for_trade",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &deposit_instruction(program_id, user, user_vault, sol / 2),
+                ),
+            ),
+            (
+                "execute_trade",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &execute_trade_instruction(program_id, delegate, user_vault, 1_000, 10_000, 1),
+                ),
+            ),
+            (
+                "execute_trades_batch",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &execute_trades_batch_instruction(
+                        program_id,
+                        delegate,
+                        user_vault,
+                        &trade_entries(3),
+                    ),
+                ),
+            ),
+            (
+                "withdraw_balance",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &withdraw_instruction(program_id, user, user_vault, 0),
+                ),
+            ),
+            (
+                "revoke_access",
+                fuzz_input(0, &pool, &revoke_instruction(program_id, user, user_vault)),
+            ),
+            (
+                "reactivate_vault",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &reactivate_instruction(program_id, attacker, attacker_vault),
+                ),
+            ),
+            (
+                "update_approved_amount",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &update_approved_amount_instruction(program_id, user, user_vault, 3 * sol),
+                ),
+            ),
+            (
+                "emergency_pause",
+                fuzz_input(0, &pool, &pause_instruction(program_id, user, user_vault)),
+            ),
+            (
+                "unpause_vault",
+                fuzz_input(0, &pool, &unpause_instruction(program_id, user, user_vault)),
+            ),
+            (
+                "cleanup_vault",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &cleanup_instruction(program_id, attacker_vault, attacker, user),
+                ),
+            ),
+            (
+                "migrate_vault",
+                fuzz_input(
+                    0,
+                    &pool,
+                    &migrate_vault_instruction(program_id, user, user_vault),
+                ),
+            ),
+        ]
+    }
+
+    /// Keeps the committed fuzz seeds in sync with the encoders. Run with
+    /// `UPDATE_FUZZ_SEEDS=1` to rewrite them after changing an instruction.
+    #[test]
+    fn fuzz_seeds_match_instruction_encoders() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../programs/ephemeralvault/fuzz/seeds/entrypoint");
+        let update = std::env::var_os("UPDATE_FUZZ_SEEDS").is_some();
+
+        for (name, input) in fuzz_seeds() {
+            let path = dir.join(name);
+            if update {
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(&path, &input).unwrap();
+            }
+            assert_eq!(
+                std::fs::read(&path).ok().as_deref(),
+                Some(input.as_slice()),
+                "{} is stale; rerun with UPDATE_FUZZ_SEEDS=1",
+                path.display()
+            );
+        }
+    }
 }
@@ -0,0 +1,333 @@
+//! Fee-payer sponsorship: owner transactions requested with `"sponsored":
+//! true` are paid for and partially signed by the backend's fee payer, within
+//! per-owner and total daily budgets kept in `sponsorship_usage` and
+//! `sponsorship_totals`, for owners whose vault exists and is active.
+
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+
+use crate::config::Config;
+use crate::db::models::{SponsorshipLimits, SponsorshipReservation};
+use crate::db::queries;
+use crate::error::{AppError, Result};
+use crate::solana::{self, TxEnvelope, TxOptions};
+use crate::AppState;
+
+fn fee_payer(state: &AppState) -> Result<&Keypair> {
+    state
+        .fee_payer
+        .as_deref()
+        .filter(|_| state.config.sponsorship_enabled)
+        .ok_or_else(|| AppError::Validation("transaction sponsorship is disabled".into()))
+}
+
+/// `options` with the fee payer filled in when the request asks for
+/// sponsorship. Sponsored requests must keep the state checks, so the fee
+/// payer never pays for a transaction the program is known to reject.
+pub fn options(state: &AppState, options: &TxOptions) -> Result<TxOptions> {
+    let mut options = options.clone();
+    if options.sponsored {
+        if options.skip_state_checks {
+            return Err(AppError::Validation(
+                "skipStateChecks cannot be used with sponsored".into(),
+            ));
+        }
+        options.fee_payer = Some(fee_payer(state)?.pubkey());
+    }
+    Ok(options)
+}
+
+/// Fails unless `owner` has an active vault; only those are sponsored.
+async fn check_vault(rpc: &RpcClient, config: &Config, owner: Pubkey) -> Result<()> {
+    let vault = solana::fetch_vault_by_user(rpc, config, owner).await?;
+    if !vault.is_active {
+        return Err(AppError::Validation(format!(
+            "vault {} is not active; only active vaults are sponsored",
+            vault.address
+        )));
+    }
+    Ok(())
+}
+
+/// Signs `envelope` as its fee payer, leaving the other signatures for the
+/// owner's wallet. Refuses messages whose instructions use the fee payer's
+/// account, so its signature can only ever pay fees.
+pub fn partially_sign(envelope: &mut TxEnvelope, fee_payer: &Keypair) -> Result<()> {
+    let mut tx = solana::decode_transaction_base64(&envelope.transaction_base64)?;
+    if tx.message.static_account_keys().first() != Some(&fee_payer.pubkey()) {
+        return Err(AppError::Internal(
+            "sponsored transaction is not paid by the fee payer".into(),
+        ));
+    }
+    if tx
+        .message
+        .instructions()
+        .iter()
+        .any(|ix| ix.accounts.contains(&0))
+    {
+        return Err(AppError::Validation(
+            "a sponsored transaction may only use the fee payer to pay fees".into(),
+        ));
+    }
+    tx.signatures[0] = fee_payer.sign_message(&tx.message.serialize());
+    envelope.transaction_base64 = solana::encode_transaction_base64(&tx)?;
+    Ok(())
+}
+
+/// Signs a sponsored `envelope` as its fee payer and charges it to `owner`'s
+/// budget and the total budget for today. Envelopes built without
+/// sponsorship pass through.
+pub async fn finish(
+    state: &AppState,
+    owner: Pubkey,
+    options: &TxOptions,
+    mut envelope: TxEnvelope,
+) -> Result<TxEnvelope> {
+    if options.fee_payer.is_none() {
+        return Ok(envelope);
+    }
+    let fee_payer = fee_payer(state)?;
+    let config = &state.config;
+    check_vault(&state.rpc, config, owner).await?;
+
+    let tx = solana::decode_transaction_base64(&envelope.transaction_base64)?;
+    let fee = solana::estimated_fee_lamports(
+        &tx.message,
+        envelope.compute_unit_limit,
+        envelope.compute_unit_price_micro_lamports,
+    );
+    if fee > config.sponsorship_max_fee_lamports {
+        return Err(AppError::Validation(format!(
+            "transaction fee of {fee} lamports exceeds the sponsorship limit of {} per transaction",
+            config.sponsorship_max_fee_lamports
+        )));
+    }
+
+    // Sign first so a transaction the fee payer refuses spends no budget.
+    partially_sign(&mut envelope, fee_payer)?;
+
+    let limits = SponsorshipLimits {
+        owner_transactions: config.sponsorship_daily_transactions as i64,
+        owner_fee_lamports: config.sponsorship_daily_lamports as i64,
+        total_transactions: config.sponsorship_total_daily_transactions as i64,
+        total_fee_lamports: config.sponsorship_total_daily_lamports as i64,
+    };
+    match queries::reserve_sponsorship(
+        &state.db,
+        &owner.to_string(),
+        Utc::now().date_naive(),
+        fee as i64,
+        &limits,
+    )
+    .await?
+    {
+        SponsorshipReservation::Reserved(_) => {}
+        SponsorshipReservation::OwnerBudgetSpent => {
+            return Err(AppError::RateLimited(format!(
+                "daily sponsorship budget for {owner} is used up"
+            )))
+        }
+        SponsorshipReservation::TotalBudgetSpent => {
+            return Err(AppError::RateLimited(
+                "daily sponsorship budget across all owners is used up".into(),
+            ))
+        }
+    }
+
+    envelope.sponsored_fee_lamports = Some(fee);
+    Ok(envelope)
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SponsorshipBudgetDto {
+    pub owner: String,
+    pub enabled: bool,
+    pub day: NaiveDate,
+    pub transaction_count: u64,
+    pub fee_lamports: u64,
+    pub remaining_transactions: u64,
+    pub remaining_fee_lamports: u64,
+}
+
+pub async fn budget(state: &AppState, owner: Pubkey) -> Result<SponsorshipBudgetDto> {
+    let config = &state.config;
+    let day = Utc::now().date_naive();
+    let usage = queries::get_sponsorship_usage(&state.db, &owner.to_string(), day).await?;
+    let transaction_count = usage
+        .as_ref()
+        .map_or(0, |usage| usage.transaction_count.max(0) as u64);
+    let fee_lamports = usage
+        .as_ref()
+        .map_or(0, |usage| usage.fee_lamports.max(0) as u64);
+    let totals = queries::get_sponsorship_totals(&state.db, day).await?;
+    let total_transactions = totals
+        .as_ref()
+        .map_or(0, |totals| totals.transaction_count.max(0) as u64);
+    let total_fee_lamports = totals
+        .as_ref()
+        .map_or(0, |totals| totals.fee_lamports.max(0) as u64);
+
+    Ok(SponsorshipBudgetDto {
+        owner: owner.to_string(),
+        enabled: fee_payer(state).is_ok(),
+        day,
+        transaction_count,
+        fee_lamports,
+        remaining_transactions: config
+            .sponsorship_daily_transactions
+            .saturating_sub(transaction_count)
+            .min(
+                config
+                    .sponsorship_total_daily_transactions
+                    .saturating_sub(total_transactions),
+            ),
+        remaining_fee_lamports: config
+            .sponsorship_daily_lamports
+            .saturating_sub(fee_lamports)
+            .min(
+                config
+                    .sponsorship_total_daily_lamports
+                    .saturating_sub(total_fee_lamports),
+            ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, LocalSvm};
+    use solana_sdk::{account::Account, system_program, transaction::VersionedTransaction};
+
+    #[tokio::test]
+    async fn sponsors_only_owners_with_an_active_vault() {
+        let svm = LocalSvm::new(1_700_000_000);
+        let rpc = svm.rpc();
+        let config = test_support::config();
+        let active = Pubkey::new_unique();
+        let inactive = Pubkey::new_unique();
+        svm.add_vault(&test_support::vault(active, 1_700_000_000), 0);
+        svm.add_vault(
+            &crate::solana::EphemeralVaultAccount {
+                is_active: false,
+                ..test_support::vault(inactive, 1_700_000_000)
+            },
+            0,
+        );
+
+        assert!(check_vault(&rpc, &config, active).await.is_ok());
+        assert!(matches!(
+            check_vault(&rpc, &config, inactive).await,
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(
+            check_vault(&rpc, &config, Pubkey::new_unique()).await,
+            Err(AppError::VaultNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sponsored_transactions_only_cost_the_fee_payer() {
+        let svm = LocalSvm::new(1_700_000_000);
+        let rpc = svm.rpc();
+        let config = test_support::config();
+        let owner = Keypair::new();
+        let fee_payer = Keypair::new();
+        svm.add_vault(&test_support::vault(owner.pubkey(), 1_700_000_000), 0);
+        svm.set_account(
+            fee_payer.pubkey(),
+            Account::new(1_000_000_000, 0, &system_program::ID),
+        );
+
+        let options = TxOptions {
+            sponsored: true,
+            fee_payer: Some(fee_payer.pubkey()),
+            ..TxOptions::default()
+        };
+        let mut envelope = solana::build_pause_tx(&rpc, &config, owner.pubkey(), &options)
+            .await
+            .unwrap();
+        assert_eq!(envelope.fee_payer, fee_payer.pubkey().to_string());
+        partially_sign(&mut envelope, &fee_payer).unwrap();
+
+        let mut tx = solana::decode_transaction_base64(&envelope.transaction_base64).unwrap();
+        let keys = tx.message.static_account_keys().to_vec();
+        assert_eq!(keys[0], fee_payer.pubkey());
+        assert_eq!(
+            tx.verify_with_results(),
+            vec![true, false],
+            "only the fee payer has signed"
+        );
+        assert_eq!(
+            solana::estimated_fee_lamports(&tx.message, None, None),
+            10_000
+        );
+
+        tx.signatures[1] = owner.sign_message(&tx.message.serialize());
+        let signed = VersionedTransaction {
+            signatures: tx.signatures,
+            message: tx.message,
+        };
+        let encoded = solana::encode_transaction_base64(&signed).unwrap();
+        let simulation = solana::simulate_transaction_base64(&rpc, &config, &encoded)
+            .await
+            .unwrap();
+        assert!(simulation.ok, "{:?}", simulation.error);
+
+        let unsponsorable = TxOptions {
+            sponsored: true,
+            ..TxOptions::default()
+        };
+        assert!(matches!(
+            solana::build_pause_tx(&rpc, &config, owner.pubkey(), &unsponsorable).await,
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_sign_for_the_fee_payers_own_accounts() {
+        let svm = LocalSvm::new(1_700_000_000);
+        let rpc = svm.rpc();
+        let config = test_support::config();
+        let owner = Keypair::new();
+        svm.add_vault(&test_support::vault(owner.pubkey(), 1_700_000_000), 0);
+
+        let options = TxOptions {
+            sponsored: true,
+            fee_payer: Some(owner.pubkey()),
+            ..TxOptions::default()
+        };
+        let mut envelope = solana::build_pause_tx(&rpc, &config, owner.pubkey(), &options)
+            .await
+            .unwrap();
+        assert!(matches!(
+            partially_sign(&mut envelope, &owner),
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn estimates_priority_fees_from_the_unit_limit() {
+        let payer = Pubkey::new_unique();
+        let message =
+            solana_sdk::message::VersionedMessage::Legacy(solana_sdk::message::Message::new(
+                &[solana_sdk::system_instruction::transfer(
+                    &payer,
+                    &Pubkey::new_unique(),
+                    1,
+                )],
+                Some(&payer),
+            ));
+        assert_eq!(solana::estimated_fee_lamports(&message, None, None), 5_000);
+        assert_eq!(
+            solana::estimated_fee_lamports(&message, Some(50_000), Some(1_000_000)),
+            55_000
+        );
+        assert_eq!(
+            solana::estimated_fee_lamports(&message, None, Some(1_000)),
+            5_200
+        );
+    }
+}
@@ -1,12 +1,23 @@
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::signature::Keypair;
 use sqlx::PgPool;
 use std::sync::Arc;
 
 use crate::config::Config;
+use crate::custody::KeySigner;
+use crate::rpc::RpcPool;
+use crate::websocket::VaultFeed;
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Config,
     pub db: PgPool,
+    /// Sends every request through `rpc_pool`.
     pub rpc: Arc<RpcClient>,
+    pub rpc_pool: Arc<RpcPool>,
+    pub vault_feed: Arc<VaultFeed>,
+    /// Pays for sponsored transactions; loaded when sponsorship is enabled.
+    pub fee_payer: Option<Arc<Keypair>>,
+    /// Holds custodial delegate keys; set when custody is enabled.
+    pub key_signer: Option<Arc<dyn KeySigner>>,
 }
@@ -0,0 +1,741 @@
+//! Relays signed vault transactions for `POST /tx/submit` and tracks each
+//! one in `transactions` until it is finalized or fails.
+
+use std::time::Duration;
+
+use anchor_lang::Discriminator;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::RpcSendTransactionConfig,
+    rpc_request::{RpcError, RpcResponseErrorData},
+};
+use solana_sdk::{
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    compute_budget,
+    hash::Hash,
+    instruction::CompiledInstruction,
+    pubkey::Pubkey,
+    signature::Signature,
+    system_program,
+    transaction::VersionedTransaction,
+};
+use solana_transaction_status::{TransactionConfirmationStatus, TransactionStatus};
+use sqlx::PgPool;
+
+use crate::config::Config;
+use crate::db::models::{NewSubmittedTransaction, SubmittedTransaction};
+use crate::db::queries;
+use crate::error::{AppError, Result};
+use crate::program_errors;
+use crate::solana;
+use crate::AppState;
+
+/// Programs a submitted transaction may invoke besides the vault program.
+const ALLOWED_PROGRAMS: [Pubkey; 2] = [system_program::ID, compute_budget::ID];
+/// `getSignatureStatuses` accepts at most 256 signatures.
+const MAX_TRACKED_PER_CHECK: i64 = 256;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(250);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(8);
+const EXPIRED_ERROR: &str = "blockhash expired or nonce advanced before the transaction landed";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lifecycle {
+    Submitted,
+    Processed,
+    Confirmed,
+    Finalized,
+    Failed,
+}
+
+impl Lifecycle {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Submitted => "submitted",
+            Self::Processed => "processed",
+            Self::Confirmed => "confirmed",
+            Self::Finalized => "finalized",
+            Self::Failed => "failed",
+        }
+    }
+
+    pub fn parse(raw: &str) -> Option<Self> {
+        [
+            Self::Submitted,
+            Self::Processed,
+            Self::Confirmed,
+            Self::Finalized,
+            Self::Failed,
+        ]
+        .into_iter()
+        .find(|status| status.as_str() == raw)
+    }
+}
+
+/// The lifecycle state a signature status reports, with its error.
+pub fn observed(status: &TransactionStatus) -> (Lifecycle, Option<String>) {
+    if let Some(err) = &status.err {
+        return (
+            Lifecycle::Failed,
+            Some(program_errors::decode(err, &[], true).to_string()),
+        );
+    }
+    let lifecycle = match status.confirmation_status {
+        Some(TransactionConfirmationStatus::Processed) => Lifecycle::Processed,
+        Some(TransactionConfirmationStatus::Confirmed) => Lifecycle::Confirmed,
+        // Statuses without a confirmation level predate it and are rooted.
+        Some(TransactionConfirmationStatus::Finalized) | None => Lifecycle::Finalized,
+    };
+    (lifecycle, None)
+}
+
+/// The vault an `ephemeral_vault` instruction acts on: the second account of
+/// `create_ephemeral_vault`, the first of every other instruction.
+fn instruction_vault(instruction: &CompiledInstruction, keys: &[Pubkey]) -> Result<Pubkey> {
+    let position = usize::from(
+        instruction
+            .data
+            .starts_with(ephemeralvault::instruction::CreateEphemeralVault::DISCRIMINATOR),
+    );
+    instruction
+        .accounts
+        .get(position)
+        .and_then(|&index| keys.get(usize::from(index)))
+        .copied()
+        .ok_or_else(|| AppError::Validation("vault instruction is missing its vault".into()))
+}
+
+/// Checks that `tx` is fully signed and only invokes the vault program plus
+/// system and compute-budget instructions. `keys` are the message's accounts
+/// with lookup table addresses resolved. Returns the vault of its first
+/// vault instruction.
+pub fn check_transaction(
+    tx: &VersionedTransaction,
+    keys: &[Pubkey],
+    program_id: &Pubkey,
+) -> Result<Pubkey> {
+    tx.verify_and_hash_message().map_err(|e| {
+        AppError::InvalidSignature(format!("transaction signatures do not verify: {e}"))
+    })?;
+
+    let mut vault = None;
+    for (index, instruction) in tx.message.instructions().iter().enumerate() {
+        let program = keys
+            .get(usize::from(instruction.program_id_index))
+            .ok_or_else(|| AppError::Validation(format!("instruction {index} has no program")))?;
+        if program == program_id {
+            if vault.is_none() {
+                vault = Some(instruction_vault(instruction, keys)?);
+            }
+        } else if !ALLOWED_PROGRAMS.contains(program) {
+            return Err(AppError::Validation(format!(
+                "instruction {index} invokes {program}, which is not allowed"
+            )));
+        }
+    }
+
+    vault.ok_or_else(|| {
+        AppError::Validation("transaction has no ephemeral_vault instruction".into())
+    })
+}
+
+/// Whether `tx` can still land: its durable nonce account still holds the
+/// message's blockhash, or else that blockhash is still recent. `keys` are
+/// the message's accounts with lookup table addresses resolved.
+async fn lifetime_is_valid(
+    rpc: &RpcClient,
+    tx: &VersionedTransaction,
+    keys: &[Pubkey],
+) -> Result<bool> {
+    let blockhash = tx.message.recent_blockhash();
+    if let Some(nonce_account) = solana::durable_nonce_account(&tx.message, keys) {
+        return match solana::fetch_nonce_data(rpc, &nonce_account).await {
+            Ok((_, data)) => Ok(data.blockhash() == *blockhash),
+            Err(AppError::NotFound(_) | AppError::Validation(_)) => Ok(false),
+            Err(e) => Err(e),
+        };
+    }
+    blockhash_is_valid(rpc, blockhash).await
+}
+
+async fn blockhash_is_valid(rpc: &RpcClient, blockhash: &Hash) -> Result<bool> {
+    rpc.is_blockhash_valid(blockhash, CommitmentConfig::processed())
+        .await
+        .map_err(|e| AppError::SolanaRpc(format!("failed to check blockhash: {e}")))
+}
+
+/// Decodes and checks a submission, rejecting it if its blockhash has
+/// already expired or its durable nonce has been advanced.
+pub async fn prepare(
+    rpc: &RpcClient,
+    config: &Config,
+    transaction_base64: &str,
+) -> Result<(VersionedTransaction, NewSubmittedTransaction)> {
+    let tx = solana::decode_transaction_base64(transaction_base64)?;
+    let loaded = solana::load_addresses(rpc, &tx.message).await?;
+    let keys = solana::message_account_keys(&tx.message, &loaded);
+    let vault = check_transaction(&tx, &keys, &solana::program_id(config)?)?;
+
+    if !lifetime_is_valid(rpc, &tx, &keys).await? {
+        return Err(AppError::Validation(
+            "transaction blockhash has expired or its nonce was advanced; rebuild and sign it again"
+                .into(),
+        ));
+    }
+
+    let record = NewSubmittedTransaction {
+        signature: tx.signatures[0].to_string(),
+        vault_address: vault.to_string(),
+        fee_payer: keys[0].to_string(),
+        transaction_base64: BASE64.encode(transaction_base64_bytes(&tx)?),
+        recent_blockhash: tx.message.recent_blockhash().to_string(),
+    };
+    Ok((tx, record))
+}
+
+fn transaction_base64_bytes(tx: &VersionedTransaction) -> Result<Vec<u8>> {
+    bincode::serialize(tx)
+        .map_err(|e| AppError::Internal(format!("failed to serialize transaction: {e}")))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// The RPC refused the transaction, e.g. in preflight. Resending the same
+    /// bytes cannot succeed.
+    Rejected(String),
+    /// The RPC could not be reached.
+    Unavailable(String),
+}
+
+fn send_error(error: ClientError) -> SendError {
+    match error.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { data, message, .. }) => match data {
+            RpcResponseErrorData::SendTransactionPreflightFailure(result) => {
+                SendError::Rejected(match &result.err {
+                    Some(err) => program_errors::decode(
+                        err,
+                        result.logs.as_deref().unwrap_or_default(),
+                        true,
+                    )
+                    .to_string(),
+                    None => message.clone(),
+                })
+            }
+            _ => SendError::Rejected(message.clone()),
+        },
+        _ => SendError::Unavailable(error.to_string()),
+    }
+}
+
+/// Backoff before resending after failed attempt `attempt`, doubling from
+/// `RETRY_BASE_DELAY` up to `RETRY_MAX_DELAY`.
+fn retry_delay(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(RETRY_MAX_DELAY)
+}
+
+/// Sends `tx`, retrying up to `max_retries` times with backoff while the RPC
+/// is unreachable. Returns the number of attempts made.
+pub async fn send(
+    rpc: &RpcClient,
+    tx: &VersionedTransaction,
+    max_retries: u64,
+    skip_preflight: bool,
+) -> (i32, std::result::Result<Signature, SendError>) {
+    let config = RpcSendTransactionConfig {
+        skip_preflight,
+        preflight_commitment: Some(CommitmentLevel::Confirmed),
+        ..RpcSendTransactionConfig::default()
+    };
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match rpc
+            .send_transaction_with_config(tx, config)
+            .await
+            .map_err(send_error)
+        {
+            Err(SendError::Unavailable(_)) if u64::from(attempts as u32) <= max_retries => {
+                tokio::time::sleep(retry_delay(attempts as u32)).await;
+            }
+            result => return (attempts, result),
+        }
+    }
+}
+
+/// Records and sends a signed transaction. Resubmitting a known signature
+/// returns its current record without sending it again.
+pub async fn submit(state: &AppState, transaction_base64: &str) -> Result<SubmittedTransaction> {
+    let (tx, record) = prepare(&state.rpc, &state.config, transaction_base64).await?;
+    let signature = record.signature.clone();
+
+    if queries::insert_submitted_transaction(&state.db, &record)
+        .await?
+        .is_some()
+    {
+        let (attempts, sent) =
+            send(&state.rpc, &tx, state.config.tx_submit_max_retries, false).await;
+        queries::add_transaction_send_attempts(&state.db, &signature, attempts).await?;
+        match sent {
+            Ok(_) => {}
+            Err(SendError::Rejected(error)) => {
+                queries::update_transaction_status(
+                    &state.db,
+                    &signature,
+                    Lifecycle::Failed.as_str(),
+                    None,
+                    Some(&error),
+                )
+                .await?;
+            }
+            // Left as submitted; the tracker resends it while the blockhash
+            // or nonce is still valid.
+            Err(SendError::Unavailable(error)) => {
+                queries::update_transaction_status(
+                    &state.db,
+                    &signature,
+                    Lifecycle::Submitted.as_str(),
+                    None,
+                    Some(&error),
+                )
+                .await?;
+            }
+        }
+    }
+
+    queries::get_submitted_transaction(&state.db, &signature)
+        .await?
+        .ok_or_else(|| AppError::Internal(format!("submitted transaction {signature} vanished")))
+}
+
+/// Advances every pending submission once: records newer confirmation
+/// levels, resends transactions that have not landed, and fails those whose
+/// blockhash expired or whose nonce was advanced. Submissions that no longer
+/// decode are failed and ones that cannot be checked right now are skipped,
+/// so neither holds up the rest. Returns the number of status changes.
+pub async fn track_once(rpc: &RpcClient, db: &PgPool) -> Result<usize> {
+    let pending = queries::get_pending_transactions(db, MAX_TRACKED_PER_CHECK).await?;
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let mut changed = 0;
+    let mut tracked = Vec::with_capacity(pending.len());
+    let mut signatures = Vec::with_capacity(pending.len());
+    for record in &pending {
+        match record.signature.parse::<Signature>() {
+            Ok(signature) => {
+                tracked.push(record);
+                signatures.push(signature);
+            }
+            Err(e) => {
+                fail(
+                    db,
+                    &record.signature,
+                    &format!("stored signature is invalid: {e}"),
+                )
+                .await?;
+                changed += 1;
+            }
+        }
+    }
+    if signatures.is_empty() {
+        return Ok(changed);
+    }
+    let statuses = rpc
+        .get_signature_statuses_with_history(&signatures)
+        .await
+        .map_err(|e| AppError::SolanaRpc(format!("failed to fetch transaction statuses: {e}")))?
+        .value;
+
+    for (record, status) in tracked.into_iter().zip(statuses) {
+        let current = Lifecycle::parse(&record.status).unwrap_or(Lifecycle::Submitted);
+        if let Some(status) = status {
+            let (next, error) = observed(&status);
+            if next > current {
+                queries::update_transaction_status(
+                    db,
+                    &record.signature,
+                    next.as_str(),
+                    Some(status.slot as i64),
+                    error.as_deref(),
+                )
+                .await?;
+                changed += 1;
+            }
+            continue;
+        }
+
+        match resend_if_live(rpc, &record.transaction_base64).await {
+            Ok(Some(attempts)) => {
+                queries::add_transaction_send_attempts(db, &record.signature, attempts).await?;
+            }
+            Ok(None) => {
+                fail(db, &record.signature, EXPIRED_ERROR).await?;
+                changed += 1;
+            }
+            // A stored transaction that no longer decodes or resolves can
+            // never land; failing it keeps it from holding a tracking slot.
+            Err(e @ (AppError::Validation(_) | AppError::NotFound(_))) => {
+                tracing::warn!(signature = %record.signature, "failing untrackable transaction: {e}");
+                fail(db, &record.signature, &e.to_string()).await?;
+                changed += 1;
+            }
+            Err(e) => {
+                tracing::warn!(signature = %record.signature, "could not track transaction: {e}")
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// Resends a stored transaction that has not been seen on chain, unless its
+/// blockhash expired or its nonce was advanced. Returns the attempts made, or
+/// `None` once it can no longer land.
+async fn resend_if_live(rpc: &RpcClient, transaction_base64: &str) -> Result<Option<i32>> {
+    let tx = solana::decode_transaction_base64(transaction_base64)?;
+    let loaded = solana::load_addresses(rpc, &tx.message).await?;
+    let keys = solana::message_account_keys(&tx.message, &loaded);
+    if !lifetime_is_valid(rpc, &tx, &keys).await? {
+        return Ok(None);
+    }
+    let (attempts, _) = send(rpc, &tx, 0, true).await;
+    Ok(Some(attempts))
+}
+
+async fn fail(db: &PgPool, signature: &str, error: &str) -> Result<()> {
+    queries::update_transaction_status(db, signature, Lifecycle::Failed.as_str(), None, Some(error))
+        .await
+}
+
+/// Tracks pending submissions every `TX_TRACK_INTERVAL_SECS`.
+pub async fn run(state: AppState) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.tx_track_interval_secs));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        match track_once(&state.rpc, &state.db).await {
+            Ok(changed) if changed > 0 => {
+                tracing::debug!(changed, "updated submitted transactions")
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("transaction tracking failed: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solana::EphemeralVaultAccount;
+    use crate::test_support::{self, program_id, LocalSvm};
+    use solana_sdk::{
+        account::Account,
+        address_lookup_table::AddressLookupTableAccount,
+        instruction::{AccountMeta, Instruction},
+        message::{v0, VersionedMessage},
+        signature::Keypair,
+        signer::Signer,
+        system_instruction,
+        transaction::Transaction,
+    };
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn signed(
+        payer: &Keypair,
+        instructions: &[Instruction],
+        blockhash: Hash,
+    ) -> VersionedTransaction {
+        Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &[payer], blockhash)
+            .into()
+    }
+
+    fn check(tx: &VersionedTransaction) -> Result<Pubkey> {
+        check_transaction(tx, tx.message.static_account_keys(), &program_id())
+    }
+
+    fn cleanup(vault: Pubkey, owner: Pubkey, cleaner: Pubkey) -> Instruction {
+        solana::cleanup_instruction(program_id(), vault, owner, cleaner)
+    }
+
+    /// Stores an inactive vault that anyone may clean up at `NOW`.
+    fn add_stale_vault(svm: &LocalSvm, owner: Pubkey) -> Pubkey {
+        let vault = EphemeralVaultAccount {
+            is_active: false,
+            ..test_support::vault(owner, NOW - 600)
+        };
+        svm.add_vault(&vault, 100_000_000)
+    }
+
+    #[test]
+    fn accepts_only_signed_vault_transactions() {
+        let payer = Keypair::new();
+        let owner = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let blockhash = Hash::new_unique();
+        let priority = compute_budget::ComputeBudgetInstruction::set_compute_unit_price(1_000);
+
+        let tx = signed(
+            &payer,
+            &[priority.clone(), cleanup(vault, owner, payer.pubkey())],
+            blockhash,
+        );
+        assert_eq!(check(&tx).unwrap(), vault);
+
+        let create = Instruction {
+            program_id: program_id(),
+            accounts: vec![
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new(vault, false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data: anchor_lang::InstructionData::data(
+                &ephemeralvault::instruction::CreateEphemeralVault { approved_amount: 1 },
+            ),
+        };
+        let tx = signed(&payer, &[create], blockhash);
+        assert_eq!(check(&tx).unwrap(), vault);
+
+        let transfer = system_instruction::transfer(&payer.pubkey(), &owner, 1);
+        let tx = signed(&payer, std::slice::from_ref(&transfer), blockhash);
+        assert!(matches!(check(&tx), Err(AppError::Validation(_))));
+
+        let foreign = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+        let tx = signed(
+            &payer,
+            &[cleanup(vault, owner, payer.pubkey()), foreign],
+            blockhash,
+        );
+        assert!(matches!(check(&tx), Err(AppError::Validation(_))));
+
+        let unsigned: VersionedTransaction =
+            Transaction::new_unsigned(solana_sdk::message::Message::new(
+                &[cleanup(vault, owner, payer.pubkey())],
+                Some(&payer.pubkey()),
+            ))
+            .into();
+        assert!(matches!(
+            check(&unsigned),
+            Err(AppError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn maps_signature_statuses_to_lifecycle() {
+        let status = |confirmation_status, err| TransactionStatus {
+            slot: 7,
+            confirmations: None,
+            status: Ok(()),
+            err,
+            confirmation_status,
+        };
+
+        assert_eq!(
+            observed(&status(
+                Some(TransactionConfirmationStatus::Processed),
+                None
+            )),
+            (Lifecycle::Processed, None)
+        );
+        assert_eq!(
+            observed(&status(
+                Some(TransactionConfirmationStatus::Confirmed),
+                None
+            )),
+            (Lifecycle::Confirmed, None)
+        );
+        assert_eq!(observed(&status(None, None)).0, Lifecycle::Finalized);
+        let (failed, error) = observed(&status(
+            Some(TransactionConfirmationStatus::Confirmed),
+            Some(solana_sdk::transaction::TransactionError::AccountNotFound),
+        ));
+        assert_eq!(failed, Lifecycle::Failed);
+        assert!(error.unwrap().starts_with("AccountNotFound: "));
+
+        assert!(Lifecycle::Confirmed > Lifecycle::Processed);
+        assert_eq!(Lifecycle::parse("finalized"), Some(Lifecycle::Finalized));
+        assert_eq!(Lifecycle::parse("expired"), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_blockhashes_before_sending() {
+        let svm = LocalSvm::new(NOW);
+        let rpc = svm.rpc();
+        let payer = Keypair::new();
+        let vault = add_stale_vault(&svm, Pubkey::new_unique());
+        let owner = Pubkey::new_unique();
+        let blockhash = rpc.get_latest_blockhash().await.unwrap();
+        let tx = signed(&payer, &[cleanup(vault, owner, payer.pubkey())], blockhash);
+        let encoded = BASE64.encode(bincode::serialize(&tx).unwrap());
+
+        let (_, record) = prepare(&rpc, &test_support::config(), &encoded)
+            .await
+            .unwrap();
+        assert_eq!(record.signature, tx.signatures[0].to_string());
+        assert_eq!(record.vault_address, vault.to_string());
+        assert_eq!(record.fee_payer, payer.pubkey().to_string());
+        assert_eq!(record.transaction_base64, encoded);
+
+        svm.expire_blockhash();
+        assert!(matches!(
+            prepare(&rpc, &test_support::config(), &encoded).await,
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn resends_live_transactions_and_flags_ones_that_cannot_land() {
+        let svm = LocalSvm::new(NOW);
+        let rpc = svm.rpc();
+        let payer = Keypair::new();
+        let vault = add_stale_vault(&svm, Pubkey::new_unique());
+        let blockhash = rpc.get_latest_blockhash().await.unwrap();
+        let tx = signed(
+            &payer,
+            &[cleanup(vault, Pubkey::new_unique(), payer.pubkey())],
+            blockhash,
+        );
+        let encoded = BASE64.encode(bincode::serialize(&tx).unwrap());
+
+        assert_eq!(resend_if_live(&rpc, &encoded).await.unwrap(), Some(1));
+        assert!(matches!(
+            resend_if_live(&rpc, "not a transaction").await,
+            Err(AppError::Validation(_))
+        ));
+
+        svm.expire_blockhash();
+        assert_eq!(resend_if_live(&rpc, &encoded).await.unwrap(), None);
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_a_cap() {
+        assert_eq!(retry_delay(1), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(3), RETRY_BASE_DELAY * 4);
+        assert_eq!(retry_delay(10), RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(u32::MAX), RETRY_MAX_DELAY);
+    }
+
+    #[tokio::test]
+    async fn sends_valid_transactions_and_reports_preflight_rejections() {
+        let svm = LocalSvm::new(NOW);
+        let rpc = svm.rpc();
+        let cleaner = Keypair::new();
+        svm.set_account(
+            cleaner.pubkey(),
+            Account::new(1_000_000_000, 0, &system_program::ID),
+        );
+        let owner = Pubkey::new_unique();
+        let vault = add_stale_vault(&svm, owner);
+        let blockhash = rpc.get_latest_blockhash().await.unwrap();
+
+        let tx = signed(
+            &cleaner,
+            &[cleanup(vault, owner, cleaner.pubkey())],
+            blockhash,
+        );
+        let (attempts, sent) = send(&rpc, &tx, 3, false).await;
+        assert_eq!(attempts, 1);
+        assert_eq!(sent, Ok(tx.signatures[0]));
+        assert!(svm.account(&vault).is_none());
+
+        // The vault is gone, so a second cleanup fails preflight and is not
+        // retried.
+        let again = signed(
+            &cleaner,
+            &[
+                compute_budget::ComputeBudgetInstruction::set_compute_unit_price(1),
+                cleanup(vault, owner, cleaner.pubkey()),
+            ],
+            blockhash,
+        );
+        let (attempts, sent) = send(&rpc, &again, 3, false).await;
+        assert_eq!(attempts, 1);
+        assert!(matches!(sent, Err(SendError::Rejected(_))), "{sent:?}");
+    }
+
+    #[tokio::test]
+    async fn rejects_durable_nonce_transactions_once_the_nonce_advances() {
+        let svm = LocalSvm::new(NOW);
+        let rpc = svm.rpc();
+        let cleaner = Keypair::new();
+        svm.set_account(
+            cleaner.pubkey(),
+            Account::new(1_000_000_000, 0, &system_program::ID),
+        );
+        let owner = Pubkey::new_unique();
+        let vault = add_stale_vault(&svm, owner);
+        let nonce_account = Pubkey::new_unique();
+        let nonce = svm.add_nonce_account(nonce_account, cleaner.pubkey());
+
+        let tx = signed(
+            &cleaner,
+            &[
+                system_instruction::advance_nonce_account(&nonce_account, &cleaner.pubkey()),
+                cleanup(vault, owner, cleaner.pubkey()),
+            ],
+            nonce,
+        );
+        let encoded = BASE64.encode(bincode::serialize(&tx).unwrap());
+
+        // The nonce, not the latest blockhash, keeps the transaction valid.
+        svm.expire_blockhash();
+        let (prepared, record) = prepare(&rpc, &test_support::config(), &encoded)
+            .await
+            .unwrap();
+        assert_eq!(record.recent_blockhash, nonce.to_string());
+        let (_, sent) = send(&rpc, &prepared, 0, false).await;
+        assert_eq!(sent, Ok(tx.signatures[0]));
+        assert!(svm.account(&vault).is_none());
+
+        assert!(matches!(
+            prepare(&rpc, &test_support::config(), &encoded).await,
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn accepts_v0_transactions_drawing_the_vault_from_a_lookup_table() {
+        let svm = LocalSvm::new(NOW);
+        let rpc = svm.rpc();
+        let cleaner = Keypair::new();
+        svm.set_account(
+            cleaner.pubkey(),
+            Account::new(1_000_000_000, 0, &system_program::ID),
+        );
+        let owner = Pubkey::new_unique();
+        let vault = add_stale_vault(&svm, owner);
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![owner, vault],
+        };
+        svm.set_lookup_table(table.key, table.addresses.clone());
+
+        let blockhash = rpc.get_latest_blockhash().await.unwrap();
+        let message = v0::Message::try_compile(
+            &cleaner.pubkey(),
+            &[cleanup(vault, owner, cleaner.pubkey())],
+            &[table],
+            blockhash,
+        )
+        .unwrap();
+        let tx = VersionedTransaction::try_new(VersionedMessage::V0(message), &[&cleaner]).unwrap();
+        assert!(!tx.message.static_account_keys().contains(&vault));
+        let encoded = BASE64.encode(bincode::serialize(&tx).unwrap());
+
+        let (prepared, record) = prepare(&rpc, &test_support::config(), &encoded)
+            .await
+            .unwrap();
+        assert_eq!(record.vault_address, vault.to_string());
+        assert_eq!(record.fee_payer, cleaner.pubkey().to_string());
+
+        let (_, sent) = send(&rpc, &prepared, 0, false).await;
+        assert_eq!(sent, Ok(tx.signatures[0]));
+        assert!(svm.account(&vault).is_none());
+    }
+}
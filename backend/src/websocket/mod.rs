@@ -0,0 +1,848 @@
+//! Live vault updates over `/ws`.
+//!
+//! Clients send `{"type":"subscribe","vaults":[...],"owners":[...]}` and get
+//! the current state of each vault, then a `vault` message whenever its
+//! account changes. `sessionExpiringSoon` and `sessionExpired` are pushed
+//! when a session enters those states, including when time alone moves it
+//! there. After reconnecting, clients resend their subscriptions and receive
+//! fresh snapshots.
+//!
+//! A single background task follows every watched vault with
+//! `accountSubscribe`, so the server caps both the vaults it watches and the
+//! open connections. It reconnects with backoff and refetches each vault
+//! once resubscribed, so changes made while disconnected are not lost.
+//! Connections that fall behind the shared feed get a `lagged` notice and
+//! fresh snapshots instead of the skipped updates.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::{IntoResponse, Response},
+};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_config::RpcAccountInfoConfig,
+};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey};
+use tokio::sync::{broadcast, broadcast::error::RecvError, mpsc, oneshot, Notify};
+
+use crate::config::Config;
+use crate::error::{AppError, Result};
+use crate::handlers::parse_pubkey;
+use crate::solana::{self, derive_vault_pda, program_id, SessionStatusDto, VaultDto};
+use crate::AppState;
+
+/// Updates buffered for slow connections before they are marked as lagged.
+const FEED_CAPACITY: usize = 1024;
+const MAX_SUBSCRIPTIONS_PER_CONNECTION: usize = 100;
+/// Distinct vaults watched across all connections; each one holds an
+/// upstream `accountSubscribe`.
+const MAX_WATCHED_VAULTS: usize = 5_000;
+const MAX_CONNECTIONS: usize = 1_000;
+const MAX_CLIENT_MESSAGE_BYTES: usize = 16 * 1024;
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    Subscribe(SubscriptionRequest),
+    Unsubscribe(SubscriptionRequest),
+    Ping,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SubscriptionRequest {
+    #[serde(default)]
+    vaults: Vec<String>,
+    #[serde(default)]
+    owners: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ServerMessage {
+    Subscribed {
+        vaults: Vec<String>,
+    },
+    Unsubscribed {
+        vaults: Vec<String>,
+    },
+    Vault {
+        vault: Box<VaultDto>,
+    },
+    VaultNotFound {
+        address: String,
+    },
+    SessionExpiringSoon {
+        address: String,
+        owner: String,
+        delegate: Option<String>,
+        seconds_until_expiry: Option<i64>,
+        can_renew: bool,
+    },
+    SessionExpired {
+        address: String,
+        owner: String,
+        delegate: Option<String>,
+    },
+    /// Sent to owners with a renewal policy; the transaction is unsigned.
+    SessionRenewalDue {
+        address: String,
+        owner: String,
+        session_expiry: i64,
+        seconds_until_expiry: Option<i64>,
+        transaction_base64: String,
+    },
+    Lagged {
+        skipped: u64,
+    },
+    Error {
+        message: String,
+    },
+    Pong,
+}
+
+/// A message for everyone subscribed to `vault`.
+#[derive(Clone, Debug)]
+pub struct FeedEvent {
+    pub vault: Pubkey,
+    pub message: ServerMessage,
+}
+
+struct Cached {
+    account: Account,
+    session_status: SessionStatusDto,
+}
+
+#[derive(Default)]
+struct Watched {
+    watchers: usize,
+    /// `None` until first fetched, `Some(None)` when no vault account exists.
+    latest: Option<Option<Cached>>,
+}
+
+/// Shared state of every vault watched by at least one connection.
+pub struct VaultFeed {
+    watched: Mutex<HashMap<Pubkey, Watched>>,
+    events: broadcast::Sender<FeedEvent>,
+    changed: Notify,
+    max_watched: usize,
+    max_connections: usize,
+    connections: AtomicUsize,
+}
+
+/// An open `/ws` connection, counted against the feed's limit until dropped.
+pub struct FeedConnection(Arc<VaultFeed>);
+
+impl Drop for FeedConnection {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for VaultFeed {
+    fn default() -> Self {
+        Self::new(FEED_CAPACITY)
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn vault_dto(vault: Pubkey, account: &Account, now_ts: i64) -> Result<VaultDto> {
+    solana::to_vault_dto(
+        vault,
+        solana::parse_vault_account(&account.data)?,
+        solana::vault_rent_surplus(account),
+        now_ts,
+    )
+}
+
+fn session_warning(vault: &VaultDto) -> Option<ServerMessage> {
+    match vault.session_status {
+        SessionStatusDto::ExpiringSoon => Some(ServerMessage::SessionExpiringSoon {
+            address: vault.address.clone(),
+            owner: vault.owner.clone(),
+            delegate: vault.delegate.clone(),
+            seconds_until_expiry: vault.seconds_until_expiry,
+            can_renew: vault.can_renew,
+        }),
+        SessionStatusDto::Expired => Some(ServerMessage::SessionExpired {
+            address: vault.address.clone(),
+            owner: vault.owner.clone(),
+            delegate: vault.delegate.clone(),
+        }),
+        SessionStatusDto::NoSession | SessionStatusDto::Active => None,
+    }
+}
+
+/// The `vault` message plus any session warning that applies right now.
+fn vault_messages(vault: VaultDto) -> Vec<ServerMessage> {
+    let warning = session_warning(&vault);
+    let mut messages = vec![ServerMessage::Vault {
+        vault: Box::new(vault),
+    }];
+    messages.extend(warning);
+    messages
+}
+
+impl VaultFeed {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_limits(capacity, MAX_WATCHED_VAULTS, MAX_CONNECTIONS)
+    }
+
+    pub fn with_limits(capacity: usize, max_watched: usize, max_connections: usize) -> Self {
+        Self {
+            watched: Mutex::new(HashMap::new()),
+            events: broadcast::channel(capacity).0,
+            changed: Notify::new(),
+            max_watched,
+            max_connections,
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    /// Admits a connection, or `None` once `max_connections` are open.
+    pub fn connect(self: &Arc<Self>) -> Option<FeedConnection> {
+        self.connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < self.max_connections).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| FeedConnection(Arc::clone(self)))
+    }
+
+    pub fn events(&self) -> broadcast::Receiver<FeedEvent> {
+        self.events.subscribe()
+    }
+
+    /// Adds a watcher to each of `vaults`, or none when that would take the
+    /// feed past `max_watched` distinct vaults.
+    pub fn watch(&self, vaults: impl IntoIterator<Item = Pubkey>) -> Result<()> {
+        let vaults: Vec<Pubkey> = vaults.into_iter().collect();
+        let mut watched = self.watched.lock().expect("vault feed lock");
+        let new: HashSet<&Pubkey> = vaults
+            .iter()
+            .filter(|vault| !watched.contains_key(vault))
+            .collect();
+        if watched.len() + new.len() > self.max_watched {
+            return Err(AppError::RateLimited(format!(
+                "the server is watching its limit of {} vaults",
+                self.max_watched
+            )));
+        }
+        for vault in vaults {
+            watched.entry(vault).or_default().watchers += 1;
+        }
+        drop(watched);
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    pub fn unwatch(&self, vaults: impl IntoIterator<Item = Pubkey>) {
+        let mut watched = self.watched.lock().expect("vault feed lock");
+        for vault in vaults {
+            if let Some(entry) = watched.get_mut(&vault) {
+                entry.watchers -= 1;
+                if entry.watchers == 0 {
+                    watched.remove(&vault);
+                }
+            }
+        }
+        drop(watched);
+        self.changed.notify_one();
+    }
+
+    pub fn watched(&self) -> HashSet<Pubkey> {
+        let watched = self.watched.lock().expect("vault feed lock");
+        watched.keys().copied().collect()
+    }
+
+    /// Records the latest account for `vault` and broadcasts what changed.
+    /// A missing, closed or foreign account is reported as not found.
+    /// Returns `false` when the vault is unwatched or nothing changed.
+    pub fn apply(&self, vault: Pubkey, account: Option<Account>, now_ts: i64) -> bool {
+        let parsed = account.and_then(|account| {
+            vault_dto(vault, &account, now_ts)
+                .ok()
+                .map(|dto| (account, dto))
+        });
+
+        let mut watched = self.watched.lock().expect("vault feed lock");
+        let Some(entry) = watched.get_mut(&vault) else {
+            return false;
+        };
+
+        let messages = match (parsed, &entry.latest) {
+            (None, Some(None)) => return false,
+            (None, _) => {
+                entry.latest = Some(None);
+                vec![ServerMessage::VaultNotFound {
+                    address: vault.to_string(),
+                }]
+            }
+            (Some((account, _)), Some(Some(cached))) if cached.account == account => return false,
+            (Some((account, dto)), latest) => {
+                let previous_status = latest
+                    .as_ref()
+                    .and_then(Option::as_ref)
+                    .map(|cached| cached.session_status);
+                let session_status = dto.session_status;
+                entry.latest = Some(Some(Cached {
+                    account,
+                    session_status,
+                }));
+
+                let mut messages = vault_messages(dto);
+                if previous_status == Some(session_status) {
+                    messages.truncate(1);
+                }
+                messages
+            }
+        };
+        drop(watched);
+
+        self.publish(vault, messages);
+        true
+    }
+
+    /// Re-evaluates session status at `now_ts`, so vaults whose account did
+    /// not change still move into `expiring_soon` and `expired`.
+    pub fn tick(&self, now_ts: i64) {
+        let mut changed = Vec::new();
+        {
+            let mut watched = self.watched.lock().expect("vault feed lock");
+            for (vault, entry) in watched.iter_mut() {
+                let Some(Some(cached)) = entry.latest.as_mut() else {
+                    continue;
+                };
+                let Ok(dto) = vault_dto(*vault, &cached.account, now_ts) else {
+                    continue;
+                };
+                if dto.session_status != cached.session_status {
+                    cached.session_status = dto.session_status;
+                    changed.push((*vault, vault_messages(dto)));
+                }
+            }
+        }
+
+        for (vault, messages) in changed {
+            self.publish(vault, messages);
+        }
+    }
+
+    /// Current state of `vault` as it would be pushed, or `None` when it has
+    /// not been fetched yet.
+    pub fn snapshot(&self, vault: &Pubkey, now_ts: i64) -> Option<Vec<ServerMessage>> {
+        let watched = self.watched.lock().expect("vault feed lock");
+        match watched.get(vault)?.latest.as_ref()? {
+            Some(cached) => vault_dto(*vault, &cached.account, now_ts)
+                .ok()
+                .map(vault_messages),
+            None => Some(vec![ServerMessage::VaultNotFound {
+                address: vault.to_string(),
+            }]),
+        }
+    }
+
+    pub fn publish(&self, vault: Pubkey, messages: Vec<ServerMessage>) {
+        for message in messages {
+            // No receivers just means nobody is connected right now.
+            let _ = self.events.send(FeedEvent { vault, message });
+        }
+    }
+}
+
+/// Fetches `vault` over RPC and applies it to the feed.
+pub async fn refresh(rpc: &RpcClient, feed: &VaultFeed, vault: Pubkey) -> Result<bool> {
+    let account = rpc
+        .get_account_with_commitment(&vault, CommitmentConfig::confirmed())
+        .await
+        .map_err(|e| AppError::SolanaRpc(e.to_string()))?
+        .value;
+    Ok(feed.apply(vault, account, now()))
+}
+
+pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    let Some(connection) = state.vault_feed.connect() else {
+        return AppError::RateLimited(format!(
+            "the server has its limit of {} live update connections open",
+            state.vault_feed.max_connections
+        ))
+        .into_response();
+    };
+    ws.max_message_size(MAX_CLIENT_MESSAGE_BYTES)
+        .on_upgrade(move |socket| serve(socket, state, connection))
+}
+
+async fn serve(socket: WebSocket, state: AppState, _connection: FeedConnection) {
+    let (mut sink, mut stream) = socket.split();
+    let mut events = state.vault_feed.events();
+    let mut subscribed = HashSet::new();
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    keepalive.tick().await;
+
+    loop {
+        let outgoing = tokio::select! {
+            incoming = stream.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    handle_client_message(&state, &mut subscribed, &text).await
+                }
+                Some(Ok(Message::Binary(_))) => vec![ServerMessage::Error {
+                    message: "binary messages are not supported".into(),
+                }],
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) if subscribed.contains(&event.vault) => vec![event.message],
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    let mut messages = vec![ServerMessage::Lagged { skipped }];
+                    for vault in &subscribed {
+                        messages.extend(
+                            state.vault_feed.snapshot(vault, now()).unwrap_or_default(),
+                        );
+                    }
+                    messages
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = keepalive.tick() => {
+                if sink.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        if send_all(&mut sink, outgoing).await.is_err() {
+            break;
+        }
+    }
+
+    state.vault_feed.unwatch(subscribed);
+}
+
+async fn send_all<S>(sink: &mut S, messages: Vec<ServerMessage>) -> std::result::Result<(), ()>
+where
+    S: futures::Sink<Message> + Unpin,
+{
+    for message in messages {
+        let text = serde_json::to_string(&message).map_err(|_| ())?;
+        sink.send(Message::Text(text)).await.map_err(|_| ())?;
+    }
+    Ok(())
+}
+
+async fn handle_client_message(
+    state: &AppState,
+    subscribed: &mut HashSet<Pubkey>,
+    text: &str,
+) -> Vec<ServerMessage> {
+    let result = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(ClientMessage::Ping) => Ok(vec![ServerMessage::Pong]),
+        Ok(ClientMessage::Subscribe(request)) => subscribe(state, subscribed, &request).await,
+        Ok(ClientMessage::Unsubscribe(request)) => unsubscribe(state, subscribed, &request),
+        Err(e) => Err(AppError::Validation(format!("invalid message: {e}"))),
+    };
+    result.unwrap_or_else(|e| {
+        vec![ServerMessage::Error {
+            message: e.to_string(),
+        }]
+    })
+}
+
+/// Vault addresses named by `request`, with owners mapped to their vault PDA.
+fn resolve(config: &Config, request: &SubscriptionRequest) -> Result<Vec<Pubkey>> {
+    let program_id = program_id(config)?;
+    let mut vaults = Vec::new();
+    for raw in &request.vaults {
+        vaults.push(parse_pubkey(raw, "vault pubkey")?);
+    }
+    for raw in &request.owners {
+        let owner = parse_pubkey(raw, "owner pubkey")?;
+        vaults.push(derive_vault_pda(&program_id, &owner).0);
+    }
+    Ok(vaults)
+}
+
+fn sorted_addresses<'a>(vaults: impl IntoIterator<Item = &'a Pubkey>) -> Vec<String> {
+    let mut addresses: Vec<String> = vaults.into_iter().map(Pubkey::to_string).collect();
+    addresses.sort();
+    addresses
+}
+
+async fn subscribe(
+    state: &AppState,
+    subscribed: &mut HashSet<Pubkey>,
+    request: &SubscriptionRequest,
+) -> Result<Vec<ServerMessage>> {
+    let mut added = Vec::new();
+    for vault in resolve(&state.config, request)? {
+        if !subscribed.contains(&vault) && !added.contains(&vault) {
+            added.push(vault);
+        }
+    }
+    if subscribed.len() + added.len() > MAX_SUBSCRIPTIONS_PER_CONNECTION {
+        return Err(AppError::Validation(format!(
+            "at most {MAX_SUBSCRIPTIONS_PER_CONNECTION} vaults per connection"
+        )));
+    }
+
+    state.vault_feed.watch(added.iter().copied())?;
+    subscribed.extend(added.iter().copied());
+
+    let mut messages = vec![ServerMessage::Subscribed {
+        vaults: sorted_addresses(subscribed.iter()),
+    }];
+    for vault in added {
+        if let Some(snapshot) = state.vault_feed.snapshot(&vault, now()) {
+            messages.extend(snapshot);
+            continue;
+        }
+        // A changed vault reaches this connection through the feed.
+        match refresh(&state.rpc, &state.vault_feed, vault).await {
+            Ok(true) => {}
+            Ok(false) => {
+                messages.extend(state.vault_feed.snapshot(&vault, now()).unwrap_or_default())
+            }
+            Err(e) => messages.push(ServerMessage::Error {
+                message: format!("failed to fetch vault {vault}: {e}"),
+            }),
+        }
+    }
+    Ok(messages)
+}
+
+fn unsubscribe(
+    state: &AppState,
+    subscribed: &mut HashSet<Pubkey>,
+    request: &SubscriptionRequest,
+) -> Result<Vec<ServerMessage>> {
+    let removed: HashSet<Pubkey> = resolve(&state.config, request)?
+        .into_iter()
+        .filter(|vault| subscribed.remove(vault))
+        .collect();
+    let vaults = sorted_addresses(removed.iter());
+    state.vault_feed.unwatch(removed);
+    Ok(vec![ServerMessage::Unsubscribed { vaults }])
+}
+
+/// Follows every watched vault over `accountSubscribe` until the process
+/// exits, reconnecting to `WS_URL` with exponential backoff.
+pub async fn run(state: AppState) {
+    tokio::spawn(check_expiry(state.vault_feed.clone()));
+
+    let mut delay = MIN_RECONNECT_DELAY;
+    loop {
+        if state.vault_feed.watched().is_empty() {
+            state.vault_feed.changed.notified().await;
+            continue;
+        }
+
+        match PubsubClient::new(&state.config.ws_url).await {
+            Ok(client) => {
+                tracing::info!("Vault feed connected to {}", state.config.ws_url);
+                delay = MIN_RECONNECT_DELAY;
+                follow(&state, Arc::new(client)).await;
+                tracing::warn!("vault feed lost its pubsub connection, reconnecting");
+            }
+            Err(e) => tracing::warn!(
+                "vault feed failed to connect to {}: {e}",
+                state.config.ws_url
+            ),
+        }
+
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+async fn check_expiry(feed: Arc<VaultFeed>) {
+    let mut interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        feed.tick(now());
+    }
+}
+
+/// Keeps one subscription per watched vault. Returns when any subscription
+/// drops, which means the connection is gone; dropping the stop senders
+/// winds down the rest.
+async fn follow(state: &AppState, client: Arc<PubsubClient>) {
+    let (dropped_tx, mut dropped_rx) = mpsc::channel(1);
+    let mut following: HashMap<Pubkey, oneshot::Sender<()>> = HashMap::new();
+
+    loop {
+        let watched = state.vault_feed.watched();
+        following.retain(|vault, _| watched.contains(vault));
+        for vault in watched {
+            if following.contains_key(&vault) {
+                continue;
+            }
+            let (stop_tx, stop_rx) = oneshot::channel();
+            tokio::spawn(follow_vault(
+                client.clone(),
+                state.rpc.clone(),
+                state.vault_feed.clone(),
+                vault,
+                stop_rx,
+                dropped_tx.clone(),
+            ));
+            following.insert(vault, stop_tx);
+        }
+
+        tokio::select! {
+            _ = state.vault_feed.changed.notified() => {}
+            Some(vault) = dropped_rx.recv() => {
+                tracing::warn!("subscription for vault {vault} dropped");
+                return;
+            }
+        }
+    }
+}
+
+async fn follow_vault(
+    client: Arc<PubsubClient>,
+    rpc: Arc<RpcClient>,
+    feed: Arc<VaultFeed>,
+    vault: Pubkey,
+    mut stop: oneshot::Receiver<()>,
+    dropped: mpsc::Sender<Pubkey>,
+) {
+    let config = RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(CommitmentConfig::confirmed()),
+        ..RpcAccountInfoConfig::default()
+    };
+    let (mut notifications, unsubscribe) =
+        match client.account_subscribe(&vault, Some(config)).await {
+            Ok(subscription) => subscription,
+            Err(e) => {
+                tracing::warn!("accountSubscribe failed for vault {vault}: {e}");
+                let _ = dropped.try_send(vault);
+                return;
+            }
+        };
+
+    // Catch up on changes made before the subscription was in place.
+    if let Err(e) = refresh(&rpc, &feed, vault).await {
+        tracing::warn!("failed to refresh vault {vault}: {e}");
+    }
+
+    loop {
+        tokio::select! {
+            _ = &mut stop => break,
+            notification = notifications.next() => match notification {
+                Some(response) => {
+                    feed.apply(vault, response.value.decode::<Account>(), now());
+                }
+                None => {
+                    let _ = dropped.try_send(vault);
+                    break;
+                }
+            },
+        }
+    }
+
+    drop(notifications);
+    unsubscribe().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Discriminator;
+    use bytemuck::Zeroable;
+    use serde_json::{json, Value};
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn vault_account(owner: Pubkey, session_expires_at: i64) -> Account {
+        let mut vault = ephemeralvault::EphemeralVault::zeroed();
+        vault.user_wallet = owner.to_bytes().into();
+        vault.delegate_wallet = [7; 32].into();
+        vault.session_expires_at = session_expires_at;
+        vault.delegated_at = NOW;
+        vault.approved_amount = 1_000_000_000;
+        vault.is_active = 1;
+        vault.version = 2;
+
+        let mut data = ephemeralvault::EphemeralVault::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(bytemuck::bytes_of(&vault));
+        Account {
+            lamports: 10_000_000,
+            data,
+            owner: ephemeralvault::ID.to_bytes().into(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn drain(events: &mut broadcast::Receiver<FeedEvent>) -> Vec<Value> {
+        let mut messages = Vec::new();
+        loop {
+            match events.try_recv() {
+                Ok(event) => messages.push(serde_json::to_value(event.message).unwrap()),
+                Err(TryRecvError::Empty) => return messages,
+                Err(e) => panic!("feed closed: {e}"),
+            }
+        }
+    }
+
+    fn types(messages: &[Value]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|message| message["type"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn pushes_account_changes_and_session_warnings() {
+        let feed = VaultFeed::default();
+        let mut events = feed.events();
+        let vault = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        feed.watch([vault]).unwrap();
+
+        assert!(feed.apply(vault, Some(vault_account(owner, NOW + 1_000)), NOW));
+        let messages = drain(&mut events);
+        assert_eq!(types(&messages), ["vault"]);
+        assert_eq!(messages[0]["vault"]["owner"], owner.to_string());
+        assert_eq!(messages[0]["vault"]["sessionStatus"], "active");
+
+        assert!(!feed.apply(vault, Some(vault_account(owner, NOW + 1_000)), NOW));
+        feed.tick(NOW + 600);
+        assert!(drain(&mut events).is_empty());
+
+        feed.tick(NOW + 800);
+        let messages = drain(&mut events);
+        assert_eq!(types(&messages), ["vault", "sessionExpiringSoon"]);
+        assert_eq!(messages[1]["address"], vault.to_string());
+        assert_eq!(messages[1]["secondsUntilExpiry"], 200);
+        assert_eq!(messages[1]["canRenew"], true);
+
+        feed.tick(NOW + 1_000);
+        assert_eq!(types(&drain(&mut events)), ["vault", "sessionExpired"]);
+
+        assert!(feed.apply(vault, Some(vault_account(owner, NOW + 5_000)), NOW + 1_000));
+        assert_eq!(types(&drain(&mut events)), ["vault"]);
+
+        assert!(feed.apply(vault, None, NOW + 1_000));
+        assert!(!feed.apply(vault, None, NOW + 1_000));
+        assert_eq!(
+            drain(&mut events),
+            [json!({ "type": "vaultNotFound", "address": vault.to_string() })]
+        );
+    }
+
+    #[test]
+    fn expiring_vault_warns_on_first_sight_and_in_snapshots() {
+        let feed = VaultFeed::default();
+        let mut events = feed.events();
+        let vault = Pubkey::new_unique();
+        feed.watch([vault]).unwrap();
+
+        assert!(feed.snapshot(&vault, NOW).is_none());
+        feed.apply(
+            vault,
+            Some(vault_account(Pubkey::new_unique(), NOW + 60)),
+            NOW,
+        );
+
+        assert_eq!(types(&drain(&mut events)), ["vault", "sessionExpiringSoon"]);
+        let snapshot = feed.snapshot(&vault, NOW).unwrap();
+        assert!(matches!(
+            snapshot.as_slice(),
+            [
+                ServerMessage::Vault { .. },
+                ServerMessage::SessionExpiringSoon { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn forgets_vaults_once_the_last_watcher_leaves() {
+        let feed = VaultFeed::default();
+        let mut events = feed.events();
+        let vault = Pubkey::new_unique();
+
+        assert!(!feed.apply(vault, None, NOW));
+        feed.watch([vault]).unwrap();
+        feed.watch([vault]).unwrap();
+        feed.unwatch([vault]);
+        assert_eq!(feed.watched(), HashSet::from([vault]));
+        assert!(feed.apply(vault, None, NOW));
+
+        feed.unwatch([vault]);
+        assert!(feed.watched().is_empty());
+        assert!(!feed.apply(vault, Some(vault_account(Pubkey::new_unique(), NOW)), NOW));
+        assert!(feed.snapshot(&vault, NOW).is_none());
+        assert_eq!(types(&drain(&mut events)), ["vaultNotFound"]);
+    }
+
+    #[test]
+    fn caps_watched_vaults_and_connections() {
+        let feed = Arc::new(VaultFeed::with_limits(16, 2, 1));
+        let (first, second, third) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+
+        feed.watch([first, second]).unwrap();
+        assert!(matches!(
+            feed.watch([second, third]),
+            Err(AppError::RateLimited(_))
+        ));
+        assert_eq!(feed.watched(), HashSet::from([first, second]));
+        // Vaults already followed upstream cost nothing more.
+        feed.watch([first, second]).unwrap();
+        feed.unwatch([first, first]);
+        feed.watch([third]).unwrap();
+        assert_eq!(feed.watched(), HashSet::from([second, third]));
+
+        let connection = feed.connect().expect("below the limit");
+        assert!(feed.connect().is_none());
+        drop(connection);
+        assert!(feed.connect().is_some());
+    }
+
+    #[test]
+    fn parses_client_messages() {
+        let message: ClientMessage =
+            serde_json::from_str(r#"{"type":"subscribe","owners":["abc"]}"#).unwrap();
+        let ClientMessage::Subscribe(request) = message else {
+            panic!("expected subscribe");
+        };
+        assert!(request.vaults.is_empty());
+        assert_eq!(request.owners, ["abc"]);
+
+        assert!(matches!(
+            serde_json::from_str(r#"{"type":"ping"}"#).unwrap(),
+            ClientMessage::Ping
+        ));
+        assert!(serde_json::from_str::<ClientMessage>(r#"{"type":"watch"}"#).is_err());
+    }
+}
@@ -0,0 +1,416 @@
+//! Feeds arbitrary instruction data and account lists into the compiled
+//! program running in LiteSVM.
+//!
+//! Input layout, shared with the seed generator in `backend::solana`:
+//!
+//! | bytes    | meaning                                                         |
+//! |----------|-----------------------------------------------------------------|
+//! | 0        | clock warp in minutes before the instruction runs               |
+//! | 1        | account count `n`, taken modulo `MAX_ACCOUNTS + 1`              |
+//! | 2..2+n   | account specs: bits 0-2 pool slot, bit 4 signer, bit 5 writable |
+//! | rest     | instruction data                                                |
+//!
+//! Every run starts from the same state: an owner with a funded, delegated
+//! vault, the delegate, and an attacker whose own vault is revoked and past
+//! its cleanup grace period. After each instruction the target asserts that
+//! the program did not panic (overflow checks are on in release builds),
+//! that no wallet lost lamports without signing, that vaults only changed
+//! when someone entitled to change them signed, and that vault balances
+//! stay backed by lamports above rent.
+
+#![no_main]
+
+use anchor_lang::{system_program, Discriminator, InstructionData};
+use ephemeral_vault::{instruction as ix, EphemeralVault, VAULT_SPACE};
+use libfuzzer_sys::fuzz_target;
+use litesvm::LiteSVM;
+use solana_sdk::{
+    account::Account,
+    clock::Clock,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+const MAX_ACCOUNTS: u8 = 6;
+const SLOT_MASK: u8 = 0b111;
+const SIGNER: u8 = 1 << 4;
+const WRITABLE: u8 = 1 << 5;
+
+// Account pool slots; slot 5 is the system program and slot 6 the program.
+const USER: usize = 0;
+const DELEGATE: usize = 1;
+const ATTACKER: usize = 2;
+const USER_VAULT: usize = 3;
+const ATTACKER_VAULT: usize = 4;
+const EMPTY_VAULT: usize = 7;
+const POOL_SIZE: usize = 8;
+
+const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+
+type Snapshot = [Option<Account>; POOL_SIZE];
+
+fn program_id() -> Pubkey {
+    ephemeral_vault::ID
+}
+
+fn vault_pda(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault", owner.as_ref()], &program_id()).0
+}
+
+fn program_bytes() -> Vec<u8> {
+    let path = std::env::var_os("SBF_OUT_DIR")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| {
+            std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../../target/deploy")
+        })
+        .join("ephemeral_vault.so");
+    std::fs::read(&path).unwrap_or_else(|err| {
+        panic!(
+            "{}: {err}; build the program with `anchor build` first",
+            path.display()
+        )
+    })
+}
+
+struct Harness {
+    svm: LiteSVM,
+    payer: Keypair,
+    wallets: [Keypair; 3],
+    pool: [Pubkey; POOL_SIZE],
+}
+
+impl Clone for Harness {
+    fn clone(&self) -> Self {
+        Self {
+            svm: self.svm.clone(),
+            payer: self.payer.insecure_clone(),
+            wallets: self.wallets.each_ref().map(Keypair::insecure_clone),
+            pool: self.pool,
+        }
+    }
+}
+
+impl Harness {
+    fn new() -> Self {
+        let mut svm = LiteSVM::new();
+        svm.add_program(program_id(), &program_bytes())
+            .expect("load program");
+
+        let payer = Keypair::new();
+        let wallets = [Keypair::new(), Keypair::new(), Keypair::new()];
+        for key in std::iter::once(&payer).chain(&wallets) {
+            svm.airdrop(&key.pubkey(), 100 * LAMPORTS_PER_SOL)
+                .expect("airdrop");
+        }
+
+        let pool = [
+            wallets[USER].pubkey(),
+            wallets[DELEGATE].pubkey(),
+            wallets[ATTACKER].pubkey(),
+            vault_pda(&wallets[USER].pubkey()),
+            vault_pda(&wallets[ATTACKER].pubkey()),
+            system_program::ID,
+            program_id(),
+            vault_pda(&Pubkey::new_unique()),
+        ];
+        let mut harness = Self {
+            svm,
+            payer,
+            wallets,
+            pool,
+        };
+        harness.seed_state();
+        harness
+    }
+
+    /// Builds the starting state through the program itself.
+    fn seed_state(&mut self) {
+        let delegate = self.pool[DELEGATE];
+        let steps = [
+            (USER, self.create_ix(USER, USER_VAULT, 5 * LAMPORTS_PER_SOL)),
+            (
+                USER,
+                self.owner_ix(
+                    USER_VAULT,
+                    USER,
+                    ix::ApproveDelegate {
+                        delegate,
+                        custom_duration: None,
+                    }
+                    .data(),
+                ),
+            ),
+            (USER, self.deposit_ix(USER, USER_VAULT, LAMPORTS_PER_SOL)),
+            (
+                ATTACKER,
+                self.create_ix(ATTACKER, ATTACKER_VAULT, LAMPORTS_PER_SOL),
+            ),
+            (
+                ATTACKER,
+                self.owner_ix(ATTACKER_VAULT, ATTACKER, ix::RevokeAccess {}.data()),
+            ),
+        ];
+        for (signer, instruction) in steps {
+            self.send(instruction, &[signer]).expect("seed state");
+        }
+        self.warp(60);
+    }
+
+    fn create_ix(&self, owner: usize, vault: usize, approved_amount: u64) -> Instruction {
+        Instruction {
+            program_id: program_id(),
+            accounts: vec![
+                AccountMeta::new(self.pool[owner], true),
+                AccountMeta::new(self.pool[vault], false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data: ix::CreateEphemeralVault { approved_amount }.data(),
+        }
+    }
+
+    fn deposit_ix(&self, owner: usize, vault: usize, amount: u64) -> Instruction {
+        Instruction {
+            program_id: program_id(),
+            accounts: vec![
+                AccountMeta::new(self.pool[vault], false),
+                AccountMeta::new(self.pool[owner], true),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data: ix::AutoDepositForTrade {
+                trade_fee_estimate: amount,
+            }
+            .data(),
+        }
+    }
+
+    fn owner_ix(&self, vault: usize, owner: usize, data: Vec<u8>) -> Instruction {
+        Instruction {
+            program_id: program_id(),
+            accounts: vec![
+                AccountMeta::new(self.pool[vault], false),
+                AccountMeta::new(self.pool[owner], true),
+            ],
+            data,
+        }
+    }
+
+    fn warp(&mut self, seconds: i64) {
+        let mut clock = self.svm.get_sysvar::<Clock>();
+        clock.unix_timestamp += seconds;
+        self.svm.set_sysvar(&clock);
+    }
+
+    /// Sends `instruction` paid by the harness payer; failures carry the logs.
+    fn send(&mut self, instruction: Instruction, signers: &[usize]) -> Result<(), Vec<String>> {
+        let mut keypairs = vec![&self.payer];
+        keypairs.extend(signers.iter().map(|&slot| &self.wallets[slot]));
+        let tx = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&self.payer.pubkey()),
+            &keypairs,
+            self.svm.latest_blockhash(),
+        );
+        let result = self
+            .svm
+            .send_transaction(tx)
+            .map(|_| ())
+            .map_err(|failed| failed.meta.logs);
+        self.svm.expire_blockhash();
+        result
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        std::array::from_fn(|slot| self.svm.get_account(&self.pool[slot]))
+    }
+}
+
+fn vault_state(account: &Option<Account>) -> Option<EphemeralVault> {
+    let account = account.as_ref()?;
+    if account.data.len() != VAULT_SPACE || !account.data.starts_with(EphemeralVault::DISCRIMINATOR)
+    {
+        return None;
+    }
+    Some(bytemuck::pod_read_unaligned(&account.data[8..]))
+}
+
+fn lamports(account: &Option<Account>) -> u64 {
+    account.as_ref().map_or(0, |account| account.lamports)
+}
+
+struct Outcome<'a> {
+    before: &'a Snapshot,
+    after: &'a Snapshot,
+    signed: [bool; 3],
+    delegate: Pubkey,
+    data: &'a [u8],
+}
+
+impl Outcome<'_> {
+    /// A vault may only change if its owner signed, its live delegate traded,
+    /// or anyone cleaned it up after revocation.
+    fn check_vault(&self, slot: usize, owner: usize) {
+        let (before, after) = (&self.before[slot], &self.after[slot]);
+        if before == after || self.signed[owner] {
+            return;
+        }
+        let old = vault_state(before).expect("vault existed before");
+
+        let Some(new) = vault_state(after) else {
+            assert!(
+                !old.active(),
+                "active vault {slot} closed without its owner"
+            );
+            assert!(
+                self.data.starts_with(ix::CleanupVault::DISCRIMINATOR),
+                "vault {slot} closed outside cleanup"
+            );
+            assert!(
+                lamports(&self.after[owner]) > lamports(&self.before[owner]),
+                "cleanup of vault {slot} did not pay its owner"
+            );
+            return;
+        };
+
+        let delegate = old.delegate();
+        assert!(
+            self.signed[DELEGATE] && delegate == Some(self.delegate),
+            "vault {slot} changed without its owner or delegate"
+        );
+        assert!(
+            self.data.starts_with(ix::ExecuteTrade::DISCRIMINATOR)
+                || self.data.starts_with(ix::ExecuteTradesBatch::DISCRIMINATOR),
+            "delegate changed vault {slot} outside a trade"
+        );
+        assert_eq!(
+            lamports(before),
+            lamports(after),
+            "delegate moved vault lamports"
+        );
+        assert_eq!(new.user_wallet, old.user_wallet);
+        assert_eq!(new.approved_amount, old.approved_amount);
+        assert_eq!(new.delegate(), old.delegate());
+        assert_eq!(new.session_expires_at, old.session_expires_at);
+        assert_eq!(new.is_active, old.is_active);
+        assert_eq!(new.is_paused, old.is_paused);
+    }
+
+    fn check_wallets(&self) {
+        for wallet in USER..=ATTACKER {
+            if !self.signed[wallet] {
+                assert!(
+                    lamports(&self.after[wallet]) >= lamports(&self.before[wallet]),
+                    "wallet {wallet} lost lamports without signing"
+                );
+            }
+        }
+    }
+
+    fn check_balances(&self) {
+        let rent = Rent::default().minimum_balance(VAULT_SPACE);
+        for slot in [USER_VAULT, ATTACKER_VAULT, EMPTY_VAULT] {
+            let Some(vault) = vault_state(&self.after[slot]) else {
+                continue;
+            };
+            let lamports = lamports(&self.after[slot]);
+            assert!(lamports >= rent, "vault {slot} dropped below rent");
+            assert!(
+                lamports - rent >= vault.available_amount,
+                "vault {slot} balance is not backed by lamports"
+            );
+            assert!(vault.used_amount <= vault.approved_amount);
+        }
+
+        let total = |accounts: &Snapshot| -> u64 {
+            [
+                USER,
+                DELEGATE,
+                ATTACKER,
+                USER_VAULT,
+                ATTACKER_VAULT,
+                EMPTY_VAULT,
+            ]
+            .into_iter()
+            .map(|slot| lamports(&accounts[slot]))
+            .sum()
+        };
+        assert_eq!(
+            total(self.before),
+            total(self.after),
+            "lamports created or destroyed"
+        );
+    }
+}
+
+thread_local! {
+    static BASE: Harness = Harness::new();
+}
+
+fuzz_target!(|input: &[u8]| {
+    let [warp_minutes, count, rest @ ..] = input else {
+        return;
+    };
+    let count = usize::from(count % (MAX_ACCOUNTS + 1));
+    if rest.len() < count {
+        return;
+    }
+    let (specs, data) = rest.split_at(count);
+
+    let mut harness = BASE.with(Harness::clone);
+    harness.warp(i64::from(*warp_minutes) * 60);
+
+    let mut signed = [false; 3];
+    let accounts = specs
+        .iter()
+        .map(|&spec| {
+            let slot = usize::from(spec & SLOT_MASK);
+            // Only the three wallets have keys the harness can sign with.
+            let is_signer = spec & SIGNER != 0 && slot <= ATTACKER;
+            if is_signer {
+                signed[slot] = true;
+            }
+            AccountMeta {
+                pubkey: harness.pool[slot],
+                is_signer,
+                is_writable: spec & WRITABLE != 0,
+            }
+        })
+        .collect();
+    let signers: Vec<usize> = (USER..=ATTACKER).filter(|&slot| signed[slot]).collect();
+    let instruction = Instruction {
+        program_id: program_id(),
+        accounts,
+        data: data.to_vec(),
+    };
+
+    let before = harness.snapshot();
+    match harness.send(instruction, &signers) {
+        Ok(()) => {
+            let after = harness.snapshot();
+            let outcome = Outcome {
+                before: &before,
+                after: &after,
+                signed,
+                delegate: harness.pool[DELEGATE],
+                data,
+            };
+            outcome.check_vault(USER_VAULT, USER);
+            outcome.check_vault(ATTACKER_VAULT, ATTACKER);
+            outcome.check_wallets();
+            outcome.check_balances();
+        }
+        Err(logs) => {
+            assert!(
+                !logs.iter().any(|log| log.contains("panicked")),
+                "program panicked: {logs:#?}"
+            );
+            assert_eq!(
+                harness.snapshot(),
+                before,
+                "failed transaction changed state"
+            );
+        }
+    }
+});
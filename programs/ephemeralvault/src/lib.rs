@@ -5,7 +5,7 @@ use anchor_lang::system_program::{transfer, Transfer};
 
 declare_id!("3L2LMJHHvgaGnvQ2ic7a5yu6DffLfoAQFLwFSjFJ4QQt");
 
-const SESSION_DURATION: i64 = 3600; // 1 hour
+pub const SESSION_DURATION: i64 = 3600; // 1 hour
 const SESSION_RENEWAL_WINDOW: i64 = 300; // 5 minutes before expiry
 const MAX_APPROVED_AMOUNT: u64 = 1_000_000_000_000; // 1000 SOL
 const MIN_APPROVED_AMOUNT: u64 = 1_000_000; // 0.001 SOL
@@ -14,11 +14,24 @@ const MAX_DEPOSIT_AMOUNT: u64 = 100_000_000_000; // 100 SOL per deposit
 const CLEANUP_REWARD_BPS: u64 = 100; // 1%
 const MIN_CLEANUP_REWARD: u64 = 100_000; // 0.0001 SOL minimum reward
 const CLEANUP_GRACE_PERIOD: i64 = 1; // 1 second before an inactive vault may be closed
+/// Maximum number of entries accepted by `execute_trades_batch`.
+pub const MAX_BATCH_TRADES: usize = 32;
+/// Number of client order ids below the highest seen id that may still land.
+pub const CLIENT_ORDER_WINDOW: u64 = 64;
 #[allow(dead_code)]
 const EMERGENCY_PAUSE_AUTHORITY: Pubkey = Pubkey::new_from_array([0; 32]); // Set in production
 
 // Version for upgrade tracking
-const PROGRAM_VERSION: u8 = 1;
+const PROGRAM_VERSION: u8 = 2;
+const LEGACY_PROGRAM_VERSION: u8 = 1;
+
+/// Account size (discriminator included) of the zero-copy vault layout.
+pub const VAULT_SPACE: usize = 8 + std::mem::size_of::<EphemeralVault>();
+/// Account size of vaults created with the Borsh layout (`version == 1`).
+pub const LEGACY_VAULT_SPACE: usize = 208;
+
+// Sentinel stored in timestamp fields when no value is set.
+const NO_TIMESTAMP: i64 = 0;
 
 fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()> {
     if amount == 0 {
@@ -38,6 +51,27 @@ fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()
     Ok(())
 }
 
+/// Lamports held by `account` above its rent-exempt minimum.
+pub fn rent_surplus(account: &AccountInfo) -> Result<u64> {
+    let rent_exempt = Rent::get()?.minimum_balance(account.data_len());
+    Ok(account.lamports().saturating_sub(rent_exempt))
+}
+
+/// Reward paid to the cleaner of a vault holding `surplus_lamports` above rent.
+pub fn cleanup_reward(surplus_lamports: u64) -> Result<u64> {
+    if surplus_lamports == 0 {
+        return Ok(0);
+    }
+
+    Ok(surplus_lamports
+        .checked_mul(CLEANUP_REWARD_BPS)
+        .ok_or(EphemeralVaultError::MathOverflow)?
+        .checked_div(10000)
+        .ok_or(EphemeralVaultError::MathOverflow)?
+        .max(MIN_CLEANUP_REWARD)
+        .min(surplus_lamports / 10)) // Cap at 10%
+}
+
 #[program]
 pub mod ephemeral_vault {
     use super::*;
@@ -53,7 +87,7 @@ pub mod ephemeral_vault {
 
         let clock = Clock::get()?;
         let vault_key = ctx.accounts.vault.key();
-        let vault = &mut ctx.accounts.vault;
+        let mut vault = ctx.accounts.vault.load_init()?;
 
         vault.user_wallet = ctx.accounts.user.key();
         vault.vault_pda = vault_key;
@@ -62,14 +96,12 @@ pub mod ephemeral_vault {
         vault.approved_amount = approved_amount;
         vault.used_amount = 0;
         vault.available_amount = 0;
-        vault.delegate_wallet = None;
-        vault.delegated_at = None;
-        vault.session_expires_at = None;
+        vault.clear_delegate();
         vault.total_deposited = 0;
         vault.total_withdrawn = 0;
         vault.trade_count = 0;
-        vault.is_active = true;
-        vault.is_paused = false;
+        vault.set_active(true);
+        vault.set_paused(false);
         vault.version = PROGRAM_VERSION;
         vault.bump = ctx.bumps.vault;
 
@@ -89,11 +121,12 @@ pub mod ephemeral_vault {
         delegate: Pubkey,
         custom_duration: Option<i64>,
     ) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let mut vault = ctx.accounts.vault.load_mut()?;
         let clock = Clock::get()?;
 
-        require!(vault.is_active, EphemeralVaultError::VaultInactive);
-        require!(!vault.is_paused, EphemeralVaultError::VaultPaused);
+        require!(vault.active(), EphemeralVaultError::VaultInactive);
+        require!(!vault.paused(), EphemeralVaultError::VaultPaused);
         require_keys_eq!(
             vault.user_wallet,
             ctx.accounts.user.key(),
@@ -104,6 +137,12 @@ pub mod ephemeral_vault {
             ctx.accounts.user.key(),
             EphemeralVaultError::InvalidDelegate
         );
+        // The default key marks an empty delegate slot.
+        require_keys_neq!(
+            delegate,
+            Pubkey::default(),
+            EphemeralVaultError::InvalidDelegate
+        );
 
         // Calculate session expiry
         let duration = custom_duration
@@ -116,14 +155,12 @@ pub mod ephemeral_vault {
             .checked_add(duration)
             .ok_or(EphemeralVaultError::MathOverflow)?;
 
-        vault.delegate_wallet = Some(delegate);
-        vault.delegated_at = Some(clock.unix_timestamp);
-        vault.session_expires_at = Some(expires_at);
+        vault.set_delegate(delegate, clock.unix_timestamp, expires_at);
         vault.last_activity = clock.unix_timestamp;
 
         emit!(DelegateApproved {
             user: ctx.accounts.user.key(),
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             delegate,
             expires_at,
             timestamp: clock.unix_timestamp,
@@ -134,11 +171,12 @@ pub mod ephemeral_vault {
 
     /// Renews an existing session before it expires
     pub fn renew_session(ctx: Context<RenewSession>) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let mut vault = ctx.accounts.vault.load_mut()?;
         let clock = Clock::get()?;
 
-        require!(vault.is_active, EphemeralVaultError::VaultInactive);
-        require!(!vault.is_paused, EphemeralVaultError::VaultPaused);
+        require!(vault.active(), EphemeralVaultError::VaultInactive);
+        require!(!vault.paused(), EphemeralVaultError::VaultPaused);
         require_keys_eq!(
             vault.user_wallet,
             ctx.accounts.user.key(),
@@ -146,10 +184,10 @@ pub mod ephemeral_vault {
         );
 
         let delegate = vault
-            .delegate_wallet
+            .delegate()
             .ok_or(EphemeralVaultError::NoActiveSession)?;
         let expires_at = vault
-            .session_expires_at
+            .session_expiry()
             .ok_or(EphemeralVaultError::NoActiveSession)?;
 
         require!(
@@ -173,12 +211,12 @@ pub mod ephemeral_vault {
             .checked_add(SESSION_DURATION)
             .ok_or(EphemeralVaultError::MathOverflow)?;
 
-        vault.session_expires_at = Some(new_expires_at);
+        vault.session_expires_at = new_expires_at;
         vault.last_activity = clock.unix_timestamp;
 
         emit!(SessionRenewed {
             user: ctx.accounts.user.key(),
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             delegate,
             new_expires_at,
             timestamp: clock.unix_timestamp,
@@ -192,46 +230,51 @@ pub mod ephemeral_vault {
         ctx: Context<AutoDeposit>,
         trade_fee_estimate: u64,
     ) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
         let clock = Clock::get()?;
 
-        require!(vault.is_active, EphemeralVaultError::VaultInactive);
-        require!(!vault.is_paused, EphemeralVaultError::VaultPaused);
-        require_keys_eq!(
-            vault.user_wallet,
-            ctx.accounts.user.key(),
-            EphemeralVaultError::Unauthorized
-        );
-        require!(
-            trade_fee_estimate >= MIN_DEPOSIT_AMOUNT,
-            EphemeralVaultError::DepositTooSmall
-        );
-        require!(
-            trade_fee_estimate <= MAX_DEPOSIT_AMOUNT,
-            EphemeralVaultError::DepositTooLarge
-        );
+        {
+            let vault = ctx.accounts.vault.load()?;
 
-        // Check deposit limit
-        let new_available_amount = vault
-            .available_amount
-            .checked_add(trade_fee_estimate)
-            .ok_or(EphemeralVaultError::MathOverflow)?;
-        require!(
-            new_available_amount <= vault.approved_amount,
-            EphemeralVaultError::OverDeposit
-        );
+            require!(vault.active(), EphemeralVaultError::VaultInactive);
+            require!(!vault.paused(), EphemeralVaultError::VaultPaused);
+            require_keys_eq!(
+                vault.user_wallet,
+                ctx.accounts.user.key(),
+                EphemeralVaultError::Unauthorized
+            );
+            require!(
+                trade_fee_estimate >= MIN_DEPOSIT_AMOUNT,
+                EphemeralVaultError::DepositTooSmall
+            );
+            require!(
+                trade_fee_estimate <= MAX_DEPOSIT_AMOUNT,
+                EphemeralVaultError::DepositTooLarge
+            );
+
+            // Check deposit limit
+            let new_available_amount = vault
+                .available_amount
+                .checked_add(trade_fee_estimate)
+                .ok_or(EphemeralVaultError::MathOverflow)?;
+            require!(
+                new_available_amount <= vault.approved_amount,
+                EphemeralVaultError::OverDeposit
+            );
+        }
 
-        // Transfer SOL using safe CPI
+        // Transfer SOL using safe CPI (the vault data must not be borrowed here)
         let cpi_context = CpiContext::new(
             ctx.accounts.system_program.to_account_info(),
             Transfer {
                 from: ctx.accounts.user.to_account_info(),
-                to: vault.to_account_info(),
+                to: ctx.accounts.vault.to_account_info(),
             },
         );
         transfer(cpi_context, trade_fee_estimate)?;
 
         // Update vault accounting
+        let mut vault = ctx.accounts.vault.load_mut()?;
         vault.total_deposited = vault
             .total_deposited
             .checked_add(trade_fee_estimate)
@@ -244,7 +287,7 @@ pub mod ephemeral_vault {
 
         emit!(AutoDepositEvent {
             user: ctx.accounts.user.key(),
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             amount: trade_fee_estimate,
             total_deposited: vault.total_deposited,
             available_amount: vault.available_amount,
@@ -255,80 +298,102 @@ pub mod ephemeral_vault {
     }
 
     /// Executes a trade using vault funds (called by delegate)
+    ///
+    /// `client_order_id` must be unique per vault; see `CLIENT_ORDER_WINDOW`.
     pub fn execute_trade(
         ctx: Context<ExecuteTrade>,
         trade_fee: u64,
         trade_amount: u64,
+        client_order_id: u64,
     ) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let mut vault = ctx.accounts.vault.load_mut()?;
         let delegate = &ctx.accounts.delegate;
         let clock = Clock::get()?;
 
-        require!(vault.is_active, EphemeralVaultError::VaultInactive);
-        require!(!vault.is_paused, EphemeralVaultError::VaultPaused);
+        vault.require_live_delegate(delegate.key(), clock.unix_timestamp)?;
+        vault.record_client_order_id(client_order_id)?;
+        vault.apply_trade(trade_fee, trade_amount)?;
+        vault.last_activity = clock.unix_timestamp;
 
-        // Verify delegate
-        require!(
-            vault.delegate_wallet == Some(delegate.key()),
-            EphemeralVaultError::Unauthorized
-        );
+        emit!(TradeExecuted {
+            delegate: delegate.key(),
+            vault_pda: vault_key,
+            client_order_id,
+            trade_fee,
+            trade_amount,
+            remaining_available: vault.available_amount,
+            trade_number: vault.trade_count,
+            timestamp: clock.unix_timestamp,
+        });
 
-        // Check session expiry
-        let expires_at = vault
-            .session_expires_at
-            .ok_or(EphemeralVaultError::DelegateNotProperlySet)?;
+        Ok(())
+    }
 
+    /// Executes several trades atomically (called by delegate)
+    ///
+    /// Every entry goes through the same checks as `execute_trade`; if any
+    /// entry fails, the whole batch is rejected.
+    ///
+    /// # Errors
+    /// * `EmptyTradeBatch` - If `entries` is empty
+    /// * `TradeBatchTooLarge` - If `entries` exceeds `MAX_BATCH_TRADES`
+    pub fn execute_trades_batch(
+        ctx: Context<ExecuteTrade>,
+        entries: Vec<TradeEntry>,
+    ) -> Result<()> {
+        require!(!entries.is_empty(), EphemeralVaultError::EmptyTradeBatch);
         require!(
-            clock.unix_timestamp < expires_at,
-            EphemeralVaultError::SessionExpired
+            entries.len() <= MAX_BATCH_TRADES,
+            EphemeralVaultError::TradeBatchTooLarge
         );
 
-        require!(
-            vault.available_amount >= trade_fee,
-            EphemeralVaultError::InsufficientFunds
-        );
+        let vault_key = ctx.accounts.vault.key();
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let delegate = &ctx.accounts.delegate;
+        let clock = Clock::get()?;
 
-        // Validate trade_amount
-        require!(
-            trade_amount > 0 && trade_amount <= vault.approved_amount,
-            EphemeralVaultError::InvalidTradeAmount
-        );
-        let new_used_amount = vault
-            .used_amount
-            .checked_add(trade_amount)
-            .ok_or(EphemeralVaultError::MathOverflow)?;
-        require!(
-            new_used_amount <= vault.approved_amount,
-            EphemeralVaultError::TradeLimitExceeded
-        );
+        vault.require_live_delegate(delegate.key(), clock.unix_timestamp)?;
 
-        // Update vault state
-        vault.available_amount = vault
-            .available_amount
-            .checked_sub(trade_fee)
-            .ok_or(EphemeralVaultError::MathOverflow)?;
-        vault.used_amount = new_used_amount;
-        vault.trade_count = vault
+        let first_trade_number = vault
             .trade_count
             .checked_add(1)
             .ok_or(EphemeralVaultError::MathOverflow)?;
+        let mut total_fee: u64 = 0;
+        let mut total_amount: u64 = 0;
+
+        for entry in &entries {
+            vault.record_client_order_id(entry.client_id)?;
+            vault.apply_trade(entry.trade_fee, entry.trade_amount)?;
+            total_fee = total_fee
+                .checked_add(entry.trade_fee)
+                .ok_or(EphemeralVaultError::MathOverflow)?;
+            total_amount = total_amount
+                .checked_add(entry.trade_amount)
+                .ok_or(EphemeralVaultError::MathOverflow)?;
+        }
         vault.last_activity = clock.unix_timestamp;
 
-        emit!(TradeExecuted {
+        emit!(TradesBatchExecuted {
             delegate: delegate.key(),
-            vault_pda: vault.key(),
-            trade_fee,
-            trade_amount,
+            vault_pda: vault_key,
+            client_ids: entries.iter().map(|entry| entry.client_id).collect(),
+            total_fee,
+            total_amount,
             remaining_available: vault.available_amount,
-            trade_number: vault.trade_count,
+            first_trade_number,
+            last_trade_number: vault.trade_count,
             timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
+
     /// Withdraws available balance back to user wallet
     pub fn withdraw_balance(ctx: Context<WithdrawBalance>, amount: u64) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let vault_info = ctx.accounts.vault.to_account_info();
+        let mut vault = ctx.accounts.vault.load_mut()?;
         let clock = Clock::get()?;
 
         require_keys_eq!(
@@ -337,8 +402,8 @@ pub mod ephemeral_vault {
             EphemeralVaultError::Unauthorized
         );
 
-        let vault_lamports = vault.to_account_info().lamports();
-        let rent_exempt = Rent::get()?.minimum_balance(vault.to_account_info().data_len());
+        let vault_lamports = vault_info.lamports();
+        let rent_exempt = Rent::get()?.minimum_balance(vault_info.data_len());
         let max_withdrawable = vault_lamports
             .checked_sub(rent_exempt)
             .ok_or(EphemeralVaultError::InsufficientFunds)?;
@@ -356,7 +421,7 @@ pub mod ephemeral_vault {
 
         if withdraw_amount > 0 {
             move_lamports(
-                &vault.to_account_info(),
+                &vault_info,
                 &ctx.accounts.user.to_account_info(),
                 withdraw_amount,
             )?;
@@ -375,7 +440,7 @@ pub mod ephemeral_vault {
 
         emit!(BalanceWithdrawn {
             user: ctx.accounts.user.key(),
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             amount: withdraw_amount,
             timestamp: clock.unix_timestamp,
         });
@@ -385,7 +450,9 @@ pub mod ephemeral_vault {
 
     /// Revokes delegate access and returns all funds
     pub fn revoke_access(ctx: Context<RevokeAccess>) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let vault_info = ctx.accounts.vault.to_account_info();
+        let mut vault = ctx.accounts.vault.load_mut()?;
         let clock = Clock::get()?;
 
         require_keys_eq!(
@@ -395,27 +462,32 @@ pub mod ephemeral_vault {
         );
 
         // Revoke delegate
-        let was_delegated = vault.delegate_wallet.is_some();
-        vault.delegate_wallet = None;
-        vault.delegated_at = None;
-        vault.session_expires_at = None;
+        let was_delegated = vault.delegate().is_some();
+        vault.clear_delegate();
 
         // Return all available balance
-        let vault_lamports = vault.to_account_info().lamports();
-        let rent_exempt = Rent::get()?.minimum_balance(vault.to_account_info().data_len());
+        let vault_lamports = vault_info.lamports();
+        let rent_exempt = Rent::get()?.minimum_balance(vault_info.data_len());
         let transferable = vault_lamports.saturating_sub(rent_exempt);
 
         let returned_amount = if transferable > 0 {
             move_lamports(
-                &vault.to_account_info(),
+                &vault_info,
                 &ctx.accounts.user.to_account_info(),
                 transferable,
             )?;
 
-            vault.available_amount = 0;
+            // Lamports beyond `available_amount` are trade fees retained by
+            // the vault; they are released here but were never withdrawable,
+            // so only the available portion counts towards `total_withdrawn`.
+            let withdrawn = vault.available_amount.min(transferable);
+            vault.available_amount = vault
+                .available_amount
+                .checked_sub(withdrawn)
+                .ok_or(EphemeralVaultError::MathOverflow)?;
             vault.total_withdrawn = vault
                 .total_withdrawn
-                .checked_add(transferable)
+                .checked_add(withdrawn)
                 .ok_or(EphemeralVaultError::MathOverflow)?;
 
             transferable
@@ -423,12 +495,12 @@ pub mod ephemeral_vault {
             0
         };
 
-        vault.is_active = false;
+        vault.set_active(false);
         vault.last_activity = clock.unix_timestamp;
 
         emit!(AccessRevoked {
             user: ctx.accounts.user.key(),
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             was_delegated,
             returned_amount,
             timestamp: clock.unix_timestamp,
@@ -439,26 +511,25 @@ pub mod ephemeral_vault {
 
     /// Reactivates an inactive vault (clears delegate for security)
     pub fn reactivate_vault(ctx: Context<ReactivateVault>) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let mut vault = ctx.accounts.vault.load_mut()?;
 
         require_keys_eq!(
             vault.user_wallet,
             ctx.accounts.user.key(),
             EphemeralVaultError::Unauthorized
         );
-        require!(!vault.is_active, EphemeralVaultError::VaultAlreadyActive);
+        require!(!vault.active(), EphemeralVaultError::VaultAlreadyActive);
 
         // Clear delegate for security
-        vault.delegate_wallet = None;
-        vault.delegated_at = None;
-        vault.session_expires_at = None;
-        vault.is_active = true;
-        vault.is_paused = false;
+        vault.clear_delegate();
+        vault.set_active(true);
+        vault.set_paused(false);
         vault.last_activity = Clock::get()?.unix_timestamp;
 
         emit!(VaultReactivated {
             user: ctx.accounts.user.key(),
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             timestamp: vault.last_activity,
         });
 
@@ -470,7 +541,8 @@ pub mod ephemeral_vault {
         ctx: Context<UpdateApprovedAmount>,
         new_approved_amount: u64,
     ) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let mut vault = ctx.accounts.vault.load_mut()?;
 
         require_keys_eq!(
             vault.user_wallet,
@@ -493,7 +565,7 @@ pub mod ephemeral_vault {
 
         emit!(ApprovedAmountUpdated {
             user: ctx.accounts.user.key(),
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             old_amount,
             new_amount: new_approved_amount,
             timestamp: vault.last_activity,
@@ -504,7 +576,8 @@ pub mod ephemeral_vault {
 
     /// Emergency pause (can only be called by vault owner)
     pub fn emergency_pause(ctx: Context<EmergencyPause>) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let mut vault = ctx.accounts.vault.load_mut()?;
 
         require_keys_eq!(
             vault.user_wallet,
@@ -512,12 +585,12 @@ pub mod ephemeral_vault {
             EphemeralVaultError::Unauthorized
         );
 
-        vault.is_paused = true;
+        vault.set_paused(true);
         vault.last_activity = Clock::get()?.unix_timestamp;
 
         emit!(VaultPaused {
             user: ctx.accounts.user.key(),
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             timestamp: vault.last_activity,
         });
 
@@ -529,7 +602,8 @@ pub mod ephemeral_vault {
     /// # Errors
     /// * `Unauthorized` - If caller is not vault owner
     pub fn unpause_vault(ctx: Context<UnpauseVault>) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let mut vault = ctx.accounts.vault.load_mut()?;
 
         require_keys_eq!(
             vault.user_wallet,
@@ -537,12 +611,12 @@ pub mod ephemeral_vault {
             EphemeralVaultError::Unauthorized
         );
 
-        vault.is_paused = false;
+        vault.set_paused(false);
         vault.last_activity = Clock::get()?.unix_timestamp;
 
         emit!(VaultUnpaused {
             user: ctx.accounts.user.key(),
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             timestamp: vault.last_activity,
         });
 
@@ -551,40 +625,22 @@ pub mod ephemeral_vault {
 
     /// Cleans up expired, inactive vaults (with reward)
     pub fn cleanup_vault(ctx: Context<CleanupVault>) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
+        let vault_key = ctx.accounts.vault.key();
+        let vault_info = ctx.accounts.vault.to_account_info();
+        let vault = ctx.accounts.vault.load()?;
         let clock = Clock::get()?;
 
-        require!(!vault.is_active, EphemeralVaultError::VaultStillActive);
-
-        // Check expiry
-        let check_timestamp = vault
-            .session_expires_at
-            .or(vault.delegated_at)
-            .unwrap_or(vault.last_activity);
-
-        let elapsed = clock
-            .unix_timestamp
-            .checked_sub(check_timestamp)
-            .ok_or(EphemeralVaultError::MathOverflow)?;
-
+        require!(!vault.active(), EphemeralVaultError::VaultStillActive);
         require!(
-            elapsed > CLEANUP_GRACE_PERIOD,
+            vault.cleanup_allowed(clock.unix_timestamp)?,
             EphemeralVaultError::SessionNotExpired
         );
 
         // Calculate rewards
-        let vault_lamports = vault.to_account_info().lamports();
-        let rent_exempt = Rent::get()?.minimum_balance(vault.to_account_info().data_len());
-        let available = vault_lamports.saturating_sub(rent_exempt);
+        let available = rent_surplus(&vault_info)?;
 
         let (to_user, reward) = if available > 0 {
-            let reward = available
-                .checked_mul(CLEANUP_REWARD_BPS)
-                .ok_or(EphemeralVaultError::MathOverflow)?
-                .checked_div(10000)
-                .ok_or(EphemeralVaultError::MathOverflow)?
-                .max(MIN_CLEANUP_REWARD)
-                .min(available / 10); // Cap at 10%
+            let reward = cleanup_reward(available)?;
 
             let to_user = available
                 .checked_sub(reward)
@@ -593,11 +649,7 @@ pub mod ephemeral_vault {
             // Pay the cleaner from the vault. The remaining lamports,
             // including reclaimed rent, are sent to the user when the
             // account is closed at the end of the instruction.
-            move_lamports(
-                &vault.to_account_info(),
-                &ctx.accounts.cleaner.to_account_info(),
-                reward,
-            )?;
+            move_lamports(&vault_info, &ctx.accounts.cleaner.to_account_info(), reward)?;
 
             (to_user, reward)
         } else {
@@ -607,7 +659,7 @@ pub mod ephemeral_vault {
         emit!(VaultCleaned {
             cleaner: ctx.accounts.cleaner.key(),
             user_wallet: vault.user_wallet,
-            vault_pda: vault.key(),
+            vault_pda: vault_key,
             returned_to_user: to_user,
             cleaner_reward: reward,
             timestamp: clock.unix_timestamp,
@@ -618,31 +670,106 @@ pub mod ephemeral_vault {
 
     /// Gets vault statistics (view function)
     pub fn get_vault_stats(ctx: Context<GetVaultStats>) -> Result<VaultStats> {
-        let vault = &ctx.accounts.vault;
+        let surplus = rent_surplus(&ctx.accounts.vault.to_account_info())?;
+        let vault = ctx.accounts.vault.load()?;
         let clock = Clock::get()?;
 
-        let session_status = if let Some(expires_at) = vault.session_expires_at {
-            if clock.unix_timestamp >= expires_at {
-                SessionStatus::Expired
-            } else if expires_at - clock.unix_timestamp <= SESSION_RENEWAL_WINDOW {
-                SessionStatus::ExpiringSoon
-            } else {
-                SessionStatus::Active
-            }
-        } else {
-            SessionStatus::NoSession
+        vault.stats(clock.unix_timestamp, surplus)
+    }
+
+    /// Confirms the signer is a live delegate with at least
+    /// `min_remaining_budget` lamports of approved budget left (view function)
+    ///
+    /// Intended for other programs to call via CPI before acting on a
+    /// delegate's behalf.
+    ///
+    /// # Errors
+    /// * `VaultInactive` / `VaultPaused` - If the vault cannot trade
+    /// * `Unauthorized` - If the signer is not the approved delegate
+    /// * `SessionExpired` - If the session has expired
+    /// * `InsufficientDelegateBudget` - If the remaining budget is below the minimum
+    pub fn check_delegate(
+        ctx: Context<CheckDelegate>,
+        min_remaining_budget: u64,
+    ) -> Result<DelegateCheck> {
+        let surplus = rent_surplus(&ctx.accounts.vault.to_account_info())?;
+        let vault = ctx.accounts.vault.load()?;
+        let clock = Clock::get()?;
+
+        vault.check_delegate(
+            ctx.accounts.delegate.key(),
+            clock.unix_timestamp,
+            min_remaining_budget,
+            surplus,
+        )
+    }
+
+    /// Migrates a vault created with the Borsh layout to the zero-copy layout
+    ///
+    /// The account is resized in place and the owner pays the additional rent,
+    /// so `available_amount` stays fully withdrawable.
+    ///
+    /// # Errors
+    /// * `VaultAlreadyMigrated` - If the account already uses the zero-copy layout
+    /// * `Unauthorized` - If caller is not vault owner
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        let vault_info = ctx.accounts.vault.to_account_info();
+        let clock = Clock::get()?;
+
+        require!(
+            vault_info.data_len() == LEGACY_VAULT_SPACE,
+            EphemeralVaultError::VaultAlreadyMigrated
+        );
+
+        let legacy = {
+            let data = vault_info.try_borrow_data()?;
+            require!(
+                data[..8] == *EphemeralVault::DISCRIMINATOR,
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            LegacyEphemeralVault::deserialize(&mut &data[8..])?
         };
 
-        Ok(VaultStats {
-            total_deposited: vault.total_deposited,
-            total_withdrawn: vault.total_withdrawn,
-            available_amount: vault.available_amount,
-            used_amount: vault.used_amount,
-            trade_count: vault.trade_count,
-            session_status,
-            is_active: vault.is_active,
-            is_paused: vault.is_paused,
-        })
+        require!(
+            legacy.version == LEGACY_PROGRAM_VERSION,
+            EphemeralVaultError::VaultAlreadyMigrated
+        );
+        require_keys_eq!(
+            legacy.user_wallet,
+            ctx.accounts.user.key(),
+            EphemeralVaultError::Unauthorized
+        );
+
+        // Top up the rent difference so the resize does not eat into funds
+        let rent = Rent::get()?;
+        let top_up = rent
+            .minimum_balance(VAULT_SPACE)
+            .saturating_sub(rent.minimum_balance(LEGACY_VAULT_SPACE));
+        if top_up > 0 {
+            let cpi_context = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user.to_account_info(),
+                    to: vault_info.clone(),
+                },
+            );
+            transfer(cpi_context, top_up)?;
+        }
+
+        vault_info.resize(VAULT_SPACE)?;
+
+        let vault = legacy.into_zero_copy();
+        vault_info.try_borrow_mut_data()?[8..].copy_from_slice(bytemuck::bytes_of(&vault));
+
+        emit!(VaultMigrated {
+            user: ctx.accounts.user.key(),
+            vault_pda: vault_info.key(),
+            from_version: LEGACY_PROGRAM_VERSION,
+            to_version: PROGRAM_VERSION,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
     }
 }
 
@@ -653,11 +780,11 @@ pub struct CreateEphemeralVault<'info> {
     #[account(
         init,
         payer = user,
-        space = 8 + std::mem::size_of::<EphemeralVault>(),
+        space = VAULT_SPACE,
         seeds = [b"vault", user.key().as_ref()],
         bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     pub system_program: Program<'info, System>,
 }
 
@@ -665,10 +792,10 @@ pub struct CreateEphemeralVault<'info> {
 pub struct ApproveDelegate<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     #[account(mut)]
     pub user: Signer<'info>,
 }
@@ -677,10 +804,10 @@ pub struct ApproveDelegate<'info> {
 pub struct RenewSession<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     pub user: Signer<'info>,
 }
 
@@ -688,10 +815,10 @@ pub struct RenewSession<'info> {
 pub struct AutoDeposit<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     #[account(mut)]
     pub user: Signer<'info>,
     pub system_program: Program<'info, System>,
@@ -701,10 +828,10 @@ pub struct AutoDeposit<'info> {
 pub struct ExecuteTrade<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     pub delegate: Signer<'info>,
 }
 
@@ -712,10 +839,10 @@ pub struct ExecuteTrade<'info> {
 pub struct WithdrawBalance<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     #[account(mut)]
     pub user: Signer<'info>,
 }
@@ -724,10 +851,10 @@ pub struct WithdrawBalance<'info> {
 pub struct RevokeAccess<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     #[account(mut)]
     pub user: Signer<'info>,
 }
@@ -736,10 +863,10 @@ pub struct RevokeAccess<'info> {
 pub struct ReactivateVault<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     pub user: Signer<'info>,
 }
 
@@ -747,10 +874,10 @@ pub struct ReactivateVault<'info> {
 pub struct UpdateApprovedAmount<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     pub user: Signer<'info>,
 }
 
@@ -758,10 +885,10 @@ pub struct UpdateApprovedAmount<'info> {
 pub struct EmergencyPause<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     pub user: Signer<'info>,
 }
 
@@ -769,10 +896,10 @@ pub struct EmergencyPause<'info> {
 pub struct UnpauseVault<'info> {
     #[account(
         mut,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     pub user: Signer<'info>,
 }
 
@@ -781,46 +908,388 @@ pub struct CleanupVault<'info> {
     #[account(
         mut,
         close = user_wallet,
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
     /// CHECK: vault.user_wallet validated from vault data
-    #[account(mut, address = vault.user_wallet)]
+    #[account(mut, address = vault.load()?.user_wallet)]
     pub user_wallet: AccountInfo<'info>,
     #[account(mut)]
     pub cleaner: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    /// CHECK: still in the legacy Borsh layout; size, discriminator and owner
+    /// wallet are validated in the handler before it is rewritten
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"vault", user.key().as_ref()],
+        bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct GetVaultStats<'info> {
     #[account(
-        seeds = [b"vault", vault.user_wallet.as_ref()],
-        bump = vault.bump
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
+    )]
+    pub vault: AccountLoader<'info, EphemeralVault>,
+}
+
+#[derive(Accounts)]
+pub struct CheckDelegate<'info> {
+    #[account(
+        seeds = [b"vault", vault.load()?.user_wallet.as_ref()],
+        bump = vault.load()?.bump
     )]
-    pub vault: Account<'info, EphemeralVault>,
+    pub vault: AccountLoader<'info, EphemeralVault>,
+
+    pub delegate: Signer<'info>,
 }
 
-#[account]
+/// Vault state, stored as a fixed `repr(C)` layout and accessed in place.
+///
+/// `Option` values use sentinels instead: `Pubkey::default()` for no delegate
+/// and `0` for unset timestamps. Flags are stored as `u8` (0 or 1).
+#[account(zero_copy)]
 pub struct EphemeralVault {
     pub user_wallet: Pubkey,
     pub vault_pda: Pubkey,
+    pub delegate_wallet: Pubkey,
     pub created_at: i64,
     pub last_activity: i64,
+    pub delegated_at: i64,
+    pub session_expires_at: i64,
     pub approved_amount: u64,
     pub used_amount: u64,
     pub available_amount: u64,
-    pub delegate_wallet: Option<Pubkey>,
-    pub delegated_at: Option<i64>,
-    pub session_expires_at: Option<i64>,
     pub total_deposited: u64,
     pub total_withdrawn: u64,
     pub trade_count: u64,
-    pub is_active: bool,
-    pub is_paused: bool,
+    pub is_active: u8,
+    pub is_paused: u8,
     pub version: u8,
     pub bump: u8,
-} // Total: ~191 bytes + discriminator (8) = ~199 bytes
+    pub _padding: [u8; 4],
+    pub max_client_order_id: u64,
+    pub client_order_bitmap: u64,
+    pub _reserved: [u8; 48],
+} // Total: 248 bytes + discriminator (8) = 256 bytes
+
+const _: () = assert!(VAULT_SPACE == 256);
+
+impl EphemeralVault {
+    pub fn delegate(&self) -> Option<Pubkey> {
+        (self.delegate_wallet != Pubkey::default()).then_some(self.delegate_wallet)
+    }
+
+    pub fn delegated_since(&self) -> Option<i64> {
+        (self.delegated_at != NO_TIMESTAMP).then_some(self.delegated_at)
+    }
+
+    pub fn session_expiry(&self) -> Option<i64> {
+        (self.session_expires_at != NO_TIMESTAMP).then_some(self.session_expires_at)
+    }
+
+    pub fn active(&self) -> bool {
+        self.is_active != 0
+    }
+
+    pub fn paused(&self) -> bool {
+        self.is_paused != 0
+    }
+
+    fn set_active(&mut self, active: bool) {
+        self.is_active = active.into();
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.is_paused = paused.into();
+    }
+
+    fn set_delegate(&mut self, delegate: Pubkey, delegated_at: i64, expires_at: i64) {
+        self.delegate_wallet = delegate;
+        self.delegated_at = delegated_at;
+        self.session_expires_at = expires_at;
+    }
+
+    fn clear_delegate(&mut self) {
+        self.set_delegate(Pubkey::default(), NO_TIMESTAMP, NO_TIMESTAMP);
+    }
+
+    /// Checks that `delegate` holds a live session on an active, unpaused vault.
+    fn require_live_delegate(&self, delegate: Pubkey, now: i64) -> Result<()> {
+        require!(self.active(), EphemeralVaultError::VaultInactive);
+        require!(!self.paused(), EphemeralVaultError::VaultPaused);
+
+        // Verify delegate
+        require!(
+            self.delegate() == Some(delegate),
+            EphemeralVaultError::Unauthorized
+        );
+
+        // Check session expiry
+        let expires_at = self
+            .session_expiry()
+            .ok_or(EphemeralVaultError::DelegateNotProperlySet)?;
+
+        require!(now < expires_at, EphemeralVaultError::SessionExpired);
+
+        Ok(())
+    }
+
+    /// Approved budget the delegate has not yet spent on trades.
+    pub fn remaining_budget(&self) -> Result<u64> {
+        self.approved_amount
+            .checked_sub(self.used_amount)
+            .ok_or_else(|| EphemeralVaultError::MathOverflow.into())
+    }
+
+    pub fn session_status(&self, now: i64) -> SessionStatus {
+        match self.session_expiry() {
+            Some(expires_at) if now >= expires_at => SessionStatus::Expired,
+            Some(expires_at) if expires_at - now <= SESSION_RENEWAL_WINDOW => {
+                SessionStatus::ExpiringSoon
+            }
+            Some(_) => SessionStatus::Active,
+            None => SessionStatus::NoSession,
+        }
+    }
+
+    /// Seconds left in the session, `0` once expired and `None` without one.
+    pub fn seconds_until_expiry(&self, now: i64) -> Option<i64> {
+        self.session_expiry()
+            .map(|expires_at| expires_at.saturating_sub(now).max(0))
+    }
+
+    /// Whether `renew_session` would currently succeed.
+    pub fn renewal_allowed(&self, now: i64) -> bool {
+        self.active()
+            && !self.paused()
+            && self.delegate().is_some()
+            && self.session_status(now) == SessionStatus::ExpiringSoon
+    }
+
+    /// Whether `cleanup_vault` would currently succeed.
+    pub fn cleanup_allowed(&self, now: i64) -> Result<bool> {
+        if self.active() {
+            return Ok(false);
+        }
+
+        let check_timestamp = self
+            .session_expiry()
+            .or(self.delegated_since())
+            .unwrap_or(self.last_activity);
+
+        let elapsed = now
+            .checked_sub(check_timestamp)
+            .ok_or(EphemeralVaultError::MathOverflow)?;
+
+        Ok(elapsed > CLEANUP_GRACE_PERIOD)
+    }
+
+    /// Snapshot of the vault as seen at `now`.
+    ///
+    /// `surplus_lamports` is the account balance above rent exemption (see
+    /// [`rent_surplus`]) and drives `expected_cleanup_reward`.
+    pub fn stats(&self, now: i64, surplus_lamports: u64) -> Result<VaultStats> {
+        Ok(VaultStats {
+            total_deposited: self.total_deposited,
+            total_withdrawn: self.total_withdrawn,
+            available_amount: self.available_amount,
+            used_amount: self.used_amount,
+            trade_count: self.trade_count,
+            session_status: self.session_status(now),
+            is_active: self.active(),
+            is_paused: self.paused(),
+            remaining_budget: self.remaining_budget()?,
+            seconds_until_expiry: self.seconds_until_expiry(now),
+            can_renew: self.renewal_allowed(now),
+            can_cleanup: self.cleanup_allowed(now)?,
+            expected_cleanup_reward: cleanup_reward(surplus_lamports)?,
+        })
+    }
+
+    /// Read-only delegate authorization check.
+    ///
+    /// Programs depending on this crate with the `cpi` feature can call this
+    /// on a loaded vault instead of issuing a `check_delegate` CPI.
+    pub fn check_delegate(
+        &self,
+        delegate: Pubkey,
+        now: i64,
+        min_remaining_budget: u64,
+        surplus_lamports: u64,
+    ) -> Result<DelegateCheck> {
+        self.require_live_delegate(delegate, now)?;
+
+        let remaining_budget = self.remaining_budget()?;
+        require!(
+            remaining_budget >= min_remaining_budget,
+            EphemeralVaultError::InsufficientDelegateBudget
+        );
+
+        Ok(DelegateCheck {
+            delegate,
+            remaining_budget,
+            session_expires_at: self.session_expires_at,
+            stats: self.stats(now, surplus_lamports)?,
+        })
+    }
+
+    /// Read-only check that `entries` would pass `execute_trades_batch` for
+    /// `delegate` at `now`, without changing the vault.
+    ///
+    /// Off-chain clients can call this before sending, as with
+    /// [`Self::check_delegate`].
+    pub fn check_trades(&self, delegate: Pubkey, now: i64, entries: &[TradeEntry]) -> Result<()> {
+        require!(!entries.is_empty(), EphemeralVaultError::EmptyTradeBatch);
+        require!(
+            entries.len() <= MAX_BATCH_TRADES,
+            EphemeralVaultError::TradeBatchTooLarge
+        );
+        self.require_live_delegate(delegate, now)?;
+
+        let mut vault = *self;
+        for entry in entries {
+            vault.record_client_order_id(entry.client_id)?;
+            vault.apply_trade(entry.trade_fee, entry.trade_amount)?;
+        }
+
+        Ok(())
+    }
+
+    /// Records a client order id, rejecting replays.
+    ///
+    /// Bit `n` of `client_order_bitmap` marks `max_client_order_id - n` as
+    /// used, so ids may land out of order within `CLIENT_ORDER_WINDOW`.
+    fn record_client_order_id(&mut self, client_order_id: u64) -> Result<()> {
+        require!(
+            client_order_id > 0,
+            EphemeralVaultError::InvalidClientOrderId
+        );
+
+        if client_order_id > self.max_client_order_id {
+            let shift = client_order_id - self.max_client_order_id;
+            self.client_order_bitmap = if shift >= CLIENT_ORDER_WINDOW {
+                0
+            } else {
+                self.client_order_bitmap << shift
+            };
+            self.client_order_bitmap |= 1;
+            self.max_client_order_id = client_order_id;
+            return Ok(());
+        }
+
+        let offset = self.max_client_order_id - client_order_id;
+        require!(
+            offset < CLIENT_ORDER_WINDOW,
+            EphemeralVaultError::StaleClientOrderId
+        );
+
+        let bit = 1u64 << offset;
+        require!(
+            self.client_order_bitmap & bit == 0,
+            EphemeralVaultError::DuplicateClientOrderId
+        );
+        self.client_order_bitmap |= bit;
+
+        Ok(())
+    }
+
+    /// Charges one trade against the vault's fee balance and trade budget.
+    fn apply_trade(&mut self, trade_fee: u64, trade_amount: u64) -> Result<()> {
+        require!(
+            self.available_amount >= trade_fee,
+            EphemeralVaultError::InsufficientFunds
+        );
+
+        // Validate trade_amount
+        require!(
+            trade_amount > 0 && trade_amount <= self.approved_amount,
+            EphemeralVaultError::InvalidTradeAmount
+        );
+        let new_used_amount = self
+            .used_amount
+            .checked_add(trade_amount)
+            .ok_or(EphemeralVaultError::MathOverflow)?;
+        require!(
+            new_used_amount <= self.approved_amount,
+            EphemeralVaultError::TradeLimitExceeded
+        );
+
+        // Update vault state
+        self.available_amount = self
+            .available_amount
+            .checked_sub(trade_fee)
+            .ok_or(EphemeralVaultError::MathOverflow)?;
+        self.used_amount = new_used_amount;
+        self.trade_count = self
+            .trade_count
+            .checked_add(1)
+            .ok_or(EphemeralVaultError::MathOverflow)?;
+
+        Ok(())
+    }
+}
+
+/// Borsh layout used by `version == 1` vaults, kept only for `migrate_vault`.
+#[derive(AnchorDeserialize)]
+struct LegacyEphemeralVault {
+    user_wallet: Pubkey,
+    vault_pda: Pubkey,
+    created_at: i64,
+    last_activity: i64,
+    approved_amount: u64,
+    used_amount: u64,
+    available_amount: u64,
+    delegate_wallet: Option<Pubkey>,
+    delegated_at: Option<i64>,
+    session_expires_at: Option<i64>,
+    total_deposited: u64,
+    total_withdrawn: u64,
+    trade_count: u64,
+    is_active: bool,
+    is_paused: bool,
+    version: u8,
+    bump: u8,
+}
+
+impl LegacyEphemeralVault {
+    fn into_zero_copy(self) -> EphemeralVault {
+        EphemeralVault {
+            user_wallet: self.user_wallet,
+            vault_pda: self.vault_pda,
+            delegate_wallet: self.delegate_wallet.unwrap_or_default(),
+            created_at: self.created_at,
+            last_activity: self.last_activity,
+            delegated_at: self.delegated_at.unwrap_or(NO_TIMESTAMP),
+            session_expires_at: self.session_expires_at.unwrap_or(NO_TIMESTAMP),
+            approved_amount: self.approved_amount,
+            used_amount: self.used_amount,
+            available_amount: self.available_amount,
+            total_deposited: self.total_deposited,
+            total_withdrawn: self.total_withdrawn,
+            trade_count: self.trade_count,
+            is_active: self.is_active.into(),
+            is_paused: self.is_paused.into(),
+            version: PROGRAM_VERSION,
+            bump: self.bump,
+            _padding: [0; 4],
+            max_client_order_id: 0,
+            client_order_bitmap: 0,
+            _reserved: [0; 48],
+        }
+    }
+}
 
 #[event]
 pub struct VaultCreated {
@@ -862,6 +1331,7 @@ pub struct AutoDepositEvent {
 pub struct TradeExecuted {
     pub delegate: Pubkey,
     pub vault_pda: Pubkey,
+    pub client_order_id: u64,
     pub trade_fee: u64,
     pub trade_amount: u64,
     pub remaining_available: u64,
@@ -869,6 +1339,19 @@ pub struct TradeExecuted {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct TradesBatchExecuted {
+    pub delegate: Pubkey,
+    pub vault_pda: Pubkey,
+    pub client_ids: Vec<u64>,
+    pub total_fee: u64,
+    pub total_amount: u64,
+    pub remaining_available: u64,
+    pub first_trade_number: u64,
+    pub last_trade_number: u64,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct BalanceWithdrawn {
     pub user: Pubkey,
@@ -916,6 +1399,15 @@ pub struct VaultUnpaused {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct VaultMigrated {
+    pub user: Pubkey,
+    pub vault_pda: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct VaultCleaned {
     pub cleaner: Pubkey,
@@ -926,6 +1418,15 @@ pub struct VaultCleaned {
     pub timestamp: i64,
 }
 
+/// One fill settled by `execute_trades_batch`. `client_id` is the client
+/// order id and shares the replay window with `execute_trade`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TradeEntry {
+    pub trade_fee: u64,
+    pub trade_amount: u64,
+    pub client_id: u64,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
 pub enum SessionStatus {
     NoSession,
@@ -944,6 +1445,20 @@ pub struct VaultStats {
     pub session_status: SessionStatus,
     pub is_active: bool,
     pub is_paused: bool,
+    pub remaining_budget: u64,
+    pub seconds_until_expiry: Option<i64>,
+    pub can_renew: bool,
+    pub can_cleanup: bool,
+    pub expected_cleanup_reward: u64,
+}
+
+/// Result of a successful `check_delegate`.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct DelegateCheck {
+    pub delegate: Pubkey,
+    pub remaining_budget: u64,
+    pub session_expires_at: i64,
+    pub stats: VaultStats,
 }
 
 #[error_code]
@@ -999,7 +1514,7 @@ pub enum EphemeralVaultError {
     #[msg("Delegate wallet not properly set")]
     DelegateNotProperlySet,
 
-    #[msg("Cannot delegate to self")]
+    #[msg("Cannot delegate to self or to the default pubkey")]
     InvalidDelegate,
 
     #[msg("Session duration must be greater than zero")]
@@ -1010,4 +1525,25 @@ pub enum EphemeralVaultError {
 
     #[msg("Approved amount cannot be lower than current vault state")]
     ApprovedAmountTooLow,
+
+    #[msg("Vault already uses the current account layout")]
+    VaultAlreadyMigrated,
+
+    #[msg("Trade batch must contain at least one entry")]
+    EmptyTradeBatch,
+
+    #[msg("Trade batch exceeds the maximum number of entries")]
+    TradeBatchTooLarge,
+
+    #[msg("Client order id must be greater than zero")]
+    InvalidClientOrderId,
+
+    #[msg("Client order id was already used")]
+    DuplicateClientOrderId,
+
+    #[msg("Client order id is older than the replay window")]
+    StaleClientOrderId,
+
+    #[msg("Remaining approved budget is below the requested minimum")]
+    InsufficientDelegateBudget,
 }
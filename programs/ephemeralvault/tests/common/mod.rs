@@ -0,0 +1,586 @@
+//! Shared `solana-program-test` harness for the compiled `ephemeral_vault`.
+//!
+//! The program is loaded from `target/deploy/ephemeral_vault.so` (or
+//! `$SBF_OUT_DIR`), so build it first with `anchor build` or
+//! `cargo build-sbf`. Tests are skipped when the shared object is missing,
+//! unless `EPHEMERAL_VAULT_REQUIRE_SBF` is set.
+
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use anchor_lang::{
+    prelude::{borsh, Pubkey as AnchorPubkey},
+    AnchorDeserialize, AnchorSerialize, Discriminator, InstructionData, ToAccountMetas,
+};
+use ephemeral_vault::{EphemeralVault, TradeEntry, VaultStats};
+use solana_program_test::{BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    bpf_loader,
+    clock::Clock,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    system_program,
+    transaction::{Transaction, TransactionError},
+};
+
+pub const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+pub const SESSION_DURATION: i64 = 3600;
+pub const SESSION_RENEWAL_WINDOW: i64 = 300;
+pub const MIN_CLEANUP_REWARD: u64 = 100_000;
+
+/// Unix timestamp the cluster clock starts at.
+const START_TIMESTAMP: i64 = 1_700_000_000;
+
+pub type TxResult = Result<(), BanksClientError>;
+
+pub fn program_id() -> Pubkey {
+    Pubkey::new_from_array(ephemeral_vault::ID.to_bytes())
+}
+
+pub fn to_anchor(pubkey: Pubkey) -> AnchorPubkey {
+    AnchorPubkey::new_from_array(pubkey.to_bytes())
+}
+
+pub fn vault_pda(user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault", user.as_ref()], &program_id()).0
+}
+
+fn program_path() -> PathBuf {
+    std::env::var_os("SBF_OUT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../target/deploy"))
+        .join("ephemeral_vault.so")
+}
+
+/// Converts the program's own account metas so instruction layouts cannot drift.
+fn account_metas(accounts: impl ToAccountMetas) -> Vec<AccountMeta> {
+    accounts
+        .to_account_metas(None)
+        .into_iter()
+        .map(|meta| AccountMeta {
+            pubkey: Pubkey::new_from_array(meta.pubkey.to_bytes()),
+            is_signer: meta.is_signer,
+            is_writable: meta.is_writable,
+        })
+        .collect()
+}
+
+fn instruction(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: account_metas(accounts),
+        data: data.data(),
+    }
+}
+
+/// Asserts that `result` failed with the custom program error `expected`.
+pub fn assert_error(result: TxResult, expected: impl Into<u32>) {
+    match result {
+        Err(err) => assert_tx_error(err.unwrap(), expected),
+        Ok(()) => panic!("expected error {}, transaction succeeded", expected.into()),
+    }
+}
+
+pub fn assert_tx_error(err: TransactionError, expected: impl Into<u32>) {
+    let expected = expected.into();
+    match err {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            assert_eq!(code, expected, "unexpected custom error code")
+        }
+        other => panic!("expected error {expected}, got {other:?}"),
+    }
+}
+
+/// Whether the compiled program exists; logs a skip notice when it does not.
+pub fn program_available() -> bool {
+    let path = program_path();
+    if path.exists() {
+        return true;
+    }
+    assert!(
+        std::env::var_os("EPHEMERAL_VAULT_REQUIRE_SBF").is_none(),
+        "{} not found",
+        path.display()
+    );
+    eprintln!(
+        "skipping: {} not found, build the program with `anchor build`",
+        path.display()
+    );
+    false
+}
+
+/// A vault owner, their delegate and an unrelated wallet, all funded.
+pub struct Actors {
+    pub user: Keypair,
+    pub delegate: Keypair,
+    pub attacker: Keypair,
+    pub vault: Pubkey,
+}
+
+pub struct VaultTest {
+    pub ctx: ProgramTestContext,
+    pub now: i64,
+    slot: u64,
+}
+
+impl VaultTest {
+    /// Starts a bank with the program deployed, or `None` if it is not built.
+    pub async fn start() -> Option<Self> {
+        if !program_available() {
+            return None;
+        }
+
+        let data = std::fs::read(program_path()).expect("read program");
+        let mut program_test = ProgramTest::default();
+        program_test.add_account(
+            program_id(),
+            Account {
+                lamports: Rent::default().minimum_balance(data.len()).max(1),
+                data,
+                owner: bpf_loader::id(),
+                executable: true,
+                rent_epoch: 0,
+            },
+        );
+
+        let mut test = Self {
+            ctx: program_test.start_with_context().await,
+            now: START_TIMESTAMP,
+            slot: 1,
+        };
+        test.advance_slot();
+        Some(test)
+    }
+
+    /// Moves to a fresh slot so repeated transactions get a new blockhash,
+    /// keeping the clock pinned to `now`.
+    fn advance_slot(&mut self) {
+        self.slot += 1;
+        self.ctx.warp_to_slot(self.slot).expect("warp");
+        self.set_clock();
+    }
+
+    fn set_clock(&mut self) {
+        let clock = Clock {
+            slot: self.slot,
+            unix_timestamp: self.now,
+            ..Clock::default()
+        };
+        self.ctx.set_sysvar(&clock);
+    }
+
+    /// Moves the cluster clock forward by `seconds`.
+    pub fn warp_seconds(&mut self, seconds: i64) {
+        self.now += seconds;
+        self.set_clock();
+    }
+
+    /// Pins the cluster clock to `unix_timestamp`.
+    pub fn warp_to_timestamp(&mut self, unix_timestamp: i64) {
+        self.now = unix_timestamp;
+        self.set_clock();
+    }
+
+    pub fn rent(&self) -> Rent {
+        Rent::default()
+    }
+
+    pub fn fund(&mut self, pubkey: &Pubkey, lamports: u64) {
+        self.ctx.set_account(
+            pubkey,
+            &AccountSharedData::new(lamports, 0, &system_program::id()),
+        );
+    }
+
+    pub async fn balance(&mut self, pubkey: &Pubkey) -> u64 {
+        self.ctx
+            .banks_client
+            .get_balance(*pubkey)
+            .await
+            .expect("balance")
+    }
+
+    pub async fn account(&mut self, pubkey: &Pubkey) -> Option<Account> {
+        self.ctx
+            .banks_client
+            .get_account(*pubkey)
+            .await
+            .expect("account")
+    }
+
+    pub async fn vault(&mut self, vault: &Pubkey) -> EphemeralVault {
+        let account = self.account(vault).await.expect("vault exists");
+        assert_eq!(account.data.len(), ephemeral_vault::VAULT_SPACE);
+        assert_eq!(&account.data[..8], EphemeralVault::DISCRIMINATOR);
+        bytemuck::pod_read_unaligned(&account.data[8..])
+    }
+
+    /// Sends `ix` paid by the test payer and signed by `signers`.
+    pub async fn send(&mut self, ix: Instruction, signers: &[&Keypair]) -> TxResult {
+        self.advance_slot();
+        let tx = self.transaction(ix, signers);
+        self.ctx.banks_client.process_transaction(tx).await
+    }
+
+    /// Simulates `ix` and decodes its return data.
+    pub async fn view<T: AnchorDeserialize>(
+        &mut self,
+        ix: Instruction,
+        signers: &[&Keypair],
+    ) -> Result<T, TransactionError> {
+        let tx = self.transaction(ix, signers);
+        let simulation = self
+            .ctx
+            .banks_client
+            .simulate_transaction(tx)
+            .await
+            .expect("simulate");
+        simulation.result.expect("simulation result")?;
+
+        let return_data = simulation
+            .simulation_details
+            .and_then(|details| details.return_data)
+            .expect("return data");
+        assert_eq!(return_data.program_id, program_id());
+        Ok(T::try_from_slice(&return_data.data).expect("decode return data"))
+    }
+
+    fn transaction(&self, ix: Instruction, signers: &[&Keypair]) -> Transaction {
+        let mut all_signers = vec![&self.ctx.payer];
+        all_signers.extend_from_slice(signers);
+        Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&self.ctx.payer.pubkey()),
+            &all_signers,
+            self.ctx.last_blockhash,
+        )
+    }
+
+    /// Funds fresh actors and creates their vault with `approved_amount`.
+    pub async fn setup(&mut self, approved_amount: u64) -> Actors {
+        let actors = Actors {
+            user: Keypair::new(),
+            delegate: Keypair::new(),
+            attacker: Keypair::new(),
+            vault: Pubkey::default(),
+        };
+        let vault = vault_pda(&actors.user.pubkey());
+        for actor in [&actors.user, &actors.delegate, &actors.attacker] {
+            self.fund(&actor.pubkey(), 200 * LAMPORTS_PER_SOL);
+        }
+
+        self.create_vault(&actors.user, approved_amount)
+            .await
+            .expect("create vault");
+        Actors { vault, ..actors }
+    }
+
+    /// `setup` plus an approved delegate and `deposit` lamports of fee balance.
+    pub async fn setup_delegated(&mut self, approved_amount: u64, deposit: u64) -> Actors {
+        let actors = self.setup(approved_amount).await;
+        self.approve_delegate(&actors.user, actors.delegate.pubkey(), None)
+            .await
+            .expect("approve delegate");
+        if deposit > 0 {
+            self.deposit(&actors.user, deposit).await.expect("deposit");
+        }
+        actors
+    }
+
+    pub async fn create_vault(&mut self, user: &Keypair, approved_amount: u64) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::CreateEphemeralVault {
+                user: to_anchor(user.pubkey()),
+                vault: to_anchor(vault_pda(&user.pubkey())),
+                system_program: to_anchor(system_program::id()),
+            },
+            ephemeral_vault::instruction::CreateEphemeralVault { approved_amount },
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn approve_delegate(
+        &mut self,
+        user: &Keypair,
+        delegate: Pubkey,
+        custom_duration: Option<i64>,
+    ) -> TxResult {
+        self.approve_delegate_on(user, vault_pda(&user.pubkey()), delegate, custom_duration)
+            .await
+    }
+
+    pub async fn approve_delegate_on(
+        &mut self,
+        user: &Keypair,
+        vault: Pubkey,
+        delegate: Pubkey,
+        custom_duration: Option<i64>,
+    ) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::ApproveDelegate {
+                vault: to_anchor(vault),
+                user: to_anchor(user.pubkey()),
+            },
+            ephemeral_vault::instruction::ApproveDelegate {
+                delegate: to_anchor(delegate),
+                custom_duration,
+            },
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn renew_session(&mut self, user: &Keypair, vault: Pubkey) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::RenewSession {
+                vault: to_anchor(vault),
+                user: to_anchor(user.pubkey()),
+            },
+            ephemeral_vault::instruction::RenewSession {},
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn deposit(&mut self, user: &Keypair, amount: u64) -> TxResult {
+        self.deposit_to(user, vault_pda(&user.pubkey()), amount)
+            .await
+    }
+
+    pub async fn deposit_to(&mut self, user: &Keypair, vault: Pubkey, amount: u64) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::AutoDeposit {
+                vault: to_anchor(vault),
+                user: to_anchor(user.pubkey()),
+                system_program: to_anchor(system_program::id()),
+            },
+            ephemeral_vault::instruction::AutoDepositForTrade {
+                trade_fee_estimate: amount,
+            },
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn execute_trade(
+        &mut self,
+        delegate: &Keypair,
+        vault: Pubkey,
+        trade_fee: u64,
+        trade_amount: u64,
+        client_order_id: u64,
+    ) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::ExecuteTrade {
+                vault: to_anchor(vault),
+                delegate: to_anchor(delegate.pubkey()),
+            },
+            ephemeral_vault::instruction::ExecuteTrade {
+                trade_fee,
+                trade_amount,
+                client_order_id,
+            },
+        );
+        self.send(ix, &[delegate]).await
+    }
+
+    pub async fn execute_trades_batch(
+        &mut self,
+        delegate: &Keypair,
+        vault: Pubkey,
+        entries: Vec<TradeEntry>,
+    ) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::ExecuteTrade {
+                vault: to_anchor(vault),
+                delegate: to_anchor(delegate.pubkey()),
+            },
+            ephemeral_vault::instruction::ExecuteTradesBatch { entries },
+        );
+        self.send(ix, &[delegate]).await
+    }
+
+    pub async fn withdraw(&mut self, user: &Keypair, vault: Pubkey, amount: u64) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::WithdrawBalance {
+                vault: to_anchor(vault),
+                user: to_anchor(user.pubkey()),
+            },
+            ephemeral_vault::instruction::WithdrawBalance { amount },
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn revoke(&mut self, user: &Keypair, vault: Pubkey) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::RevokeAccess {
+                vault: to_anchor(vault),
+                user: to_anchor(user.pubkey()),
+            },
+            ephemeral_vault::instruction::RevokeAccess {},
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn reactivate(&mut self, user: &Keypair, vault: Pubkey) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::ReactivateVault {
+                vault: to_anchor(vault),
+                user: to_anchor(user.pubkey()),
+            },
+            ephemeral_vault::instruction::ReactivateVault {},
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn update_approved_amount(
+        &mut self,
+        user: &Keypair,
+        vault: Pubkey,
+        new_approved_amount: u64,
+    ) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::UpdateApprovedAmount {
+                vault: to_anchor(vault),
+                user: to_anchor(user.pubkey()),
+            },
+            ephemeral_vault::instruction::UpdateApprovedAmount {
+                new_approved_amount,
+            },
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn pause(&mut self, user: &Keypair, vault: Pubkey) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::EmergencyPause {
+                vault: to_anchor(vault),
+                user: to_anchor(user.pubkey()),
+            },
+            ephemeral_vault::instruction::EmergencyPause {},
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn unpause(&mut self, user: &Keypair, vault: Pubkey) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::UnpauseVault {
+                vault: to_anchor(vault),
+                user: to_anchor(user.pubkey()),
+            },
+            ephemeral_vault::instruction::UnpauseVault {},
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn cleanup(
+        &mut self,
+        cleaner: &Keypair,
+        vault: Pubkey,
+        user_wallet: Pubkey,
+    ) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::CleanupVault {
+                vault: to_anchor(vault),
+                user_wallet: to_anchor(user_wallet),
+                cleaner: to_anchor(cleaner.pubkey()),
+            },
+            ephemeral_vault::instruction::CleanupVault {},
+        );
+        self.send(ix, &[cleaner]).await
+    }
+
+    pub async fn migrate(&mut self, user: &Keypair) -> TxResult {
+        let ix = instruction(
+            ephemeral_vault::accounts::MigrateVault {
+                vault: to_anchor(vault_pda(&user.pubkey())),
+                user: to_anchor(user.pubkey()),
+                system_program: to_anchor(system_program::id()),
+            },
+            ephemeral_vault::instruction::MigrateVault {},
+        );
+        self.send(ix, &[user]).await
+    }
+
+    pub async fn stats(&mut self, vault: Pubkey) -> VaultStats {
+        let ix = instruction(
+            ephemeral_vault::accounts::GetVaultStats {
+                vault: to_anchor(vault),
+            },
+            ephemeral_vault::instruction::GetVaultStats {},
+        );
+        self.view(ix, &[]).await.expect("get_vault_stats")
+    }
+
+    pub async fn check_delegate(
+        &mut self,
+        delegate: &Keypair,
+        vault: Pubkey,
+        min_remaining_budget: u64,
+    ) -> Result<ephemeral_vault::DelegateCheck, TransactionError> {
+        let ix = instruction(
+            ephemeral_vault::accounts::CheckDelegate {
+                vault: to_anchor(vault),
+                delegate: to_anchor(delegate.pubkey()),
+            },
+            ephemeral_vault::instruction::CheckDelegate {
+                min_remaining_budget,
+            },
+        );
+        self.view(ix, &[delegate]).await
+    }
+
+    /// Overwrites the vault's zero-copy state, e.g. to reach states that no
+    /// instruction sequence produces.
+    pub async fn write_vault(&mut self, vault: &Pubkey, state: &EphemeralVault) {
+        let mut account = self.account(vault).await.expect("vault exists");
+        account.data[8..].copy_from_slice(bytemuck::bytes_of(state));
+        self.ctx.set_account(vault, &account.into());
+    }
+
+    pub async fn set_lamports(&mut self, pubkey: &Pubkey, lamports: u64) {
+        let mut account = self.account(pubkey).await.expect("account exists");
+        account.lamports = lamports;
+        self.ctx.set_account(pubkey, &account.into());
+    }
+
+    /// Stores a `version == 1` Borsh vault for `user` holding `available` lamports.
+    pub fn install_legacy_vault(&mut self, user: &Pubkey, legacy: &LegacyVault) -> Pubkey {
+        let vault = vault_pda(user);
+        let mut data = EphemeralVault::DISCRIMINATOR.to_vec();
+        legacy.serialize(&mut data).expect("serialize legacy vault");
+        data.resize(ephemeral_vault::LEGACY_VAULT_SPACE, 0);
+
+        let lamports = self
+            .rent()
+            .minimum_balance(ephemeral_vault::LEGACY_VAULT_SPACE)
+            + legacy.available_amount;
+        let mut account = AccountSharedData::new(lamports, data.len(), &program_id());
+        account.set_data_from_slice(&data);
+        self.ctx.set_account(&vault, &account);
+        vault
+    }
+}
+
+/// Borsh layout of `version == 1` vaults.
+#[derive(AnchorSerialize)]
+pub struct LegacyVault {
+    pub user_wallet: AnchorPubkey,
+    pub vault_pda: AnchorPubkey,
+    pub created_at: i64,
+    pub last_activity: i64,
+    pub approved_amount: u64,
+    pub used_amount: u64,
+    pub available_amount: u64,
+    pub delegate_wallet: Option<AnchorPubkey>,
+    pub delegated_at: Option<i64>,
+    pub session_expires_at: Option<i64>,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    pub trade_count: u64,
+    pub is_active: bool,
+    pub is_paused: bool,
+    pub version: u8,
+    pub bump: u8,
+}
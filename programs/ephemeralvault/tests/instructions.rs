@@ -0,0 +1,968 @@
+//! Instruction and error-path coverage against the compiled program.
+
+mod common;
+
+use anchor_lang::error::ErrorCode as AnchorErrorCode;
+use common::*;
+use ephemeral_vault::{
+    EphemeralVaultError, SessionStatus, TradeEntry, LEGACY_VAULT_SPACE, MAX_BATCH_TRADES,
+    VAULT_SPACE,
+};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+const SOL: u64 = LAMPORTS_PER_SOL;
+
+fn entry(trade_fee: u64, trade_amount: u64, client_id: u64) -> TradeEntry {
+    TradeEntry {
+        trade_fee,
+        trade_amount,
+        client_id,
+    }
+}
+
+#[tokio::test]
+async fn create_ephemeral_vault_initializes_state() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+
+    let vault = t.vault(&a.vault).await;
+    assert_eq!(vault.user_wallet, to_anchor(a.user.pubkey()));
+    assert_eq!(vault.vault_pda, to_anchor(a.vault));
+    assert_eq!(vault.approved_amount, 2 * SOL);
+    assert_eq!(vault.available_amount, 0);
+    assert_eq!(vault.created_at, t.now);
+    assert_eq!(vault.delegate(), None);
+    assert_eq!(vault.session_expiry(), None);
+    assert!(vault.active());
+    assert!(!vault.paused());
+    assert_eq!(vault.version, 2);
+    assert_eq!(
+        t.balance(&a.vault).await,
+        t.rent().minimum_balance(VAULT_SPACE)
+    );
+}
+
+#[tokio::test]
+async fn create_ephemeral_vault_rejects_out_of_range_amounts() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let user = Keypair::new();
+    t.fund(&user.pubkey(), 10 * SOL);
+
+    assert_error(
+        t.create_vault(&user, 999_999).await,
+        EphemeralVaultError::InvalidApprovedAmount,
+    );
+    assert_error(
+        t.create_vault(&user, 1_000_000_000_001).await,
+        EphemeralVaultError::InvalidApprovedAmount,
+    );
+    assert!(t.account(&vault_pda(&user.pubkey())).await.is_none());
+}
+
+#[tokio::test]
+async fn approve_delegate_sets_capped_session() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+    let delegate = to_anchor(a.delegate.pubkey());
+
+    t.approve_delegate(&a.user, a.delegate.pubkey(), None)
+        .await
+        .unwrap();
+    let vault = t.vault(&a.vault).await;
+    assert_eq!(vault.delegate(), Some(delegate));
+    assert_eq!(vault.delegated_since(), Some(t.now));
+    assert_eq!(vault.session_expiry(), Some(t.now + SESSION_DURATION));
+
+    t.approve_delegate(&a.user, a.delegate.pubkey(), Some(60))
+        .await
+        .unwrap();
+    assert_eq!(t.vault(&a.vault).await.session_expiry(), Some(t.now + 60));
+
+    t.approve_delegate(&a.user, a.delegate.pubkey(), Some(10 * SESSION_DURATION))
+        .await
+        .unwrap();
+    assert_eq!(
+        t.vault(&a.vault).await.session_expiry(),
+        Some(t.now + SESSION_DURATION)
+    );
+}
+
+#[tokio::test]
+async fn approve_delegate_rejects_invalid_requests() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+
+    assert_error(
+        t.approve_delegate_on(&a.attacker, a.vault, a.attacker.pubkey(), None)
+            .await,
+        EphemeralVaultError::Unauthorized,
+    );
+    assert_error(
+        t.approve_delegate(&a.user, a.user.pubkey(), None).await,
+        EphemeralVaultError::InvalidDelegate,
+    );
+    assert_error(
+        t.approve_delegate(&a.user, Pubkey::default(), None).await,
+        EphemeralVaultError::InvalidDelegate,
+    );
+    assert_error(
+        t.approve_delegate(&a.user, a.delegate.pubkey(), Some(0))
+            .await,
+        EphemeralVaultError::InvalidSessionDuration,
+    );
+    assert_error(
+        t.approve_delegate(&a.user, a.delegate.pubkey(), Some(-5))
+            .await,
+        EphemeralVaultError::InvalidSessionDuration,
+    );
+
+    t.pause(&a.user, a.vault).await.unwrap();
+    assert_error(
+        t.approve_delegate(&a.user, a.delegate.pubkey(), None).await,
+        EphemeralVaultError::VaultPaused,
+    );
+    t.unpause(&a.user, a.vault).await.unwrap();
+
+    t.revoke(&a.user, a.vault).await.unwrap();
+    assert_error(
+        t.approve_delegate(&a.user, a.delegate.pubkey(), None).await,
+        EphemeralVaultError::VaultInactive,
+    );
+}
+
+#[tokio::test]
+async fn approve_delegate_reports_expiry_overflow() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+
+    t.warp_to_timestamp(i64::MAX - 100);
+    assert_error(
+        t.approve_delegate(&a.user, a.delegate.pubkey(), None).await,
+        EphemeralVaultError::MathOverflow,
+    );
+}
+
+#[tokio::test]
+async fn renew_session_only_inside_renewal_window() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+
+    assert_error(
+        t.renew_session(&a.user, a.vault).await,
+        EphemeralVaultError::NoActiveSession,
+    );
+
+    t.approve_delegate(&a.user, a.delegate.pubkey(), None)
+        .await
+        .unwrap();
+    assert_error(
+        t.renew_session(&a.user, a.vault).await,
+        EphemeralVaultError::SessionNotExpiringSoon,
+    );
+    assert_error(
+        t.renew_session(&a.attacker, a.vault).await,
+        EphemeralVaultError::Unauthorized,
+    );
+
+    t.warp_seconds(SESSION_DURATION - SESSION_RENEWAL_WINDOW);
+    t.renew_session(&a.user, a.vault).await.unwrap();
+    assert_eq!(
+        t.vault(&a.vault).await.session_expiry(),
+        Some(t.now + SESSION_DURATION)
+    );
+
+    t.warp_seconds(SESSION_DURATION);
+    assert_error(
+        t.renew_session(&a.user, a.vault).await,
+        EphemeralVaultError::SessionExpired,
+    );
+}
+
+#[tokio::test]
+async fn auto_deposit_moves_lamports_into_vault() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+    let user_before = t.balance(&a.user.pubkey()).await;
+    let vault_before = t.balance(&a.vault).await;
+
+    t.deposit(&a.user, SOL / 2).await.unwrap();
+
+    assert_eq!(t.balance(&a.user.pubkey()).await, user_before - SOL / 2);
+    assert_eq!(t.balance(&a.vault).await, vault_before + SOL / 2);
+    let vault = t.vault(&a.vault).await;
+    assert_eq!(vault.available_amount, SOL / 2);
+    assert_eq!(vault.total_deposited, SOL / 2);
+}
+
+#[tokio::test]
+async fn auto_deposit_enforces_limits() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+
+    assert_error(
+        t.deposit(&a.user, 999_999).await,
+        EphemeralVaultError::DepositTooSmall,
+    );
+    assert_error(
+        t.deposit_to(&a.attacker, a.vault, SOL).await,
+        EphemeralVaultError::Unauthorized,
+    );
+
+    t.deposit(&a.user, 3 * SOL / 2).await.unwrap();
+    assert_error(
+        t.deposit(&a.user, SOL).await,
+        EphemeralVaultError::OverDeposit,
+    );
+
+    let big = t.setup(1_000 * SOL).await;
+    assert_error(
+        t.deposit(&big.user, 100 * SOL + 1).await,
+        EphemeralVaultError::DepositTooLarge,
+    );
+
+    t.pause(&a.user, a.vault).await.unwrap();
+    assert_error(
+        t.deposit(&a.user, SOL / 10).await,
+        EphemeralVaultError::VaultPaused,
+    );
+    t.unpause(&a.user, a.vault).await.unwrap();
+    t.revoke(&a.user, a.vault).await.unwrap();
+    assert_error(
+        t.deposit(&a.user, SOL / 10).await,
+        EphemeralVaultError::VaultInactive,
+    );
+}
+
+#[tokio::test]
+async fn execute_trade_updates_accounting() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL / 2).await;
+    let vault_lamports = t.balance(&a.vault).await;
+
+    t.execute_trade(&a.delegate, a.vault, 100_000, 1_000_000, 1)
+        .await
+        .unwrap();
+
+    let vault = t.vault(&a.vault).await;
+    assert_eq!(vault.available_amount, SOL / 2 - 100_000);
+    assert_eq!(vault.used_amount, 1_000_000);
+    assert_eq!(vault.trade_count, 1);
+    assert_eq!(vault.max_client_order_id, 1);
+    assert_eq!(vault.last_activity, t.now);
+    // Fees are accounted for, not transferred out of the vault.
+    assert_eq!(t.balance(&a.vault).await, vault_lamports);
+}
+
+#[tokio::test]
+async fn execute_trade_rejects_invalid_trades() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL / 2).await;
+
+    assert_error(
+        t.execute_trade(&a.attacker, a.vault, 1_000, 1_000, 1).await,
+        EphemeralVaultError::Unauthorized,
+    );
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, SOL, 1_000, 1).await,
+        EphemeralVaultError::InsufficientFunds,
+    );
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, 0, 1).await,
+        EphemeralVaultError::InvalidTradeAmount,
+    );
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, 2 * SOL + 1, 1)
+            .await,
+        EphemeralVaultError::InvalidTradeAmount,
+    );
+
+    t.execute_trade(&a.delegate, a.vault, 1_000, 3 * SOL / 2, 1)
+        .await
+        .unwrap();
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, SOL, 2).await,
+        EphemeralVaultError::TradeLimitExceeded,
+    );
+
+    t.pause(&a.user, a.vault).await.unwrap();
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 3).await,
+        EphemeralVaultError::VaultPaused,
+    );
+    t.unpause(&a.user, a.vault).await.unwrap();
+    t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 3)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn execute_trade_enforces_client_order_ids() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL / 2).await;
+
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 0).await,
+        EphemeralVaultError::InvalidClientOrderId,
+    );
+
+    t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 100)
+        .await
+        .unwrap();
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 100)
+            .await,
+        EphemeralVaultError::DuplicateClientOrderId,
+    );
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 36)
+            .await,
+        EphemeralVaultError::StaleClientOrderId,
+    );
+
+    // Out-of-order ids inside the window still land once.
+    t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 37)
+        .await
+        .unwrap();
+    assert_eq!(t.vault(&a.vault).await.trade_count, 2);
+}
+
+#[tokio::test]
+async fn execute_trade_requires_live_session() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL / 2).await;
+
+    t.warp_seconds(SESSION_DURATION);
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 1).await,
+        EphemeralVaultError::SessionExpired,
+    );
+
+    // A delegate without a session timestamp is never reachable through
+    // instructions, but must still be rejected.
+    let mut vault = t.vault(&a.vault).await;
+    vault.session_expires_at = 0;
+    t.write_vault(&a.vault, &vault).await;
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 1).await,
+        EphemeralVaultError::DelegateNotProperlySet,
+    );
+}
+
+#[tokio::test]
+async fn execute_trades_batch_applies_all_entries() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL / 2).await;
+
+    t.execute_trades_batch(
+        &a.delegate,
+        a.vault,
+        vec![
+            entry(1_000, 10_000, 1),
+            entry(2_000, 20_000, 3),
+            entry(3_000, 30_000, 2),
+        ],
+    )
+    .await
+    .unwrap();
+
+    let vault = t.vault(&a.vault).await;
+    assert_eq!(vault.trade_count, 3);
+    assert_eq!(vault.used_amount, 60_000);
+    assert_eq!(vault.available_amount, SOL / 2 - 6_000);
+    assert_eq!(vault.max_client_order_id, 3);
+}
+
+#[tokio::test]
+async fn execute_trades_batch_is_all_or_nothing() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL / 2).await;
+
+    assert_error(
+        t.execute_trades_batch(&a.delegate, a.vault, vec![]).await,
+        EphemeralVaultError::EmptyTradeBatch,
+    );
+    let too_many = (1..=MAX_BATCH_TRADES as u64 + 1)
+        .map(|id| entry(1_000, 1_000, id))
+        .collect();
+    assert_error(
+        t.execute_trades_batch(&a.delegate, a.vault, too_many).await,
+        EphemeralVaultError::TradeBatchTooLarge,
+    );
+    assert_error(
+        t.execute_trades_batch(
+            &a.delegate,
+            a.vault,
+            vec![entry(1_000, 1_000, 1), entry(1_000, 1_000, 1)],
+        )
+        .await,
+        EphemeralVaultError::DuplicateClientOrderId,
+    );
+
+    let vault = t.vault(&a.vault).await;
+    assert_eq!(vault.trade_count, 0);
+    assert_eq!(vault.available_amount, SOL / 2);
+    assert_eq!(vault.max_client_order_id, 0);
+}
+
+#[tokio::test]
+async fn withdraw_balance_returns_available_funds() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL).await;
+    let rent = t.rent().minimum_balance(VAULT_SPACE);
+    let user_before = t.balance(&a.user.pubkey()).await;
+
+    assert_error(
+        t.withdraw(&a.attacker, a.vault, SOL / 10).await,
+        EphemeralVaultError::Unauthorized,
+    );
+    assert_error(
+        t.withdraw(&a.user, a.vault, SOL + 1).await,
+        EphemeralVaultError::InsufficientFunds,
+    );
+
+    t.withdraw(&a.user, a.vault, 4 * SOL / 10).await.unwrap();
+    assert_eq!(
+        t.balance(&a.user.pubkey()).await,
+        user_before + 4 * SOL / 10
+    );
+    let vault = t.vault(&a.vault).await;
+    assert_eq!(vault.available_amount, 6 * SOL / 10);
+    assert_eq!(vault.total_withdrawn, 4 * SOL / 10);
+
+    // Zero withdraws everything that is available.
+    t.withdraw(&a.user, a.vault, 0).await.unwrap();
+    assert_eq!(t.balance(&a.user.pubkey()).await, user_before + SOL);
+    assert_eq!(t.balance(&a.vault).await, rent);
+    assert_eq!(t.vault(&a.vault).await.available_amount, 0);
+}
+
+#[tokio::test]
+async fn withdraw_balance_never_touches_rent() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL).await;
+    let rent = t.rent().minimum_balance(VAULT_SPACE);
+
+    // Lamports fell behind the recorded balance: only the surplus is paid out.
+    t.set_lamports(&a.vault, rent + SOL / 4).await;
+    assert_error(
+        t.withdraw(&a.user, a.vault, SOL / 2).await,
+        EphemeralVaultError::InsufficientFunds,
+    );
+    let user_before = t.balance(&a.user.pubkey()).await;
+    t.withdraw(&a.user, a.vault, 0).await.unwrap();
+    assert_eq!(t.balance(&a.user.pubkey()).await, user_before + SOL / 4);
+    assert_eq!(t.balance(&a.vault).await, rent);
+    assert_eq!(t.vault(&a.vault).await.available_amount, 3 * SOL / 4);
+
+    // Below the rent-exempt minimum nothing can be withdrawn.
+    t.set_lamports(&a.vault, rent - 1).await;
+    assert_error(
+        t.withdraw(&a.user, a.vault, 0).await,
+        EphemeralVaultError::InsufficientFunds,
+    );
+}
+
+#[tokio::test]
+async fn revoke_access_returns_surplus_and_deactivates() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL).await;
+    let rent = t.rent().minimum_balance(VAULT_SPACE);
+    let user_before = t.balance(&a.user.pubkey()).await;
+
+    assert_error(
+        t.revoke(&a.attacker, a.vault).await,
+        EphemeralVaultError::Unauthorized,
+    );
+
+    t.revoke(&a.user, a.vault).await.unwrap();
+    assert_eq!(t.balance(&a.user.pubkey()).await, user_before + SOL);
+    assert_eq!(t.balance(&a.vault).await, rent);
+
+    let vault = t.vault(&a.vault).await;
+    assert!(!vault.active());
+    assert_eq!(vault.delegate(), None);
+    assert_eq!(vault.session_expiry(), None);
+    assert_eq!(vault.available_amount, 0);
+    assert_eq!(vault.total_withdrawn, SOL);
+
+    assert_error(
+        t.execute_trade(&a.delegate, a.vault, 1_000, 1_000, 1).await,
+        EphemeralVaultError::VaultInactive,
+    );
+}
+
+#[tokio::test]
+async fn revoke_access_releases_retained_fees_without_counting_them() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL).await;
+    t.execute_trade(&a.delegate, a.vault, SOL / 10, 1_000, 1)
+        .await
+        .unwrap();
+    let user_before = t.balance(&a.user.pubkey()).await;
+
+    t.revoke(&a.user, a.vault).await.unwrap();
+
+    // The full surplus goes back, but only the available part is a withdrawal.
+    assert_eq!(t.balance(&a.user.pubkey()).await, user_before + SOL);
+    let vault = t.vault(&a.vault).await;
+    assert_eq!(vault.available_amount, 0);
+    assert_eq!(vault.total_withdrawn, SOL - SOL / 10);
+    assert_eq!(
+        vault.total_deposited - vault.total_withdrawn - SOL / 10,
+        vault.available_amount
+    );
+}
+
+#[tokio::test]
+async fn reactivate_vault_requires_inactive_vault() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, 0).await;
+
+    assert_error(
+        t.reactivate(&a.user, a.vault).await,
+        EphemeralVaultError::VaultAlreadyActive,
+    );
+
+    t.revoke(&a.user, a.vault).await.unwrap();
+    assert_error(
+        t.reactivate(&a.attacker, a.vault).await,
+        EphemeralVaultError::Unauthorized,
+    );
+    t.reactivate(&a.user, a.vault).await.unwrap();
+
+    let vault = t.vault(&a.vault).await;
+    assert!(vault.active());
+    assert!(!vault.paused());
+    assert_eq!(vault.delegate(), None);
+}
+
+#[tokio::test]
+async fn update_approved_amount_respects_vault_state() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+    t.deposit(&a.user, SOL).await.unwrap();
+
+    assert_error(
+        t.update_approved_amount(&a.attacker, a.vault, 3 * SOL)
+            .await,
+        EphemeralVaultError::Unauthorized,
+    );
+    assert_error(
+        t.update_approved_amount(&a.user, a.vault, 0).await,
+        EphemeralVaultError::InvalidApprovedAmount,
+    );
+    assert_error(
+        t.update_approved_amount(&a.user, a.vault, SOL / 2).await,
+        EphemeralVaultError::ApprovedAmountTooLow,
+    );
+
+    t.update_approved_amount(&a.user, a.vault, 5 * SOL)
+        .await
+        .unwrap();
+    assert_eq!(t.vault(&a.vault).await.approved_amount, 5 * SOL);
+}
+
+#[tokio::test]
+async fn emergency_pause_and_unpause_are_owner_only() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+
+    assert_error(
+        t.pause(&a.attacker, a.vault).await,
+        EphemeralVaultError::Unauthorized,
+    );
+    t.pause(&a.user, a.vault).await.unwrap();
+    assert!(t.vault(&a.vault).await.paused());
+
+    assert_error(
+        t.unpause(&a.attacker, a.vault).await,
+        EphemeralVaultError::Unauthorized,
+    );
+    t.unpause(&a.user, a.vault).await.unwrap();
+    assert!(!t.vault(&a.vault).await.paused());
+}
+
+#[tokio::test]
+async fn cleanup_vault_requires_inactive_and_grace_period() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL).await;
+    let cleaner = Keypair::new();
+    t.fund(&cleaner.pubkey(), SOL);
+
+    assert_error(
+        t.cleanup(&cleaner, a.vault, a.user.pubkey()).await,
+        EphemeralVaultError::VaultStillActive,
+    );
+
+    t.revoke(&a.user, a.vault).await.unwrap();
+    assert_error(
+        t.cleanup(&cleaner, a.vault, a.user.pubkey()).await,
+        EphemeralVaultError::SessionNotExpired,
+    );
+
+    t.warp_seconds(2);
+    assert_error(
+        t.cleanup(&cleaner, a.vault, a.attacker.pubkey()).await,
+        AnchorErrorCode::ConstraintAddress,
+    );
+}
+
+#[tokio::test]
+async fn cleanup_vault_returns_rent_to_owner() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL).await;
+    let cleaner = Keypair::new();
+    t.fund(&cleaner.pubkey(), SOL);
+    let rent = t.rent().minimum_balance(VAULT_SPACE);
+
+    t.revoke(&a.user, a.vault).await.unwrap();
+    t.warp_seconds(2);
+    let user_before = t.balance(&a.user.pubkey()).await;
+
+    t.cleanup(&cleaner, a.vault, a.user.pubkey()).await.unwrap();
+
+    assert!(t.account(&a.vault).await.is_none());
+    assert_eq!(t.balance(&a.user.pubkey()).await, user_before + rent);
+    assert_eq!(t.balance(&cleaner.pubkey()).await, SOL);
+}
+
+#[tokio::test]
+async fn cleanup_vault_pays_reward_from_surplus() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL).await;
+    let cleaner = Keypair::new();
+    t.fund(&cleaner.pubkey(), SOL);
+    let rent = t.rent().minimum_balance(VAULT_SPACE);
+
+    t.revoke(&a.user, a.vault).await.unwrap();
+    // Lamports sent to the vault after revocation are split on cleanup.
+    t.set_lamports(&a.vault, rent + 5 * SOL).await;
+    t.warp_seconds(2);
+    let user_before = t.balance(&a.user.pubkey()).await;
+
+    let stats = t.stats(a.vault).await;
+    assert!(stats.can_cleanup);
+    let reward = ephemeral_vault::cleanup_reward(5 * SOL).unwrap();
+    assert_eq!(stats.expected_cleanup_reward, reward);
+    assert_eq!(reward, 5 * SOL / 100);
+
+    t.cleanup(&cleaner, a.vault, a.user.pubkey()).await.unwrap();
+
+    assert!(t.account(&a.vault).await.is_none());
+    assert_eq!(t.balance(&cleaner.pubkey()).await, SOL + reward);
+    assert_eq!(
+        t.balance(&a.user.pubkey()).await,
+        user_before + rent + 5 * SOL - reward
+    );
+}
+
+#[tokio::test]
+async fn cleanup_reward_is_floored_and_capped() {
+    assert_eq!(ephemeral_vault::cleanup_reward(0).unwrap(), 0);
+    // 1% of 0.005 SOL is below the minimum reward...
+    assert_eq!(
+        ephemeral_vault::cleanup_reward(SOL / 200).unwrap(),
+        MIN_CLEANUP_REWARD
+    );
+    // ...which is itself capped at 10% of tiny balances.
+    assert_eq!(ephemeral_vault::cleanup_reward(500_000).unwrap(), 50_000);
+}
+
+fn legacy_vault(user: &Keypair, delegate: &Keypair, now: i64) -> LegacyVault {
+    let (vault, bump) = solana_sdk::pubkey::Pubkey::find_program_address(
+        &[b"vault", user.pubkey().as_ref()],
+        &program_id(),
+    );
+    LegacyVault {
+        user_wallet: to_anchor(user.pubkey()),
+        vault_pda: to_anchor(vault),
+        created_at: now - 100,
+        last_activity: now - 10,
+        approved_amount: 2 * SOL,
+        used_amount: 250_000,
+        available_amount: SOL,
+        delegate_wallet: Some(to_anchor(delegate.pubkey())),
+        delegated_at: Some(now - 50),
+        session_expires_at: Some(now + SESSION_DURATION),
+        total_deposited: SOL + 100_000,
+        total_withdrawn: 0,
+        trade_count: 3,
+        is_active: true,
+        is_paused: false,
+        version: 1,
+        bump,
+    }
+}
+
+#[tokio::test]
+async fn migrate_vault_upgrades_legacy_layout_in_place() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let user = Keypair::new();
+    let delegate = Keypair::new();
+    t.fund(&user.pubkey(), 10 * SOL);
+    let legacy = legacy_vault(&user, &delegate, t.now);
+    let vault_key = t.install_legacy_vault(&user.pubkey(), &legacy);
+    let user_before = t.balance(&user.pubkey()).await;
+
+    t.migrate(&user).await.unwrap();
+
+    let rent = t.rent();
+    let top_up = rent.minimum_balance(VAULT_SPACE) - rent.minimum_balance(LEGACY_VAULT_SPACE);
+    assert_eq!(t.balance(&user.pubkey()).await, user_before - top_up);
+    assert_eq!(
+        t.balance(&vault_key).await,
+        rent.minimum_balance(VAULT_SPACE) + SOL
+    );
+
+    let vault = t.vault(&vault_key).await;
+    assert_eq!(vault.version, 2);
+    assert_eq!(vault.user_wallet, legacy.user_wallet);
+    assert_eq!(vault.available_amount, SOL);
+    assert_eq!(vault.used_amount, 250_000);
+    assert_eq!(vault.trade_count, 3);
+    assert_eq!(vault.delegate(), legacy.delegate_wallet);
+    assert_eq!(vault.session_expiry(), legacy.session_expires_at);
+    assert_eq!(vault.max_client_order_id, 0);
+
+    assert_error(
+        t.migrate(&user).await,
+        EphemeralVaultError::VaultAlreadyMigrated,
+    );
+
+    // The migrated vault is fully usable.
+    t.fund(&delegate.pubkey(), SOL);
+    t.execute_trade(&delegate, vault_key, 1_000, 1_000, 1)
+        .await
+        .unwrap();
+    t.withdraw(&user, vault_key, 0).await.unwrap();
+    assert_eq!(
+        t.balance(&vault_key).await,
+        rent.minimum_balance(VAULT_SPACE) + 1_000
+    );
+}
+
+#[tokio::test]
+async fn migrate_vault_rejects_foreign_owner() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let user = Keypair::new();
+    let attacker = Keypair::new();
+    t.fund(&attacker.pubkey(), 10 * SOL);
+
+    // A legacy vault at the attacker's PDA that records someone else as owner.
+    let legacy = legacy_vault(&user, &Keypair::new(), t.now);
+    t.install_legacy_vault(&attacker.pubkey(), &legacy);
+
+    assert_error(
+        t.migrate(&attacker).await,
+        EphemeralVaultError::Unauthorized,
+    );
+}
+
+#[tokio::test]
+async fn get_vault_stats_derives_session_metrics() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup(2 * SOL).await;
+
+    let stats = t.stats(a.vault).await;
+    assert!(matches!(stats.session_status, SessionStatus::NoSession));
+    assert_eq!(stats.seconds_until_expiry, None);
+    assert_eq!(stats.remaining_budget, 2 * SOL);
+    assert!(!stats.can_renew);
+    assert!(!stats.can_cleanup);
+
+    t.approve_delegate(&a.user, a.delegate.pubkey(), None)
+        .await
+        .unwrap();
+    t.deposit(&a.user, SOL / 2).await.unwrap();
+    t.execute_trade(&a.delegate, a.vault, 1_000, SOL / 4, 1)
+        .await
+        .unwrap();
+
+    let stats = t.stats(a.vault).await;
+    assert!(matches!(stats.session_status, SessionStatus::Active));
+    assert_eq!(stats.seconds_until_expiry, Some(SESSION_DURATION));
+    assert_eq!(stats.remaining_budget, 2 * SOL - SOL / 4);
+    assert_eq!(stats.available_amount, SOL / 2 - 1_000);
+    assert_eq!(
+        stats.expected_cleanup_reward,
+        ephemeral_vault::cleanup_reward(SOL / 2).unwrap()
+    );
+
+    t.warp_seconds(SESSION_DURATION - 100);
+    let stats = t.stats(a.vault).await;
+    assert!(matches!(stats.session_status, SessionStatus::ExpiringSoon));
+    assert_eq!(stats.seconds_until_expiry, Some(100));
+    assert!(stats.can_renew);
+
+    t.warp_seconds(100);
+    let stats = t.stats(a.vault).await;
+    assert!(matches!(stats.session_status, SessionStatus::Expired));
+    assert_eq!(stats.seconds_until_expiry, Some(0));
+    assert!(!stats.can_renew);
+}
+
+#[tokio::test]
+async fn check_delegate_reports_budget_for_live_delegate() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL / 2).await;
+    t.execute_trade(&a.delegate, a.vault, 1_000, SOL / 2, 1)
+        .await
+        .unwrap();
+
+    let check = t.check_delegate(&a.delegate, a.vault, SOL).await.unwrap();
+    assert_eq!(check.delegate, to_anchor(a.delegate.pubkey()));
+    assert_eq!(check.remaining_budget, 3 * SOL / 2);
+    assert_eq!(check.session_expires_at, t.now + SESSION_DURATION);
+    assert_eq!(check.stats.trade_count, 1);
+
+    assert_tx_error(
+        t.check_delegate(&a.delegate, a.vault, 2 * SOL)
+            .await
+            .err()
+            .unwrap(),
+        EphemeralVaultError::InsufficientDelegateBudget,
+    );
+    assert_tx_error(
+        t.check_delegate(&a.attacker, a.vault, 0)
+            .await
+            .err()
+            .unwrap(),
+        EphemeralVaultError::Unauthorized,
+    );
+
+    t.warp_seconds(SESSION_DURATION);
+    assert_tx_error(
+        t.check_delegate(&a.delegate, a.vault, 0)
+            .await
+            .err()
+            .unwrap(),
+        EphemeralVaultError::SessionExpired,
+    );
+}
+
+fn error_number(result: anchor_lang::Result<()>) -> u32 {
+    match result.expect_err("check should fail") {
+        anchor_lang::error::Error::AnchorError(err) => err.error_code_number,
+        err => panic!("unexpected error {err:?}"),
+    }
+}
+
+#[tokio::test]
+async fn check_trades_mirrors_execute_trades_batch() {
+    let Some(mut t) = VaultTest::start().await else {
+        return;
+    };
+    let a = t.setup_delegated(2 * SOL, SOL / 2).await;
+    t.execute_trade(&a.delegate, a.vault, 1_000, SOL, 1)
+        .await
+        .unwrap();
+    let vault = t.vault(&a.vault).await;
+    let delegate = to_anchor(a.delegate.pubkey());
+
+    let entries = [entry(1_000, SOL / 2, 2), entry(1_000, SOL / 2, 3)];
+    vault.check_trades(delegate, t.now, &entries).unwrap();
+    t.execute_trades_batch(&a.delegate, a.vault, entries.to_vec())
+        .await
+        .unwrap();
+
+    let vault = t.vault(&a.vault).await;
+    let cases = [
+        (
+            vec![entry(1_000, 1, 3)],
+            EphemeralVaultError::DuplicateClientOrderId,
+        ),
+        (
+            vec![entry(1_000, 1, 4)],
+            EphemeralVaultError::TradeLimitExceeded,
+        ),
+        (
+            vec![entry(SOL, 0, 4)],
+            EphemeralVaultError::InsufficientFunds,
+        ),
+        (Vec::new(), EphemeralVaultError::EmptyTradeBatch),
+        (
+            (4..=MAX_BATCH_TRADES as u64 + 4)
+                .map(|id| entry(0, 0, id))
+                .collect(),
+            EphemeralVaultError::TradeBatchTooLarge,
+        ),
+    ];
+    for (entries, expected) in cases {
+        assert_eq!(
+            error_number(vault.check_trades(delegate, t.now, &entries)),
+            u32::from(expected)
+        );
+        assert_error(
+            t.execute_trades_batch(&a.delegate, a.vault, entries).await,
+            expected,
+        );
+    }
+    let next = [entry(1_000, 0, 4)];
+    assert_eq!(
+        error_number(vault.check_trades(to_anchor(a.attacker.pubkey()), t.now, &next)),
+        u32::from(EphemeralVaultError::Unauthorized)
+    );
+    assert_eq!(
+        error_number(vault.check_trades(delegate, t.now + SESSION_DURATION, &next)),
+        u32::from(EphemeralVaultError::SessionExpired)
+    );
+}
@@ -0,0 +1,294 @@
+//! Property tests: random instruction sequences must keep vault accounting
+//! consistent with real lamports.
+//!
+//! After every step the harness checks that
+//! - no lamports are created or destroyed between the owner, delegate,
+//!   cleaner and vault (transaction fees are paid by a separate payer),
+//! - `total_deposited - total_withdrawn - fees == available_amount`,
+//! - the vault always holds its rent-exempt minimum plus `available_amount`
+//!   plus trade fees retained since the last revocation,
+//! - failed instructions leave the vault untouched.
+//!
+//! Set `PROPTEST_CASES` to change the number of generated sequences.
+
+mod common;
+
+use common::*;
+use ephemeral_vault::{TradeEntry, VAULT_SPACE};
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestRunner};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+const SOL: u64 = LAMPORTS_PER_SOL;
+const MAX_STEPS: usize = 40;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Create {
+        approved: u64,
+    },
+    Approve,
+    Deposit {
+        amount: u64,
+    },
+    Trade {
+        fee: u64,
+        amount: u64,
+        client_id: u64,
+    },
+    Batch {
+        trades: Vec<(u64, u64, u64)>,
+    },
+    Withdraw {
+        amount: u64,
+    },
+    Revoke,
+    Reactivate,
+    Update {
+        approved: u64,
+    },
+    Pause,
+    Unpause,
+    Warp {
+        seconds: i64,
+    },
+    Cleanup,
+}
+
+/// Lamport amounts biased towards the boundaries the program checks.
+fn lamports() -> impl Strategy<Value = u64> {
+    prop_oneof![
+        Just(0),
+        1..=SOL / 100,
+        SOL / 100..=3 * SOL,
+        Just(MIN_CLEANUP_REWARD),
+    ]
+}
+
+fn fee() -> impl Strategy<Value = u64> {
+    prop_oneof![Just(0), 1..=SOL / 10]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        1 => (SOL / 100..=5 * SOL).prop_map(|approved| Op::Create { approved }),
+        2 => Just(Op::Approve),
+        4 => lamports().prop_map(|amount| Op::Deposit { amount }),
+        4 => (fee(), lamports(), 0..100u64).prop_map(|(fee, amount, client_id)| Op::Trade {
+            fee,
+            amount,
+            client_id,
+        }),
+        1 => prop::collection::vec((fee(), lamports(), 0..100u64), 0..4)
+            .prop_map(|trades| Op::Batch { trades }),
+        3 => lamports().prop_map(|amount| Op::Withdraw { amount }),
+        1 => Just(Op::Revoke),
+        1 => Just(Op::Reactivate),
+        1 => lamports().prop_map(|approved| Op::Update { approved }),
+        1 => Just(Op::Pause),
+        1 => Just(Op::Unpause),
+        2 => prop_oneof![0..5i64, 250..4_000i64].prop_map(|seconds| Op::Warp { seconds }),
+        1 => Just(Op::Cleanup),
+    ]
+}
+
+/// What the harness knows that the vault does not record.
+#[derive(Default)]
+struct Model {
+    /// Trade fees charged over the vault's lifetime.
+    fees: u64,
+    /// Trade fees still held by the vault as lamports.
+    retained_fees: u64,
+    exists: bool,
+}
+
+struct Run {
+    t: VaultTest,
+    user: Keypair,
+    delegate: Keypair,
+    cleaner: Keypair,
+    vault: Pubkey,
+    model: Model,
+    total_lamports: u64,
+}
+
+impl Run {
+    async fn start() -> Self {
+        let mut t = VaultTest::start().await.expect("program available");
+        let user = Keypair::new();
+        let delegate = Keypair::new();
+        let cleaner = Keypair::new();
+        for wallet in [&user, &delegate, &cleaner] {
+            t.fund(&wallet.pubkey(), 100 * SOL);
+        }
+        let vault = vault_pda(&user.pubkey());
+        let mut run = Self {
+            t,
+            user,
+            delegate,
+            cleaner,
+            vault,
+            model: Model::default(),
+            total_lamports: 0,
+        };
+        run.total_lamports = run.tracked_lamports().await;
+        run
+    }
+
+    async fn tracked_lamports(&mut self) -> u64 {
+        let mut total = 0;
+        for pubkey in [
+            self.user.pubkey(),
+            self.delegate.pubkey(),
+            self.cleaner.pubkey(),
+            self.vault,
+        ] {
+            total += self.t.balance(&pubkey).await;
+        }
+        total
+    }
+
+    async fn apply(&mut self, op: &Op) -> TxResult {
+        let vault = self.vault;
+        let t = &mut self.t;
+        match *op {
+            Op::Create { approved } => t.create_vault(&self.user, approved).await,
+            Op::Approve => {
+                t.approve_delegate(&self.user, self.delegate.pubkey(), None)
+                    .await
+            }
+            Op::Deposit { amount } => t.deposit(&self.user, amount).await,
+            Op::Trade {
+                fee,
+                amount,
+                client_id,
+            } => {
+                t.execute_trade(&self.delegate, vault, fee, amount, client_id)
+                    .await
+            }
+            Op::Batch { ref trades } => {
+                let entries = trades
+                    .iter()
+                    .map(|&(trade_fee, trade_amount, client_id)| TradeEntry {
+                        trade_fee,
+                        trade_amount,
+                        client_id,
+                    })
+                    .collect();
+                t.execute_trades_batch(&self.delegate, vault, entries).await
+            }
+            Op::Withdraw { amount } => t.withdraw(&self.user, vault, amount).await,
+            Op::Revoke => t.revoke(&self.user, vault).await,
+            Op::Reactivate => t.reactivate(&self.user, vault).await,
+            Op::Update { approved } => t.update_approved_amount(&self.user, vault, approved).await,
+            Op::Pause => t.pause(&self.user, vault).await,
+            Op::Unpause => t.unpause(&self.user, vault).await,
+            Op::Warp { seconds } => {
+                t.warp_seconds(seconds);
+                Ok(())
+            }
+            Op::Cleanup => t.cleanup(&self.cleaner, vault, self.user.pubkey()).await,
+        }
+    }
+
+    /// Folds a successful `op` into the model.
+    fn record(&mut self, op: &Op) {
+        let model = &mut self.model;
+        match op {
+            Op::Create { .. } => {
+                *model = Model {
+                    exists: true,
+                    ..Model::default()
+                }
+            }
+            Op::Trade { fee, .. } => {
+                model.fees += fee;
+                model.retained_fees += fee;
+            }
+            Op::Batch { trades } => {
+                let fees: u64 = trades.iter().map(|&(fee, _, _)| fee).sum();
+                model.fees += fees;
+                model.retained_fees += fees;
+            }
+            Op::Revoke => model.retained_fees = 0,
+            Op::Cleanup => *model = Model::default(),
+            _ => {}
+        }
+    }
+
+    async fn step(&mut self, op: &Op) {
+        let before = self.t.account(&self.vault).await;
+        let result = self.apply(op).await;
+        match result {
+            Ok(()) => self.record(op),
+            Err(_) => assert_eq!(
+                self.t.account(&self.vault).await,
+                before,
+                "failed {op:?} modified the vault"
+            ),
+        }
+        self.check_invariants(op).await;
+    }
+
+    async fn check_invariants(&mut self, op: &Op) {
+        assert_eq!(
+            self.tracked_lamports().await,
+            self.total_lamports,
+            "lamports created or destroyed by {op:?}"
+        );
+
+        if !self.model.exists {
+            assert!(self.t.account(&self.vault).await.is_none());
+            return;
+        }
+
+        let vault = self.t.vault(&self.vault).await;
+        let lamports = self.t.balance(&self.vault).await;
+        let rent = self.t.rent().minimum_balance(VAULT_SPACE);
+
+        assert_eq!(
+            vault.total_deposited - vault.total_withdrawn - self.model.fees,
+            vault.available_amount,
+            "accounting drifted after {op:?}"
+        );
+        assert_eq!(
+            lamports,
+            rent + vault.available_amount + self.model.retained_fees,
+            "vault lamports do not back its balance after {op:?}"
+        );
+        assert!(vault.available_amount <= vault.approved_amount);
+        assert!(vault.used_amount <= vault.approved_amount);
+    }
+}
+
+#[test]
+fn random_operation_sequences_preserve_vault_invariants() {
+    if !program_available() {
+        return;
+    }
+    let runtime = tokio::runtime::Runtime::new().expect("runtime");
+    let mut runner = TestRunner::new(Config::default());
+
+    runner
+        .run(
+            &(
+                SOL / 100..=5 * SOL,
+                prop::collection::vec(op(), 1..MAX_STEPS),
+            ),
+            |(approved, ops)| {
+                runtime.block_on(async {
+                    let mut run = Run::start().await;
+                    let create = Op::Create { approved };
+                    run.step(&create).await;
+                    for op in &ops {
+                        run.step(op).await;
+                    }
+                });
+                Ok(())
+            },
+        )
+        .unwrap();
+}
@@ -99,6 +99,7 @@ async function main() {
     delegatePubkey: delegate.publicKey.toBase58(),
     tradeFeeLamports: Math.round(tradeFee),
     tradeAmountLamports: Math.round(tradeAmount),
+    clientOrderId: 1,
   });
   await sendBase64Tx(connection, execResp.transactionBase64, delegate);
 
@@ -16,7 +16,7 @@ const MIN_DEPOSIT_AMOUNT = new BN(1_000_000);
 const MAX_DEPOSIT_AMOUNT = new BN(100_000_000_000);
 const SESSION_DURATION_SECONDS = 3600;
 const RENEWAL_WINDOW_SECONDS = 300;
-const PROGRAM_VERSION = 1;
+const PROGRAM_VERSION = 2;
 
 const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
 
@@ -164,13 +164,13 @@ describe("ephemeral_vault (TypeScript)", () => {
       );
       assert.strictEqual(vault.vaultPda.toBase58(), f.vaultPda.toBase58());
       assert.strictEqual(vault.approvedAmount.toNumber(), 2 * LAMPORTS_PER_SOL);
-      assert.strictEqual(vault.isActive, true);
-      assert.strictEqual(vault.isPaused, false);
+      assert.strictEqual(vault.isActive, 1);
+      assert.strictEqual(vault.isPaused, 0);
       assert.strictEqual(vault.version, PROGRAM_VERSION);
       assert.strictEqual(vault.bump, f.bump);
       assert.strictEqual(vault.availableAmount.toNumber(), 0);
       assert.strictEqual(vault.totalDeposited.toNumber(), 0);
-      assert.isNull(vault.delegateWallet);
+      assert.isTrue(vault.delegateWallet.equals(PublicKey.default));
     });
 
     it("rejects invalid approved amount bounds", async () => {
@@ -218,10 +218,10 @@ describe("ephemeral_vault (TypeScript)", () => {
 
       const vault = await program.account.ephemeralVault.fetch(f.vaultPda);
       assert.strictEqual(
-        vault.delegateWallet?.toBase58(),
+        vault.delegateWallet.toBase58(),
         f.delegate.publicKey.toBase58(),
       );
-      assert.isNotNull(vault.sessionExpiresAt);
+      assert.isAbove(vault.sessionExpiresAt.toNumber(), 0);
 
       await expectError(
         program.methods
@@ -252,8 +252,8 @@ describe("ephemeral_vault (TypeScript)", () => {
         .rpc();
 
       const vault = await program.account.ephemeralVault.fetch(f.vaultPda);
-      const delegatedAt = vault.delegatedAt!.toNumber();
-      const expiresAt = vault.sessionExpiresAt!.toNumber();
+      const delegatedAt = vault.delegatedAt.toNumber();
+      const expiresAt = vault.sessionExpiresAt.toNumber();
       assert.isAtMost(expiresAt - delegatedAt, SESSION_DURATION_SECONDS);
     });
 
@@ -302,11 +302,11 @@ describe("ephemeral_vault (TypeScript)", () => {
 
       const vault = await program.account.ephemeralVault.fetch(f.vaultPda);
       assert.isAbove(
-        vault.sessionExpiresAt!.toNumber(),
-        beforeRenewal.sessionExpiresAt!.toNumber(),
+        vault.sessionExpiresAt.toNumber(),
+        beforeRenewal.sessionExpiresAt.toNumber(),
       );
       assert.strictEqual(
-        vault.sessionExpiresAt!.toNumber() - vault.lastActivity.toNumber(),
+        vault.sessionExpiresAt.toNumber() - vault.lastActivity.toNumber(),
         SESSION_DURATION_SECONDS,
       );
     });
@@ -329,7 +329,7 @@ describe("ephemeral_vault (TypeScript)", () => {
         .rpc();
 
       await program.methods
-        .executeTrade(new BN(100_000), new BN(1_000_000))
+        .executeTrade(new BN(100_000), new BN(1_000_000), new BN(1))
         .accounts({ delegate: f.delegate.publicKey, vault: f.vaultPda })
         .signers([f.delegate])
         .rpc();
@@ -398,7 +398,7 @@ describe("ephemeral_vault (TypeScript)", () => {
       // Test wrong delegate
       await expectError(
         program.methods
-          .executeTrade(new BN(1000), new BN(1000))
+          .executeTrade(new BN(1000), new BN(1000), new BN(2))
           .accounts({ delegate: f.attacker.publicKey, vault: f.vaultPda })
           .signers([f.attacker])
           .rpc(),
@@ -411,7 +411,7 @@ describe("ephemeral_vault (TypeScript)", () => {
       // Test expired session
       await expectError(
         program.methods
-          .executeTrade(new BN(1000), new BN(1000))
+          .executeTrade(new BN(1000), new BN(1000), new BN(3))
           .accounts({ delegate: f.delegate.publicKey, vault: f.vaultPda })
           .signers([f.delegate])
           .rpc(),
@@ -422,10 +422,10 @@ describe("ephemeral_vault (TypeScript)", () => {
       // User must manually revoke or reapprove
       const vault = await program.account.ephemeralVault.fetch(f.vaultPda);
       assert.strictEqual(
-        vault.delegateWallet?.toBase58(),
+        vault.delegateWallet.toBase58(),
         f.delegate.publicKey.toBase58(),
       );
-      assert.isNotNull(vault.sessionExpiresAt);
+      assert.isAbove(vault.sessionExpiresAt.toNumber(), 0);
     });
 
     it("rejects cumulative trade amount above the approved limit", async () => {
@@ -444,14 +444,14 @@ describe("ephemeral_vault (TypeScript)", () => {
         .rpc();
 
       await program.methods
-        .executeTrade(new BN(1_000), new BN(1_500_000))
+        .executeTrade(new BN(1_000), new BN(1_500_000), new BN(4))
         .accounts({ delegate: f.delegate.publicKey, vault: f.vaultPda })
         .signers([f.delegate])
         .rpc();
 
       await expectError(
         program.methods
-          .executeTrade(new BN(1_000), new BN(1_100_000))
+          .executeTrade(new BN(1_000), new BN(1_100_000), new BN(5))
           .accounts({ delegate: f.delegate.publicKey, vault: f.vaultPda })
           .signers([f.delegate])
           .rpc(),
@@ -460,6 +460,121 @@ describe("ephemeral_vault (TypeScript)", () => {
     });
   });
 
+  describe("client order id replay protection", () => {
+    it("rejects reused and stale client order ids", async () => {
+      const f = await createFixture(new BN(10 * LAMPORTS_PER_SOL));
+
+      await program.methods
+        .approveDelegate(f.delegate.publicKey, null)
+        .accounts({ user: f.user.publicKey, vault: f.vaultPda })
+        .signers([f.user])
+        .rpc();
+
+      await program.methods
+        .autoDepositForTrade(MIN_DEPOSIT_AMOUNT)
+        .accounts({ user: f.user.publicKey, vault: f.vaultPda })
+        .signers([f.user])
+        .rpc();
+
+      const trade = (clientOrderId: number) =>
+        program.methods
+          .executeTrade(new BN(1_000), new BN(1_000_000), new BN(clientOrderId))
+          .accounts({ delegate: f.delegate.publicKey, vault: f.vaultPda })
+          .signers([f.delegate])
+          .rpc();
+
+      await trade(100);
+      await expectError(trade(100), "DuplicateClientOrderId");
+
+      // Out-of-order ids inside the window are still accepted once
+      await trade(90);
+      await expectError(trade(90), "DuplicateClientOrderId");
+
+      await expectError(trade(100 - 64), "StaleClientOrderId");
+      await expectError(trade(0), "InvalidClientOrderId");
+
+      const vault = await program.account.ephemeralVault.fetch(f.vaultPda);
+      assert.strictEqual(vault.tradeCount.toNumber(), 2);
+      assert.strictEqual(vault.maxClientOrderId.toNumber(), 100);
+    });
+  });
+
+  describe("execute_trades_batch", () => {
+    it("settles all entries atomically", async () => {
+      const f = await createFixture(new BN(5_000_000));
+
+      await program.methods
+        .approveDelegate(f.delegate.publicKey, null)
+        .accounts({ user: f.user.publicKey, vault: f.vaultPda })
+        .signers([f.user])
+        .rpc();
+
+      await program.methods
+        .autoDepositForTrade(MIN_DEPOSIT_AMOUNT)
+        .accounts({ user: f.user.publicKey, vault: f.vaultPda })
+        .signers([f.user])
+        .rpc();
+
+      await program.methods
+        .executeTradesBatch([
+          {
+            tradeFee: new BN(1_000),
+            tradeAmount: new BN(1_000_000),
+            clientId: new BN(1),
+          },
+          {
+            tradeFee: new BN(2_000),
+            tradeAmount: new BN(2_000_000),
+            clientId: new BN(2),
+          },
+        ])
+        .accounts({ delegate: f.delegate.publicKey, vault: f.vaultPda })
+        .signers([f.delegate])
+        .rpc();
+
+      let vault = await program.account.ephemeralVault.fetch(f.vaultPda);
+      assert.strictEqual(vault.tradeCount.toNumber(), 2);
+      assert.strictEqual(vault.usedAmount.toNumber(), 3_000_000);
+      assert.strictEqual(
+        vault.availableAmount.toNumber(),
+        MIN_DEPOSIT_AMOUNT.toNumber() - 3_000,
+      );
+
+      // Second entry exceeds the remaining budget, so the first must not apply
+      await expectError(
+        program.methods
+          .executeTradesBatch([
+            {
+              tradeFee: new BN(1_000),
+              tradeAmount: new BN(1_000_000),
+              clientId: new BN(3),
+            },
+            {
+              tradeFee: new BN(1_000),
+              tradeAmount: new BN(1_500_000),
+              clientId: new BN(4),
+            },
+          ])
+          .accounts({ delegate: f.delegate.publicKey, vault: f.vaultPda })
+          .signers([f.delegate])
+          .rpc(),
+        "TradeLimitExceeded",
+      );
+
+      vault = await program.account.ephemeralVault.fetch(f.vaultPda);
+      assert.strictEqual(vault.tradeCount.toNumber(), 2);
+
+      await expectError(
+        program.methods
+          .executeTradesBatch([])
+          .accounts({ delegate: f.delegate.publicKey, vault: f.vaultPda })
+          .signers([f.delegate])
+          .rpc(),
+        "EmptyTradeBatch",
+      );
+    });
+  });
+
   describe("withdraw / pause / unpause", () => {
     it("owner withdraws and non-owner is blocked", async () => {
       const f = await createFixture();
@@ -523,7 +638,7 @@ describe("ephemeral_vault (TypeScript)", () => {
         .rpc();
 
       const vault = await program.account.ephemeralVault.fetch(f.vaultPda);
-      assert.strictEqual(vault.isPaused, false);
+      assert.strictEqual(vault.isPaused, 0);
       assert.isAtLeast(
         vault.totalDeposited.toNumber(),
         MIN_DEPOSIT_AMOUNT.toNumber(),
@@ -548,8 +663,8 @@ describe("ephemeral_vault (TypeScript)", () => {
         .rpc();
 
       let vault = await program.account.ephemeralVault.fetch(f.vaultPda);
-      assert.strictEqual(vault.isActive, false);
-      assert.isNull(vault.delegateWallet);
+      assert.strictEqual(vault.isActive, 0);
+      assert.isTrue(vault.delegateWallet.equals(PublicKey.default));
 
       await program.methods
         .reactivateVault()
@@ -558,10 +673,10 @@ describe("ephemeral_vault (TypeScript)", () => {
         .rpc();
 
       vault = await program.account.ephemeralVault.fetch(f.vaultPda);
-      assert.strictEqual(vault.isActive, true);
-      assert.strictEqual(vault.isPaused, false);
-      assert.isNull(vault.delegateWallet);
-      assert.isNull(vault.sessionExpiresAt);
+      assert.strictEqual(vault.isActive, 1);
+      assert.strictEqual(vault.isPaused, 0);
+      assert.isTrue(vault.delegateWallet.equals(PublicKey.default));
+      assert.strictEqual(vault.sessionExpiresAt.toNumber(), 0);
     });
 
     it("cleanup rejects active vault and non-expired inactive vault", async () => {
@@ -624,6 +739,10 @@ describe("ephemeral_vault (TypeScript)", () => {
         .view();
 
       assert.isDefined(stats.sessionStatus.noSession);
+      assert.isNull(stats.secondsUntilExpiry);
+      assert.strictEqual(stats.remainingBudget.toNumber(), 2 * LAMPORTS_PER_SOL);
+      assert.isFalse(stats.canRenew);
+      assert.isFalse(stats.canCleanup);
 
       await program.methods
         .approveDelegate(f.delegate.publicKey, null)
@@ -652,6 +771,65 @@ describe("ephemeral_vault (TypeScript)", () => {
         .accounts({ vault: f.vaultPda })
         .view();
       assert.isDefined(stats.sessionStatus.expiringSoon);
+      assert.isAtMost(stats.secondsUntilExpiry.toNumber(), RENEWAL_WINDOW_SECONDS);
+      assert.isTrue(stats.canRenew);
+
+      await program.methods
+        .revokeAccess()
+        .accounts({ user: f.user.publicKey, vault: f.vaultPda })
+        .signers([f.user])
+        .rpc();
+      await sleep(2500);
+
+      stats = await program.methods
+        .getVaultStats()
+        .accounts({ vault: f.vaultPda })
+        .view();
+      assert.isFalse(stats.canRenew);
+      assert.isTrue(stats.canCleanup);
+    });
+
+    it("check_delegate reports remaining budget for a live delegate", async () => {
+      const f = await createFixture();
+
+      await expectError(
+        program.methods
+          .checkDelegate(new BN(0))
+          .accounts({ vault: f.vaultPda, delegate: f.delegate.publicKey })
+          .signers([f.delegate])
+          .view(),
+        "Unauthorized",
+      );
+
+      await program.methods
+        .approveDelegate(f.delegate.publicKey, null)
+        .accounts({ user: f.user.publicKey, vault: f.vaultPda })
+        .signers([f.user])
+        .rpc();
+
+      const check = await program.methods
+        .checkDelegate(new BN(LAMPORTS_PER_SOL))
+        .accounts({ vault: f.vaultPda, delegate: f.delegate.publicKey })
+        .signers([f.delegate])
+        .view();
+      const vault = await program.account.ephemeralVault.fetch(f.vaultPda);
+
+      assert.ok(check.delegate.equals(f.delegate.publicKey));
+      assert.strictEqual(check.remainingBudget.toNumber(), 2 * LAMPORTS_PER_SOL);
+      assert.strictEqual(
+        check.sessionExpiresAt.toNumber(),
+        vault.sessionExpiresAt.toNumber(),
+      );
+      assert.isDefined(check.stats.sessionStatus.active);
+
+      await expectError(
+        program.methods
+          .checkDelegate(new BN(3 * LAMPORTS_PER_SOL))
+          .accounts({ vault: f.vaultPda, delegate: f.delegate.publicKey })
+          .signers([f.delegate])
+          .view(),
+        "InsufficientDelegateBudget",
+      );
     });
 
     it("rejects lowering approved amount below current available or used state", async () => {
@@ -679,7 +857,7 @@ describe("ephemeral_vault (TypeScript)", () => {
         .rpc();
 
       await program.methods
-        .executeTrade(new BN(100_000), new BN(1_000_000))
+        .executeTrade(new BN(100_000), new BN(1_000_000), new BN(6))
         .accounts({ delegate: f.delegate.publicKey, vault: f.vaultPda })
         .signers([f.delegate])
         .rpc();